@@ -1,8 +1,18 @@
 fn main() {
+    // the io_uring batch reads and writes only exist on Linux, other targets use plain positioned IO
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("linux") {
+        return;
+    }
+    // the C is only ever checked by this build, so its warnings fail it
     cc::Build::new()
         .file("native/io.c")
+        .warnings(true)
+        .extra_warnings(true)
+        .warnings_into_errors(true)
         .compile("io");
 
+    println!("cargo:rerun-if-changed=native/io.c");
+
     println!("cargo:rustc-link-lib=static=io");
     println!("cargo:rustc-link-lib=uring");
-}
\ No newline at end of file
+}
@@ -9,62 +9,74 @@ typedef struct {
     off_t offset; 
 } WriteEntry;
 
-int batch_write_data_c(WriteEntry* entries, size_t len, const int file) {
+/* Error codes for batch_write_data_c
+ *
+ * -1 : failed to get SQE
+ * -2 : failed to init queue
+ * -3 : failed to submit io_uring_submit
+ * -4 : failed to wait for a completion
+ * -5 : a write failed or came back short
+ * -6 : the fsync failed
+ *
+ * sync : 0 skips the fsync, 1 syncs data and metadata, 2 only the data (fdatasync)
+ * */
+int batch_write_data_c(WriteEntry* entries, size_t len, const int file, const int sync) {
     struct io_uring ring;
+    size_t submitted = sync ? len + 1 : len;
     if (io_uring_queue_init(len + 1, &ring, 0) < 0) {
-        perror("io_uring_queue_init");
-        return -1;
+        return -2;
     }
 
     for (size_t index = 0; index < len; index++) {
         struct io_uring_sqe* sqe = io_uring_get_sqe(&ring);
         if (!sqe) {
-            fprintf(stderr, "No submission queue entry available\n");
             io_uring_queue_exit(&ring);
             return -1;
         }
-        WriteEntry en = entries[index];
-        io_uring_prep_write(sqe, file, en.buffer, en.length, en.offset);
+        WriteEntry* en = &entries[index];
+        io_uring_prep_write(sqe, file, en->buffer, en->length, en->offset);
+        io_uring_sqe_set_data(sqe, en);
     }
 
-    struct io_uring_sqe* sqe = io_uring_get_sqe(&ring);
-    if (!sqe) {
-        fprintf(stderr, "No submission queue entry for fsync\n");
-        io_uring_queue_exit(&ring);
-        return -1;
-    }
+    if (sync) {
+        struct io_uring_sqe* sqe = io_uring_get_sqe(&ring);
+        if (!sqe) {
+            io_uring_queue_exit(&ring);
+            return -1;
+        }
 
-    io_uring_prep_fsync(sqe, file, 0);
+        io_uring_prep_fsync(sqe, file, sync == 2 ? IORING_FSYNC_DATASYNC : 0);
+        // the fsync only starts once every write before it completed, otherwise it could miss them
+        io_uring_sqe_set_flags(sqe, IOSQE_IO_DRAIN);
+        io_uring_sqe_set_data(sqe, NULL);
+    }
 
-    int submitted = io_uring_submit(&ring);
-    if (submitted < 0) {
-        perror("io_uring_submit");
+    if (io_uring_submit(&ring) < 0) {
         io_uring_queue_exit(&ring);
-        return -1;
+        return -3;
     }
 
-    for (size_t i = 0; i < len + 1; i++) {
+    int result = 0;
+    for (size_t i = 0; i < submitted; i++) {
         struct io_uring_cqe* cqe;
-        int ret = io_uring_wait_cqe(&ring, &cqe);
-        if (ret < 0) {
-            perror("io_uring_wait_cqe");
+        if (io_uring_wait_cqe(&ring, &cqe) < 0) {
             io_uring_queue_exit(&ring);
-            return -1;
+            return -4;
         }
-        if (cqe->res < 0) {
-            fprintf(stderr, "Async operation failed: %d\n", cqe->res);
-            io_uring_cqe_seen(&ring, cqe);
-            io_uring_queue_exit(&ring);
-            return -1;
+        WriteEntry* en = io_uring_cqe_get_data(cqe);
+        if (en == NULL) {
+            if (cqe->res < 0 && result == 0) {
+                result = -6;
+            }
+        } else if (cqe->res < 0 || (size_t)cqe->res != en->length) {
+            result = -5;
         }
         io_uring_cqe_seen(&ring, cqe);
     }
     io_uring_queue_exit(&ring);
-    return 0;
+    return result;
 }
 
-/*
-
 struct ReadInstance{
     uint64_t size;
     unsigned char* buffer;
@@ -75,52 +87,51 @@ struct ReadEntry{
     uint64_t len;
     struct ReadInstance* buffer_array;
 };
-*/
+
 /* Error codes for batch_reads
  *
  * -1 : failed to get SQE
  * -2 : failed to init queue
  * -3 : failed to submit io_uring_submit
- *  -4 : failed
+ * -4 : failed to wait for a completion
+ * -5 : a read failed or came back short
  * */
-/*
 int batch_reads(struct ReadEntry* re, int file){
     struct io_uring ring;
 
-    if (!io_uring_queue_init(re->len + 1,&ring, 0)){
+    if (io_uring_queue_init(re->len + 1, &ring, 0) < 0){
         return -2;
     }
-    
+
     const uint64_t entry_size = re->len;
-    for (uint64_t index; index<entry_size; index++){
+    for (uint64_t index = 0; index < entry_size; index++){
         struct io_uring_sqe* sqe = io_uring_get_sqe(&ring);
         if (!sqe){
             io_uring_queue_exit(&ring);
             return -1;
         }
-        io_uring_prep_read(sqe,file,re->buffer_array[index].buffer,re->buffer_array[index].size,re->buffer_array[index].offset);
+        io_uring_prep_read(sqe, file, re->buffer_array[index].buffer, re->buffer_array[index].size, re->buffer_array[index].offset);
+        io_uring_sqe_set_data(sqe, &re->buffer_array[index]);
     }
 
-    if (!io_uring_submit(&ring)){
+    if (io_uring_submit(&ring) < 0){
         io_uring_queue_exit(&ring);
         return -3;
     }
-    for (uint64_t index; index < re->len + 1; index ++) {
+    int result = 0;
+    for (uint64_t index = 0; index < entry_size; index++) {
         struct io_uring_cqe* cqe;
-        int abc = io_uring_wait_cqe(&ring, &cqe);
-        if (!abc){
+        if (io_uring_wait_cqe(&ring, &cqe) < 0){
             io_uring_queue_exit(&ring);
             return -4;
         }
+        // the reads cover whole records inside the file, anything less than the full size is a failure
+        struct ReadInstance* instance = io_uring_cqe_get_data(cqe);
+        if (cqe->res < 0 || (uint64_t)cqe->res != instance->size){
+            result = -5;
+        }
+        io_uring_cqe_seen(&ring, cqe);
     }
     io_uring_queue_exit(&ring);
-    return 0;
+    return result;
 }
-
-
-
-
-
-
-
-*/
@@ -0,0 +1,141 @@
+use std::{cmp::Ordering, io::Error};
+
+use crate::{alba_types::AlbaTypes, decimal::Decimal, gerr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggKind{
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggKind{
+    pub fn name(&self) -> &'static str{
+        match self{
+            AggKind::Sum => "sum",
+            AggKind::Avg => "avg",
+            AggKind::Min => "min",
+            AggKind::Max => "max",
+        }
+    }
+}
+
+/// Folds one column of the matched rows into a single value.
+/// Sum, Min and Max keep the column type, Avg is a Float except over Decimal columns, where it stays an exact Decimal.
+/// With no rows Sum is zero and the others are NONE.
+#[derive(Debug, Clone)]
+pub struct Accumulator{
+    kind : AggKind,
+    column : String,
+    position : usize,
+    ty : AlbaTypes,
+    count : u64,
+    int : i128,
+    float : f64,
+    decimal : Decimal,
+    best : Option<AlbaTypes>,
+}
+
+impl Accumulator{
+    pub fn new(kind : AggKind, column : &str, headers : &[(String,AlbaTypes)]) -> Result<Self,Error>{
+        let position = headers.iter().position(|h|h.0 == column).ok_or(gerr(&format!("Can't {} the column '{}', it doesn't exist", kind.name(), column)))?;
+        let ty = headers[position].1.clone();
+        match ty{
+            AlbaTypes::Int(_)|AlbaTypes::Bigint(_)|AlbaTypes::Float(_)|AlbaTypes::Decimal(_) => {},
+            _ => return Err(gerr(&format!("Can't {} the column '{}', it holds {:?} values and only Int, Bigint, Float and Decimal are numeric", kind.name(), column, ty)))
+        }
+        Ok(Accumulator{kind, column: column.to_string(), position, ty, count: 0, int: 0, float: 0.0, decimal: Decimal::from(0), best: None})
+    }
+    pub fn label(&self) -> String{
+        format!("{}({})", self.kind.name(), self.column)
+    }
+    pub fn push(&mut self, row : &[AlbaTypes]) -> Result<(),Error>{
+        let value = match row.get(self.position){
+            Some(AlbaTypes::NONE)|None => return Ok(()),
+            Some(v) => v
+        };
+        self.count += 1;
+        match value{
+            AlbaTypes::Int(i) => {self.int += *i as i128; self.float += *i as f64},
+            AlbaTypes::Bigint(i) => {self.int += *i as i128; self.float += *i as f64},
+            AlbaTypes::Float(f) => self.float += *f,
+            AlbaTypes::Decimal(d) => self.decimal = self.decimal.checked_add(d).map_err(|_|gerr(&format!("The sum of '{}' doesn't fit in a Decimal", self.column)))?,
+            _ => return Err(gerr(&format!("Can't {} the column '{}', found a {:?} value", self.kind.name(), self.column, value)))
+        }
+        let replace = match &self.best{
+            None => true,
+            Some(best) => {
+                let ord = compare(value, best);
+                (self.kind == AggKind::Min && ord == Ordering::Less) || (self.kind == AggKind::Max && ord == Ordering::Greater)
+            }
+        };
+        if replace{
+            self.best = Some(value.clone());
+        }
+        Ok(())
+    }
+    pub fn finish(&self) -> Result<AlbaTypes,Error>{
+        Ok(match self.kind{
+            AggKind::Sum => match self.ty{
+                AlbaTypes::Int(_) => AlbaTypes::Int(i32::try_from(self.int).map_err(|_|gerr(&format!("The sum of '{}' doesn't fit in an Int", self.column)))?),
+                AlbaTypes::Bigint(_) => AlbaTypes::Bigint(i64::try_from(self.int).map_err(|_|gerr(&format!("The sum of '{}' doesn't fit in a Bigint", self.column)))?),
+                AlbaTypes::Decimal(_) => AlbaTypes::Decimal(self.decimal),
+                _ => AlbaTypes::Float(self.float),
+            },
+            AggKind::Avg => {
+                if self.count == 0{
+                    AlbaTypes::NONE
+                }else if let AlbaTypes::Decimal(_) = self.ty{
+                    AlbaTypes::Decimal(self.decimal.div_int(self.count as i128)?)
+                }else if let AlbaTypes::Float(_) = self.ty{
+                    AlbaTypes::Float(self.float / self.count as f64)
+                }else{
+                    AlbaTypes::Float(self.int as f64 / self.count as f64)
+                }
+            },
+            AggKind::Min|AggKind::Max => self.best.clone().unwrap_or(AlbaTypes::NONE),
+        })
+    }
+}
+
+fn compare(a : &AlbaTypes, b : &AlbaTypes) -> Ordering{
+    match (a,b){
+        (AlbaTypes::Int(x),AlbaTypes::Int(y)) => x.cmp(y),
+        (AlbaTypes::Bigint(x),AlbaTypes::Bigint(y)) => x.cmp(y),
+        (AlbaTypes::Float(x),AlbaTypes::Float(y)) => x.total_cmp(y),
+        (AlbaTypes::Decimal(x),AlbaTypes::Decimal(y)) => x.cmp(y),
+        _ => Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn decimal(s : &str) -> AlbaTypes{
+        AlbaTypes::Decimal(s.parse().unwrap())
+    }
+
+    fn fold(kind : AggKind, values : &[&str]) -> AlbaTypes{
+        let mut acc = Accumulator::new(kind, "amount", &[("amount".to_string(),AlbaTypes::Decimal(Decimal::from(0)))]).unwrap();
+        for value in values{
+            acc.push(&[decimal(value)]).unwrap();
+        }
+        acc.finish().unwrap()
+    }
+
+    #[test]
+    fn decimal_sums_and_averages_stay_exact(){
+        assert_ne!(0.1f64 + 0.2, 0.3);
+        assert_eq!(fold(AggKind::Sum, &["0.1","0.2"]), decimal("0.3"));
+        assert_eq!(fold(AggKind::Sum, &["0.1";10]), decimal("1"));
+        assert_eq!(fold(AggKind::Sum, &["19.99","-0.01","0.020"]), decimal("20"));
+        assert_eq!(fold(AggKind::Avg, &["0.1","0.2"]), decimal("0.15"));
+        // a third is rounded at the last digit kept
+        assert_eq!(fold(AggKind::Avg, &["1","0","0"]), decimal("0.3333333333333333333333333333"));
+        assert_eq!(fold(AggKind::Max, &["0.30","0.3000001","0.29"]), decimal("0.3000001"));
+        assert_eq!(fold(AggKind::Sum, &[]), decimal("0"));
+        assert_eq!(fold(AggKind::Avg, &[]), AlbaTypes::NONE);
+    }
+}
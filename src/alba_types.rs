@@ -1,9 +1,10 @@
 use std::{fmt, io::{Error, ErrorKind}};
 
 use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::{database::MAX_STR_LEN, Token};
+use crate::{decimal::Decimal, Token};
 
 #[derive( Clone, PartialEq, Deserialize, Serialize)]
 pub enum AlbaTypes{
@@ -23,31 +24,95 @@ pub enum AlbaTypes{
     MediumBytes(Vec<u8>),
     BigSBytes(Vec<u8>),
     LargeBytes(Vec<u8>),
+    /// 16 raw bytes, read from and shown as the hyphenated hex form.
+    Uuid([u8;16]),
+    /// Microseconds since the Unix epoch in UTC, read from and shown as ISO-8601.
+    Timestamp(i64),
+    /// Exact base-10 number, for amounts a float would round.
+    Decimal(Decimal),
     NONE
 }
 
-fn serialize_closed_string(item_size : usize,s : &String,buffer : &mut Vec<u8>){
-    let mut bytes = Vec::with_capacity(item_size);
-    let size = s.len().to_be_bytes();
-    let mut str = s.to_owned();
-    let _ = s;
-    
-    if str.len() > item_size - 8{
-        str.truncate(item_size-8);
+/// Reads an ISO-8601 date and time into microseconds since the epoch. An offset is honoured when given,
+/// without one the time is taken as UTC and a bare date is its midnight.
+pub fn parse_timestamp(s : &str) -> Result<i64,Error>{
+    let invalid = || Error::new(ErrorKind::InvalidData, format!("{} is not an ISO-8601 timestamp", s));
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s){
+        return Ok(datetime.timestamp_micros())
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]{
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format){
+            return Ok(datetime.and_utc().timestamp_micros())
+        }
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_|invalid())?;
+    Ok(date.and_hms_opt(0, 0, 0).ok_or(invalid())?.and_utc().timestamp_micros())
+}
+/// RFC 3339 form of a timestamp in UTC, the raw microseconds when it is out of chrono's range.
+pub fn format_timestamp(micros : i64) -> String{
+    match DateTime::<Utc>::from_timestamp_micros(micros){
+        Some(datetime) => datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        None => micros.to_string()
+    }
+}
+
+/// Reads a UUID in its hyphenated form (`8-4-4-4-12` hex digits) or as 32 bare hex digits.
+pub fn parse_uuid(s : &str) -> Result<[u8;16],Error>{
+    let invalid = || Error::new(ErrorKind::InvalidData, format!("{} is not a valid UUID", s));
+    let hex : Vec<u8> = match s.len(){
+        36 => {
+            if [8,13,18,23].iter().any(|i|s.as_bytes()[*i] != b'-'){
+                return Err(invalid())
+            }
+            s.bytes().filter(|b|*b != b'-').collect()
+        },
+        32 => s.bytes().collect(),
+        _ => return Err(invalid())
+    };
+    // from_str_radix would also take a sign
+    if hex.len() != 32 || !hex.iter().all(|b|b.is_ascii_hexdigit()){
+        return Err(invalid())
+    }
+    let mut uuid = [0u8;16];
+    for (byte,pair) in uuid.iter_mut().zip(hex.chunks_exact(2)){
+        let pair = std::str::from_utf8(pair).map_err(|_|invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_|invalid())?;
+    }
+    Ok(uuid)
+}
+/// Hyphenated lowercase form of a UUID.
+pub fn format_uuid(uuid : &[u8;16]) -> String{
+    let hex : String = uuid.iter().map(|b|format!("{:02x}",b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Longest prefix of `s` that fits in `max_len` bytes without splitting a character.
+pub fn truncate_at_char_boundary(s : &str, max_len : usize) -> &str{
+    if s.len() <= max_len{
+        return s
     }
-    let str_bytes = str.as_bytes();
-    bytes.extend_from_slice(&size);
-    bytes.extend_from_slice(str_bytes);
-    
-    if bytes.len() < item_size{
-        bytes.resize(item_size, 0);
+    let mut end = max_len;
+    while !s.is_char_boundary(end){
+        end -= 1;
     }
+    &s[..end]
+}
+
+// Fixed size strings and blobs are stored as their length (u64, little-endian) followed by the data, zero padded to the type size.
+/// Length stored for a NONE string, no string is that long. Text pointers use it as their length too.
+pub const NULL_LENGTH : u64 = u64::MAX;
+fn serialize_closed_string(item_size : usize,s : &String,buffer : &mut Vec<u8>){
+    let mut bytes = Vec::with_capacity(item_size);
+    let str = truncate_at_char_boundary(s, item_size - 8);
+    bytes.extend_from_slice(&(str.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(str.as_bytes());
+    bytes.resize(item_size, 0);
     buffer.extend_from_slice(&bytes);
 }
 fn serialize_closed_blob(item_size : usize,mut blob : Vec<u8>,buffer : &mut Vec<u8>){
     let mut bytes: Vec<u8> = Vec::with_capacity(item_size);
-    let blob_length: Vec<u8> = blob.len().to_le_bytes().to_vec();
     blob.truncate(item_size-size_of::<u64>());
+    let blob_length: Vec<u8> = (blob.len() as u64).to_le_bytes().to_vec();
     bytes.extend_from_slice(&blob_length);
     bytes.extend_from_slice(&blob);
     bytes.resize(item_size,0);
@@ -62,6 +127,10 @@ pub fn into_schema(target: &mut Vec<AlbaTypes>, schema: &Vec<AlbaTypes>) -> Resu
     }
 
     for (t, s) in target.iter_mut().zip(schema.iter()) {
+        // string columns store NONE as it is
+        if matches!(t, AlbaTypes::NONE) && s.is_string() {
+            continue
+        }
         if std::mem::discriminant(t) != std::mem::discriminant(s) {
 
             match convert_to_schema_type(t.clone(), s) {
@@ -96,16 +165,20 @@ fn convert_to_schema_type(source: AlbaTypes, schema_type: &AlbaTypes) -> Result<
         AlbaTypes::MediumBytes(_) => AlbaTypes::MediumBytes(Vec::new()).try_from_existing(source),
         AlbaTypes::BigSBytes(_) => AlbaTypes::BigSBytes(Vec::new()).try_from_existing(source),
         AlbaTypes::LargeBytes(_) => AlbaTypes::LargeBytes(Vec::new()).try_from_existing(source),
+        AlbaTypes::Uuid(_) => AlbaTypes::Uuid([0;16]).try_from_existing(source),
+        AlbaTypes::Timestamp(_) => AlbaTypes::Timestamp(0).try_from_existing(source),
+        AlbaTypes::Decimal(_) => AlbaTypes::Decimal(Decimal::from(0)).try_from_existing(source),
         AlbaTypes::NONE => Ok(AlbaTypes::NONE),
     }
 }
 impl AlbaTypes {
     pub fn serialize_into(&self,array : &mut Vec<u8>){
         match self{
-            AlbaTypes::Text(a) => array.extend_from_slice(a.as_bytes()),
-            AlbaTypes::Int(a) => array.extend_from_slice(&a.to_be_bytes()),
-            AlbaTypes::Bigint(a) => array.extend_from_slice(&a.to_be_bytes()),
-            AlbaTypes::Float(a) => array.extend_from_slice(&a.to_be_bytes()),
+            // the row only holds a pointer to the text, written by `Container::serialize_row`
+            AlbaTypes::Text(_) => {},
+            AlbaTypes::Int(a) => array.extend_from_slice(&a.to_le_bytes()),
+            AlbaTypes::Bigint(a) => array.extend_from_slice(&a.to_le_bytes()),
+            AlbaTypes::Float(a) => array.extend_from_slice(&a.to_le_bytes()),
             AlbaTypes::Bool(a) => array.push(*a as u8),
             AlbaTypes::Char(a) => array.extend_from_slice(&(*a as u32).to_le_bytes()),
             AlbaTypes::NanoString(a) => serialize_closed_string(self.size(),a,array),
@@ -118,11 +191,135 @@ impl AlbaTypes {
             AlbaTypes::MediumBytes(items) => serialize_closed_blob(self.size(),items.to_owned(),array),
             AlbaTypes::BigSBytes(items) => serialize_closed_blob(self.size(),items.to_owned(),array),
             AlbaTypes::LargeBytes(items) => serialize_closed_blob(self.size(),items.to_owned(),array),
+            AlbaTypes::Uuid(a) => array.extend_from_slice(a),
+            AlbaTypes::Timestamp(a) => array.extend_from_slice(&a.to_le_bytes()),
+            AlbaTypes::Decimal(a) => array.extend_from_slice(&a.to_bytes()),
             AlbaTypes::NONE => {},
         }
     }
 }
 
+/// Bytes a Text value took inline in rows of row format 0.
+pub const LEGACY_TEXT_SIZE : usize = 256;
+
+impl AlbaTypes {
+    /// Bytes the value took in rows of row format 0, which only knew the types up to LargeBytes.
+    pub fn legacy_size(&self) -> Result<usize,Error>{
+        match self{
+            AlbaTypes::Text(_) => Ok(LEGACY_TEXT_SIZE),
+            AlbaTypes::Uuid(_)|AlbaTypes::Timestamp(_)|AlbaTypes::Decimal(_)|AlbaTypes::NONE => Err(Error::new(ErrorKind::InvalidData, format!("Row format 0 has no {:?} columns", self))),
+            _ => Ok(self.size())
+        }
+    }
+    /// Reads a value of this column type from a row of row format 0, where Int, Bigint, Float and the length
+    /// of fixed strings were big-endian, string and blob lengths weren't capped at the slot and Text was stored inline.
+    pub fn decode_legacy(&self, slot : &[u8]) -> Result<AlbaTypes,Error>{
+        let size = self.legacy_size()?;
+        let slot = slot.get(..size).ok_or(Error::new(ErrorKind::InvalidData, format!("Incomplete {:?} value in a row of format 0", self)))?;
+        let u64_at = |bytes : &[u8]| -> [u8;8]{bytes[..8].try_into().unwrap()};
+        let string = |bytes : &[u8]| String::from_utf8_lossy(bytes).to_string();
+        Ok(match self{
+            AlbaTypes::Int(_) => AlbaTypes::Int(i32::from_be_bytes(slot.try_into().unwrap())),
+            AlbaTypes::Bigint(_) => AlbaTypes::Bigint(i64::from_be_bytes(slot.try_into().unwrap())),
+            AlbaTypes::Float(_) => AlbaTypes::Float(f64::from_be_bytes(slot.try_into().unwrap())),
+            AlbaTypes::Bool(_) => AlbaTypes::Bool(slot[0] != 0),
+            AlbaTypes::Char(_) => AlbaTypes::Char(char::from_u32(u32::from_le_bytes(slot.try_into().unwrap())).ok_or(Error::new(ErrorKind::InvalidData, "Invalid Unicode scalar value"))?),
+            AlbaTypes::Text(_) => AlbaTypes::Text(string(slot).trim_end_matches('\0').to_string()),
+            AlbaTypes::NanoString(_)|AlbaTypes::SmallString(_)|AlbaTypes::MediumString(_)|AlbaTypes::BigString(_)|AlbaTypes::LargeString(_) => {
+                let len = (u64::from_be_bytes(u64_at(slot)) as usize).min(size - 8);
+                let text = string(&slot[8..8+len]);
+                match self{
+                    AlbaTypes::NanoString(_) => AlbaTypes::NanoString(text),
+                    AlbaTypes::SmallString(_) => AlbaTypes::SmallString(text),
+                    AlbaTypes::MediumString(_) => AlbaTypes::MediumString(text),
+                    AlbaTypes::BigString(_) => AlbaTypes::BigString(text),
+                    _ => AlbaTypes::LargeString(text),
+                }
+            },
+            _ => {
+                let len = (u64::from_le_bytes(u64_at(slot)) as usize).min(size - 8);
+                let blob = slot[8..8+len].to_vec();
+                match self{
+                    AlbaTypes::NanoBytes(_) => AlbaTypes::NanoBytes(blob),
+                    AlbaTypes::SmallBytes(_) => AlbaTypes::SmallBytes(blob),
+                    AlbaTypes::MediumBytes(_) => AlbaTypes::MediumBytes(blob),
+                    AlbaTypes::BigSBytes(_) => AlbaTypes::BigSBytes(blob),
+                    _ => AlbaTypes::LargeBytes(blob),
+                }
+            }
+        })
+    }
+    /// String types, the only ones a row can store NONE in.
+    pub fn is_string(&self) -> bool{
+        matches!(self, AlbaTypes::Text(_)|AlbaTypes::NanoString(_)|AlbaTypes::SmallString(_)|AlbaTypes::MediumString(_)|AlbaTypes::BigString(_)|AlbaTypes::LargeString(_))
+    }
+    /// CSV encoding of the value: strings and chars are always quoted with inner quotes doubled, bytes are standard
+    /// base64 with padding, NONE is an empty field, numbers and booleans are written as they print.
+    pub fn to_csv_field(&self) -> String{
+        let quote = |s : &str| format!("\"{}\"", s.replace('"', "\"\""));
+        match self{
+            AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+            AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => quote(s),
+            AlbaTypes::Char(c) => quote(&c.to_string()),
+            AlbaTypes::Int(n) => n.to_string(),
+            AlbaTypes::Bigint(n) => n.to_string(),
+            AlbaTypes::Float(f) => f.to_string(),
+            AlbaTypes::Bool(b) => b.to_string(),
+            AlbaTypes::NanoBytes(b) | AlbaTypes::SmallBytes(b) | AlbaTypes::MediumBytes(b) |
+            AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => general_purpose::STANDARD.encode(b),
+            AlbaTypes::Uuid(u) => format_uuid(u),
+            AlbaTypes::Timestamp(t) => format_timestamp(*t),
+            AlbaTypes::Decimal(d) => d.to_string(),
+            AlbaTypes::NONE => String::new(),
+        }
+    }
+}
+
+impl AlbaTypes {
+    /// JSON value for the value: strings and chars are strings, bytes are standard base64 strings, NONE is null
+    /// and so is a float that JSON can't hold (NaN or infinite).
+    pub fn to_json(&self) -> serde_json::Value{
+        use serde_json::Value;
+        match self{
+            AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+            AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => Value::String(s.clone()),
+            AlbaTypes::Char(c) => Value::String(c.to_string()),
+            AlbaTypes::Int(n) => Value::from(*n),
+            AlbaTypes::Bigint(n) => Value::from(*n),
+            AlbaTypes::Float(f) => serde_json::Number::from_f64(*f).map(Value::Number).unwrap_or(Value::Null),
+            AlbaTypes::Bool(b) => Value::Bool(*b),
+            AlbaTypes::NanoBytes(b) | AlbaTypes::SmallBytes(b) | AlbaTypes::MediumBytes(b) |
+            AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => Value::String(general_purpose::STANDARD.encode(b)),
+            AlbaTypes::Uuid(u) => Value::String(format_uuid(u)),
+            AlbaTypes::Timestamp(t) => Value::String(format_timestamp(*t)),
+            // a string, a JSON number would go through a float on most readers
+            AlbaTypes::Decimal(d) => Value::String(d.to_string()),
+            AlbaTypes::NONE => Value::Null,
+        }
+    }
+    /// Reads `value` as a value of this column type, the reverse of `to_json`. Strings going into bytes columns are base64
+    /// decoded, anything else goes through `try_from_existing`, so a number held in a string column is accepted.
+    pub fn from_json(&self, value : serde_json::Value) -> Result<AlbaTypes, Error>{
+        use serde_json::Value;
+        let value = match value{
+            Value::Null => return Ok(AlbaTypes::NONE),
+            Value::Bool(b) => AlbaTypes::Bool(b),
+            Value::Number(n) => match n.as_i64(){
+                Some(i) => AlbaTypes::Bigint(i),
+                None => AlbaTypes::Float(n.as_f64().ok_or(Error::new(ErrorKind::InvalidData, format!("{} is out of range", n)))?)
+            },
+            Value::String(s) => match self{
+                AlbaTypes::NanoBytes(_) | AlbaTypes::SmallBytes(_) | AlbaTypes::MediumBytes(_) |
+                AlbaTypes::BigSBytes(_) | AlbaTypes::LargeBytes(_) => AlbaTypes::LargeBytes(general_purpose::STANDARD.decode(s.as_bytes())
+                    .map_err(|e|Error::new(ErrorKind::InvalidData, format!("Bytes must be base64 encoded: {}", e)))?),
+                _ => AlbaTypes::LargeString(s)
+            },
+            Value::Array(_) | Value::Object(_) => return Err(Error::new(ErrorKind::InvalidData, "Arrays and objects can't be stored in a column")),
+        };
+        self.try_from_existing(value)
+    }
+}
+
 fn format_bytes_debug(
     f: &mut fmt::Formatter<'_>,
     variant_name: &str,
@@ -158,6 +355,9 @@ impl fmt::Debug for AlbaTypes {
             AlbaTypes::MediumBytes(bytes) => format_bytes_debug(f, "MediumBytes", bytes, 10),
             AlbaTypes::BigSBytes(bytes) => format_bytes_debug(f, "BigSBytes", bytes, 10),
             AlbaTypes::LargeBytes(bytes) => format_bytes_debug(f, "LargeBytes", bytes, 10),
+            AlbaTypes::Uuid(u) => f.debug_tuple("Uuid").field(&format_uuid(u)).finish(),
+            AlbaTypes::Timestamp(t) => f.debug_tuple("Timestamp").field(&format_timestamp(*t)).finish(),
+            AlbaTypes::Decimal(d) => write!(f, "Decimal({})", d),
             AlbaTypes::NONE => write!(f, "NONE"),
         }
     }
@@ -198,6 +398,9 @@ impl AlbaTypes {
             14 => Ok(AlbaTypes::MediumBytes(Vec::new())),
             15 => Ok(AlbaTypes::BigSBytes(Vec::new())),
             16 => Ok(AlbaTypes::LargeBytes(Vec::new())),
+            17 => Ok(AlbaTypes::Uuid([0;16])),
+            18 => Ok(AlbaTypes::Timestamp(0)),
+            19 => Ok(AlbaTypes::Decimal(Decimal::from(0))),
             x  => Err(Error::new(
                       ErrorKind::InvalidData,
                       format!("Unknown AlbaTypes code: {}", x)
@@ -223,6 +426,9 @@ impl AlbaTypes {
             AlbaTypes::MediumBytes(_)  => 14,
             AlbaTypes::BigSBytes(_)    => 15,
             AlbaTypes::LargeBytes(_)   => 16,
+            AlbaTypes::Uuid(_)         => 17,
+            AlbaTypes::Timestamp(_)    => 18,
+            AlbaTypes::Decimal(_)      => 19,
         }
     }
     // pub fn get_id_from_text(keyword: &str) -> Result<u8, Error> {
@@ -267,6 +473,9 @@ impl AlbaTypes {
                     AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => {
                         general_purpose::STANDARD.encode(&b)
                     }
+                    AlbaTypes::Uuid(u) => format_uuid(&u),
+                    AlbaTypes::Timestamp(t) => format_timestamp(t),
+                    AlbaTypes::Decimal(d) => d.to_string(),
                     AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Text")),
                 };
                 Ok(AlbaTypes::Text(text))
@@ -301,6 +510,7 @@ impl AlbaTypes {
                 let bigint_val = match i {
                     AlbaTypes::Bigint(n) => n,
                     AlbaTypes::Int(n) => n as i64,
+                    AlbaTypes::Timestamp(t) => t,
                     AlbaTypes::Float(f) => {
                         if f.is_nan() || f.is_infinite() {
                             return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NaN or infinite float to i64"));
@@ -322,6 +532,7 @@ impl AlbaTypes {
                     AlbaTypes::Float(f) => f,
                     AlbaTypes::Int(n) => n as f64,
                     AlbaTypes::Bigint(n) => n as f64,
+                    AlbaTypes::Decimal(d) => d.to_f64(),
                     AlbaTypes::Bool(b) => if b { 1.0 } else { 0.0 },
                     AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
                     AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
@@ -408,6 +619,45 @@ impl AlbaTypes {
                 let bytes = get_bytes_from_alba_type(i)?;
                 Ok(AlbaTypes::LargeBytes(truncate_or_pad_bytes(bytes, 1_000_000)))
             }
+            AlbaTypes::Uuid(_) => {
+                let uuid = match i {
+                    AlbaTypes::Uuid(u) => u,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => parse_uuid(s.trim())?,
+                    AlbaTypes::NanoBytes(b) | AlbaTypes::SmallBytes(b) | AlbaTypes::MediumBytes(b) |
+                    AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => {
+                        b.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, "A UUID is exactly 16 bytes"))?
+                    }
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Uuid")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Uuid")),
+                };
+                Ok(AlbaTypes::Uuid(uuid))
+            }
+            AlbaTypes::Timestamp(_) => {
+                let micros = match i {
+                    AlbaTypes::Timestamp(t) => t,
+                    AlbaTypes::Int(n) => n as i64,
+                    AlbaTypes::Bigint(n) => n,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => parse_timestamp(s.trim())?,
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Timestamp")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Timestamp")),
+                };
+                Ok(AlbaTypes::Timestamp(micros))
+            }
+            AlbaTypes::Decimal(_) => {
+                let decimal = match i {
+                    AlbaTypes::Decimal(d) => d,
+                    AlbaTypes::Int(n) => Decimal::from(n as i64),
+                    AlbaTypes::Bigint(n) => Decimal::from(n),
+                    AlbaTypes::Float(f) => Decimal::from_f64(f)?,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.trim().parse()?,
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Decimal")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Decimal")),
+                };
+                Ok(AlbaTypes::Decimal(decimal))
+            }
             AlbaTypes::NONE => Ok(AlbaTypes::NONE),
         }
     }
@@ -417,7 +667,8 @@ impl AlbaTypes {
             AlbaTypes::Int(_) => size_of::<i32>(),
             AlbaTypes::Float(_) => size_of::<f64>(),
             AlbaTypes::Bool(_) => size_of::<bool>(),
-            AlbaTypes::Text(_) => MAX_STR_LEN,
+            // offset and length inside the container's text heap
+            AlbaTypes::Text(_) => 2 * size_of::<u64>(),
             AlbaTypes::NONE => 0,
             AlbaTypes::Char(_) => size_of::<char>(),
             AlbaTypes::NanoString(_) => 10 + size_of::<u64>(),
@@ -430,6 +681,10 @@ impl AlbaTypes {
             AlbaTypes::MediumBytes(_) => 10_000 + size_of::<u64>(),
             AlbaTypes::BigSBytes(_) => 100_000 + size_of::<u64>(),
             AlbaTypes::LargeBytes(_) => 1_000_000 + size_of::<u64>(),
+            AlbaTypes::Uuid(_) => 16,
+            AlbaTypes::Timestamp(_) => size_of::<i64>(),
+            // mantissa and scale
+            AlbaTypes::Decimal(_) => size_of::<i128>() + 1,
         }
     }
 
@@ -448,13 +703,16 @@ fn get_string_from_alba_type(i: AlbaTypes) -> Result<String, Error> {
         AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => {
             Ok(general_purpose::STANDARD.encode(&b))
         }
+        AlbaTypes::Uuid(u) => Ok(format_uuid(&u)),
+        AlbaTypes::Timestamp(t) => Ok(format_timestamp(t)),
+        AlbaTypes::Decimal(d) => Ok(d.to_string()),
         AlbaTypes::NONE => Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to string")),
     }
 }
 
 fn truncate_or_pad_string(s: String, max_len: usize) -> String {
     if s.len() > max_len {
-        s[..max_len].to_string()
+        truncate_at_char_boundary(&s, max_len).to_string()
     } else {
         s
     }
@@ -464,6 +722,7 @@ fn get_bytes_from_alba_type(i: AlbaTypes) -> Result<Vec<u8>, Error> {
     match i {
         AlbaTypes::NanoBytes(b) | AlbaTypes::SmallBytes(b) | AlbaTypes::MediumBytes(b) |
         AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => Ok(b),
+        AlbaTypes::Uuid(u) => Ok(u.to_vec()),
         AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
         AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
             general_purpose::STANDARD
@@ -542,6 +801,9 @@ impl TryFrom<Token> for AlbaTypes {
                 "MEDIUM-BYTES" => Ok(AlbaTypes::MediumBytes(Vec::new())),
                 "BIG-BYTES" => Ok(AlbaTypes::BigSBytes(Vec::new())),
                 "LARGE-BYTES" => Ok(AlbaTypes::LargeBytes(Vec::new())),
+                "UUID" => Ok(AlbaTypes::Uuid([0;16])),
+                "TIMESTAMP" => Ok(AlbaTypes::Timestamp(0)),
+                "DECIMAL" => Ok(AlbaTypes::Decimal(Decimal::from(0))),
                 _ => return Err(format!("Unknown type keyword: {}", s).leak()),
             },
             _ => {
@@ -1,53 +1,89 @@
+/// Environment variable holding the log filter, e.g. `TYTODB_LOG=debug` or
+/// `TYTODB_LOG=tyto_db::container=trace`.
+pub const LOG_ENV: &str = "TYTODB_LOG";
+const DEFAULT_LOG_FILTER: &str = "info";
 
-use std::io::{self, Write};
+/// Installs the default stderr logger filtered by `TYTODB_LOG`. Does nothing
+/// if a logger is already installed, so embedders can route the records
+/// through their own `log` implementation by setting it up first.
+pub fn init() {
+    let _ = env_logger::Builder::from_env(
+        env_logger::Env::new().filter_or(LOG_ENV, DEFAULT_LOG_FILTER)
+    ).try_init();
+}
 
-pub fn __logerr_with_loc(
-    file: &str,
+#[doc(hidden)]
+pub fn __log_with_loc(
+    level: log::Level,
+    file: &'static str,
     line: u32,
+    module: &'static str,
     args: std::fmt::Arguments
 ) {
-    let _ = writeln!(
-        io::stderr(),
-        "</ERROR/> [{}:{}] {}",
-        file,
-        line,
-        args
-    );
+    if level > log::max_level() {
+        return;
+    }
+    let logger = log::logger();
+    if !logger.enabled(&log::Metadata::builder().level(level).target(module).build()) {
+        return;
+    }
+    logger.log(&log::Record::builder()
+        .level(level)
+        .target(module)
+        .module_path_static(Some(module))
+        .file_static(Some(file))
+        .line(Some(line))
+        .args(format_args!("[{}:{}] {}", file, line, args))
+        .build());
 }
 
 #[macro_export]
 macro_rules! logerr {
     ($($arg:tt)*) => {{
-        $crate::better_logs::__logerr_with_loc(
+        $crate::better_logs::__log_with_loc(
+            ::log::Level::Error,
             file!(),
             line!(),
+            module_path!(),
             ::std::format_args!($($arg)*),
         )
     }};
 }
 
-
-
-pub fn __loginfo_with_loc(
-    file: &str,
-    line: u32,
-    args: std::fmt::Arguments
-) {
-    let _ = writeln!(
-        io::stdout(),
-        "</INFO/> [{}:{}] {}",
-        file,
-        line,
-        args
-    );
+#[macro_export]
+macro_rules! logwarn {
+    ($($arg:tt)*) => {{
+        $crate::better_logs::__log_with_loc(
+            ::log::Level::Warn,
+            file!(),
+            line!(),
+            module_path!(),
+            ::std::format_args!($($arg)*),
+        )
+    }};
 }
 
 #[macro_export]
 macro_rules! loginfo {
     ($($arg:tt)*) => {{
-        $crate::better_logs::__loginfo_with_loc(
+        $crate::better_logs::__log_with_loc(
+            ::log::Level::Info,
+            file!(),
+            line!(),
+            module_path!(),
+            ::std::format_args!($($arg)*),
+        )
+    }};
+}
+
+#[macro_export]
+macro_rules! logdebug {
+    ($($arg:tt)*) => {{
+        $crate::better_logs::__log_with_loc(
+            ::log::Level::Debug,
             file!(),
             line!(),
+            module_path!(),
             ::std::format_args!($($arg)*),
         )
     }};
@@ -1,61 +1,320 @@
 
-use std::{collections::{BTreeMap, BTreeSet, HashMap}, fs::{self, File, OpenOptions}, hash::{DefaultHasher, Hash, Hasher}, io::{Error, ErrorKind, Read, Write}, os::{fd::AsRawFd, unix::fs::{FileExt, MetadataExt}}, sync::Arc};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, hash::{DefaultHasher, Hash, Hasher}, io::{Error, ErrorKind, Write}, sync::{atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering}, Arc}};
 use tokio::sync::Mutex;
-use crate::{alba_types::{into_schema,AlbaTypes}, database::{batch_write_data, WriteEntry}, gerr, indexing:: Hashmap as IndexingHashMap};
+use crate::{alba_types::{into_schema,AlbaTypes,NULL_LENGTH}, database::WriteEntry, decimal::Decimal, error::TytoError, gerr, logdebug, indexing::{IndexKind, PrimaryIndex, SecondaryIndex, SecondaryIndexes}, row::Row, storage::{flush, group_commit_enabled, join_commit_group, Backend, DiskBackend, Durability, MemoryBackend, SharedStorage, Storage, SyncTicket}};
 use bitvec::prelude::*;
+/// Default of `ContainerTuning::max_graveyard_length`.
 pub const MAX_GRAVEYARD_LENGTH_IN_MEMORY : usize = 1250;
+/// Default of `ContainerTuning::vacuum_chunk_size`.
+pub const VACCUM_SIZE : u64 = 4194304;
+/// Default of `ContainerTuning::max_vacuum_pairs`.
+pub const MAX_VACUUM_LENGTH : usize = 625000;
+/// Appended to the container path for its MVCC record.
+pub const MVCC_RECORD_SUFFIX : &str = ".mr";
+/// Appended to the container path for the file holding the content of its Text columns.
+pub const TEXT_HEAP_SUFFIX : &str = ".text";
+/// Appended to the container path for the second file of the text heap, see `TextHeap`.
+pub const TEXT_HEAP_ALT_SUFFIX : &str = ".text2";
+/// Appended to the container path for the counter of an auto-increment key.
+pub const SEQUENCE_SUFFIX : &str = ".seq";
+/// Appended to the container path for the free slots the graveyard held at the last commit, as little-endian u64 offsets.
+pub const GRAVEYARD_SUFFIX : &str = ".graveyard";
+/// Source of `Container::version`, global so a container recreated under the same name never reuses a version.
+static NEXT_VERSION : AtomicU64 = AtomicU64::new(1);
+/// `Container::live_rows` until the first `row_count` counts the file.
+const UNCOUNTED : u64 = u64::MAX;
 
-type MvccType = Arc<Mutex<(BTreeMap<u64,(MvccState,Vec<AlbaTypes>)>,HashMap<String,(bool,String)>)>>;
+/// Append-only store for Text values, rows keep the offset and length of their text in it.
+/// It is spread over two files: new text goes to the active one, and a vacuum copies the text of the live rows
+/// into the other before emptying the active one, see `compact`. The top bit of a pointer's offset picks the file.
+#[derive(Debug)]
+pub struct TextHeap{
+    files : [Box<dyn Storage>;2],
+    ends : [AtomicU64;2],
+    active : usize,
+}
+
+/// Set on the offset of a pointer into the second heap file.
+const TEXT_FILE_BIT : u64 = 1 << 63;
+
+impl TextHeap{
+    pub fn open(backend : &dyn Backend) -> Result<Self,Error>{
+        let files = [backend.open(TEXT_HEAP_SUFFIX)?, backend.open(TEXT_HEAP_ALT_SUFFIX)?];
+        let ends = [AtomicU64::new(files[0].len()?), AtomicU64::new(files[1].len()?)];
+        // only a compaction that crashed before emptying its source leaves both files with text, either can take more
+        let active = if ends[0].load(Ordering::SeqCst) == 0 && ends[1].load(Ordering::SeqCst) > 0{1}else{0};
+        Ok(TextHeap{files,ends,active})
+    }
+    /// Appends `text`, returning the pointer stored in the row.
+    fn store(&self, text : &str) -> Result<[u8;16],Error>{
+        self.store_in(self.active, text.as_bytes())
+    }
+    fn store_in(&self, file : usize, text : &[u8]) -> Result<[u8;16],Error>{
+        let offset = self.ends[file].fetch_add(text.len() as u64, Ordering::SeqCst);
+        self.files[file].write_all_at(text, offset)?;
+        let mut pointer = [0u8;16];
+        pointer[..8].copy_from_slice(&(offset | if file == 1{TEXT_FILE_BIT}else{0}).to_le_bytes());
+        pointer[8..].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        Ok(pointer)
+    }
+    /// Makes the appended text durable, which must happen before any row pointing at it is written.
+    pub fn sync(&self) -> Result<(),Error>{
+        flush(&*self.files[self.active])
+    }
+    fn clear(&mut self) -> Result<(),Error>{
+        for (file,end) in self.files.iter().zip(self.ends.iter()){
+            file.set_len(0)?;
+            end.store(0, Ordering::SeqCst);
+        }
+        self.active = 0;
+        Ok(())
+    }
+    fn load_bytes(&self, pointer : &[u8]) -> Result<Vec<u8>,Error>{
+        let offset = u64::from_le_bytes(pointer[..8].try_into().unwrap());
+        let len = u64::from_le_bytes(pointer[8..16].try_into().unwrap());
+        let file = (offset & TEXT_FILE_BIT != 0) as usize;
+        let offset = offset & !TEXT_FILE_BIT;
+        if offset + len > self.ends[file].load(Ordering::SeqCst){
+            return Err(gerr(&format!("Text pointer {}+{} is past the end of the text heap",offset,len)))
+        }
+        let mut buffer = vec![0u8;len as usize];
+        self.files[file].read_exact_at(&mut buffer, offset)?;
+        Ok(buffer)
+    }
+    fn load(&self, pointer : &[u8]) -> Result<String,Error>{
+        String::from_utf8(self.load_bytes(pointer)?).map_err(|e|gerr(&format!("Failed to read text: {}",e)))
+    }
+    /// Copies the text the live rows of `file` point at into the inactive heap file, points the rows at the copies
+    /// and empties the active file, returning the bytes given back. `text_offsets` are the positions of the Text
+    /// pointers inside a row. Until the old file is emptied both copies exist, so a crash at any step loses nothing.
+    fn compact(&mut self, file : &dyn Storage, headers_offset : u64, element_size : usize, data_end : u64, text_offsets : &[usize], chunk_size : u64) -> Result<u64,Error>{
+        let (source,target) = (self.active, 1 - self.active);
+        let rows_per_chunk = (chunk_size/element_size.max(1) as u64).max(1);
+        let total_rows = data_end.saturating_sub(headers_offset)/element_size.max(1) as u64;
+        let empty = vec![255u8;element_size];
+        let mut moved = Vec::new();
+        let mut row = 0u64;
+        while row < total_rows{
+            let rows = (total_rows - row).min(rows_per_chunk);
+            let mut buffer = vec![0u8;(rows*element_size as u64) as usize];
+            file.read_exact_at(&mut buffer, headers_offset + row*element_size as u64)?;
+            for (n,slot) in buffer.chunks_exact(element_size).enumerate(){
+                if slot == empty{
+                    continue
+                }
+                for at in text_offsets{
+                    let pointer = &slot[*at..*at+16];
+                    if pointer[8..16] == NULL_LENGTH.to_le_bytes(){
+                        continue
+                    }
+                    let copy = self.store_in(target, &self.load_bytes(pointer)?)?;
+                    moved.push(WriteEntry{buffer: Arc::new(copy.to_vec()), length: 16, offset: (headers_offset + (row + n as u64)*element_size as u64 + *at as u64) as i64});
+                }
+            }
+            row += rows;
+        }
+        flush(&*self.files[target])?;
+        file.write_batch(&moved, Durability::current())?;
+        let copied : u64 = moved.iter().map(|m|u64::from_le_bytes(m.buffer[8..16].try_into().unwrap())).sum();
+        let reclaimed = self.ends[source].load(Ordering::SeqCst).saturating_sub(copied);
+        self.files[source].set_len(0)?;
+        self.ends[source].store(0, Ordering::SeqCst);
+        self.active = target;
+        Ok(reclaimed)
+    }
+}
+
+/// Memory and IO sizes of a container, set from `settings.yaml` and shared by every container of a database.
+#[derive(Debug,Clone,Copy)]
+pub struct ContainerTuning{
+    /// Most free slots the graveyard keeps, in memory and in its file.
+    pub max_graveyard_length : usize,
+    /// Bytes read at once by vacuums and the scans done on load, rounded down to whole rows.
+    pub vacuum_chunk_size : u64,
+    /// Most rows a single vacuum relocates, the rest waits for the next one.
+    pub max_vacuum_pairs : usize,
+}
+
+impl Default for ContainerTuning{
+    fn default() -> Self{
+        ContainerTuning{max_graveyard_length:MAX_GRAVEYARD_LENGTH_IN_MEMORY,vacuum_chunk_size:VACCUM_SIZE,max_vacuum_pairs:MAX_VACUUM_LENGTH}
+    }
+}
+
+/// Progress of the running or last vacuum of a container. Updated as the vacuum goes,
+/// so it can be read while the vacuum holds the container.
+#[derive(Debug,Default)]
+pub struct VacuumProgress{
+    pub running : AtomicBool,
+    /// Slots read while mapping the used and empty ones.
+    pub rows_scanned : AtomicU64,
+    /// Rows moved into an earlier empty slot so far, out of `pairs_planned`.
+    pub pairs_relocated : AtomicU64,
+    pub pairs_planned : AtomicU64,
+    /// Bytes cut off the end of the data file.
+    pub bytes_reclaimed : AtomicU64,
+    /// Asks the running vacuum to stop at its next safe point, see `Container::vacuum`.
+    pub cancel : AtomicBool,
+}
+
+impl VacuumProgress{
+    fn start(&self){
+        self.rows_scanned.store(0, Ordering::Relaxed);
+        self.pairs_relocated.store(0, Ordering::Relaxed);
+        self.pairs_planned.store(0, Ordering::Relaxed);
+        self.bytes_reclaimed.store(0, Ordering::Relaxed);
+        self.running.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Counter handing out auto-increment keys, the next value is kept on disk as a little-endian i64.
+#[derive(Debug)]
+pub struct Sequence{
+    file : Box<dyn Storage>,
+    next : AtomicI64,
+}
+
+impl Sequence{
+    fn open(backend : &dyn Backend) -> Result<Self,Error>{
+        let file = backend.open(SEQUENCE_SUFFIX)?;
+        let mut next = [0u8;8];
+        let next = if file.len()? >= 8{
+            file.read_exact_at(&mut next, 0)?;
+            i64::from_le_bytes(next)
+        }else{
+            1
+        };
+        Ok(Sequence{file,next:AtomicI64::new(next)})
+    }
+    /// Takes the next value, persisting the one after it before returning.
+    fn take(&self) -> Result<i64,Error>{
+        let value = self.next.fetch_add(1, Ordering::SeqCst);
+        self.file.write_all_at(&(value + 1).to_le_bytes(), 0)?;
+        Ok(value)
+    }
+}
+
+/// The session the changes found in the MVCC record on load belong to, until they are committed or rolled back.
+pub const RECOVERED_SESSION : u64 = u64::MAX;
+/// Changes staged by one session, keyed by the offset of the row they touch.
+pub type Staged = (BTreeMap<u64,(MvccState,Vec<AlbaTypes>)>,HashMap<String,(bool,String)>);
+type MvccType = Arc<Mutex<Staged>>;
+/// Bytes each slot held when a snapshot was opened, `None` when the slot was empty or past the end of the file.
+pub type SnapshotImages = BTreeMap<u64,Option<Vec<u8>>>;
 
 #[derive(Debug)]
-pub struct MvccRecord(Arc<Mutex<File>>);
+pub struct MvccRecord(SharedStorage);
 impl MvccRecord{
-    fn new(name : String) -> Result<Self,Error>{
-        let file = OpenOptions::new().read(true).write(true).append(true).create(!fs::exists(&name)?).open(name)?;
-        Ok(MvccRecord(Arc::new(Mutex::new(file))))
+    fn new(backend : &dyn Backend) -> Result<Self,Error>{
+        Ok(MvccRecord(Arc::new(Mutex::new(backend.open(MVCC_RECORD_SUFFIX)?))))
     }
+    /// Appends `bytes` and syncs them as the durability level asks.
     async fn put(&mut self,bytes : Vec<u8>) -> Result<(),Error>{
         let reference = self.0.clone();
-        let _ = tokio::task::spawn_blocking(async move || -> Result<(),Error> {
-            let mut bibi = reference.lock().await;
-            let e0 = bibi.write_all(&bytes);
-            let e1 = bibi.sync_all();
-            if let Err(e) = e0{eprintln!("ERROR{:?}",e)}
-            if let Err(e) = e1{eprintln!("ERROR {:?}",e)}
-            Ok(())
-        }).await;
-        Ok(())
+        tokio::task::spawn_blocking(move || -> Result<(),Error> {
+            let file = reference.blocking_lock();
+            file.write_all_at(&bytes, file.len()?)?;
+            flush(&**file)
+        }).await.map_err(|e|gerr(&format!("Writing the MVCC record failed: {}",e)))?
     }
     async fn yield_(&mut self) -> Result<Vec<u8>,Error>{
-        let mut buffer = Vec::new();
-        self.0.lock().await.read_to_end(&mut buffer)?;
+        let file = self.0.lock().await;
+        let mut buffer = vec![0u8;file.len()? as usize];
+        file.read_exact_at(&mut buffer, 0)?;
         Ok(buffer)
     }
+    async fn len(&self) -> Result<u64,Error>{
+        self.0.lock().await.len()
+    }
+    /// Drops whatever was appended past `len`.
+    async fn truncate(&mut self, len : u64) -> Result<(),Error>{
+        self.0.lock().await.set_len(len)?; self.sync().await?;Ok(())
+    }
     async fn clear(&mut self) -> Result<(),Error> {
         self.0.lock().await.set_len(0)?; self.sync().await?;Ok(())
     }
     async fn sync(&mut self) -> Result<(),Error>{
         let reference = self.0.clone();
-        tokio::task::spawn_blocking(async move ||{    
-            let n = reference.lock().await;
-            let _ = n.sync_data();
-        });
-        Ok(())
+        tokio::task::spawn_blocking(move || flush(&**reference.blocking_lock()))
+            .await.map_err(|e|gerr(&format!("Syncing the MVCC record failed: {}",e)))?
     }
 }
 
 #[derive(Debug)]
 pub struct Container{
-    pub file : Arc<Mutex<std::fs::File>>,
+    pub file : SharedStorage,
+    /// Where the container's files live, see `Container::new_in_memory`.
+    pub backend : Arc<dyn Backend>,
     pub element_size : usize,
     pub headers : Vec<(String,AlbaTypes)>,
+    /// Column name to type, derived from `headers` when the container is opened; schema changes reopen the container.
+    pub column_properties : HashMap<String,AlbaTypes>,
+    /// Columns forming the primary key, in key order.
+    pub primary_key : Vec<String>,
+    /// Positions of the `primary_key` columns inside a row, derived from `headers`.
+    pub key_positions : Vec<usize>,
+    pub options : ContainerOptions,
     pub mvcc : MvccType,
     pub headers_offset : u64,
     pub graveyard : Arc<Mutex<BTreeSet<u64>>>,
-    pub index_map : Arc<Mutex<IndexingHashMap>>,
-    pub mvcc_record : Arc<Mutex<MvccRecord>>
+    /// Where the graveyard is saved on commit, vacuum and truncate, so freed slots are reused right after a restart.
+    pub graveyard_file : Box<dyn Storage>,
+    pub tuning : ContainerTuning,
+    pub index_map : Arc<Mutex<PrimaryIndex>>,
+    pub secondary_indexes : Arc<Mutex<SecondaryIndexes>>,
+    pub mvcc_record : Arc<Mutex<MvccRecord>>,
+    pub snapshots : Arc<Mutex<HashMap<u64,SnapshotImages>>>,
+    /// Offset right after the last slot that holds data, empty slots from here to the end of the file were pre-allocated.
+    pub data_end : Arc<Mutex<u64>>,
+    /// Committed rows, counted from the file on the first `row_count` and kept up to date by commit, bulk load and truncate.
+    pub live_rows : AtomicU64,
+    /// Only opened for containers with Text columns.
+    pub text_heap : Option<TextHeap>,
+    /// Only opened for containers with an auto-increment key.
+    pub sequence : Option<Sequence>,
+    /// Changes every time the file is written, results computed under an older version may be stale.
+    pub version : AtomicU64,
+    /// The session whose changes are in `mvcc`, see `switch_session`.
+    pub session : u64,
+    /// Changes staged by the other sessions.
+    pub parked : HashMap<u64,Staged>,
+    /// Counts the times the changes of every session were thrown away, marks taken before that can't be restored.
+    pub discards : u64,
+    /// Record entries of the commits still waiting for their group sync. Written back whenever the record is rewritten,
+    /// so a crash before the sync replays them on start.
+    pub unsynced_record : Vec<u8>,
+    /// Group syncs the commits since the last `take_sync_tickets` wait on.
+    pub sync_tickets : Vec<SyncTicket>,
+    /// Commits whose group sync wasn't settled yet, see `settle_synced`.
+    pub awaiting_sync : usize,
 
 }
+/// State of a container's pending changes, see `Container::mark_pending`.
+#[derive(Debug)]
+pub struct PendingMark{
+    pending : Staged,
+    record_len : u64,
+    discards : u64,
+}
+/// Container settings kept in the header after the column list, only written when something differs from the defaults.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct ContainerOptions{
+    /// Key columns in key order, empty means the first column alone.
+    pub primary_key : Vec<String>,
+    /// The first column is filled from the container's sequence when a row leaves it out.
+    pub auto_increment : bool,
+    /// Values given to columns a new row leaves out, already converted to the column type.
+    pub defaults : Vec<(String,AlbaTypes)>,
+    /// Columns that can never hold NONE.
+    pub not_null : Vec<String>,
+    /// Columns besides the primary key whose values must be unique, each backed by an ordered secondary index.
+    pub unique : Vec<String>,
+    /// Kind of the primary key index, ordered keys also answer ranges over the key.
+    pub primary_index : IndexKind,
+    /// String columns where an empty string is stored as NONE, elsewhere `''` and NONE stay distinct.
+    pub empty_as_null : Vec<String>,
+}
+
 #[derive(Debug,Copy,Clone)]
 pub enum MvccState{
     Delete,
@@ -66,7 +325,7 @@ pub enum MvccState{
 pub fn get_index(i : AlbaTypes) -> u64{
     match i{
         AlbaTypes::Int(b) => b as u64,
-        AlbaTypes::Bigint(b) => b as u64,
+        AlbaTypes::Bigint(b) | AlbaTypes::Timestamp(b) => b as u64,
         AlbaTypes::Float(b) => b as u64,
         AlbaTypes::Char(b) => b as u64,
         AlbaTypes::Bool(b) => b as u64,
@@ -75,6 +334,16 @@ pub fn get_index(i : AlbaTypes) -> u64{
             b.hash(&mut hasher);
             hasher.finish()
         },
+        AlbaTypes::Decimal(b) => {
+            let mut hasher = DefaultHasher::new();
+            b.hash(&mut hasher);
+            hasher.finish()
+        },
+        AlbaTypes::Uuid(b) => {
+            let mut hasher = DefaultHasher::new();
+            b.hash(&mut hasher);
+            hasher.finish()
+        },
         AlbaTypes::NanoString(b)|AlbaTypes::SmallString(b)|AlbaTypes::MediumString(b)|AlbaTypes::BigString(b)|AlbaTypes::LargeString(b)|AlbaTypes::Text(b) => {
             let mut hasher = DefaultHasher::new();
             b.hash(&mut hasher);
@@ -85,20 +354,78 @@ pub fn get_index(i : AlbaTypes) -> u64{
     }
 }
 
+/// Index key of a primary key made of `values`, a single column keeps the `get_index` key so existing indexes stay valid.
+pub fn get_key_index(values : &[AlbaTypes]) -> u64{
+    if let [value] = values{
+        return get_index(value.clone())
+    }
+    let mut hasher = DefaultHasher::new();
+    for value in values{
+        ordered_key(value).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Order preserving encoding of a primary key. A single column key is its `ordered_key`, each column of a composite one
+/// has its zero bytes escaped and is closed by two zero bytes, so a shorter column always sorts before a longer one sharing its start.
+pub fn ordered_key_index(values : &[AlbaTypes]) -> Vec<u8>{
+    if let [value] = values{
+        return ordered_key(value)
+    }
+    let mut key = Vec::new();
+    for value in values{
+        for byte in ordered_key(value){
+            key.push(byte);
+            if byte == 0{
+                key.push(0xFF);
+            }
+        }
+        key.extend_from_slice(&[0,0]);
+    }
+    key
+}
+
+/// Encodes a value so that comparing the encoded bytes gives the same order as comparing the values, used by ordered indexes.
+pub fn ordered_key(i : &AlbaTypes) -> Vec<u8>{
+    match i{
+        AlbaTypes::Int(b) => ((*b as i64) ^ i64::MIN).to_be_bytes().to_vec(),
+        AlbaTypes::Bigint(b) | AlbaTypes::Timestamp(b) => (b ^ i64::MIN).to_be_bytes().to_vec(),
+        AlbaTypes::Float(b) => {
+            let bits = b.to_bits();
+            let ordered = if bits >> 63 == 1 { !bits } else { bits | (1 << 63) };
+            ordered.to_be_bytes().to_vec()
+        },
+        AlbaTypes::Char(b) => (*b as u32).to_be_bytes().to_vec(),
+        AlbaTypes::Bool(b) => vec![*b as u8],
+        AlbaTypes::NanoBytes(b)|AlbaTypes::SmallBytes(b)|AlbaTypes::MediumBytes(b)|AlbaTypes::BigSBytes(b)|AlbaTypes::LargeBytes(b) => b.clone(),
+        AlbaTypes::Uuid(b) => b.to_vec(),
+        AlbaTypes::Decimal(b) => b.ordered_key(),
+        AlbaTypes::NanoString(b)|AlbaTypes::SmallString(b)|AlbaTypes::MediumString(b)|AlbaTypes::BigString(b)|AlbaTypes::LargeString(b)|AlbaTypes::Text(b) => b.as_bytes().to_vec(),
+        AlbaTypes::NONE => Vec::new()
+    }
+}
+
 impl Container {
-    pub async fn new(path : &str,element_size : usize, columns : Vec<AlbaTypes>,headers_offset : u64,column_names : Vec<String>) -> Result<Arc<Mutex<Self>>,Error> {
+    pub async fn new(path : &str,element_size : usize, columns : Vec<AlbaTypes>,headers_offset : u64,column_names : Vec<String>,options : ContainerOptions,tuning : ContainerTuning) -> Result<Arc<Mutex<Self>>,Error> {
+        Self::open(Arc::new(DiskBackend::new(path)), element_size, columns, headers_offset, column_names, options, tuning).await
+    }
+    /// A container whose data file starts as `content` and whose files all stay in memory, nothing of it survives a restart.
+    pub async fn new_in_memory(content : Vec<u8>,element_size : usize, columns : Vec<AlbaTypes>,headers_offset : u64,column_names : Vec<String>,options : ContainerOptions,tuning : ContainerTuning) -> Result<Arc<Mutex<Self>>,Error> {
+        Self::open(Arc::new(MemoryBackend::new(content)), element_size, columns, headers_offset, column_names, options, tuning).await
+    }
+    async fn open(backend : Arc<dyn Backend>,element_size : usize, columns : Vec<AlbaTypes>,headers_offset : u64,column_names : Vec<String>,options : ContainerOptions,tuning : ContainerTuning) -> Result<Arc<Mutex<Self>>,Error> {
         let mut  headers = Vec::new();
         for index in 0..((columns.len()+column_names.len())/2){
             let name = match column_names.get(index){
                 Some(nm) => nm,
                 None => {
-                    return Err(gerr("Failed to create container, the size of column types and names must be equal. And this error is a consequence of that property not being respected."))
+                    return Err(Error::from(TytoError::Schema("Failed to create container, the size of column types and names must be equal. And this error is a consequence of that property not being respected.".to_string())))
                 } 
             };
             let value = match columns.get(index){
                 Some(vl) => vl,
                 None => {
-                    return Err(gerr("Failed to create container, the size of column types and names must be equal. And this error is a consequence of that property not being respected."))
+                    return Err(Error::from(TytoError::Schema("Failed to create container, the size of column types and names must be equal. And this error is a consequence of that property not being respected.".to_string())))
                 }
             };
             if name.is_empty(){
@@ -109,21 +436,59 @@ impl Container {
             }
             headers.push((name.to_owned(), value.to_owned()));
         }
-        let regen_hm = !fs::exists(format!("{}.hashmap",path))? && fs::exists(path.to_string())?;
-        let file =std::fs::OpenOptions::new().read(true).write(true).open(path).unwrap();
+        let regen_hm = !backend.exists(PrimaryIndex::suffix(options.primary_index))? && backend.exists("")?;
+        let file = backend.open("")?;
+        let data_end = find_data_end(&*file, headers_offset, element_size, tuning.vacuum_chunk_size)?;
+        let graveyard_file = backend.open(GRAVEYARD_SUFFIX)?;
+        let graveyard = load_graveyard(&*graveyard_file, &*file, headers_offset, element_size, data_end, tuning.max_graveyard_length)?;
+        let text_heap = if headers.iter().any(|h|matches!(h.1,AlbaTypes::Text(_))){Some(TextHeap::open(&*backend)?)}else{None};
+        let sequence = if options.auto_increment{Some(Sequence::open(&*backend)?)}else{None};
         let mut hash_header = HashMap::new();
         for i in headers.iter(){
             hash_header.insert(i.0.clone(),i.1.clone());
         }
+        let primary_key = if options.primary_key.is_empty(){
+            headers.first().map(|h|vec![h.0.clone()]).unwrap_or_default()
+        }else{
+            options.primary_key.clone()
+        };
+        let mut key_positions = Vec::with_capacity(primary_key.len());
+        for column in primary_key.iter(){
+            match headers.iter().position(|h|h.0 == *column){
+                Some(position) => key_positions.push(position),
+                None => return Err(Error::from(TytoError::Schema(format!("Failed to load container, the primary key column {} does not exist.",column))))
+            }
+        }
+        let index_map = PrimaryIndex::new(backend.clone(), options.primary_index)?;
         let container = Arc::new(Mutex::new(Container{
             element_size,
             mvcc: Arc::new(Mutex::new((BTreeMap::new(),HashMap::new()))),
             headers_offset,
             headers,
-            graveyard: Arc::new(Mutex::new(BTreeSet::new())),
-            mvcc_record: Arc::new(Mutex::new(MvccRecord::new(format!("{}.mr",path))?)),
-            index_map: Arc::new(Mutex::new(IndexingHashMap::new(path.to_string())?)),
-            file: Arc::new(Mutex::new(file))
+            column_properties: hash_header,
+            primary_key,
+            key_positions,
+            options,
+            graveyard: Arc::new(Mutex::new(graveyard)),
+            graveyard_file,
+            tuning,
+            mvcc_record: Arc::new(Mutex::new(MvccRecord::new(&*backend)?)),
+            index_map: Arc::new(Mutex::new(index_map)),
+            secondary_indexes: Arc::new(Mutex::new(SecondaryIndexes::new(backend.clone())?)),
+            snapshots: Arc::new(Mutex::new(HashMap::new())),
+            data_end: Arc::new(Mutex::new(data_end)),
+            live_rows: AtomicU64::new(UNCOUNTED),
+            text_heap,
+            sequence,
+            version: AtomicU64::new(NEXT_VERSION.fetch_add(1, Ordering::SeqCst)),
+            session: 0,
+            parked: HashMap::new(),
+            discards: 0,
+            unsynced_record: Vec::new(),
+            sync_tickets: Vec::new(),
+            awaiting_sync: 0,
+            file: Arc::new(Mutex::new(file)),
+            backend
         }));
         let mut c = container.lock().await;
         c.load_mvcc().await?;
@@ -139,15 +504,16 @@ impl Container{
         let element_size = self.element_size;
         let headers_offset = self.headers_offset;
         let mut b = self.index_map.lock().await;
+        logdebug!("Rebuilding the primary key index from the data file");
         let empty = vec![255u8;element_size];
                     
-                        let total_rows = (file.metadata()?.len() as usize - headers_offset as usize)/element_size;
+                        let total_rows = (file.len()? as usize - headers_offset as usize)/element_size;
                         let rows_per_it = ((4096*5) / element_size).max(1);
-                        let chunk_size = (rows_per_it * element_size).min(total_rows*element_size);
-                        let count_its = (total_rows / rows_per_it).max(1);
+                        let chunk_size = rows_per_it * element_size;
+                        let count_its = total_rows.div_ceil(rows_per_it);
  
                         for i in 0..count_its{ 
-                            let mut buffer = vec![0u8;chunk_size];
+                            let mut buffer = vec![0u8;(total_rows - i * rows_per_it).min(rows_per_it) * element_size];
                             let file_offset = headers_offset + (i * chunk_size) as u64;
                             file.read_exact_at(&mut buffer, file_offset).unwrap();
 
@@ -158,15 +524,100 @@ impl Container{
                                     continue;
                                 }
                             let bare_row = self.deserialize_row(row_bin).await?;
-                            b.insert(get_index(bare_row[0].clone()), offset_in_file as u64)?;                          
+                            b.insert(&self.key_values(&bare_row), offset_in_file as u64)?;                          
                             
                             }
                         }           
         Ok(())
     }
+    /// Primary key values of `row`, in key order.
+    pub fn key_values(&self, row : &[AlbaTypes]) -> Vec<AlbaTypes>{
+        self.key_positions.iter().map(|p|row[*p].clone()).collect()
+    }
+    /// Index key of `row`'s primary key.
+    pub fn key_of(&self, row : &[AlbaTypes]) -> u64{
+        get_key_index(&self.key_values(row))
+    }
     pub fn column_names(&self) -> Vec<String>{
         self.headers.iter().map(|v|v.0.to_string()).collect()
     }
+    /// Saves the current content of `offsets` into every open snapshot that has not seen them change yet.
+    /// Must run before the slots are overwritten.
+    async fn preserve_for_snapshots(&self, file : &dyn Storage, offsets : impl Iterator<Item = u64>) -> Result<(),Error>{
+        let mut snapshots = self.snapshots.lock().await;
+        if snapshots.is_empty(){
+            return Ok(())
+        }
+        let size = file.len()?;
+        for offset in offsets{
+            if snapshots.values().all(|images|images.contains_key(&offset)){
+                continue;
+            }
+            let image = if offset + self.element_size as u64 <= size{
+                let mut buffer = vec![0u8;self.element_size];
+                file.read_exact_at(&mut buffer, offset)?;
+                if buffer.iter().all(|b|*b == 255){None}else{Some(buffer)}
+            }else{
+                None
+            };
+            for images in snapshots.values_mut(){
+                images.entry(offset).or_insert_with(||image.clone());
+            }
+        }
+        Ok(())
+    }
+    /// Declares a secondary index on `column` and fills it from the rows already on disk.
+    pub async fn create_index(&mut self, column : &str, kind : IndexKind) -> Result<(),Error>{
+        let position = match self.headers.iter().position(|h|h.0 == column){
+            Some(p) => p,
+            None => return Err(Error::from(TytoError::NotFound(format!("Failed to create index, there is no column named {}",column))))
+        };
+        if self.primary_key.len() == 1 && self.key_positions[0] == position{
+            return Err(Error::from(TytoError::Conflict("Failed to create index, the primary key is already indexed.".to_string())))
+        }
+        let mut secondary = self.secondary_indexes.lock().await;
+        if secondary.get(column).is_some(){
+            return Err(Error::from(TytoError::Conflict(format!("Failed to create index, the column {} is already indexed.",column))))
+        }
+        let mut index = SecondaryIndex::new(column.to_string(), kind);
+        let file = self.file.lock().await;
+        let empty = vec![255u8;self.element_size];
+        let total_rows = (file.len()? - self.headers_offset) / self.element_size as u64;
+        let mut buffer = vec![0u8;self.element_size];
+        for row in 0..total_rows{
+            let offset = self.headers_offset + row * self.element_size as u64;
+            file.read_exact_at(&mut buffer, offset)?;
+            if buffer == empty{
+                continue;
+            }
+            let values = self.deserialize_row(&buffer).await?;
+            index.insert(&values[position], offset);
+        }
+        drop(file);
+        secondary.indexes.push(index);
+        secondary.sync()
+    }
+    /// Reads the single row stored at `offset`, returning `None` if the slot is empty or sits in the graveyard.
+    pub async fn read_row_at(&self, offset : u64) -> Result<Option<Row>,Error>{
+        let file = self.file.lock().await;
+        let size = file.len()?;
+        if offset < self.headers_offset || (offset - self.headers_offset) % self.element_size as u64 != 0{
+            return Err(Error::new(ErrorKind::InvalidInput,format!("Offset {} is not aligned to a row of this container (headers end at {}, rows are {} bytes)",offset,self.headers_offset,self.element_size)))
+        }
+        if offset + self.element_size as u64 > size{
+            return Err(Error::new(ErrorKind::InvalidInput,format!("Offset {} is out of the container bounds ({} bytes)",offset,size)))
+        }
+        if self.graveyard.lock().await.contains(&offset){
+            return Ok(None)
+        }
+        let mut buffer = vec![0u8;self.element_size];
+        file.read_exact_at(&mut buffer, offset)?;
+        drop(file);
+        if buffer.iter().all(|b|*b == 255){
+            return Ok(None)
+        }
+        Ok(Some(Row{data:self.deserialize_row(&buffer).await?}))
+    }
 }
 
 fn handle_fixed_string(buf: &[u8],index: &mut usize,instance_size: usize,values: &mut Vec<AlbaTypes>) -> Result<(), Error> {
@@ -174,7 +625,12 @@ fn handle_fixed_string(buf: &[u8],index: &mut usize,instance_size: usize,values:
     let mut size_bytes : [u8;8] = [0u8;8];
     size_bytes.clone_from_slice(&bytes[..8]); 
 
-    let string_length = u64::from_be_bytes(size_bytes) as usize;
+    if u64::from_le_bytes(size_bytes) == NULL_LENGTH{
+        *index += instance_size;
+        values.push(AlbaTypes::NONE);
+        return Ok(())
+    }
+    let string_length = u64::from_le_bytes(size_bytes) as usize;
 
     if 8 + string_length > instance_size {
         return Err(gerr(&format!("Invalid string length in data, expected at most {} but got {}", instance_size - 8, string_length)));
@@ -200,27 +656,8 @@ fn handle_bytes(buf: &[u8],index: &mut usize,size: usize,values: &mut Vec<AlbaTy
     let bytes = buf[*index..*index+size].to_vec();
     let mut blob_size : [u8;8] = [0u8;8];
     blob_size.clone_from_slice(&bytes[..8]); 
-    let blob_length = u64::from_le_bytes(blob_size);
-    let blob : Vec<u8> = if blob_length > 0 {
-        if blob_length >= bytes.len() as u64{
-            bytes[8..].to_vec()
-        }else{
-           bytes[8..(8+blob_length as usize)].to_vec() 
-        }
-        
-    }else{
-        
-        let blob = Vec::new();
-        match size {
-            18 => values.push(AlbaTypes::NanoBytes(blob)),
-            1008 => values.push(AlbaTypes::SmallBytes(blob)),
-            10_008 => values.push(AlbaTypes::MediumBytes(blob)),
-            100_008 => values.push(AlbaTypes::BigSBytes(blob)),
-            1_000_008 => values.push(AlbaTypes::LargeBytes(blob)),
-            _ => unreachable!(),
-        }
-        return Ok(())
-    };
+    let blob_length = (u64::from_le_bytes(blob_size) as usize).min(size - 8);
+    let blob : Vec<u8> = bytes[8..(8+blob_length)].to_vec();
 
     *index += size;
     
@@ -234,30 +671,214 @@ fn handle_bytes(buf: &[u8],index: &mut usize,size: usize,values: &mut Vec<AlbaTy
     }
     Ok(())
 }
-const VACCUM_SIZE : u64 = 4194304;
-const MAX_VACUUM_LENGTH : usize = 625000;
+/// Serializes `row` for a layout of `columns`, appending its Text values to `heap`.
+/// NONE is stored as a string of length `NULL_LENGTH`, other column types can't hold it.
+fn serialize_with(heap: Option<&TextHeap>, row: &[AlbaTypes], columns: &[AlbaTypes]) -> Result<Vec<u8>, Error> {
+    serialize_texts(&mut TextSink::Heap(heap), row, columns)
+}
+/// Where `serialize_texts` puts the Text values of a row.
+enum TextSink<'a>{
+    Heap(Option<&'a TextHeap>),
+    /// Kept next to the row, the pointers are offsets into the buffer.
+    Inline(Vec<u8>),
+}
+fn serialize_texts(sink: &mut TextSink, row: &[AlbaTypes], columns: &[AlbaTypes]) -> Result<Vec<u8>, Error> {
+    let element_size : usize = columns.iter().map(|c|c.size()).sum();
+    let mut buffer = Vec::new();
+    for (i,column) in row.iter().zip(columns.iter().chain(std::iter::repeat(&AlbaTypes::NONE))){
+        match i{
+            AlbaTypes::Text(text) => match sink{
+                TextSink::Heap(heap) => {
+                    let heap = heap.ok_or(gerr("This container has no text heap to store Text values in"))?;
+                    buffer.extend_from_slice(&heap.store(text)?);
+                },
+                TextSink::Inline(texts) => {
+                    buffer.extend_from_slice(&(texts.len() as u64).to_le_bytes());
+                    buffer.extend_from_slice(&(text.len() as u64).to_le_bytes());
+                    texts.extend_from_slice(text.as_bytes());
+                }
+            },
+            AlbaTypes::NONE if column.is_string() => {
+                let mut slot = vec![0u8;column.size()];
+                // a Text pointer keeps its length after the offset
+                let at = if let AlbaTypes::Text(_) = column{8}else{0};
+                slot[at..at+8].copy_from_slice(&NULL_LENGTH.to_le_bytes());
+                buffer.extend_from_slice(&slot);
+            },
+            AlbaTypes::NONE => return Err(gerr(&format!("A {:?} column can't hold NONE, only string columns can",column))),
+            i => i.serialize_into(&mut buffer)
+        }
+    }
+    //println!("data: {:?}",buffer);
+    // Validate buffer size matches element_size
+    if buffer.len() != element_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Serialized size mismatch: expected {}, got {}",
+                element_size,
+                buffer.len()
+            )
+        ));
+    }
+
+    Ok(buffer)
+}
+/// Rows of a container file in row format 0 converted to the current layout, dropping the empty slots in between.
+/// The Text values move into `heap`, which must be synced before the rows are written anywhere.
+pub fn convert_legacy_rows(file : &dyn Storage, heap : Option<&TextHeap>, columns : &[AlbaTypes], headers_offset : u64, chunk_size : u64) -> Result<Vec<u8>,Error>{
+    let sizes = columns.iter().map(|c|c.legacy_size()).collect::<Result<Vec<usize>,Error>>()?;
+    let element_size : usize = sizes.iter().sum();
+    let total_rows = if element_size == 0{0}else{file.len()?.saturating_sub(headers_offset)/element_size as u64};
+    let rows_per_chunk = (chunk_size/element_size.max(1) as u64).max(1);
+    let empty = vec![255u8;element_size];
+    let mut body = Vec::new();
+    let mut row = 0u64;
+    while row < total_rows{
+        let rows = (total_rows - row).min(rows_per_chunk);
+        let mut buffer = vec![0u8;(rows*element_size as u64) as usize];
+        file.read_exact_at(&mut buffer, headers_offset + row*element_size as u64)?;
+        for slot in buffer.chunks_exact(element_size).filter(|slot|*slot != empty){
+            let mut values = Vec::with_capacity(columns.len());
+            let mut at = 0;
+            for (column,size) in columns.iter().zip(sizes.iter()){
+                values.push(column.decode_legacy(&slot[at..at+size])?);
+                at += size;
+            }
+            body.extend_from_slice(&serialize_with(heap, &values, columns)?);
+        }
+        row += rows;
+    }
+    Ok(body)
+}
+/// Walks back from the end of the file over empty slots, returning where the stored rows end.
+fn find_data_end(file : &dyn Storage, headers_offset : u64, element_size : usize, chunk_size : u64) -> Result<u64,Error>{
+    let element_size = element_size as u64;
+    let size = file.len()?;
+    if size <= headers_offset || element_size == 0{
+        return Ok(size.max(headers_offset))
+    }
+    let rows_per_chunk = (chunk_size/element_size).max(1);
+    let mut end = headers_offset + ((size - headers_offset)/element_size)*element_size;
+    let empty = vec![255u8;element_size as usize];
+    while end > headers_offset{
+        let rows = ((end - headers_offset)/element_size).min(rows_per_chunk);
+        let start = end - rows*element_size;
+        let mut buffer = vec![0u8;(rows*element_size) as usize];
+        file.read_exact_at(&mut buffer, start)?;
+        for (i,slot) in buffer.chunks_exact(element_size as usize).enumerate().rev(){
+            if slot != empty{
+                return Ok(start + (i as u64 + 1)*element_size)
+            }
+        }
+        end = start;
+    }
+    Ok(headers_offset)
+}
+/// Reads the free slots saved in the graveyard file, at most `max_length` of them.
+/// The file is written after the data without a sync, so a crash can leave it behind the data file:
+/// only the offsets that are aligned, below `data_end` and still empty are kept.
+fn load_graveyard(graveyard : &dyn Storage, file : &dyn Storage, headers_offset : u64, element_size : usize, data_end : u64, max_length : usize) -> Result<BTreeSet<u64>,Error>{
+    let mut buffer = vec![0u8;(graveyard.len()? as usize).min(max_length*8)];
+    graveyard.read_exact_at(&mut buffer, 0)?;
+    let offsets : Vec<u64> = buffer.chunks_exact(8)
+        .map(|b|u64::from_le_bytes(b.try_into().unwrap()))
+        .filter(|o|*o >= headers_offset && (o - headers_offset) % element_size as u64 == 0 && o + element_size as u64 <= data_end)
+        .collect();
+    let empty = vec![255u8;element_size];
+    let slots = file.read_batch(&offsets, element_size)?;
+    Ok(offsets.into_iter().zip(slots).filter(|(_,slot)|*slot == empty).map(|(offset,_)|offset).collect())
+}
+/// Slots holding a row between the headers and `data_end`.
+fn count_live_rows(file : &dyn Storage, headers_offset : u64, element_size : usize, data_end : u64, chunk_size : u64) -> Result<u64,Error>{
+    if element_size == 0 || data_end <= headers_offset{
+        return Ok(0)
+    }
+    let rows_per_chunk = (chunk_size/element_size as u64).max(1);
+    let total_rows = (data_end - headers_offset)/element_size as u64;
+    let empty = vec![255u8;element_size];
+    let mut live = 0u64;
+    let mut row = 0u64;
+    while row < total_rows{
+        let rows = (total_rows - row).min(rows_per_chunk);
+        let mut buffer = vec![0u8;(rows*element_size as u64) as usize];
+        file.read_exact_at(&mut buffer, headers_offset + row*element_size as u64)?;
+        live += buffer.chunks_exact(element_size).filter(|slot|*slot != empty).count() as u64;
+        row += rows;
+    }
+    Ok(live)
+}
 impl Container{
-    pub async fn get_next_addr(&self) -> Result<u64, Error> {
-        let mv = self.mvcc.lock().await;
-        let mut gy = self.graveyard.lock().await;
-        if let Some(s) = gy.pop_first(){
-            return Ok(s)
+    /// A slot no committed row and no staged change of any session uses: a free slot from the graveyard,
+    /// otherwise the first slot past both the stored data and every staged insert.
+    fn next_addr(&self, pending : &BTreeMap<u64,(MvccState,Vec<AlbaTypes>)>, graveyard : &mut BTreeSet<u64>, data_end : u64) -> u64{
+        let staged = |offset : &u64| pending.contains_key(offset) || self.parked.values().any(|p|p.0.contains_key(offset));
+        // a scan puts back every empty slot it finds, including those a staged insert took but hasn't written yet
+        while let Some(s) = graveyard.pop_first(){
+            if !staged(&s){
+                return s
+            }
         }
-        let m = mv.0.keys().max();
-        let size = self.file.lock().await.metadata()?.size();
-        if let Some(m) = m{
-            return Ok(*m+self.element_size as u64)
+        // the slots other sessions staged inserts on are taken as well
+        let parked = self.parked.values().filter_map(|p|p.0.keys().max());
+        if let Some(m) = pending.keys().max().into_iter().chain(parked).max(){
+            return (*m+self.element_size as u64).max(data_end)
         }
-        Ok(size)
+        data_end
     }
-    pub async fn vacuum(&mut self) -> Result<(),Error> {
+    /// Marks the committed data as changed, called before anything writes to the file.
+    pub fn bump_version(&self){
+        self.version.store(NEXT_VERSION.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+    }
+
+    /// Appends `rows` empty slots to the file so later inserts land on already allocated space.
+    pub async fn preallocate(&mut self, rows : u64) -> Result<(),Error>{
+        self.bump_version();
+        let fi = self.file.lock().await;
+        let element_size = self.element_size as u64;
+        let size = fi.len()?;
+        let start = self.headers_offset + size.saturating_sub(self.headers_offset).div_ceil(element_size)*element_size;
+        let rows_per_chunk = (self.tuning.vacuum_chunk_size/element_size).max(1);
+        let mut written = 0u64;
+        while written < rows{
+            let n = (rows - written).min(rows_per_chunk);
+            fi.write_all_at(&vec![255u8;(n*element_size) as usize], start + written*element_size)?;
+            written += n;
+        }
+        fi.sync_all()?;
+        Ok(())
+    }
+    /// Moves the rows at the end of the file into the empty slots before them and cuts the file after the last row,
+    /// reporting how far it got in `progress`. Setting `progress.cancel` stops it before the next chunk is mapped
+    /// or the next row is moved: each move is synced on its own, so the rows moved so far stay moved and the file is cut after them.
+    pub async fn vacuum(&mut self, progress : &VacuumProgress) -> Result<(),Error> {
+        progress.start();
+        let result = self.compact(progress).await;
+        progress.running.store(false, Ordering::Relaxed);
+        // a cancel asked for before the vacuum started still stops it, one asked for after it ended is dropped
+        progress.cancel.store(false, Ordering::Relaxed);
+        result
+    }
+    async fn compact(&mut self, progress : &VacuumProgress) -> Result<(),Error> {
+        self.bump_version();
         self.graveyard.lock().await.clear();
         let mut mvcc = self.mvcc.lock().await;
         mvcc.0.clear(); mvcc.1.clear();
+        // rows are moved around, so the offsets staged changes point at mean nothing afterwards
+        self.parked.clear();
+        self.discards += 1;
 
         let fi = self.file.lock().await;
+        if !self.unsynced_record.is_empty(){
+            // the commits still waiting for their group sync are made durable before their rows move
+            fi.sync_all()?;
+            self.index_map.lock().await.sync_as(Durability::Full)?;
+            self.unsynced_record.clear();
+        }
+        // nothing is staged anymore, the record must not replay the dropped changes on start
+        self.mvcc_record.lock().await.clear().await?;
         let element_size = self.element_size as u64;
-        let length = (fi.metadata()?.size()-self.headers_offset)/element_size;
+        let length = (fi.len()?-self.headers_offset)/element_size;
 
         if length == 0{
             return Ok(());
@@ -265,11 +886,14 @@ impl Container{
 
         let mut map = bitvec!();
         let mut readen = 0u64;
-        let chunk_size : u64 = VACCUM_SIZE/self.element_size as u64;
+        let chunk_size : u64 = (self.tuning.vacuum_chunk_size/element_size).max(1);
         let empty = vec![255u8;self.element_size];
         let mut pairs : Vec<(u64,u64)> = Vec::new();
         
-        for _ in 0..(length/chunk_size).max(1){
+        for _ in 0..length.div_ceil(chunk_size){
+            if progress.cancel.load(Ordering::Relaxed){
+                return self.save_graveyard().await
+            }
             let etr = (length - readen).min(chunk_size) as u64; //elements to read
             let offset : u64 = self.headers_offset + (readen * element_size);
             readen += etr;
@@ -278,6 +902,7 @@ impl Container{
             for j in buffer.chunks_exact(self.element_size){
                 map.push(j != empty)
             }
+            progress.rows_scanned.fetch_add(etr, Ordering::Relaxed);
             drop(buffer); 
         }
         map.shrink_to_fit();
@@ -298,9 +923,12 @@ impl Container{
                 if let Some(val) = map.get(back_c){
                     if *val{
                         pairs.push((cursor as u64, back_c as u64));
-                        if pairs.len() > MAX_VACUUM_LENGTH{
+                        if pairs.len() > self.tuning.max_vacuum_pairs{
                             break;
                         }
+                        // both slots are taken by this pair, planning them again would move an emptied slot
+                        cursor += 1;
+                        back_c -= 1;
                         run = false;
                     }else{
                         back_c = back_c.saturating_sub(1);
@@ -308,19 +936,30 @@ impl Container{
                 }
             }
         }
+        progress.pairs_planned.store(pairs.len() as u64, Ordering::Relaxed);
         let mut indexing = self.index_map.lock().await;
+        let mut secondary = self.secondary_indexes.lock().await;
         for (dead, alive) in pairs{
+            if progress.cancel.load(Ordering::Relaxed){
+                break
+            }
             let mut buffer = vec![0u8;self.element_size];
             let alive_offset = (alive*element_size) + self.headers_offset;
             fi.read_exact_at(&mut buffer,alive_offset)?;
-            let row_pk = self.deserialize_row(&buffer).await?[0].clone();
+            let row_pk = self.key_values(&self.deserialize_row(&buffer).await?);
             let dead_offset = (dead*element_size)+ self.headers_offset;
+            self.preserve_for_snapshots(&**fi, [dead_offset,alive_offset].into_iter()).await?;
             fi.write_all_at(&buffer, dead_offset)?;
             fi.write_all_at(&vec![255u8;self.element_size], alive_offset)?;
-            indexing.insert(get_index(row_pk),dead_offset)?;
+            indexing.insert(&row_pk,dead_offset)?;
+            for index in secondary.indexes.iter_mut(){
+                index.relocate(alive_offset, dead_offset);
+            }
             fi.sync_all()?;
             indexing.sync()?;
+            secondary.sync()?;
             map.swap(dead as usize, alive as usize);
+            progress.pairs_relocated.fetch_add(1, Ordering::Relaxed);
         }
             
         let mut rows_to_remove = 0u64;
@@ -330,54 +969,433 @@ impl Container{
         }
 
         if rows_to_remove > 0{
-            let new_len = fi.metadata()?.size().saturating_sub(rows_to_remove*element_size).max(self.headers_offset);
+            let old_len = fi.len()?;
+            let new_len = old_len.saturating_sub(rows_to_remove*element_size).max(self.headers_offset);
             fi.set_len(new_len)?;
             fi.sync_all()?;
+            progress.bytes_reclaimed.store(old_len - new_len, Ordering::Relaxed);
         }
-
-
-        
-        Ok(())
+        let data_end = find_data_end(&**fi, self.headers_offset, self.element_size, self.tuning.vacuum_chunk_size)?;
+        *self.data_end.lock().await = data_end;
+        // open snapshots may still point at the text of old row images, which the compaction would drop
+        if !progress.cancel.load(Ordering::Relaxed) && self.snapshots.lock().await.is_empty(){
+            let mut text_offsets = Vec::new();
+            let mut at = 0;
+            for (_,column) in self.headers.iter(){
+                if let AlbaTypes::Text(_) = column{
+                    text_offsets.push(at);
+                }
+                at += column.size();
+            }
+            if let Some(heap) = &mut self.text_heap{
+                let reclaimed = heap.compact(&**fi, self.headers_offset, self.element_size, data_end, &text_offsets, self.tuning.vacuum_chunk_size)?;
+                progress.bytes_reclaimed.fetch_add(reclaimed, Ordering::Relaxed);
+            }
+        }
+        self.save_graveyard().await
     }
+    /// Reads the changes a previous run left in the record into the `RECOVERED_SESSION`.
     pub async fn load_mvcc(&mut self) -> Result<(),Error>{
         let mut mvcc_record = self.mvcc_record.lock().await;
         let b = mvcc_record.yield_().await?;
-        let mut mvcc = self.mvcc.lock().await;
-        for i in b.chunks_exact(1 + self.element_size){
+        drop(mvcc_record);
+        let mut recovered = Staged::default();
+        // every entry is the state byte, the serialized row, the 8 byte key and the row's text, as written by `mvcc_record_entry`
+        let fixed = 1 + self.element_size + 8;
+        let mut cursor = 0;
+        while let Some(i) = b.get(cursor..cursor + fixed + 8){
             let s = match i[0] {0 => MvccState::Insert,1 => MvccState::Edit,_ => MvccState::Delete};
-            let row = self.deserialize_row(&i[1..self.element_size]).await?;
-            let key = {
-                let mut load = [0u8;8];
-                load[..].copy_from_slice(&i[self.element_size..]);
-                u64::from_le_bytes(load)
+            let key = u64::from_le_bytes(i[1 + self.element_size..fixed].try_into().unwrap());
+            let texts_len = u64::from_le_bytes(i[fixed..].try_into().unwrap()) as usize;
+            let texts = match b.get(cursor + fixed + 8..cursor + fixed + 8 + texts_len){
+                Some(texts) => texts,
+                None => break
             };
-            mvcc.0.insert(key, (s,row));
+            let row = self.decode_with(&i[1..1 + self.element_size], Some(texts))?;
+            recovered.0.insert(key, (s,row));
+            cursor += fixed + 8 + texts_len;
+        }
+        if !recovered.0.is_empty(){
+            logdebug!("Loaded {} recovered changes from the MVCC record", recovered.0.len());
+            self.parked.insert(RECOVERED_SESSION, recovered);
         }
         Ok(())
     }
-    pub async fn record_mvcc(&mut self, key : u64, data : Vec<AlbaTypes>,state: MvccState) -> Result<(),Error>{
+    /// The row's Text values are kept in the entry itself, they only reach the text heap when the row is committed.
+    fn mvcc_record_entry(&self, key : u64, data : &[AlbaTypes],state: MvccState) -> Result<Vec<u8>,Error>{
         let mut b = Vec::new();
         b.push(match state{MvccState::Delete => 2, MvccState::Insert => 0, MvccState::Edit => 1});
-        b.extend_from_slice(&self.serialize_row(&data)?);
+        let mut texts = TextSink::Inline(Vec::new());
+        b.extend_from_slice(&serialize_texts(&mut texts, data, &self.columns())?);
         b.extend_from_slice(&key.to_le_bytes());
+        if let TextSink::Inline(texts) = texts{
+            b.extend_from_slice(&(texts.len() as u64).to_le_bytes());
+            b.extend_from_slice(&texts);
+        }
+        Ok(b)
+    }
+    pub async fn record_mvcc(&mut self, key : u64, data : Vec<AlbaTypes>,state: MvccState) -> Result<(),Error>{
+        let b = self.mvcc_record_entry(key, &data, state)?;
         let mut l = self.mvcc_record.lock().await;
         l.put(b).await?;
         Ok(())
     }
-    pub async fn push_row(&mut self, data : Vec<AlbaTypes>) -> Result<(),Error>{
+    /// Stages `rows` as `state` changes of the rows at their offsets, writing them to the record first
+    /// so they survive a crash. Nothing is staged when the record can't be written.
+    pub async fn stage(&self, state : MvccState, mut rows : Vec<(u64,Vec<AlbaTypes>)>) -> Result<(),Error>{
+        let schema = self.columns();
+        let mut record = Vec::new();
+        for (offset,row) in rows.iter_mut(){
+            into_schema(row, &schema)?;
+            record.extend_from_slice(&self.mvcc_record_entry(*offset, row, state)?);
+        }
+        self.mvcc_record.lock().await.put(record).await?;
+        let mut mvcc = self.mvcc.lock().await;
+        for (offset,row) in rows{
+            mvcc.0.insert(offset, (state,row));
+        }
+        Ok(())
+    }
+    /// Fills an auto-increment key left unset with the next value of the sequence, skipping values already taken.
+    /// Returns the generated key, `None` when the row brought its own.
+    fn assign_key(&self, row : &mut [AlbaTypes], indexing : &mut PrimaryIndex) -> Result<Option<AlbaTypes>,Error>{
+        let sequence = match &self.sequence{
+            Some(sequence) => sequence,
+            None => return Ok(None)
+        };
+        let position = self.key_positions[0];
+        if !matches!(row[position], AlbaTypes::NONE){
+            return Ok(None)
+        }
+        loop{
+            let value = sequence.take()?;
+            row[position] = match self.headers[position].1{
+                AlbaTypes::Int(_) => AlbaTypes::Int(i32::try_from(value).map_err(|_|gerr("The auto-increment key ran out of INT values"))?),
+                _ => AlbaTypes::Bigint(value)
+            };
+            if indexing.get(&self.key_values(row))?.is_none(){
+                return Ok(Some(row[position].clone()))
+            }
+        }
+    }
+    /// Primary keys of the inserts staged by every session, not committed yet.
+    fn staged_keys(&self, current : &Staged) -> HashSet<u64>{
+        std::iter::once(current).chain(self.parked.values())
+            .flat_map(|staged|staged.0.values())
+            .filter(|(state,_)|matches!(state, MvccState::Insert))
+            .map(|(_,row)|self.key_of(row))
+            .collect()
+    }
+    /// Stages `data` as a new row, returning the key generated for it if the container auto-increments.
+    pub async fn push_row(&mut self, mut data : Vec<AlbaTypes>) -> Result<Option<AlbaTypes>,Error>{
         let mut indexing = self.index_map.lock().await;
-        let i = get_index(data[0].clone());
-        if indexing.get(i)?.is_some(){
-            return Err(Error::new(ErrorKind::AddrInUse,"This primary key is in use, they must be always unique."))
+        let generated = self.assign_key(&mut data, &mut indexing)?;
+        // values come as the client typed them, the key checks and the record need them as the columns store them
+        into_schema(&mut data, &self.columns())?;
+        self.empty_strings_to_null(&mut data);
+        self.check_not_null(&data)?;
+        let i = self.key_of(&data);
+        if indexing.get(&self.key_values(&data))?.is_some(){
+            return Err(Error::from(TytoError::Conflict("This primary key is in use, they must be always unique.".to_string())))
         }
         drop(indexing);
-        let ind = self.get_next_addr().await?;
+        if self.staged_keys(&*self.mvcc.lock().await).contains(&i){
+            return Err(Error::from(TytoError::Conflict("This primary key is in use, they must be always unique.".to_string())))
+        }
+        // the slot is picked and taken under the same lock, so no other push can be handed it in between
         let mut mvcc_guard = self.mvcc.lock().await;
+        let ind = {
+            let mut gy = self.graveyard.lock().await;
+            let end = *self.data_end.lock().await;
+            self.next_addr(&mvcc_guard.0, &mut gy, end)
+        };
         //println!("PUSH_ROW - OFFSET : {}",ind);
         let d = data.clone();
         mvcc_guard.0.insert(ind, (MvccState::Insert,data));
         drop(mvcc_guard);
-        let _ = self.record_mvcc(ind, d, MvccState::Insert).await;
+        // a row that isn't in the record wouldn't survive a crash, so it isn't staged either
+        if let Err(e) = self.record_mvcc(ind, d, MvccState::Insert).await{
+            self.mvcc.lock().await.0.remove(&ind);
+            return Err(e)
+        }
+        Ok(generated)
+    }
+    /// Overwrites every column of the row holding `data`'s primary key, or stages it as a new row when the key is unused.
+    pub async fn replace_row(&mut self, mut data : Vec<AlbaTypes>) -> Result<(),Error>{
+        if data.len() != self.headers.len(){
+            return Err(Error::from(TytoError::InvalidInput(format!("REPLACE expects {} values, one per column, but got {}", self.headers.len(), data.len()))));
+        }
+        into_schema(&mut data, &self.columns())?;
+        self.empty_strings_to_null(&mut data);
+        self.check_not_null(&data)?;
+        let offset = self.index_map.lock().await.get(&self.key_values(&data))?;
+        let offset = match offset{
+            Some(offset) => offset,
+            None => return self.push_row(data).await.map(|_|())
+        };
+        let previous = self.mvcc.lock().await.0.insert(offset, (MvccState::Edit,data.clone()));
+        if let Err(e) = self.record_mvcc(offset, data, MvccState::Edit).await{
+            let mut mvcc = self.mvcc.lock().await;
+            match previous{
+                Some(previous) => mvcc.0.insert(offset, previous),
+                None => mvcc.0.remove(&offset)
+            };
+            return Err(e)
+        }
+        Ok(())
+    }
+    /// Stages many rows at once, taking the index and MVCC locks a single time for the whole batch.
+    /// Fails without staging anything if any primary key is already in use or repeated inside the batch.
+    pub async fn push_rows(&mut self, mut rows : Vec<Vec<AlbaTypes>>) -> Result<(),Error>{
+        let mut indexing = self.index_map.lock().await;
+        let schema = self.columns();
+        for row in rows.iter_mut(){
+            self.assign_key(row, &mut indexing)?;
+            into_schema(row, &schema)?;
+            self.empty_strings_to_null(row);
+            self.check_not_null(row)?;
+        }
+        let mut seen = HashMap::with_capacity(rows.len());
+        for (position,row) in rows.iter().enumerate(){
+            let i = self.key_of(row);
+            if let Some(first) = seen.insert(i, position){
+                return Err(Error::from(TytoError::Conflict(format!("Duplicate primary key {:?} in the batch, rows {} and {} share it.",self.key_values(row),first,position))))
+            }
+            if indexing.get(&self.key_values(row))?.is_some(){
+                return Err(Error::from(TytoError::Conflict(format!("The primary key {:?} (row {} of the batch) is in use, they must be always unique.",self.key_values(row),position))))
+            }
+        }
+        drop(indexing);
+
+        let mut mvcc_guard = self.mvcc.lock().await;
+        let staged_keys = self.staged_keys(&mvcc_guard);
+        for (position,row) in rows.iter().enumerate(){
+            if staged_keys.contains(&self.key_of(row)){
+                return Err(Error::from(TytoError::Conflict(format!("The primary key {:?} (row {} of the batch) is in use, they must be always unique.",self.key_values(row),position))))
+            }
+        }
+        let mut gy = self.graveyard.lock().await;
+        let end = *self.data_end.lock().await;
+        let mut record = Vec::new();
+        let mut staged = Vec::with_capacity(rows.len());
+        for row in rows{
+            let ind = self.next_addr(&mvcc_guard.0, &mut gy, end);
+            match self.mvcc_record_entry(ind, &row, MvccState::Insert){
+                Ok(entry) => record.extend_from_slice(&entry),
+                Err(e) => {
+                    for ind in staged{
+                        mvcc_guard.0.remove(&ind);
+                    }
+                    return Err(e)
+                }
+            }
+            mvcc_guard.0.insert(ind, (MvccState::Insert,row));
+            staged.push(ind);
+        }
+        drop(gy);
+        drop(mvcc_guard);
+        if let Err(e) = self.mvcc_record.lock().await.put(record).await{
+            let mut mvcc = self.mvcc.lock().await;
+            for ind in staged{
+                mvcc.0.remove(&ind);
+            }
+            return Err(e)
+        }
+        Ok(())
+    }
+    /// Fast path for initial loads: writes `rows` straight after the stored data, skipping the MVCC log,
+    /// then sizes the primary index for the final count and fills every index in one pass.
+    /// Keys are checked for uniqueness up front, a duplicate aborts the load before anything is written.
+    pub async fn bulk_load(&mut self, mut rows : Vec<Vec<AlbaTypes>>) -> Result<(),Error>{
+        self.bump_version();
+        if self.has_pending().await{
+            return Err(Error::from(TytoError::Conflict("Failed to bulk load, commit or rollback the pending changes first.".to_string())))
+        }
+        if !self.snapshots.lock().await.is_empty(){
+            return Err(Error::from(TytoError::Conflict("Failed to bulk load, close the open snapshots first.".to_string())))
+        }
+        let mut indexing = self.index_map.lock().await;
+        let schema = self.columns();
+        for row in rows.iter_mut(){
+            self.assign_key(row, &mut indexing)?;
+            into_schema(row, &schema)?;
+            self.empty_strings_to_null(row);
+            self.check_not_null(row)?;
+        }
+        let mut seen = HashMap::with_capacity(rows.len());
+        for (position,row) in rows.iter().enumerate(){
+            let i = self.key_of(row);
+            if let Some(first) = seen.insert(i, position){
+                return Err(Error::from(TytoError::Conflict(format!("Duplicate primary key {:?} in the load, rows {} and {} share it.",self.key_values(row),first,position))))
+            }
+            if indexing.get(&self.key_values(row))?.is_some(){
+                return Err(Error::from(TytoError::Conflict(format!("The primary key {:?} (row {} of the load) is in use, they must be always unique.",self.key_values(row),position))))
+            }
+        }
+        drop(seen);
+        self.check_unique_rows(&rows.iter().collect::<Vec<_>>(), &HashSet::new()).await?;
+
+        let element_size = self.element_size as u64;
+        let mut body = Vec::with_capacity(rows.len() * self.element_size);
+        let mut loaded = Vec::with_capacity(rows.len());
+        for row in rows{
+            body.extend_from_slice(&self.serialize_row(&row)?);
+            loaded.push(row);
+        }
+        if let Some(heap) = &self.text_heap{
+            heap.sync()?;
+        }
+
+        let fi = self.file.lock().await;
+        let mut end = self.data_end.lock().await;
+        let start = *end;
+        let chunk_size = (self.tuning.vacuum_chunk_size as usize / self.element_size).max(1) * self.element_size;
+        for (i,chunk) in body.chunks(chunk_size).enumerate(){
+            fi.write_all_at(chunk, start + (i * chunk_size) as u64)?;
+        }
+        fi.sync_all()?;
+        *end = start + body.len() as u64;
+        drop(end);
+        drop(fi);
+
+        let mut secondary = self.secondary_indexes.lock().await;
+        let secondary_positions : Vec<usize> = secondary.indexes.iter().map(|i|self.headers.iter().position(|h|h.0 == i.column).unwrap_or(0)).collect();
+        indexing.reserve(loaded.len() as u64)?;
+        self.adjust_live_rows(loaded.len() as u64, 0);
+        for (n,row) in loaded.iter().enumerate(){
+            let offset = start + n as u64 * element_size;
+            indexing.insert(&self.key_values(row), offset)?;
+            for (index,position) in secondary.indexes.iter_mut().zip(secondary_positions.iter()){
+                index.insert(&row[*position], offset);
+            }
+        }
+        indexing.sync()?;
+        secondary.sync()
+    }
+    /// Empties the container in place, cutting the data file back to its header and resetting the indexes, the graveyard and the text heap.
+    /// Refused while changes are pending or snapshots are open, the auto-increment sequence keeps counting.
+    pub async fn truncate(&mut self) -> Result<(),Error>{
+        self.bump_version();
+        if self.has_pending().await{
+            return Err(Error::from(TytoError::Conflict("Failed to truncate, commit or rollback the pending changes first.".to_string())))
+        }
+        if !self.snapshots.lock().await.is_empty(){
+            return Err(Error::from(TytoError::Conflict("Failed to truncate, close the open snapshots first.".to_string())))
+        }
+        let file = self.file.lock().await;
+        file.set_len(self.headers_offset)?;
+        file.sync_all()?;
+        *self.data_end.lock().await = self.headers_offset;
+        drop(file);
+        if !self.unsynced_record.is_empty(){
+            // the rows of the commits waiting for their group sync are gone, replaying them would bring them back
+            self.unsynced_record.clear();
+            self.mvcc_record.lock().await.clear().await?;
+        }
+        self.graveyard.lock().await.clear();
+        self.save_graveyard().await?;
+        self.live_rows.store(0, Ordering::SeqCst);
+        self.index_map.lock().await.clear()?;
+        let mut secondary = self.secondary_indexes.lock().await;
+        for index in secondary.indexes.iter_mut(){
+            index.clear();
+        }
+        secondary.sync()?;
+        drop(secondary);
+        if let Some(heap) = &mut self.text_heap{
+            heap.clear()?;
+        }
+        Ok(())
+    }
+    /// Parks the changes of the current session and brings those of `session` into `mvcc`.
+    pub async fn switch_session(&mut self, session : u64){
+        if self.session == session{
+            return
+        }
+        let mut mvcc = self.mvcc.lock().await;
+        let current = std::mem::take(&mut *mvcc);
+        if !current.0.is_empty() || !current.1.is_empty(){
+            self.parked.insert(self.session, current);
+        }
+        *mvcc = self.parked.remove(&session).unwrap_or_default();
+        self.session = session;
+    }
+    /// Writes the free slots of the graveyard to its file, read back when the container is opened.
+    async fn save_graveyard(&self) -> Result<(),Error>{
+        let buffer : Vec<u8> = self.graveyard.lock().await.iter().flat_map(|o|o.to_le_bytes()).collect();
+        self.graveyard_file.write_all_at(&buffer, 0)?;
+        self.graveyard_file.set_len(buffer.len() as u64)
+    }
+    /// Takes the tickets of the group syncs the commits since the last call wait on.
+    /// Nothing they committed is durable before the tickets resolve.
+    pub fn take_sync_tickets(&mut self) -> Vec<SyncTicket>{
+        std::mem::take(&mut self.sync_tickets)
+    }
+    /// Called once the group syncs of `count` commits succeeded. When no commit is left waiting,
+    /// the record drops the committed changes it kept for them and only holds what is staged.
+    /// Commits whose sync failed are never settled, their changes stay in the record and are replayed on start.
+    pub async fn settle_synced(&mut self, count : usize) -> Result<(),Error>{
+        self.awaiting_sync = self.awaiting_sync.saturating_sub(count);
+        if self.awaiting_sync > 0{
+            return Ok(())
+        }
+        self.unsynced_record.clear();
+        let mut entries = Vec::new();
+        for (offset,(state,row)) in self.mvcc.lock().await.0.iter(){
+            entries.extend_from_slice(&self.mvcc_record_entry(*offset, row, *state)?);
+        }
+        let mut record = self.mvcc_record.lock().await;
+        record.clear().await?;
+        if !entries.is_empty(){
+            record.put(entries).await?;
+        }
+        self.record_parked(&mut record).await
+    }
+    /// Whether any session has changes staged in the container.
+    pub async fn has_pending(&self) -> bool{
+        !self.mvcc.lock().await.0.is_empty() || self.parked.values().any(|p|!p.0.is_empty())
+    }
+    /// Writes the changes of the other sessions back to the emptied MVCC record, after the current one committed or rolled back,
+    /// behind those of the commits still waiting for their group sync.
+    async fn record_parked(&self, record : &mut MvccRecord) -> Result<(),Error>{
+        let mut entries = self.unsynced_record.clone();
+        for staged in self.parked.values(){
+            for (offset,(state,row)) in staged.0.iter(){
+                entries.extend_from_slice(&self.mvcc_record_entry(*offset, row, *state)?);
+            }
+        }
+        if !entries.is_empty(){
+            record.put(entries).await?;
+        }
+        Ok(())
+    }
+    /// Takes the pending changes and the length of the MVCC record, so `restore_pending` can later drop
+    /// whatever is staged after this point, like a statement abandoned halfway or the changes after a savepoint.
+    pub async fn mark_pending(&self) -> Result<PendingMark,Error>{
+        let pending = self.mvcc.lock().await.clone();
+        let record_len = self.mvcc_record.lock().await.len().await?;
+        Ok(PendingMark{pending,record_len,discards:self.discards})
+    }
+    /// Puts back the state taken by `mark_pending`, changes staged since then are dropped, older ones stay pending.
+    /// Fails once a vacuum threw the pending changes away after the mark, the offsets it holds mean nothing by then.
+    pub async fn restore_pending(&self, mark : PendingMark) -> Result<(),Error>{
+        if self.discards != mark.discards{
+            return Err(Error::from(TytoError::Conflict("The pending changes were thrown away by a vacuum since they were marked, they can't be put back".to_string())))
+        }
+        let mut mvcc = self.mvcc.lock().await;
+        let mut graveyard = self.graveyard.lock().await;
+        let data_end = *self.data_end.lock().await;
+        // slots below the end of the data were taken from the graveyard by the inserts being dropped
+        for (offset,(state,_)) in mvcc.0.iter(){
+            if matches!(state, MvccState::Insert) && *offset < data_end && !mark.pending.0.contains_key(offset){
+                graveyard.insert(*offset);
+            }
+        }
+        *mvcc = mark.pending;
+        let mut record = self.mvcc_record.lock().await;
+        if record.len().await? > mark.record_len{
+            record.truncate(mark.record_len).await?;
+        }
         Ok(())
     }
     pub async fn rollback(&mut self) -> Result<(),Error> {
@@ -386,10 +1404,47 @@ impl Container{
         mvcc_guard.1.clear();
         let mut mvcc_rec = self.mvcc_record.lock().await;
         let _ = mvcc_rec.clear().await;
+        self.record_parked(&mut mvcc_rec).await?;
         drop(mvcc_guard);
         Ok(())
     }
+    /// Fails if the staged inserts and edits would leave two rows sharing a value of a UNIQUE column,
+    /// either among themselves or with a committed row that the same commit doesn't delete or edit.
+    async fn check_unique(&self, insertions : &[(u64,Vec<AlbaTypes>)], edits : &[(u64,Vec<AlbaTypes>)], deletes : &[(u64,Vec<AlbaTypes>)]) -> Result<(),Error>{
+        let replaced : HashSet<u64> = edits.iter().chain(deletes.iter()).map(|r|r.0).collect();
+        let rows : Vec<&Vec<AlbaTypes>> = insertions.iter().chain(edits.iter()).map(|r|&r.1).collect();
+        self.check_unique_rows(&rows, &replaced).await
+    }
+    /// Fails if two of `rows`, or one of them and a committed row whose slot isn't in `replaced`, share a value of a UNIQUE column.
+    async fn check_unique_rows(&self, rows : &[&Vec<AlbaTypes>], replaced : &HashSet<u64>) -> Result<(),Error>{
+        if self.options.unique.is_empty(){
+            return Ok(())
+        }
+        let secondary = self.secondary_indexes.lock().await;
+        for column in self.options.unique.iter(){
+            let (position,index) = match (self.headers.iter().position(|h|h.0 == *column), secondary.get(column)){
+                (Some(position),Some(index)) => (position,index),
+                _ => return Err(Error::from(TytoError::Schema(format!("The UNIQUE column {} has no index to check it against",column))))
+            };
+            let schema = &self.headers[position].1;
+            let mut seen = HashSet::new();
+            for row in rows.iter(){
+                if let AlbaTypes::NONE = row[position]{
+                    continue
+                }
+                let value = schema.try_from_existing(row[position].clone())?;
+                if !seen.insert(ordered_key(&value)) || index.lookup(&value).iter().any(|o|!replaced.contains(o)){
+                    return Err(Error::from(TytoError::Conflict(format!("The value {:?} of the UNIQUE column {} is in use, they must be always unique.",value,column))))
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Writes the changes staged by the active session and only then updates the indexes and empties the record.
+    /// If the write fails the changes stay staged and the indexes untouched, so the commit can be retried,
+    /// rewriting the slots a failed attempt may have written already.
     pub async fn commit(&mut self) -> Result<(), Error> {
+        self.bump_version();
         //let mut virtual_ward : HashMap<usize, DataReference> = HashMap::new();
         let mut mvcc = self.mvcc.lock().await;
         let mut insertions: Vec<(u64, Vec<AlbaTypes>)> = Vec::new();
@@ -404,57 +1459,27 @@ impl Container{
                 MvccState::Edit => edits.push(v)
             }
         }
-        mvcc.0.clear();
+        self.check_unique(&insertions, &edits, &deletes).await?;
         insertions.sort_by_key(|(index, _)| *index);
         deletes.sort_by_key(|(index, _)| *index);
 
-        let mut writting : Vec<(u64,Vec<u8>)> = Vec::new();
         let schema = self.columns();
-        //println!("schema {:?}",schema);
-        let mut index_batch : Vec<(AlbaTypes,u64)> = Vec::new();
-        for (row_index, mut row_data) in insertions {
-            //println!("\nrow_data: {:?}\n",row_data);
-            into_schema(&mut row_data, &schema)?;
-            let serialized = self.serialize_row(&row_data).unwrap();
-            index_batch.push((row_data[0].clone(),row_index));
-            let offset = row_index;
-            writting.push((offset,serialized));
-        }
-        let mut indexing = self.index_map.lock().await;
-        for (row_index, mut row_data) in edits{
-            //println!("\nrow_data: {:?}\n",row_data);
-            into_schema(&mut row_data, &schema)?;
-            let serialized = self.serialize_row(&row_data).unwrap();
-            let key = get_index(row_data[0].clone());
-            indexing.remove(key)?;
-            index_batch.push((row_data[0].clone(),row_index));
-            let offset = row_index;
-            writting.push((offset,serialized)); 
+        let mut writting : Vec<(u64,Vec<u8>)> = Vec::with_capacity(insertions.len() + edits.len() + deletes.len());
+        for (row_index, row_data) in insertions.iter_mut().chain(edits.iter_mut()){
+            into_schema(row_data, &schema)?;
+            writting.push((*row_index,self.serialize_row(row_data)?));
         }
-
-        drop(schema);
-
-
+        let inserted_end = writting.iter().map(|w|w.0).max();
         let buf = vec![255u8; self.element_size];
-        let mut gy = self.graveyard.lock().await;
-        let mut gyl = gy.len();
         for del in &deletes {
-            let offset = del.0;
-            if gyl < MAX_GRAVEYARD_LENGTH_IN_MEMORY{
-                gy.insert(offset);
-                gyl += 1;
-            }
-            let key = get_index(del.1[0].clone());
-
-            indexing.remove(key)?;
-            writting.push((offset,buf.clone()));
+            writting.push((del.0,buf.clone()));
         }
-       
+
         // if let Some(s) = STRIX.get(){
         //     let mut l = s.lock().await;
         //     l.wards.push(Mutex::new((std::fs::OpenOptions::new().read(true).write(true).open(&self.file_path)?,virtual_ward)));
         // }
-        
+
         let mut l = Vec::new();
         for i in writting{
             let len = i.1.len();
@@ -464,53 +1489,247 @@ impl Container{
                 offset: i.0 as i64
             });
         }
-;
+        // the rows must never reach the disk before the text they point at
+        if let Some(heap) = &self.text_heap{
+            heap.sync()?;
+        }
         let f = self.file.lock().await;
-        let c = f.as_raw_fd();
+        // the slots skipped past the end of the file were taken by inserts other sessions staged, they read as empty until those commit
+        if let Some(last) = inserted_end{
+            let written : HashSet<u64> = l.iter().map(|w|w.offset as u64).collect();
+            let mut slot = f.len()?.max(self.headers_offset);
+            while slot < last{
+                if !written.contains(&slot){
+                    l.push(WriteEntry{buffer: Arc::new(buf.clone()), length: self.element_size, offset: slot as i64});
+                }
+                slot += self.element_size as u64;
+            }
+        }
+        self.preserve_for_snapshots(&**f, l.iter().map(|w|w.offset as u64)).await?;
+        // a grouped commit leaves the sync of the file and the index to its group, see `take_sync_tickets`
+        let grouped = group_commit_enabled() && f.as_file().is_some();
+        let durability = if grouped {Durability::Off} else {Durability::current()};
+        f.write_batch(&l, durability)?;
 
-        for (alb,off) in index_batch{
-            let key = get_index(alb);
-            indexing.insert(key,off)?;    
+        let mut secondary = self.secondary_indexes.lock().await;
+        let secondary_positions : Vec<usize> = secondary.indexes.iter().map(|i|self.headers.iter().position(|h|h.0 == i.column).unwrap_or(0)).collect();
+        let mut indexing = self.index_map.lock().await;
+        let mut index_batch : Vec<(Vec<AlbaTypes>,u64)> = Vec::new();
+        for (row_index, row_data) in insertions.iter(){
+            index_batch.push((self.key_values(row_data),*row_index));
+            for (index,position) in secondary.indexes.iter_mut().zip(secondary_positions.iter()){
+                index.insert(&row_data[*position], *row_index);
+            }
+        }
+        for (row_index, row_data) in edits.iter(){
+            indexing.remove(&self.key_values(row_data))?;
+            index_batch.push((self.key_values(row_data),*row_index));
+            for (index,position) in secondary.indexes.iter_mut().zip(secondary_positions.iter()){
+                index.insert(&row_data[*position], *row_index);
+            }
+        }
+        let mut gy = self.graveyard.lock().await;
+        // a scan may have put the slot of a staged insert back in the graveyard before it was written
+        for (offset,_) in insertions.iter(){
+            gy.remove(offset);
+        }
+        let mut gyl = gy.len();
+        let mut deleted = 0u64;
+        for del in &deletes {
+            let offset = del.0;
+            if gyl < self.tuning.max_graveyard_length{
+                gy.insert(offset);
+                gyl += 1;
+            }
+            if indexing.remove(&self.key_values(&del.1))?{
+                deleted += 1;
+            }
+            for index in secondary.indexes.iter_mut(){
+                index.remove_offset(offset);
+            }
+        }
+        drop(gy);
+        self.save_graveyard().await?;
+        for (key,off) in index_batch{
+            indexing.insert(&key,off)?;    
         };
-        indexing.sync()?; 
+        self.adjust_live_rows(insertions.len() as u64, deleted);
+        indexing.sync_as(durability)?; 
+        secondary.sync()?;
+        drop(secondary);
 
-        for l in l.chunks(3000){
-            let l_1 = l.len();
-            batch_write_data(l.to_vec(), l_1, c).await;
+        if let Some(last) = inserted_end{
+            let mut end = self.data_end.lock().await;
+            *end = (*end).max(last + self.element_size as u64);
+        }
+
+        if grouped{
+            let mut files = Vec::new();
+            for storage in [&**f, indexing.storage()]{
+                if let Some(file) = storage.as_file(){
+                    files.push(file.try_clone()?);
+                }
+            }
+            let ticket = join_commit_group(files)?;
+            // the record keeps the committed changes until the group synced them, a crash before that replays them
+            let mut committed = Vec::new();
+            for (offset,(state,row)) in mvcc.0.iter(){
+                committed.extend_from_slice(&self.mvcc_record_entry(*offset, row, *state)?);
+            }
+            drop(indexing);
+            drop(f);
+            self.unsynced_record.extend_from_slice(&committed);
+            self.sync_tickets.push(ticket);
+            self.awaiting_sync += 1;
+            mvcc.1.clear(); mvcc.0.clear();
+            return Ok(())
         }
 
-        
-        
         let mut mvcc_record = self.mvcc_record.lock().await;
         mvcc_record.clear().await?;
+        self.record_parked(&mut mvcc_record).await?;
         mvcc.1.clear(); mvcc.0.clear(); 
         Ok(())
     }
     
+    /// Hands every committed row to `visit` in file order, reading the data file a chunk at a time and skipping empty slots.
+    /// Returns how many rows were visited, pending changes are not seen.
+    async fn for_each_committed_row<F>(&self, mut visit : F) -> Result<u64,Error>
+    where F : FnMut(Vec<AlbaTypes>) -> Result<(),Error>{
+        let file = self.file.lock().await;
+        let end = *self.data_end.lock().await;
+        let element_size = self.element_size as u64;
+        let rows_per_chunk = (self.tuning.vacuum_chunk_size/element_size).max(1);
+        let total_rows = end.saturating_sub(self.headers_offset)/element_size;
+        let empty = vec![255u8;self.element_size];
+        let mut row = 0u64;
+        let mut visited = 0u64;
+        while row < total_rows{
+            let rows = (total_rows - row).min(rows_per_chunk);
+            let mut buffer = vec![0u8;(rows*element_size) as usize];
+            file.read_exact_at(&mut buffer, self.headers_offset + row*element_size)?;
+            for slot in buffer.chunks_exact(self.element_size){
+                if slot == empty{
+                    continue
+                }
+                visit(self.deserialize_row(slot).await?)?;
+                visited += 1;
+            }
+            row += rows;
+        }
+        Ok(visited)
+    }
+    /// Streams every committed row to `writer` as CSV, after a header line with the column names, and returns the rows written.
+    /// Fields are encoded by `AlbaTypes::to_csv_field`.
+    pub async fn export_csv<W : Write>(&self, writer : &mut W) -> Result<u64,Error>{
+        let header : Vec<String> = self.column_names().into_iter().map(|n|AlbaTypes::Text(n).to_csv_field()).collect();
+        writeln!(writer, "{}", header.join(","))?;
+        let written = self.for_each_committed_row(|row|{
+            let fields : Vec<String> = row.iter().map(|v|v.to_csv_field()).collect();
+            writeln!(writer, "{}", fields.join(","))
+        }).await?;
+        writer.flush()?;
+        Ok(written)
+    }
+    /// Streams every committed row to `writer` as newline-delimited JSON, one object per row keyed by column name
+    /// in column order, and returns the rows written. Values are encoded by `AlbaTypes::to_json`.
+    pub async fn export_json<W : Write>(&self, writer : &mut W) -> Result<u64,Error>{
+        let keys : Vec<String> = self.column_names().into_iter().map(|n|serde_json::Value::String(n).to_string()).collect();
+        let written = self.for_each_committed_row(|row|{
+            let fields : Vec<String> = keys.iter().zip(row.iter()).map(|(k,v)|format!("{}:{}",k,v.to_json())).collect();
+            writeln!(writer, "{{{}}}", fields.join(","))
+        }).await?;
+        writer.flush()?;
+        Ok(written)
+    }
+    /// Committed rows in the container, the file is only scanned the first time.
+    pub async fn row_count(&self) -> Result<u64,Error>{
+        let live = self.live_rows.load(Ordering::SeqCst);
+        if live != UNCOUNTED{
+            return Ok(live)
+        }
+        let data_end = *self.data_end.lock().await;
+        let file = self.file.lock().await;
+        let live = count_live_rows(&**file, self.headers_offset, self.element_size, data_end, self.tuning.vacuum_chunk_size)?;
+        self.live_rows.store(live, Ordering::SeqCst);
+        Ok(live)
+    }
+    /// Leaves the count alone while it was never taken, the next `row_count` reads the file as committed.
+    fn adjust_live_rows(&self, added : u64, removed : u64){
+        let _ = self.live_rows.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live|{
+            if live == UNCOUNTED{None}else{Some((live + added).saturating_sub(removed))}
+        });
+    }
+    /// Share of the slots before the end of the data that hold no committed row, 0 for an empty container.
+    pub async fn dead_ratio(&self) -> Result<f64,Error>{
+        let slots = self.data_end.lock().await.saturating_sub(self.headers_offset)/self.element_size.max(1) as u64;
+        if slots == 0{
+            return Ok(0.0)
+        }
+        Ok(slots.saturating_sub(self.row_count().await?) as f64 / slots as f64)
+    }
     pub fn columns(&self) -> Vec<AlbaTypes>{
         self.headers.iter().map(|v|v.1.clone()).collect()
     }
-    pub fn serialize_row(&self, row: &[AlbaTypes]) -> Result<Vec<u8>, Error> {
-        let mut buffer = Vec::new();
-        for i in row{
-            i.serialize_into(&mut buffer);
-        }
-        //println!("data: {:?}",buffer);
-        // Validate buffer size matches element_size
-        if buffer.len() != self.element_size {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "Serialized size mismatch: expected {}, got {}",
-                    self.element_size,
-                    buffer.len()
-                )
-            ));
+    /// Row an insert starts from: the column defaults where set, and an auto-increment key left unset for `push_row` to fill.
+    pub fn row_template(&self) -> Vec<AlbaTypes>{
+        let mut row = self.columns();
+        for (column,value) in self.options.defaults.iter(){
+            if let Some(position) = self.headers.iter().position(|h|h.0 == *column){
+                row[position] = value.clone();
+            }
+        }
+        if self.sequence.is_some(){
+            row[self.key_positions[0]] = AlbaTypes::NONE;
+        }
+        row
+    }
+    /// NOT NULL columns an insert has to name, those without a default and not filled by the auto-increment sequence.
+    pub fn required_columns(&self) -> Vec<&String>{
+        self.options.not_null.iter().filter(|column|{
+            !self.options.defaults.iter().any(|d|d.0 == **column) && !(self.sequence.is_some() && self.primary_key[0] == **column)
+        }).collect()
+    }
+    /// Turns the empty strings of the `empty_as_null` columns of `row` into NONE.
+    pub fn empty_strings_to_null(&self, row : &mut [AlbaTypes]){
+        for column in self.options.empty_as_null.iter(){
+            if let Some(position) = self.headers.iter().position(|h|h.0 == *column){
+                if let Some(value) = row.get_mut(position){
+                    if matches!(value, AlbaTypes::Text(s)|AlbaTypes::NanoString(s)|AlbaTypes::SmallString(s)|AlbaTypes::MediumString(s)|AlbaTypes::BigString(s)|AlbaTypes::LargeString(s) if s.is_empty()){
+                        *value = AlbaTypes::NONE;
+                    }
+                }
+            }
         }
-
-        Ok(buffer)
+    }
+    /// Fails if a NOT NULL column of `row` holds NONE.
+    pub fn check_not_null(&self, row : &[AlbaTypes]) -> Result<(),Error>{
+        for column in self.options.not_null.iter(){
+            if let Some(position) = self.headers.iter().position(|h|h.0 == *column){
+                if let Some(AlbaTypes::NONE) = row.get(position){
+                    return Err(Error::from(TytoError::InvalidInput(format!("The column {} is NOT NULL and can't be set to NONE",column))))
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn serialize_row(&self, row: &[AlbaTypes]) -> Result<Vec<u8>, Error> {
+        self.serialize_sized(row, &self.columns())
+    }
+    /// Serializes `row` for a layout of `columns`, appending its Text values to the text heap.
+    pub fn serialize_sized(&self, row: &[AlbaTypes], columns: &[AlbaTypes]) -> Result<Vec<u8>, Error> {
+        serialize_with(self.text_heap.as_ref(), row, columns)
     }
     pub async fn deserialize_row(&self, buf: &[u8]) -> Result<Vec<AlbaTypes>, Error> {
+        self.decode_row(buf)
+    }
+
+    /// The blocking body of `deserialize_row`, usable from the threads of a parallel scan.
+    pub fn decode_row(&self, buf: &[u8]) -> Result<Vec<AlbaTypes>, Error> {
+        self.decode_with(buf, None)
+    }
+    /// Decodes a row whose Text pointers point into `inline` when given, into the text heap otherwise.
+    fn decode_with(&self, buf: &[u8], inline: Option<&[u8]>) -> Result<Vec<AlbaTypes>, Error> {
         let mut index = 0;
         let mut values = Vec::new();
     
@@ -522,7 +1741,7 @@ impl Container{
                     let bytes: [u8; 8] = buf[index..index+size].try_into()
                         .map_err(|e| gerr(&format!("Failed to read bigint: {}", e)))?;
                     index += size;
-                    values.push(AlbaTypes::Bigint(i64::from_be_bytes(bytes)));
+                    values.push(AlbaTypes::Bigint(i64::from_le_bytes(bytes)));
                 },
                 
                 AlbaTypes::Int(_) => {
@@ -530,7 +1749,7 @@ impl Container{
                     let bytes: [u8; 4] = buf[index..index+size].try_into()
                         .map_err(|e| gerr(&format!("Failed to read int: {}", e)))?;
                     index += size;
-                    values.push(AlbaTypes::Int(i32::from_be_bytes(bytes)));
+                    values.push(AlbaTypes::Int(i32::from_le_bytes(bytes)));
                 },
     
                 AlbaTypes::Float(_) => {
@@ -538,7 +1757,7 @@ impl Container{
                     let bytes: [u8; 8] = buf[index..index+size].try_into()
                         .map_err(|e| gerr(&format!("Failed to read float: {}", e)))?;
                     index += size;
-                    values.push(AlbaTypes::Float(f64::from_be_bytes(bytes)));
+                    values.push(AlbaTypes::Float(f64::from_le_bytes(bytes)));
                 },
     
                 AlbaTypes::Bool(_) => {
@@ -564,7 +1783,24 @@ impl Container{
     
                 // Text types
                 AlbaTypes::Text(_) => {
-                    values.push(AlbaTypes::Text(String::new()));
+                    let size = column_type.size();
+                    let pointer = buf.get(index..index+size).ok_or(gerr("Incomplete text pointer"))?;
+                    if pointer[8..16] == NULL_LENGTH.to_le_bytes(){
+                        values.push(AlbaTypes::NONE);
+                        index += size;
+                        continue
+                    }
+                    let text = match inline{
+                        Some(texts) => {
+                            let offset = u64::from_le_bytes(pointer[..8].try_into().unwrap()) as usize;
+                            let len = u64::from_le_bytes(pointer[8..16].try_into().unwrap()) as usize;
+                            let bytes = offset.checked_add(len).and_then(|end|texts.get(offset..end)).ok_or(gerr("Text pointer past the end of the record entry"))?;
+                            String::from_utf8(bytes.to_vec()).map_err(|e|gerr(&format!("Failed to read text: {}",e)))?
+                        },
+                        None => self.text_heap.as_ref().ok_or(gerr("This container has no text heap to read Text values from"))?.load(pointer)?
+                    };
+                    values.push(AlbaTypes::Text(text));
+                    index += size;
                 },
     
                 // Fixed-size string types
@@ -580,6 +1816,28 @@ impl Container{
                 AlbaTypes::MediumBytes(_) => handle_bytes(&buf, &mut index, column_type.size(), &mut values)?,
                 AlbaTypes::BigSBytes(_) => handle_bytes(&buf, &mut index, column_type.size(), &mut values)?,
                 AlbaTypes::LargeBytes(_) => handle_bytes(&buf, &mut index, column_type.size(), &mut values)?,
+
+                AlbaTypes::Timestamp(_) => {
+                    let size = std::mem::size_of::<i64>();
+                    let bytes: [u8; 8] = buf[index..index+size].try_into()
+                        .map_err(|e| gerr(&format!("Failed to read timestamp: {}", e)))?;
+                    index += size;
+                    values.push(AlbaTypes::Timestamp(i64::from_le_bytes(bytes)));
+                },
+
+                AlbaTypes::Decimal(_) => {
+                    let bytes: [u8; 17] = buf.get(index..index+17).ok_or(gerr("Incomplete decimal data"))?.try_into()
+                        .map_err(|e| gerr(&format!("Failed to read decimal: {}", e)))?;
+                    index += 17;
+                    values.push(AlbaTypes::Decimal(Decimal::from_bytes(bytes)?));
+                },
+
+                AlbaTypes::Uuid(_) => {
+                    let bytes: [u8; 16] = buf.get(index..index+16).ok_or(gerr("Incomplete uuid data"))?.try_into()
+                        .map_err(|e| gerr(&format!("Failed to read uuid: {}", e)))?;
+                    index += 16;
+                    values.push(AlbaTypes::Uuid(bytes));
+                },
     
                 // Null handling
                 AlbaTypes::NONE => {
@@ -592,3 +1850,429 @@ impl Container{
     }
     
 }
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// An in-memory container with the first column as its primary key.
+    async fn memory_container(columns : &[(&str,AlbaTypes)]) -> Arc<Mutex<Container>>{
+        memory_container_with(columns, ContainerOptions::default()).await
+    }
+    async fn memory_container_with(columns : &[(&str,AlbaTypes)], options : ContainerOptions) -> Arc<Mutex<Container>>{
+        let types : Vec<AlbaTypes> = columns.iter().map(|c|c.1.clone()).collect();
+        let names = columns.iter().map(|c|c.0.to_string()).collect();
+        let element_size = types.iter().map(|t|t.size()).sum();
+        Container::new_in_memory(Vec::new(), element_size, types, 0, names, options, ContainerTuning::default()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn back_to_back_pushes_take_distinct_slots(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new()))]).await;
+        let mut c = container.lock().await;
+        for id in 0..4{
+            c.push_row(vec![AlbaTypes::Bigint(id),AlbaTypes::SmallString(format!("row {}",id))]).await.unwrap();
+        }
+        let staged : Vec<u64> = c.mvcc.lock().await.0.keys().copied().collect();
+        assert_eq!(staged, (0..4).map(|i|i*c.element_size as u64).collect::<Vec<u64>>());
+        c.commit().await.unwrap();
+        for id in 0..4u64{
+            let row = c.read_row_at(id*c.element_size as u64).await.unwrap().unwrap();
+            assert_eq!(row.data[0], AlbaTypes::Bigint(id as i64));
+        }
+    }
+
+    #[tokio::test]
+    async fn graveyard_slots_taken_by_staged_rows_are_skipped(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0))]).await;
+        let mut c = container.lock().await;
+        let size = c.element_size as u64;
+        c.push_row(vec![AlbaTypes::Bigint(1)]).await.unwrap();
+        // a scan found the staged slot empty and put it back in the graveyard
+        c.graveyard.lock().await.insert(0);
+        c.push_row(vec![AlbaTypes::Bigint(2)]).await.unwrap();
+        let staged : Vec<u64> = c.mvcc.lock().await.0.keys().copied().collect();
+        assert_eq!(staged, vec![0,size]);
+        // once written the slot leaves the graveyard, it would be handed out again otherwise
+        c.commit().await.unwrap();
+        assert!(c.graveyard.lock().await.is_empty());
+        assert_eq!(c.read_row_at(0).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(1)]);
+    }
+
+    #[tokio::test]
+    async fn row_count_is_taken_lazily_then_tracked(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0))]).await;
+        let mut c = container.lock().await;
+        c.push_rows((0..3).map(|id|vec![AlbaTypes::Bigint(id)]).collect()).await.unwrap();
+        c.commit().await.unwrap();
+        assert_eq!(c.live_rows.load(Ordering::SeqCst), UNCOUNTED);
+        assert_eq!(c.row_count().await.unwrap(), 3);
+        c.stage(MvccState::Delete, vec![(0,vec![AlbaTypes::Bigint(0)])]).await.unwrap();
+        c.commit().await.unwrap();
+        assert_eq!(c.live_rows.load(Ordering::SeqCst), 2);
+        assert_eq!(c.row_count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_key_repeated_inside_the_batch_is_rejected(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new()))]).await;
+        let mut c = container.lock().await;
+        let rows = [1,2,1].iter().map(|id|vec![AlbaTypes::Bigint(*id),AlbaTypes::SmallString(String::new())]).collect();
+        let e = TytoError::from(c.push_rows(rows).await.unwrap_err());
+        assert!(matches!(e, TytoError::Conflict(_)));
+        assert!(e.to_string().contains("[Bigint(1)]"), "{}", e);
+        assert!(e.to_string().contains("rows 0 and 2"), "{}", e);
+        // nothing of the batch is staged
+        assert!(c.mvcc.lock().await.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replace_overwrites_the_row_in_place_or_inserts_it(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new())),("age",AlbaTypes::Int(0))]).await;
+        let mut c = container.lock().await;
+        c.push_row(vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("old".to_string()),AlbaTypes::Int(20)]).await.unwrap();
+        c.commit().await.unwrap();
+        let offset = c.index_map.lock().await.get(&[AlbaTypes::Bigint(1)]).unwrap().unwrap();
+        let e = TytoError::from(c.replace_row(vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("short".to_string())]).await.unwrap_err());
+        assert!(matches!(e, TytoError::InvalidInput(_)));
+        c.replace_row(vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("new".to_string()),AlbaTypes::Int(21)]).await.unwrap();
+        c.replace_row(vec![AlbaTypes::Bigint(2),AlbaTypes::SmallString("added".to_string()),AlbaTypes::Int(5)]).await.unwrap();
+        c.commit().await.unwrap();
+        assert_eq!(c.index_map.lock().await.get(&[AlbaTypes::Bigint(1)]).unwrap(), Some(offset));
+        assert_eq!(c.read_row_at(offset).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("new".to_string()),AlbaTypes::Int(21)]);
+        let added = c.index_map.lock().await.get(&[AlbaTypes::Bigint(2)]).unwrap().unwrap();
+        assert_eq!(c.read_row_at(added).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(2),AlbaTypes::SmallString("added".to_string()),AlbaTypes::Int(5)]);
+    }
+
+    /// Loads `rows` into a new container with an ordered index on `group`, through `bulk_load` or through `push_rows` and `commit`.
+    async fn load_groups(rows : &[Vec<AlbaTypes>], bulk : bool) -> Arc<Mutex<Container>>{
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("group",AlbaTypes::Int(0))]).await;
+        let mut c = container.lock().await;
+        c.create_index("group", IndexKind::Ordered).await.unwrap();
+        if bulk{
+            c.bulk_load(rows.to_vec()).await.unwrap();
+        }else{
+            c.push_rows(rows.to_vec()).await.unwrap();
+            c.commit().await.unwrap();
+        }
+        drop(c);
+        container
+    }
+
+    fn groups(count : i64) -> Vec<Vec<AlbaTypes>>{
+        (0..count).map(|id|vec![AlbaTypes::Bigint(id),AlbaTypes::Int((id % 7) as i32)]).collect()
+    }
+
+    /// Loads the same rows through `bulk_load` and through `push_rows` and `commit`.
+    #[tokio::test]
+    async fn bulk_load_matches_the_incremental_path(){
+        let rows = groups(20_000);
+        let bulk = load_groups(&rows, true).await;
+        let incremental = load_groups(&rows, false).await;
+        let (mut b,i) = (bulk.lock().await,incremental.lock().await);
+        assert_eq!(b.index_map.lock().await.len(), 20_000);
+        for id in 0..20_000i64{
+            let offset = b.index_map.lock().await.get(&[AlbaTypes::Bigint(id)]).unwrap().unwrap();
+            let other = i.index_map.lock().await.get(&[AlbaTypes::Bigint(id)]).unwrap().unwrap();
+            assert_eq!(b.read_row_at(offset).await.unwrap().unwrap().data, i.read_row_at(other).await.unwrap().unwrap().data);
+        }
+        for group in 0..7{
+            let loaded = b.secondary_indexes.lock().await.get("group").unwrap().lookup(&AlbaTypes::Int(group)).len();
+            assert_eq!(loaded, i.secondary_indexes.lock().await.get("group").unwrap().lookup(&AlbaTypes::Int(group)).len());
+        }
+        // a key already loaded aborts the next load before anything is written
+        let e = TytoError::from(b.bulk_load(vec![vec![AlbaTypes::Bigint(20_000),AlbaTypes::Int(0)],vec![AlbaTypes::Bigint(5),AlbaTypes::Int(0)]]).await.unwrap_err());
+        assert!(matches!(e, TytoError::Conflict(_)));
+        assert_eq!(b.row_count().await.unwrap(), 20_000);
+    }
+
+    /// Load time of `bulk_load` against `push_rows` and `commit`, the bulk path must not be the slower one.
+    /// `cargo test --release bulk_load_throughput -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bulk_load_throughput(){
+        let rows = groups(200_000);
+        let mut best = [f64::MAX;2];
+        for _ in 0..3{
+            for (n,bulk) in [true,false].into_iter().enumerate(){
+                let start = std::time::Instant::now();
+                load_groups(&rows, bulk).await;
+                best[n] = best[n].min(start.elapsed().as_secs_f64());
+            }
+        }
+        println!("bulk load {:.1} ms, push_rows and commit {:.1} ms", best[0]*1000.0, best[1]*1000.0);
+        assert!(best[0] <= best[1]);
+    }
+
+    #[tokio::test]
+    async fn the_mvcc_record_round_trips(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new())),("flags",AlbaTypes::Int(0))]).await;
+        let mut c = container.lock().await;
+        let size = c.element_size as u64;
+        // the last byte of the row is the top byte of `flags`
+        let changes = vec![
+            (0,MvccState::Insert,vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("first".to_string()),AlbaTypes::Int(i32::MIN)]),
+            (size,MvccState::Edit,vec![AlbaTypes::Bigint(2),AlbaTypes::SmallString("second".to_string()),AlbaTypes::Int(0x7f00_0001)]),
+            (7*size,MvccState::Delete,vec![AlbaTypes::Bigint(8),AlbaTypes::NONE,AlbaTypes::Int(-1)]),
+        ];
+        for (key,state,row) in changes.iter().cloned(){
+            c.record_mvcc(key, row, state).await.unwrap();
+        }
+        c.load_mvcc().await.unwrap();
+        let recovered = c.parked.remove(&RECOVERED_SESSION).unwrap();
+        assert_eq!(recovered.0.len(), changes.len());
+        for (key,state,row) in changes{
+            let (recovered_state,recovered_row) = recovered.0.get(&key).unwrap();
+            assert_eq!(std::mem::discriminant(recovered_state), std::mem::discriminant(&state));
+            assert_eq!(recovered_row, &row);
+        }
+    }
+
+    #[tokio::test]
+    async fn values_are_staged_as_the_columns_store_them(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new())),("bio",AlbaTypes::Text(String::new()))]).await;
+        let mut c = container.lock().await;
+        // the types text statements and the network protocol decode literals to
+        c.push_row(vec![AlbaTypes::Int(1),AlbaTypes::LargeString("a".to_string()),AlbaTypes::LargeString("b".to_string())]).await.unwrap();
+        assert!(c.push_row(vec![AlbaTypes::Bigint(1),AlbaTypes::NONE,AlbaTypes::NONE]).await.is_err());
+        c.commit().await.unwrap();
+        let offset = c.index_map.lock().await.get(&[AlbaTypes::Bigint(1)]).unwrap().unwrap();
+        assert_eq!(c.read_row_at(offset).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("a".to_string()),AlbaTypes::Text("b".to_string())]);
+        assert!(c.push_rows(vec![vec![AlbaTypes::Int(1),AlbaTypes::NONE,AlbaTypes::NONE]]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn slots_other_sessions_took_read_as_empty(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0))]).await;
+        let mut c = container.lock().await;
+        let size = c.element_size as u64;
+        c.switch_session(1).await;
+        c.push_row(vec![AlbaTypes::Bigint(1)]).await.unwrap();
+        c.switch_session(2).await;
+        c.push_row(vec![AlbaTypes::Bigint(2)]).await.unwrap();
+        c.commit().await.unwrap();
+        assert!(c.read_row_at(0).await.unwrap().is_none());
+        assert_eq!(c.read_row_at(size).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(2)]);
+        c.switch_session(1).await;
+        c.commit().await.unwrap();
+        assert_eq!(c.read_row_at(0).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(1)]);
+    }
+
+    #[tokio::test]
+    async fn a_key_staged_twice_is_rejected(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new()))]).await;
+        let mut c = container.lock().await;
+        c.push_row(vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("first".to_string())]).await.unwrap();
+        let e = c.push_row(vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("second".to_string())]).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+        // staged by another session counts as well
+        c.switch_session(1).await;
+        assert!(c.push_row(vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("third".to_string())]).await.is_err());
+        assert!(c.push_rows(vec![vec![AlbaTypes::Bigint(1),AlbaTypes::SmallString("fourth".to_string())]]).await.is_err());
+        c.switch_session(0).await;
+        c.commit().await.unwrap();
+        let offset = c.index_map.lock().await.get(&[AlbaTypes::Bigint(1)]).unwrap().unwrap();
+        assert_eq!(c.read_row_at(offset).await.unwrap().unwrap().data[1], AlbaTypes::SmallString("first".to_string()));
+    }
+
+    /// Fails every write while its flag is set, standing in for a disk that stopped taking them.
+    #[derive(Debug)]
+    struct FailingWrites(Box<dyn Storage>, Arc<AtomicBool>);
+
+    impl Storage for FailingWrites{
+        fn read_exact_at(&self, buffer : &mut [u8], offset : u64) -> Result<(),Error>{
+            self.0.read_exact_at(buffer, offset)
+        }
+        fn write_all_at(&self, buffer : &[u8], offset : u64) -> Result<(),Error>{
+            if self.1.load(Ordering::SeqCst){
+                return Err(Error::other("injected write failure"))
+            }
+            self.0.write_all_at(buffer, offset)
+        }
+        fn set_len(&self, len : u64) -> Result<(),Error>{
+            self.0.set_len(len)
+        }
+        fn sync_all(&self) -> Result<(),Error>{
+            self.0.sync_all()
+        }
+        fn len(&self) -> Result<u64,Error>{
+            self.0.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_write_fails_the_commit_and_keeps_the_changes(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0))]).await;
+        let mut c = container.lock().await;
+        let failing = Arc::new(AtomicBool::new(true));
+        {
+            let mut file = c.file.lock().await;
+            let inner = std::mem::replace(&mut *file, Box::new(crate::storage::MemoryFile::default()));
+            *file = Box::new(FailingWrites(inner, failing.clone()));
+        }
+        c.push_row(vec![AlbaTypes::Bigint(1)]).await.unwrap();
+        assert!(c.commit().await.is_err());
+        assert_eq!(c.mvcc.lock().await.0.len(), 1);
+        assert_eq!(c.index_map.lock().await.get(&[AlbaTypes::Bigint(1)]).unwrap(), None);
+        // the same changes go through once the writes do
+        failing.store(false, Ordering::SeqCst);
+        c.commit().await.unwrap();
+        assert!(c.mvcc.lock().await.0.is_empty());
+        let offset = c.index_map.lock().await.get(&[AlbaTypes::Bigint(1)]).unwrap().unwrap();
+        assert_eq!(c.read_row_at(offset).await.unwrap().unwrap().data, vec![AlbaTypes::Bigint(1)]);
+    }
+
+    #[tokio::test]
+    async fn uuid_keys_are_looked_up_by_their_bytes(){
+        let container = memory_container(&[("id",AlbaTypes::Uuid([0;16])),("name",AlbaTypes::SmallString(String::new()))]).await;
+        let mut c = container.lock().await;
+        let ids : Vec<[u8;16]> = (0..3u8).map(|i|[i;16]).collect();
+        c.push_rows(ids.iter().map(|id|vec![AlbaTypes::Uuid(*id),AlbaTypes::SmallString(format!("{:?}",id[0]))]).collect()).await.unwrap();
+        assert!(c.push_row(vec![AlbaTypes::Uuid(ids[1]),AlbaTypes::NONE]).await.is_err());
+        c.commit().await.unwrap();
+        for id in ids{
+            let offset = c.index_map.lock().await.get(&[AlbaTypes::Uuid(id)]).unwrap().unwrap();
+            assert_eq!(c.read_row_at(offset).await.unwrap().unwrap().data, vec![AlbaTypes::Uuid(id),AlbaTypes::SmallString(format!("{:?}",id[0]))]);
+        }
+        assert_eq!(c.index_map.lock().await.get(&[AlbaTypes::Uuid([9;16])]).unwrap(), None);
+    }
+
+    /// Pushes and commits `text` into both string columns, returning the stored row read back from the file.
+    async fn round_trip(c : &mut Container, id : i64, text : Option<&str>) -> Vec<AlbaTypes>{
+        let row = match text{
+            Some(text) => vec![AlbaTypes::Bigint(id),AlbaTypes::SmallString(text.to_string()),AlbaTypes::Text(text.to_string())],
+            None => vec![AlbaTypes::Bigint(id),AlbaTypes::NONE,AlbaTypes::NONE]
+        };
+        c.push_row(row).await.unwrap();
+        c.commit().await.unwrap();
+        let offset = c.index_map.lock().await.get(&[AlbaTypes::Bigint(id)]).unwrap().unwrap();
+        c.read_row_at(offset).await.unwrap().unwrap().data
+    }
+
+    #[tokio::test]
+    async fn empty_strings_stay_distinct_from_null_by_default(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new())),("bio",AlbaTypes::Text(String::new()))]).await;
+        let mut c = container.lock().await;
+        let row = round_trip(&mut c, 1, Some("")).await;
+        assert_eq!(row[1..], [AlbaTypes::SmallString(String::new()),AlbaTypes::Text(String::new())]);
+        let row = round_trip(&mut c, 2, None).await;
+        assert_eq!(row[1..], [AlbaTypes::NONE,AlbaTypes::NONE]);
+    }
+
+    #[tokio::test]
+    async fn empty_strings_are_stored_as_null_when_asked(){
+        let options = ContainerOptions{empty_as_null: vec!["name".to_string(),"bio".to_string()], ..Default::default()};
+        let container = memory_container_with(&[("id",AlbaTypes::Bigint(0)),("name",AlbaTypes::SmallString(String::new())),("bio",AlbaTypes::Text(String::new()))], options).await;
+        let mut c = container.lock().await;
+        let row = round_trip(&mut c, 1, Some("")).await;
+        assert_eq!(row[1..], [AlbaTypes::NONE,AlbaTypes::NONE]);
+        let row = round_trip(&mut c, 2, Some("ana")).await;
+        assert_eq!(row[1..], [AlbaTypes::SmallString("ana".to_string()),AlbaTypes::Text("ana".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn only_string_columns_hold_null(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("age",AlbaTypes::Int(0))]).await;
+        let c = container.lock().await;
+        assert!(c.serialize_row(&[AlbaTypes::Bigint(1),AlbaTypes::NONE]).is_err());
+    }
+
+    #[tokio::test]
+    async fn every_type_round_trips(){
+        let row = vec![
+            AlbaTypes::Bigint(-(1 << 40)),
+            AlbaTypes::Int(-123456),
+            AlbaTypes::Float(-1.5e-3),
+            AlbaTypes::Bool(true),
+            AlbaTypes::Char('é'),
+            AlbaTypes::Text("ünïcode ".repeat(100)),
+            AlbaTypes::NanoString("nano".to_string()),
+            AlbaTypes::SmallString("small".to_string()),
+            AlbaTypes::MediumString("medium".to_string()),
+            AlbaTypes::BigString("big".to_string()),
+            AlbaTypes::LargeString("large".to_string()),
+            AlbaTypes::NanoBytes(vec![1,2,3]),
+            AlbaTypes::SmallBytes(vec![]),
+            AlbaTypes::MediumBytes(vec![0;500]),
+            AlbaTypes::BigSBytes(vec![255;9]),
+            AlbaTypes::LargeBytes(vec![7;1000]),
+            AlbaTypes::Uuid([9;16]),
+            AlbaTypes::Timestamp(1_700_000_000_000_000),
+            AlbaTypes::Decimal("-12.345".parse().unwrap()),
+        ];
+        let columns : Vec<(String,AlbaTypes)> = row.iter().enumerate().map(|(n,v)|(format!("c{}",n),v.clone())).collect();
+        let columns : Vec<(&str,AlbaTypes)> = columns.iter().map(|c|(c.0.as_str(),c.1.clone())).collect();
+        let container = memory_container(&columns).await;
+        let c = container.lock().await;
+        let bytes = c.serialize_row(&row).unwrap();
+        assert_eq!(bytes.len(), c.element_size);
+        assert_eq!(c.decode_row(&bytes).unwrap(), row);
+    }
+
+    #[test]
+    fn legacy_rows_are_converted(){
+        let columns = vec![AlbaTypes::Bigint(0),AlbaTypes::Int(0),AlbaTypes::Float(0.0),AlbaTypes::Char('\0'),AlbaTypes::NanoString(String::new()),AlbaTypes::NanoBytes(Vec::new())];
+        let mut slot = Vec::new();
+        slot.extend_from_slice(&42i64.to_be_bytes());
+        slot.extend_from_slice(&(-7i32).to_be_bytes());
+        slot.extend_from_slice(&2.5f64.to_be_bytes());
+        slot.extend_from_slice(&('ß' as u32).to_le_bytes());
+        // format 0 kept the untruncated length of strings, big-endian
+        slot.extend_from_slice(&12u64.to_be_bytes());
+        slot.extend_from_slice(b"abcdefghij");
+        slot.extend_from_slice(&2u64.to_le_bytes());
+        slot.extend_from_slice(&[5,6]);
+        slot.resize(slot.len() + 8, 0);
+        let element_size = slot.len();
+        let backend = MemoryBackend::default();
+        let file = backend.open("").unwrap();
+        let mut content = vec![255u8;element_size];
+        content.extend_from_slice(&slot);
+        file.write_all_at(&content, 0).unwrap();
+        let body = convert_legacy_rows(&*file, None, &columns, 0, 4096).unwrap();
+        let expected = vec![AlbaTypes::Bigint(42),AlbaTypes::Int(-7),AlbaTypes::Float(2.5),AlbaTypes::Char('ß'),AlbaTypes::NanoString("abcdefghij".to_string()),AlbaTypes::NanoBytes(vec![5,6])];
+        assert_eq!(body, serialize_with(None, &expected, &columns).unwrap());
+    }
+
+    fn heap_len(c : &Container) -> u64{
+        let heap = c.text_heap.as_ref().unwrap();
+        heap.ends.iter().map(|e|e.load(Ordering::SeqCst)).sum()
+    }
+
+    #[tokio::test]
+    async fn text_reaches_the_heap_only_on_commit(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("bio",AlbaTypes::Text(String::new()))]).await;
+        let mut c = container.lock().await;
+        c.push_row(vec![AlbaTypes::Bigint(1),AlbaTypes::Text("staged".to_string())]).await.unwrap();
+        assert_eq!(heap_len(&c), 0);
+        // the record keeps the text itself, so recovering the staged row needs no heap
+        c.load_mvcc().await.unwrap();
+        let recovered = c.parked.remove(&RECOVERED_SESSION).unwrap();
+        assert_eq!(recovered.0.values().next().unwrap().1, vec![AlbaTypes::Bigint(1),AlbaTypes::Text("staged".to_string())]);
+        c.commit().await.unwrap();
+        assert_eq!(heap_len(&c), 6);
+    }
+
+    #[tokio::test]
+    async fn vacuum_compacts_the_text_heap(){
+        let container = memory_container(&[("id",AlbaTypes::Bigint(0)),("bio",AlbaTypes::Text(String::new()))]).await;
+        let mut c = container.lock().await;
+        let size = c.element_size as u64;
+        c.push_rows((0..3).map(|id|vec![AlbaTypes::Bigint(id),AlbaTypes::Text(format!("text {}",id))]).collect()).await.unwrap();
+        c.commit().await.unwrap();
+        c.stage(MvccState::Delete, vec![(0,vec![AlbaTypes::Bigint(0),AlbaTypes::Text("text 0".to_string())])]).await.unwrap();
+        c.stage(MvccState::Edit, vec![(size,vec![AlbaTypes::Bigint(1),AlbaTypes::Text("edited".to_string())])]).await.unwrap();
+        c.commit().await.unwrap();
+        assert_eq!(heap_len(&c), 3*6 + 6);
+        for _ in 0..2{
+            c.vacuum(&VacuumProgress::default()).await.unwrap();
+            assert_eq!(heap_len(&c), 2*6);
+            let mut texts = Vec::new();
+            for slot in 0..2{
+                texts.push(c.read_row_at(slot*size).await.unwrap().unwrap().data[1].clone());
+            }
+            texts.sort_by_key(|t|format!("{:?}",t));
+            assert_eq!(texts, vec![AlbaTypes::Text("edited".to_string()),AlbaTypes::Text("text 2".to_string())]);
+        }
+    }
+}
@@ -1,11 +1,14 @@
-use std::{collections::HashMap, fs::{self, File}, io::{Error, ErrorKind, Read, Write}, os::{raw::c_int, unix::fs::FileExt}, path::PathBuf, pin::Pin, sync::Arc};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::{self, File}, io::{Error, ErrorKind, Read, Write}, path::PathBuf, pin::Pin, sync::{atomic::Ordering, Arc}};
+#[cfg(target_os = "linux")]
+use std::os::{fd::AsRawFd, raw::c_int};
 
 use serde::{Deserialize, Serialize};
 use serde_yaml;
-use crate::{alba_types::AlbaTypes, container::{Container,MvccState}, gerr, logerr, query::{search, Query, SearchArguments}, query_conditions::QueryConditions, row::Row, AstCommit, AstCreateRow, AstDeleteContainer, AstDeleteRow, AstEditRow, AstRollback, AstSearch, Token, AST};
+use crate::{aggregate::Accumulator, error::TytoError, keystore::Keystore, alba_types::{format_timestamp, format_uuid, AlbaTypes}, container::{convert_legacy_rows, ordered_key, Container, ContainerOptions, ContainerTuning, VacuumProgress, MAX_GRAVEYARD_LENGTH_IN_MEMORY, MAX_VACUUM_LENGTH, VACCUM_SIZE, PendingMark, RECOVERED_SESSION, TextHeap, MvccState, GRAVEYARD_SUFFIX, MVCC_RECORD_SUFFIX, SEQUENCE_SUFFIX, TEXT_HEAP_ALT_SUFFIX, TEXT_HEAP_SUFFIX}, indexing::{IndexKind, HASHMAP_SUFFIX, HASHMAP_TEMP_SUFFIX, ORDERED_INDEX_SUFFIX, ORDERED_INDEX_TEMP_SUFFIX, SECONDARY_INDEX_SUFFIX, SECONDARY_INDEX_TEMP_SUFFIX}, gerr, logdebug, logerr, loginfo, logwarn, query::{count, explain, search, PrimitiveQueryConditions, Query, SearchArguments}, query_conditions::{QueryConditions, QueryType}, rate_limit::{RateLimit, RateLimiters}, result_cache::ResultCache, row::Row, storage::{set_group_commit_window, DiskBackend, Durability, Storage, SyncTicket}, AstCommit, AstCreateRow, AstCreateRows, AstDeleteContainer, AstDeleteRow, AstEditRow, AstRollback, AstSearch, AlterChange, Token, AST};
 use rand::{rngs::OsRng, Rng, TryRngCore};
 use tokio::sync::Mutex;
-use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use std::time::Instant;
 
 
 
@@ -13,10 +16,16 @@ use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime};
 /////////     DEFAULT_SETTINGS    ///////////////
 /////////////////////////////////////////////////
 
-pub const MAX_STR_LEN : usize = 256;
 const DEFAULT_SETTINGS: &str = r#"
 # Delete the comments if the size of the config file bothers you ;)
 
+# Read Only
+# + Rejects every statement that changes data: creating, editing or deleting rows and containers, loads, imports, restores, migrations,
+# + recovery commits and rollbacks, and vacuums. Searches, exports and backups keep working.
+# + Scheduled and automatic vacuums and the trash purge don't run, and recovered changes are kept whatever the recovery setting says.
+# + Meant for replicas and for inspecting production data without the risk of an accidental write.
+read_only: false
+
 # Container Specs
 # + The following configurations are optional but available for customization.
 # + The size of container metadata does not change based on these settings.
@@ -24,6 +33,11 @@ const DEFAULT_SETTINGS: &str = r#"
 max_columns: 125
 min_columns: 1
 
+# Conditions
+# + The maximum number of conditions a single query may carry, every one of them is evaluated against every row scanned.
+# + Queries above it are rejected before anything is read.
+max_conditions: 64
+
 # Connection
 # + These settings define the network address where the database will listen for incoming connections.
 # + I personally recommend keeping the database running locally, rather than exposing it to WAN traffic.
@@ -38,14 +52,128 @@ port: 4287
 # + It is recommended to adjust this based on the expected number of simultaneous client connections.
 workers: 1
 
+# Scan Threads
+# + Full container scans are split into this many ranges, decoded and filtered at once on a shared pool of scan threads.
+# + 1 scans on the connection's own task. 0 uses the number of workers. Searches answered from an index are not affected.
+# + A parallel scan reads ahead by up to 16 chunks of 40KB per thread, so it pays off on large unfiltered scans, not on small LIMITs.
+scan_threads: 1
+
+# Memory Mapped Scans
+# + Full scans read the container file through a memory map instead of copying it into buffers, saving allocations and syscalls.
+# + Mostly worth it for read heavy workloads on containers that fit in the page cache.
+mmap_scans: false
+
+# Result Cache
+# + Keeps the results of this many recent searches and answers identical searches from memory.
+# + A container's results are dropped as soon as anything is committed, loaded, truncated or vacuumed in it.
+# + Searches on a snapshot are never cached. 0 disables the cache.
+result_cache_size: 0
+
+# Query Timeout
+# + Statements that read or stage changes are abandoned after running for this many milliseconds and answered with a timeout error.
+# + Rows a statement staged before being abandoned are dropped, changes staged by earlier statements stay pending.
+# + Commits, vacuums and other statements that write files always run to completion. 0 disables the timeout.
+# + A client can override it for a single request, see QUERY_TIMEOUT.
+query_timeout: 0
+
+# Transaction Isolation
+# + What the searches inside a transaction see, writes always apply to the latest committed rows.
+# + read_committed: every search sees the rows committed when it runs, the transaction's own staged changes are not visible.
+# + snapshot: every search sees the rows as they were committed when the transaction started, with the changes it staged so far laid over them.
+# + A client can pick the level for a single transaction, see TRANSACTION_ISOLATION.
+transaction_isolation: read_committed
+
+# Crash Recovery
+# + Changes are written to the container's .mr record and synced before the statement staging them returns.
+# + A commit writes its rows to the container file and syncs it before emptying the record, once it returns the rows are durable.
+# + Changes still in the record on start were staged but never committed, this decides what happens to them.
+# + keep: they stay pending apart from every session, RECOVERY STATUS lists them and RECOVERY COMMIT or RECOVERY ROLLBACK settles them.
+# + commit: they are committed while the containers are loaded.
+recovery: keep
+
+# Durability
+# + How far commits, staged changes and index updates are pushed to the disk before the statement returns.
+# + full: data and metadata are synced, nothing acknowledged is ever lost. The safest and the slowest.
+# + data_only: only the data is synced (fdatasync), a power loss can lose a change that grew a file.
+# + async: the sync runs in the background, a process crash loses nothing but a power loss can lose the last changes.
+# + off: nothing is synced, the OS writes back whenever it decides. Only for data that can be rebuilt.
+# + Vacuums, bulk loads, truncates, migrations and backups always sync fully.
+durability: full
+
+# Group Commit
+# + Commits made within this many microseconds of each other share a single sync of the container file and its primary index.
+# + Each client is only answered once the shared sync is done, so nothing acknowledged is less durable than with its own sync.
+# + Raises throughput under many concurrent writers at the cost of up to this much latency per commit.
+# + Only applies to the full and data_only durability levels. 0 syncs every commit on its own.
+group_commit_window: 0
+
 # Scheduled Vacuum
 # + Vacuuming can only be done as a scheduled operation.
 # + This step is optional and primarily helps reclaim disk space. If your graveyard has been used properly, you might already be in a good state.
 # + The process may be extremely slow, as it is intentionally throttled to preserve data durability.
 # + You can configure which containers should be vacuumed.
 # + Disk space will not increase during this operation, as it does not create temporary files by design.
+# + Besides the formats in the documentation, a schedule can be a 5 field cron expression, "30 3 * * 1-5" runs at 03:30 on weekdays.
+# + Or a day and a time, like "Mon 02:00:00", "Tue,Fri 02:00:00", "weekdays 03:00:00" or "weekends 04:00:00".
+# + A container with changes pending in any session is skipped until its next scheduled time, so open transactions are never lost.
 # - For more detailed information, read the documentation.
 vacuum: []
+
+# Automatic Vacuum
+# + After a commit, containers whose share of dead slots (deleted rows not reused yet) is above auto_vacuum_threshold are vacuumed in the background.
+# + The threshold is a fraction, 0.3 vacuums once 30% of the slots are dead. 0 disables automatic vacuums.
+# + A container is vacuumed automatically at most once every auto_vacuum_interval seconds.
+# + Containers with changes pending in any session are skipped, since a vacuum would throw those changes away.
+auto_vacuum_threshold: 0.0
+auto_vacuum_interval: 3600
+
+# Vacuum and Graveyard Sizes
+# + max_graveyard_length: free slots each container remembers, in memory and in its .graveyard file. Inserts reuse them before growing the file.
+# + vacuum_chunk_size: bytes a vacuum, and the scans done when a container is loaded, read at once. At least 4096.
+# + max_vacuum_pairs: rows a single vacuum relocates at most, the rest is left for the next run.
+# + Raising them trades memory for space reuse and fewer, larger reads. Changes apply to containers loaded afterwards.
+max_graveyard_length: 1250
+vacuum_chunk_size: 4194304
+max_vacuum_pairs: 625000
+
+# Schedule Timezone
+# + The timezone the clock times of the vacuum schedules are read in, an IANA name like "Europe/Lisbon" or an offset like "+05:30".
+# + Leave it empty to use the machine's local time.
+schedule_timezone: ""
+
+# Trash
+# + Deleted containers are moved to the .trash directory instead of being erased, so they can be restored.
+# + They are purged for good once they've been there for this many seconds, checked by the same scheduler as the vacuum.
+# + Set it to 0 to erase containers right away.
+trash_retention: 604800
+
+# Snapshots
+# + A snapshot lets several searches read the database exactly as it was when the snapshot was opened.
+# + While a snapshot is open every commit keeps a copy of the rows it overwrites, so abandoned snapshots cost memory.
+# + Snapshots that are not used for this many seconds are closed automatically.
+snapshot_timeout: 300
+
+# Cursors
+# + A search with PAGE returns its rows a page at a time, the cursor remembers where the previous page stopped.
+# + Cursors that are not read for this many seconds are closed automatically.
+cursor_timeout: 300
+
+# Column Access
+# + Restricts which columns a principal may read, per container. Containers a principal doesn't list stay fully readable.
+# + A restricted principal can neither select nor filter on a column outside its list.
+# + A client's principal is the name of the key in keys.yaml it signs its requests with. Without any keys every client is unrestricted.
+# column_access:
+#   reports:
+#     employees: ["id", "name"]
+column_access: {}
+
+# Rate Limits
+# + Caps how often a principal may run full container scans and VACUUM ALL, the most expensive things a client can ask for.
+# + per_second is the sustained rate and burst how many of them can run back to back before being throttled, scans and vacuums share it.
+# + Scheduled and automatic vacuums are not subject to these limits.
+# rate_limits:
+#   reports: { per_second: 2.0, burst: 5 }
+rate_limits: {}
 "#;
 
 type VacuumSpec = (String,String);
@@ -57,22 +185,150 @@ struct Settings{
     ip:String,
     port: u32,
     workers: u32,
-    vacuum: Vec<VacuumSpec>
+    #[serde(default = "default_scan_threads")]
+    scan_threads: u32,
+    #[serde(default)]
+    mmap_scans: bool,
+    #[serde(default)]
+    result_cache_size: usize,
+    #[serde(default)]
+    query_timeout: u64,
+    #[serde(default)]
+    transaction_isolation: Isolation,
+    #[serde(default)]
+    recovery: Recovery,
+    #[serde(default)]
+    durability: Durability,
+    #[serde(default)]
+    group_commit_window: u64,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    schedule_timezone: String,
+    vacuum: Vec<VacuumSpec>,
+    #[serde(default = "default_trash_retention")]
+    trash_retention: u64,
+    #[serde(default = "default_max_conditions")]
+    max_conditions: usize,
+    #[serde(default = "default_max_graveyard_length")]
+    max_graveyard_length: usize,
+    #[serde(default = "default_vacuum_chunk_size")]
+    vacuum_chunk_size: u64,
+    #[serde(default = "default_max_vacuum_pairs")]
+    max_vacuum_pairs: usize,
+    #[serde(default)]
+    auto_vacuum_threshold: f64,
+    #[serde(default = "default_auto_vacuum_interval")]
+    auto_vacuum_interval: u64,
+    #[serde(default = "default_snapshot_timeout")]
+    snapshot_timeout: u64,
+    #[serde(default = "default_cursor_timeout")]
+    cursor_timeout: u64,
+    #[serde(default)]
+    column_access: HashMap<String,HashMap<String,Vec<String>>>,
+    #[serde(default)]
+    rate_limits: HashMap<String,RateLimit>,
+}
+
+impl Settings{
+    fn tuning(&self) -> ContainerTuning{
+        ContainerTuning{max_graveyard_length:self.max_graveyard_length,vacuum_chunk_size:self.vacuum_chunk_size,max_vacuum_pairs:self.max_vacuum_pairs}
+    }
+}
+
+/// What happens on start to the changes that were staged but not committed when the database last stopped.
+/// Staged changes are synced to the container's `.mr` record before their statement returns,
+/// and a commit only empties the record once its rows are written to the container file and synced.
+#[derive(Serialize,Deserialize,Clone,Copy,Debug,Default,PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Recovery{
+    /// They stay pending, apart from every session, until RECOVERY COMMIT or RECOVERY ROLLBACK.
+    #[default]
+    Keep,
+    /// They are committed as soon as the containers are loaded.
+    Commit,
+}
+
+/// What the searches of a transaction see. Writes always apply to the latest committed rows.
+#[derive(Serialize,Deserialize,Clone,Copy,Debug,Default,PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Isolation{
+    /// Every search sees the rows committed when it runs, the changes staged by the transaction are not visible.
+    #[default]
+    ReadCommitted,
+    /// Every search sees the rows as they were committed when the transaction started,
+    /// with the changes the transaction staged so far laid over them.
+    Snapshot,
 }
 
+fn default_scan_threads() -> u32{1}
+fn default_snapshot_timeout() -> u64{300}
+fn default_cursor_timeout() -> u64{300}
+fn default_max_conditions() -> usize{64}
+const DEFAULT_IP : &str = "127.0.0.1";
+const DEFAULT_PORT : u32 = 4287;
+fn default_max_graveyard_length() -> usize{MAX_GRAVEYARD_LENGTH_IN_MEMORY}
+fn default_vacuum_chunk_size() -> u64{VACCUM_SIZE}
+fn default_max_vacuum_pairs() -> usize{MAX_VACUUM_LENGTH}
+fn default_auto_vacuum_interval() -> u64{3600}
+/// Smallest `vacuum_chunk_size` accepted, a single page.
+const MIN_VACUUM_CHUNK_SIZE : u64 = 4096;
+fn default_trash_retention() -> u64{604800}
+
 
 
 
-const SECRET_KEY_PATH : &str = "TytoDB/.secret";
+const SECRET_KEY_PATH : &str = ".secret";
+/// Named client keys, see `Keystore`. Without this file clients only need the shared secret.
+const KEYS_PATH : &str = "keys.yaml";
+/// Data directory under `$HOME` used when `DATA_DIR_ENV` is not set.
 pub const DATABASE_PATH : &str = "TytoDB";
+/// Environment variable holding the data directory, e.g. `TYTODB_DATA_DIR=/var/lib/tytodb`.
+pub const DATA_DIR_ENV : &str = "TYTODB_DATA_DIR";
+/// Environment variable holding the path of the network secret, which defaults to `.secret` in the data directory.
+pub const SECRET_PATH_ENV : &str = "TYTODB_SECRET_PATH";
 
-pub fn database_path() -> String{
-    let first = std::env::var("HOME").unwrap();
-    return format!("{}/{}",first,DATABASE_PATH)
+/// A client that puts this byte before a command accepts an LZ4 compressed response.
+const ACCEPTS_COMPRESSION : u8 = 0xFE;
+/// A client that puts this byte and a big endian u32 of milliseconds before a command runs it with that timeout
+/// instead of `query_timeout`, 0 runs it without one.
+const QUERY_TIMEOUT : u8 = 0xFD;
+/// A client that puts this byte and a level before a transaction runs it at that level instead of `transaction_isolation`,
+/// 0 for READ COMMITTED and 1 for SNAPSHOT.
+const TRANSACTION_ISOLATION : u8 = 0xFC;
+/// A client that puts this byte and a big endian u64 before a command runs it in that session.
+/// Changes staged in a session are only seen, committed and rolled back by requests of the same session,
+/// clients that name no session share session 0.
+const SESSION : u8 = 0xFB;
+/// A client that puts this byte before a UTF-8 statement in the grammar at the top of `main.rs` sends it as text
+/// instead of an encoded command. It must be the last prefix, everything after it is the statement.
+const STATEMENT : u8 = 0xFA;
+/// Status byte of a failed response, followed by an error code and the message.
+const RESPONSE_ERROR : u8 = 1;
+/// Status byte of a successful response whose payload is LZ4 compressed, prefixed by its uncompressed size.
+const RESPONSE_COMPRESSED : u8 = 2;
+/// Responses smaller than this are sent as they are, compressing them costs more than it saves.
+const COMPRESSION_THRESHOLD : usize = 4096;
+
+/// Non empty value of the environment variable `name`.
+fn env_path(name : &str) -> Option<String>{
+    std::env::var(name).ok().filter(|v|!v.is_empty())
+}
+/// The data directory, `DATA_DIR_ENV` if set and `$HOME/TytoDB` otherwise.
+pub fn database_path() -> Result<String,Error>{
+    if let Some(dir) = env_path(DATA_DIR_ENV){
+        return Ok(dir)
+    }
+    match env_path("HOME"){
+        Some(home) => Ok(format!("{}/{}",home,DATABASE_PATH)),
+        None => Err(Error::new(ErrorKind::NotFound, format!("Cannot locate the data directory, set {} or HOME", DATA_DIR_ENV)))
+    }
 }
-fn secret_key_path() -> String{
-    let first = std::env::var("HOME").unwrap();
-    return format!("{}/{}",first,SECRET_KEY_PATH)
+fn secret_key_path(location : &str) -> String{
+    env_path(SECRET_PATH_ENV).unwrap_or_else(||format!("{}/{}",location,SECRET_KEY_PATH))
+}
+fn keys_path(location : &str) -> String{
+    format!("{}/{}",location,KEYS_PATH)
 }
 /////////////////////////////////////////////////
 /////////////////////////////////////////////////
@@ -83,8 +339,112 @@ pub enum Schedule {
     Duration(Duration), // For "X minutes/hours/months/years/decades"
     NextTime(Duration), // For "HH:MM:SS"
     NextMonthDayTime(u8, u8, NaiveTime, Duration), // For "M/D HH:MM:SS"
+    NextWeekdayTime(u8, NaiveTime, Duration), // For "Mon HH:MM:SS", "Mon,Thu HH:MM:SS", "weekdays HH:MM:SS", "weekends HH:MM:SS", days as a bit set with Sunday at bit 0
     Random(i64, i64), // For "Random N:M"
     Once, // For "Once"
+    Cron(CronSchedule, Duration), // For "minute hour day-of-month month day-of-week"
+}
+
+/// A parsed cron expression, every field is a bit set of the values it accepts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    /// Sunday is bit 0, a 7 in the expression is folded onto it.
+    weekdays: u64,
+    /// Whether the day-of-month and day-of-week fields were something other than `*`.
+    /// When both are, a day matching either of them fires, as in cron.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// Parses one cron field made of comma separated `*`, `n`, `a-b`, each optionally followed by `/step`.
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, ScheduleError> {
+    let mut bits = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| ScheduleError::InvalidNumber)?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err(ScheduleError::InvalidNumber);
+        }
+        let (low, high) = if range == "*" {
+            (min, max)
+        } else if let Some((low, high)) = range.split_once('-') {
+            (low.parse::<u32>().map_err(|_| ScheduleError::InvalidNumber)?, high.parse::<u32>().map_err(|_| ScheduleError::InvalidNumber)?)
+        } else {
+            let value = range.parse::<u32>().map_err(|_| ScheduleError::InvalidNumber)?;
+            // "5/15" runs from 5 to the end of the field
+            (value, if item.contains('/') { max } else { value })
+        };
+        if low < min || high > max || low > high {
+            return Err(ScheduleError::InvalidRange);
+        }
+        for value in (low..=high).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronSchedule {
+    pub fn parse(input: &str) -> Result<Self, ScheduleError> {
+        let fields: Vec<&str> = input.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ScheduleError::InvalidFormat);
+        }
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(CronSchedule {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first minute strictly after `after` the expression fires on, `None` if it never does
+    /// within the next 8 years (like "0 0 30 2 *").
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.date().and_hms_opt(after.hour(), after.minute(), 0)? + Duration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..366 * 8 {
+            if self.matches_day(date) {
+                let (first_hour, first_minute) = if date == start.date() { (start.hour(), start.minute()) } else { (0, 0) };
+                for hour in first_hour..24 {
+                    if self.hours & (1 << hour) == 0 {
+                        continue;
+                    }
+                    let from = if hour == first_hour { first_minute } else { 0 };
+                    if let Some(minute) = (from..60).find(|m| self.minutes & (1 << m) != 0) {
+                        return date.and_hms_opt(hour, minute, 0);
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -93,11 +453,87 @@ pub enum ScheduleError {
     InvalidNumber,
     InvalidDate,
     InvalidRange,
+    InvalidTimezone,
+    UnknownDay,
+}
+
+/// Parses "weekdays", "weekends" or a comma separated list of day names ("Mon", "monday", ...) into a bit set, Sunday being bit 0.
+fn parse_weekdays(input: &str) -> Result<u8, ScheduleError> {
+    match input.to_lowercase().as_str() {
+        "weekdays" => return Ok(0b0111110),
+        "weekends" => return Ok(0b1000001),
+        _ => {}
+    }
+    const NAMES: [(&str, &str); 7] = [("sun", "sunday"), ("mon", "monday"), ("tue", "tuesday"), ("wed", "wednesday"), ("thu", "thursday"), ("fri", "friday"), ("sat", "saturday")];
+    let mut days = 0u8;
+    for name in input.split(',') {
+        let name = name.trim().to_lowercase();
+        let day = NAMES.iter().position(|(short, full)| name == *short || name == *full).ok_or(ScheduleError::UnknownDay)?;
+        days |= 1 << day;
+    }
+    Ok(days)
+}
+
+/// The first time after `now` that falls on one of `days` at `time`, `None` only when `days` is empty.
+fn next_weekday_time(days: u8, time: NaiveTime, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    // the next week always holds a matching day, today's only counts if the time is still ahead
+    (0..=7)
+        .map(|offset| NaiveDateTime::new(now.date() + Duration::days(offset), time))
+        .find(|t| t > &now && days & (1 << t.weekday().num_days_from_sunday()) != 0)
+}
+
+/// Where the clock times of a schedule are read, the machine's local time unless `schedule_timezone` says otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleZone {
+    Local,
+    Named(chrono_tz::Tz),
+    Fixed(FixedOffset),
+}
+
+/// The instant `target` names on the clock of `zone`. A time skipped by a DST change resolves to an hour later,
+/// a repeated one to its first occurrence.
+fn resolve_in<Tz: TimeZone>(zone: &Tz, target: NaiveDateTime) -> Option<DateTime<Utc>> {
+    zone.from_local_datetime(&target).earliest()
+        .or_else(|| zone.from_local_datetime(&(target + Duration::hours(1))).earliest())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+impl ScheduleZone {
+    /// Accepts an IANA name like "Europe/Lisbon" or a fixed offset like "+05:30", an empty string is local time.
+    pub fn parse(input: &str) -> Result<Self, ScheduleError> {
+        let input = input.trim();
+        if input.is_empty() || input.eq_ignore_ascii_case("local") {
+            return Ok(ScheduleZone::Local);
+        }
+        if let Ok(offset) = input.parse::<FixedOffset>() {
+            return Ok(ScheduleZone::Fixed(offset));
+        }
+        input.parse::<chrono_tz::Tz>().map(ScheduleZone::Named).map_err(|_| ScheduleError::InvalidTimezone)
+    }
+
+    fn now(&self) -> NaiveDateTime {
+        match self {
+            ScheduleZone::Local => Local::now().naive_local(),
+            ScheduleZone::Named(tz) => Utc::now().with_timezone(tz).naive_local(),
+            ScheduleZone::Fixed(offset) => Utc::now().with_timezone(offset).naive_local(),
+        }
+    }
+
+    /// Real time left until the clock of this zone shows `target`, which is what the scheduler has to sleep.
+    fn until(&self, target: NaiveDateTime) -> Duration {
+        let instant = match self {
+            ScheduleZone::Local => resolve_in(&Local, target),
+            ScheduleZone::Named(tz) => resolve_in(tz, target),
+            ScheduleZone::Fixed(offset) => resolve_in(offset, target),
+        };
+        instant.map(|t| t.signed_duration_since(Utc::now())).unwrap_or_else(|| target.signed_duration_since(self.now()))
+    }
 }
 
-pub fn parse_schedule(input: &str) -> Result<Schedule, ScheduleError> {
+/// Clock times in `input` are read in `zone`, the returned durations are real time left from now.
+pub fn parse_schedule(input: &str, zone: &ScheduleZone) -> Result<Schedule, ScheduleError> {
     let input = input.trim();
-    let now = Local::now();
+    let now = zone.now();
 
     // Case 1: "X minutes/hours/months/years/decades"
     if let Some((num_str, unit)) = input.split_once(' ') {
@@ -122,15 +558,26 @@ pub fn parse_schedule(input: &str) -> Result<Schedule, ScheduleError> {
 
     // Case 2: "HH:MM:SS"
     if let Ok(time) = NaiveTime::parse_from_str(input, "%H:%M:%S") {
-        let today = now.date_naive();
+        let today = now.date();
         let mut target = NaiveDateTime::new(today, time);
-        if target <= now.naive_local() {
+        if target <= now {
             target = target + Duration::days(1);
         }
-        let duration = target.signed_duration_since(now.naive_local());
+        let duration = zone.until(target);
         return Ok(Schedule::NextTime(duration));
     }
 
+    // Case 3: "Mon HH:MM:SS", "Mon,Thu HH:MM:SS", "weekdays HH:MM:SS", "weekends HH:MM:SS"
+    if let Some((days_str, time_str)) = input.split_once(' ') {
+        if days_str.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            if let Ok(time) = NaiveTime::parse_from_str(time_str.trim(), "%H:%M:%S") {
+                let days = parse_weekdays(days_str)?;
+                let target = next_weekday_time(days, time, now).ok_or(ScheduleError::UnknownDay)?;
+                return Ok(Schedule::NextWeekdayTime(days, time, zone.until(target)));
+            }
+        }
+    }
+
     
 
 
@@ -141,7 +588,7 @@ pub fn parse_schedule(input: &str) -> Result<Schedule, ScheduleError> {
                     return Err(ScheduleError::InvalidDate);
                 }
                 if let Ok(time) = NaiveTime::parse_from_str(time_str, "%H:%M:%S") {
-                    let today = now.date_naive();
+                    let today = now.date();
                     let current_year = today.year();
                     let mut target_date =
                         NaiveDate::from_ymd_opt(current_year, month as u32, day as u32)
@@ -151,12 +598,12 @@ pub fn parse_schedule(input: &str) -> Result<Schedule, ScheduleError> {
                             .ok_or(ScheduleError::InvalidDate)?;
                     }
                     let target = NaiveDateTime::new(target_date, time);
-                    if target <= now.naive_local() {
+                    if target <= now {
                         target_date = NaiveDate::from_ymd_opt(current_year + 1, month as u32, day as u32)
                             .ok_or(ScheduleError::InvalidDate)?;
                     }
                     let final_target = NaiveDateTime::new(target_date, time);
-                    let duration = final_target.signed_duration_since(now.naive_local());
+                    let duration = zone.until(final_target);
                     return Ok(Schedule::NextMonthDayTime(month, day, time, duration));
                 }
             }
@@ -180,6 +627,13 @@ pub fn parse_schedule(input: &str) -> Result<Schedule, ScheduleError> {
     if input.to_lowercase() == "once" {
         return Ok(Schedule::Once);
     }
+
+    // Case 7: cron, "minute hour day-of-month month day-of-week"
+    if input.split_whitespace().count() == 5 {
+        let cron = CronSchedule::parse(input)?;
+        let next = cron.next_after(now).ok_or(ScheduleError::InvalidDate)?;
+        return Ok(Schedule::Cron(cron, zone.until(next)));
+    }
     
     Err(ScheduleError::InvalidFormat)
 }
@@ -188,6 +642,7 @@ pub fn parse_schedule(input: &str) -> Result<Schedule, ScheduleError> {
 /////////////////////////////////////////////////
 /////////////////////////////////////////////////
 
+#[cfg(target_os = "linux")]
 #[repr(C)]
 pub struct WriteEntryC{
     pub buffer : *const u8,
@@ -195,18 +650,24 @@ pub struct WriteEntryC{
     pub offset : i64,
 }
 
-// #[repr(C)]
-// pub struct ReadInstance{
-//     pub size : u64,
-//     pub offset : u64,
-//     pub buffer : *mut u8,
-// }
+#[cfg(target_os = "linux")]
+#[repr(C)]
+pub struct ReadInstance{
+    pub size : u64,
+    pub buffer : *mut u8,
+    pub offset : u64,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+pub struct ReadEntry{
+    pub len : u64,
+    pub buffer_array : *mut ReadInstance,
+}
 
-// #[repr(C)]
-// pub struct ReadEntry{
-//     pub buffer_array : *mut ReadInstance,
-//     pub len : u64
-// }
+/// Most reads submitted to a single ring, larger batches are split.
+#[cfg(target_os = "linux")]
+const BATCH_READ_LIMIT : usize = 3000;
 
 #[derive(Clone)]
 pub struct WriteEntry{
@@ -214,6 +675,7 @@ pub struct WriteEntry{
     pub length : usize,
     pub offset : i64,
 }
+#[cfg(target_os = "linux")]
 impl WriteEntry{
     fn to_c(&self) -> WriteEntryC{
         WriteEntryC{
@@ -224,33 +686,60 @@ impl WriteEntry{
     }
 }
 
+#[cfg(target_os = "linux")]
 #[link(name = "io", kind = "static")]
 unsafe extern "C" {
-    pub unsafe fn batch_write_data_c(buffer: *const WriteEntryC, len: usize, file: c_int) -> i32;
-    // unsafe fn batch_reads(re : *mut ReadEntry,file : i32) -> i32;
-}
-
-// pub fn batch_reads_abs(mut read_instances : Vec<ReadInstance>,file : &File) -> Result<(),Error>{
-//     let mut r = ReadEntry{
-//         len : read_instances.len() as u64,
-//         buffer_array: read_instances.as_mut_ptr()
-//     };
-//     let a : i32 = unsafe{batch_reads(&mut r, file.as_raw_fd().clone())};
-
-//     match a {
-//         0 => Ok(()),
-//         -1 => Err(Error::new(ErrorKind::Other, "Failed to get SQE")),
-//         -2 => Err(Error::new(ErrorKind::Other, "Failed to init queue")),
-//         -3 => Err(Error::new(ErrorKind::Other, "Failed to submit io_uring_submit")),
-//         _ => Err(Error::new(ErrorKind::Other, "Something failed :P")),
-//     }
-// }
-
-pub async fn batch_write_data(entries: Vec<WriteEntry>, len: usize, file: c_int) -> i32 {
+    pub unsafe fn batch_write_data_c(buffer: *const WriteEntryC, len: usize, file: c_int, sync: c_int) -> i32;
+    unsafe fn batch_reads(re : *mut ReadEntry,file : c_int) -> i32;
+}
+
+/// Reads `size` bytes at each of `offsets` through io_uring, returning the buffers in the order of `offsets`.
+#[cfg(target_os = "linux")]
+pub fn batch_reads_abs(offsets : &[u64], size : usize, file : &File) -> Result<Vec<Vec<u8>>,Error>{
+    let mut buffers : Vec<Vec<u8>> = offsets.iter().map(|_|vec![0u8;size]).collect();
+    for (offsets,buffers) in offsets.chunks(BATCH_READ_LIMIT).zip(buffers.chunks_mut(BATCH_READ_LIMIT)){
+        let mut read_instances : Vec<ReadInstance> = offsets.iter().zip(buffers.iter_mut()).map(|(offset,buffer)|ReadInstance{
+            size : size as u64,
+            buffer : buffer.as_mut_ptr(),
+            offset : *offset,
+        }).collect();
+        let mut r = ReadEntry{
+            len : read_instances.len() as u64,
+            buffer_array: read_instances.as_mut_ptr()
+        };
+        let a : i32 = unsafe{batch_reads(&mut r, file.as_raw_fd())};
+
+        match a {
+            0 => {},
+            -1 => return Err(Error::new(ErrorKind::Other, "Failed to get SQE")),
+            -2 => return Err(Error::new(ErrorKind::Other, "Failed to init queue")),
+            -3 => return Err(Error::new(ErrorKind::Other, "Failed to submit io_uring_submit")),
+            -4 => return Err(Error::new(ErrorKind::Other, "Failed to wait for a read to complete")),
+            -5 => return Err(Error::new(ErrorKind::UnexpectedEof, "A batched read failed or came back short")),
+            _ => return Err(Error::new(ErrorKind::Other, format!("Batched read failed with code {}", a))),
+        }
+    }
+    Ok(buffers)
+}
+
+/// Writes every entry at its offset through io_uring, then syncs the file once they are all done:
+/// `sync` 0 skips it, 1 syncs data and metadata, 2 only the data.
+#[cfg(target_os = "linux")]
+pub fn batch_write_data(entries: &[WriteEntry], file: c_int, sync: c_int) -> Result<(),Error> {
     let c_buffer: Vec<WriteEntryC> = entries.iter().map(|f| f.to_c()).collect();
     
-    unsafe {
-        batch_write_data_c(c_buffer.as_ptr(), len, file)
+    let a = unsafe {
+        batch_write_data_c(c_buffer.as_ptr(), c_buffer.len(), file, sync)
+    };
+    match a {
+        0 => Ok(()),
+        -1 => Err(Error::new(ErrorKind::Other, "Failed to get SQE")),
+        -2 => Err(Error::new(ErrorKind::Other, "Failed to init queue")),
+        -3 => Err(Error::new(ErrorKind::Other, "Failed to submit io_uring_submit")),
+        -4 => Err(Error::new(ErrorKind::Other, "Failed to wait for a write to complete")),
+        -5 => Err(Error::new(ErrorKind::WriteZero, "A batched write failed or came back short")),
+        -6 => Err(Error::new(ErrorKind::Other, "Failed to sync the written rows")),
+        _ => Err(Error::new(ErrorKind::Other, format!("Batched write failed with code {}", a))),
     }
 }
 
@@ -261,16 +750,132 @@ pub struct Database{
     containers : Vec<String>,
     headers : Vec<(Vec<String>,Vec<AlbaTypes>)>,
     pub container : HashMap<String,Arc<Mutex<Container>>>,
+    snapshots : HashMap<u64,Instant>,
+    next_snapshot : u64,
+    cursors : HashMap<u64,Cursor>,
+    next_cursor : u64,
+    prepared : HashMap<u64,PreparedStatement>,
+    next_prepared : u64,
+    rate_limiters : RateLimiters,
+    result_cache : ResultCache,
+    /// When the statement being run times out, reads started under it stop there.
+    deadline : Option<Instant>,
+    /// The savepoints of every session, oldest first, see `savepoint`.
+    savepoints : HashMap<u64,Vec<Savepoint>>,
+    /// The session whose changes the containers hold in their `mvcc`, see `use_session`.
+    active_session : u64,
+    /// Progress of the running or last vacuum of each container, by name.
+    vacuums : HashMap<String,Arc<VacuumProgress>>,
+    /// Where commits queue the containers due for an automatic vacuum, set once `run_database` started the worker.
+    auto_vacuum : Option<tokio::sync::mpsc::UnboundedSender<String>>,
+    /// When each container was last queued for an automatic vacuum.
+    auto_vacuumed : HashMap<String,Instant>,
+    /// Rejects every statement that changes data, see `read_only` in the settings.
+    read_only : bool,
+}
+
+/// The pending changes of every container when a savepoint was set.
+#[derive(Debug)]
+struct Savepoint{
+    name : String,
+    marks : HashMap<String,PendingMark>,
+}
+
+/// A search parsed once and run with new values for its `?` placeholders every time it's executed.
+#[derive(Debug)]
+struct PreparedStatement{
+    container : String,
+    conditions : QueryConditions,
+    col_nam : Vec<String>,
+    principal : Option<String>,
+    limit : Option<usize>,
+    offset : Option<usize>,
+    count : bool,
+}
+
+/// Statements a client may keep prepared at once, they live until deallocated.
+const MAX_PREPARED_STATEMENTS : usize = 1024;
+
+/// A search being read one page at a time. The conditions are resolved again for every page,
+/// each page sees what is committed when it is fetched and resumes after the last row of the previous one.
+#[derive(Clone,Debug)]
+struct Cursor{
+    container : String,
+    conditions : PrimitiveQueryConditions,
+    col_nam : Vec<String>,
+    principal : Option<String>,
+    page : usize,
+    /// OFFSET of the search that opened the cursor, only applied to the first page.
+    skip : Option<usize>,
+    after : Option<u64>,
+    used : Instant,
 }
 
 
 const SETTINGS_FILE : &str = "settings.yaml";
+const TRASH_DIR : &str = ".trash";
+const CONTAINERS_FILE : &str = "containers.yaml";
+/// Where `restore` unpacks an archive before adopting it.
+const RESTORE_DIR : &str = ".restore";
+const CONTAINER_FILE_SUFFIXES : [&str;9] = ["", SECONDARY_INDEX_SUFFIX, HASHMAP_SUFFIX, ORDERED_INDEX_SUFFIX, MVCC_RECORD_SUFFIX, TEXT_HEAP_SUFFIX, TEXT_HEAP_ALT_SUFFIX, SEQUENCE_SUFFIX, GRAVEYARD_SUFFIX];
+/// Files a crash can leave behind while an index or a migration is being rewritten, removed along with the container.
+const CONTAINER_TEMP_SUFFIXES : [&str;4] = [HASHMAP_TEMP_SUFFIX, ORDERED_INDEX_TEMP_SUFFIX, SECONDARY_INDEX_TEMP_SUFFIX, ".migrate"];
+/// How often the scheduler wakes up to purge the trash when no vacuum is scheduled.
+const TRASH_PURGE_INTERVAL : u64 = 3600;
+
 
+/// Set on the stored column count when an options section follows the columns.
+const HEADER_OPTIONS_FLAG : u64 = 1 << 63;
+/// Set on the stored column count when the row format version follows it as a little-endian u32.
+/// Files without it are in row format 0 and get upgraded when they are loaded.
+const HEADER_VERSION_FLAG : u64 = 1 << 62;
+/// Row format written by this version: numbers little-endian, string lengths capped at the slot,
+/// Text as a pointer into the text heap and NONE strings marked by `NULL_LENGTH`.
+pub const FORMAT_VERSION : u32 = 1;
+/// Each entry of the options section is `tag, payload length (u64), payload`, the section ends with `HEADER_END`.
+const HEADER_END : u8 = 0;
+const HEADER_PRIMARY_KEY : u8 = 1;
+/// Marks the first column as auto-increment, carries no payload.
+const HEADER_AUTO_INCREMENT : u8 = 2;
+/// Column defaults as a YAML list of `(column, value)` pairs.
+const HEADER_DEFAULTS : u8 = 3;
+const HEADER_NOT_NULL : u8 = 4;
+const HEADER_UNIQUE : u8 = 5;
+/// Kind of the primary key index as a single byte, absent for the hash index.
+const HEADER_PRIMARY_INDEX : u8 = 6;
+const HEADER_EMPTY_AS_NULL : u8 = 7;
+
+fn write_header_names(buffer : &mut Vec<u8>, names : &[String]){
+    buffer.extend_from_slice(&(names.len() as u64).to_le_bytes());
+    for name in names{
+        buffer.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(name.as_bytes());
+    }
+}
+fn read_header_names(payload : &[u8]) -> Result<Vec<String>,Error>{
+    let read_u64 = |at : usize| -> Result<u64,Error>{
+        payload.get(at..at+8).map(|b|u64::from_le_bytes(b.try_into().unwrap())).ok_or(gerr("The container header is truncated"))
+    };
+    let count = read_u64(0)?;
+    let mut cursor = 8;
+    let mut names = Vec::new();
+    for _ in 0..count{
+        let len = read_u64(cursor)? as usize;
+        cursor += 8;
+        let name = payload.get(cursor..cursor+len).ok_or(gerr("The container header is truncated"))?;
+        names.push(String::from_utf8_lossy(name).to_string());
+        cursor += len;
+    }
+    Ok(names)
+}
 
-fn create_container_headers(column_names : Vec<String>,column_values : Vec<AlbaTypes>) -> Vec<u8>{
+/// The options section is only written when some option differs from the defaults.
+fn create_container_headers(column_names : Vec<String>,column_values : Vec<AlbaTypes>,options : &ContainerOptions) -> Vec<u8>{
     let mut byteload : Vec<u8> = Vec::new();
     let len = column_names.len();
-    byteload.extend_from_slice(&(len as u64).to_le_bytes());
+    let extended = *options != ContainerOptions::default();
+    byteload.extend_from_slice(&(len as u64 | HEADER_VERSION_FLAG | if extended {HEADER_OPTIONS_FLAG} else {0}).to_le_bytes());
+    byteload.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
     for i in column_names.into_iter().zip(column_values){
         let size = i.0.len() as u64;
         let mut b = Vec::new();
@@ -279,9 +884,49 @@ fn create_container_headers(column_names : Vec<String>,column_values : Vec<AlbaT
         b.push(i.1.get_id());
         byteload.extend_from_slice(&b);
     }
+    if extended{
+        let mut entries : Vec<(u8,Vec<u8>)> = Vec::new();
+        if !options.primary_key.is_empty(){
+            let mut payload = Vec::new();
+            write_header_names(&mut payload, &options.primary_key);
+            entries.push((HEADER_PRIMARY_KEY,payload));
+        }
+        if options.auto_increment{
+            entries.push((HEADER_AUTO_INCREMENT,Vec::new()));
+        }
+        if !options.defaults.is_empty(){
+            entries.push((HEADER_DEFAULTS,serde_yaml::to_string(&options.defaults).unwrap_or_default().into_bytes()));
+        }
+        if !options.not_null.is_empty(){
+            let mut payload = Vec::new();
+            write_header_names(&mut payload, &options.not_null);
+            entries.push((HEADER_NOT_NULL,payload));
+        }
+        if !options.unique.is_empty(){
+            let mut payload = Vec::new();
+            write_header_names(&mut payload, &options.unique);
+            entries.push((HEADER_UNIQUE,payload));
+        }
+        if options.primary_index != IndexKind::Hash{
+            entries.push((HEADER_PRIMARY_INDEX,vec![options.primary_index.id()]));
+        }
+        if !options.empty_as_null.is_empty(){
+            let mut payload = Vec::new();
+            write_header_names(&mut payload, &options.empty_as_null);
+            entries.push((HEADER_EMPTY_AS_NULL,payload));
+        }
+        for (tag,payload) in entries{
+            byteload.push(tag);
+            byteload.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+            byteload.extend_from_slice(&payload);
+        }
+        byteload.push(HEADER_END);
+    }
     byteload
 }
-fn get_container_headers(file : &File) -> Result<(Vec<String>,Vec<AlbaTypes>,u64),Error>{
+/// Columns, options, offset of the first row and row format version of a container file.
+/// Fails on files written by a newer row format.
+fn get_container_headers(file : &File) -> Result<(Vec<String>,Vec<AlbaTypes>,ContainerOptions,u64,u32),Error>{
     let mut offset = 0u64;
     let column_count = {
         let mut buf = [0u8;8];
@@ -289,6 +934,19 @@ fn get_container_headers(file : &File) -> Result<(Vec<String>,Vec<AlbaTypes>,u64
         offset += 8;
         u64::from_le_bytes(buf)
     };
+    let extended = column_count & HEADER_OPTIONS_FLAG != 0;
+    let version = if column_count & HEADER_VERSION_FLAG != 0{
+        let mut buf = [0u8;4];
+        file.read_exact_at(&mut buf, offset)?;
+        offset += 4;
+        u32::from_le_bytes(buf)
+    }else{
+        0
+    };
+    if version > FORMAT_VERSION{
+        return Err(Error::from(TytoError::Schema(format!("The container was written in row format {}, this version only reads up to {}", version, FORMAT_VERSION))))
+    }
+    let column_count = column_count & !(HEADER_OPTIONS_FLAG | HEADER_VERSION_FLAG);
 
     let mut col_nam = Vec::new();
     let mut col_val = Vec::new();
@@ -312,7 +970,163 @@ fn get_container_headers(file : &File) -> Result<(Vec<String>,Vec<AlbaTypes>,u64
         col_nam.push(column_name);
         col_val.push(column_type);
     }
-    Ok((col_nam,col_val,offset))
+    let mut options = ContainerOptions::default();
+    if extended{
+        loop{
+            let mut tag = [0u8;1];
+            file.read_exact_at(&mut tag, offset)?;
+            offset += 1;
+            if tag[0] == HEADER_END{
+                break;
+            }
+            let mut len = [0u8;8];
+            file.read_exact_at(&mut len, offset)?;
+            offset += 8;
+            let mut payload = vec![0u8;u64::from_le_bytes(len) as usize];
+            file.read_exact_at(&mut payload, offset)?;
+            offset += payload.len() as u64;
+            // unknown tags come from newer versions and are skipped
+            match tag[0]{
+                HEADER_PRIMARY_KEY => options.primary_key = read_header_names(&payload)?,
+                HEADER_AUTO_INCREMENT => options.auto_increment = true,
+                HEADER_NOT_NULL => options.not_null = read_header_names(&payload)?,
+                HEADER_UNIQUE => options.unique = read_header_names(&payload)?,
+                HEADER_EMPTY_AS_NULL => options.empty_as_null = read_header_names(&payload)?,
+                HEADER_PRIMARY_INDEX => options.primary_index = IndexKind::from_id(*payload.first().ok_or(gerr("Empty primary index kind in the container header"))?)?,
+                HEADER_DEFAULTS => options.defaults = serde_yaml::from_slice(&payload).map_err(|e|gerr(&format!("Failed to read the column defaults: {}",e)))?,
+                _ => {}
+            }
+        }
+    }
+    Ok((col_nam,col_val,options,offset,version))
+}
+/// Rewrites a container file in row format 0 to `FORMAT_VERSION`. The new file is written next to the old one and
+/// renamed over it once complete, the indexes and the graveyard are dropped and rebuilt when the container opens.
+/// Changes left uncommitted by an older version are in the old format too, so a container with any is refused.
+fn upgrade_container(path : &str, col_nam : Vec<String>, col_val : Vec<AlbaTypes>, options : ContainerOptions, headers_offset : u64, tuning : ContainerTuning) -> Result<(),Error>{
+    let record = format!("{}{}", path, MVCC_RECORD_SUFFIX);
+    if fs::exists(&record)? && fs::metadata(&record)?.len() > 0{
+        return Err(gerr(&format!("Failed to upgrade {} to row format {}, it has uncommitted changes from an older version. Recover them with that version first.", path, FORMAT_VERSION)))
+    }
+    loginfo!("Upgrading {} to row format {}.", path, FORMAT_VERSION);
+    let backend = DiskBackend::new(path);
+    let heap = if col_val.iter().any(|v|matches!(v,AlbaTypes::Text(_))){Some(TextHeap::open(&backend)?)}else{None};
+    let body = convert_legacy_rows(&fs::File::open(path)?, heap.as_ref(), &col_val, headers_offset, tuning.vacuum_chunk_size)?;
+    if let Some(heap) = &heap{
+        heap.sync()?;
+    }
+    let temp = format!("{}.migrate", path);
+    {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(&create_container_headers(col_nam, col_val, &options))?;
+        file.write_all(&body)?;
+        file.sync_all()?;
+    }
+    for suffix in [HASHMAP_SUFFIX,ORDERED_INDEX_SUFFIX,SECONDARY_INDEX_SUFFIX,GRAVEYARD_SUFFIX]{
+        let p = format!("{}{}", path, suffix);
+        if fs::exists(&p)?{
+            fs::remove_file(p)?;
+        }
+    }
+    fs::rename(&temp, path)
+}
+
+/// Order-preserving encoding of a tuple: each value's `ordered_key` with 0x00 escaped as 0x00 0xFF, closed by 0x00 0x00.
+fn group_key(values : &[AlbaTypes]) -> Vec<u8>{
+    let mut key = Vec::new();
+    for value in values{
+        for b in ordered_key(value){
+            key.push(b);
+            if b == 0{
+                key.push(0xFF);
+            }
+        }
+        key.extend_from_slice(&[0,0]);
+    }
+    key
+}
+
+/// Keeps the `col_nam` columns of rows laid out as `columns`, in the order `col_nam` lists them.
+fn project_rows(rows : Vec<Row>, columns : &[String], col_nam : &[String]) -> Vec<Row>{
+    if col_nam == columns{
+        return rows
+    }
+    let ide : Vec<usize> = col_nam.iter().filter_map(|c|columns.iter().position(|n|n == c)).collect();
+    rows.into_iter().map(|f|Row{data:ide.iter().map(|i|f.data[*i].to_owned()).collect()}).collect()
+}
+
+/// Upper bound on how many containers a single SearchSet may fan out to.
+const MAX_CONTAINER_SET : usize = 400;
+
+/// `prefix*` matches every container starting with `prefix`, `first..last` every container sorting between both names (inclusive),
+/// anything else only the container with that exact name.
+fn container_set_matches(pattern : &str, name : &str) -> bool{
+    if let Some(prefix) = pattern.strip_suffix('*'){
+        return name.starts_with(prefix)
+    }
+    if let Some((first,last)) = pattern.split_once(".."){
+        return first <= name && name <= last
+    }
+    pattern == name
+}
+
+/// Places the given values into a full row following the container's column order, columns left out keep their type default.
+fn bind_row(container : &Container, col_nam : &[String], col_val : Vec<AlbaTypes>) -> Result<Vec<AlbaTypes>,Error>{
+    if col_nam.len() != col_val.len() {
+        
+        return Err(gerr(&format!(
+            "In CREATE ROW, expected {} values for the specified columns, but got {}",
+            col_nam.len(),
+            col_val.len()
+        )));
+    }
+
+    for column in container.required_columns(){
+        if !col_nam.contains(column){
+            return Err(Error::from(TytoError::InvalidInput(format!("In CREATE ROW, the NOT NULL column '{}' must be given a value", column))));
+        }
+    }
+    let mut val  = container.row_template();
+
+    let mut id_map = HashMap::new();
+    for i in container.column_names().into_iter().enumerate(){
+        id_map.insert(i.1, i.0);
+    }
+
+    for (name,value) in col_nam.iter().zip(col_val){
+        if let Some(a) = id_map.get(name){
+            val[*a] = value;
+        }
+    }
+    Ok(val)
+}
+
+fn bind_named_row(container : &Container, values : Vec<(String,AlbaTypes)>) -> Result<Vec<AlbaTypes>,Error>{
+    let mut val = container.row_template();
+    let mut id_map = HashMap::new();
+    for i in container.column_names().into_iter().enumerate(){
+        id_map.insert(i.1, i.0);
+    }
+
+    let mut seen = vec![false;val.len()];
+    for (name,value) in values{
+        let a = match id_map.get(&name){
+            Some(a) => *a,
+            None => return Err(Error::from(TytoError::NotFound(format!("In CREATE ROW, the column '{}' does not exist", name))))
+        };
+        if seen[a]{
+            return Err(Error::from(TytoError::InvalidInput(format!("In CREATE ROW, the column '{}' was given more than once", name))));
+        }
+        seen[a] = true;
+        val[a] = value;
+    }
+    let names = container.column_names();
+    for column in container.required_columns(){
+        if names.iter().position(|n|n == column).is_some_and(|a|!seen[a]){
+            return Err(Error::from(TytoError::InvalidInput(format!("In CREATE ROW, the NOT NULL column '{}' must be given a value", column))));
+        }
+    }
+    Ok(val)
 }
 
 impl Database{
@@ -332,7 +1146,7 @@ impl Database{
     }
     
     async fn load_containers(&mut self) -> Result<(), Error> {
-         let path = format!("{}/containers.yaml", &self.location);
+         let path = format!("{}/{}", &self.location, CONTAINERS_FILE);
         if !fs::exists(&path).unwrap() {
             
             let yaml = serde_yaml::to_string(&self.containers)
@@ -354,7 +1168,7 @@ impl Database{
         
         for contain in self.containers.iter() {
             
-            let (he,header_offset) = self.get_container_headers(&contain).unwrap();
+            let (he,options,header_offset) = self.get_container_headers(&contain).unwrap();
             
             self.headers.push(he.clone());
             
@@ -371,16 +1185,48 @@ impl Database{
                     element_size,
                     he.1,
                     header_offset,
-                    he.0
+                    he.0,
+                    options,
+                    self.settings.tuning()
                 ).await.unwrap(),
             );
             
         }        
+        self.recover().await
+    }
+
+    /// Applies the `recovery` policy to the changes found in the MVCC records,
+    /// staged before an unclean shutdown and never committed.
+    async fn recover(&mut self) -> Result<(), Error> {
+        let mut recovered = 0;
+        for c in self.container.values(){
+            recovered += c.lock().await.parked.get(&RECOVERED_SESSION).map_or(0,|p|p.0.len());
+        }
+        if recovered == 0{
+            return Ok(())
+        }
+        match self.settings.recovery{
+            Recovery::Commit if self.read_only => logwarn!("Recovered {} uncommitted changes, they stay pending while the database is read-only.", recovered),
+            Recovery::Keep => logwarn!("Recovered {} uncommitted changes, run RECOVERY COMMIT or RECOVERY ROLLBACK to settle them.", recovered),
+            Recovery::Commit => {
+                loginfo!("Committing {} recovered changes.", recovered);
+                self.settle_recovered(true).await?;
+            }
+        }
         Ok(())
     }
+
+    /// Commits or drops the recovered changes, the active session stays as it was.
+    async fn settle_recovered(&mut self, commit : bool) -> Result<(), Error> {
+        let session = self.active_session;
+        self.switch_session(RECOVERED_SESSION).await;
+        let result = if commit{self.commit().await}else{self.rollback().await};
+        self.switch_session(session).await;
+        result
+    }
     
     fn save_containers(&self) -> Result<(), Error> {
-        let path = std::path::PathBuf::from(&self.location).join("containers.yaml");
+        let path = std::path::PathBuf::from(&self.location).join(CONTAINERS_FILE);
         
         let yaml = serde_yaml::to_string(&self.containers)
             .map_err(|e| Error::new(std::io::ErrorKind::Other, e.to_string()))?;
@@ -392,19 +1238,81 @@ impl Database{
         Ok(())
     }
     
+    /// Commits the changes staged by the active session, those of other sessions stay pending.
     pub async fn commit(&mut self) -> Result<(), Error> {
-        
+        self.savepoints.remove(&self.active_session);
         for (_, c) in self.container.iter_mut() {
             
             c.lock().await.commit().await?;
             
         }
-        
+        let names : Vec<String> = self.container.keys().cloned().collect();
+        self.queue_auto_vacuums(names).await;
         Ok(())
     }
+
+    /// Queues the containers of `names` whose share of dead slots passed `auto_vacuum_threshold`,
+    /// each at most once every `auto_vacuum_interval` seconds. Nothing is queued before `run_database` starts the worker.
+    async fn queue_auto_vacuums(&mut self, names : Vec<String>) {
+        let sender = match &self.auto_vacuum {
+            Some(sender) if self.settings.auto_vacuum_threshold > 0.0 => sender.clone(),
+            _ => return
+        };
+        let interval = std::time::Duration::from_secs(self.settings.auto_vacuum_interval);
+        for name in names {
+            if self.auto_vacuumed.get(&name).is_some_and(|last|last.elapsed() < interval) {
+                continue
+            }
+            // a container still locked is being vacuumed already
+            let ratio = match self.container.get(&name).map(|c|c.try_lock()) {
+                Some(Ok(c)) => match c.dead_ratio().await {
+                    Ok(ratio) => ratio,
+                    Err(e) => {
+                        logwarn!("Failed to measure the dead slots of {}: {}", name, e);
+                        continue
+                    }
+                },
+                _ => continue
+            };
+            if ratio > self.settings.auto_vacuum_threshold {
+                logdebug!("Queuing {} for an automatic vacuum, {:.0}% of its slots are dead.", name, ratio*100.0);
+                self.auto_vacuumed.insert(name.clone(), Instant::now());
+                let _ = sender.send(name);
+            }
+        }
+    }
     
+    /// Takes the tickets of the group syncs the commits made so far wait on, by container.
+    /// They must be waited on without holding the database, otherwise no other commit can join the group.
+    pub async fn take_sync_tickets(&mut self) -> Vec<(String,Vec<SyncTicket>)> {
+        let mut tickets = Vec::new();
+        for (name, c) in self.container.iter() {
+            // called right after a statement with the database held, a container still locked is being vacuumed
+            // and got no commit from that statement
+            let taken = match c.try_lock(){
+                Ok(mut c) => c.take_sync_tickets(),
+                Err(_) => continue
+            };
+            if !taken.is_empty() {
+                tickets.push((name.clone(), taken));
+            }
+        }
+        tickets
+    }
+
+    /// Lets the containers drop the record entries kept for the commits whose group sync succeeded.
+    pub async fn settle_synced(&mut self, synced : Vec<(String,usize)>) -> Result<(), Error> {
+        for (name, count) in synced {
+            if let Some(c) = self.container.get(&name) {
+                c.lock().await.settle_synced(count).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the changes staged by the active session, those of other sessions stay pending.
     pub async fn rollback(&mut self) -> Result<(), Error> {
-        
+        self.savepoints.remove(&self.active_session);
         for (_, c) in self.container.iter_mut() {
             
             c.lock().await.rollback().await?;
@@ -414,127 +1322,1057 @@ impl Database{
         Ok(())
     }
     
-    pub async fn setup(&self) -> Result<(), Error> {
-        let db_path = database_path();
-        
-        if !std::fs::exists(&db_path)? {
-            
-            std::fs::create_dir(&db_path)?;
-            
+    /// Makes `session` the one whose changes are staged, committed and rolled back from here on.
+    /// The changes of the previous session are parked in every container until it becomes active again.
+    /// The recovered session is only settled by RECOVERY COMMIT and RECOVERY ROLLBACK, it can't be used.
+    pub async fn use_session(&mut self, session : u64) -> Result<(), Error> {
+        if session == RECOVERED_SESSION{
+            return Err(Error::from(TytoError::Protocol(format!("The session {} is reserved for recovered changes", session))))
         }
+        self.switch_session(session).await;
         Ok(())
     }
-    
-    fn load_settings(&mut self) -> Result<(), Error> {
-        let dir = PathBuf::from(&self.location);
-        
-        let path = dir.join(SETTINGS_FILE);
-        
-        fs::create_dir_all(&dir)?;
-        
-        if path.exists() && fs::metadata(&path)?.is_dir() {
-            
-            fs::remove_dir(&path)?;
-            
+
+    async fn switch_session(&mut self, session : u64) {
+        if self.active_session == session{
+            return
         }
-        if !path.is_file() {
-            
-            self.set_default_settings()?;
-            
+        for c in self.container.values(){
+            c.lock().await.switch_session(session).await;
         }
-        let mut rewrite = false;
-        
-        let raw = fs::read_to_string(&path)
-            .map_err(|e| Error::new(e.kind(), format!("Failed to read {}: {}", SETTINGS_FILE, e)))?;
-        
-        let mut settings: Settings = serde_yaml::from_str(&raw)
-            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid {}: {}", SETTINGS_FILE, e)))?;
-        
-        if settings.max_columns <= settings.min_columns {
-            eprintln!("Failed to load settings, rewriting.\nERROR: \"max_columns\" cannot be equal nor lower than \"min_columns\".");
-            settings.min_columns = 1;
-            rewrite = true;
+        self.active_session = session;
+    }
+
+    /// Marks the pending changes of every container under `name`, setting a name again moves the savepoint.
+    async fn savepoint(&mut self, name : String) -> Result<(), Error> {
+        let mut marks = HashMap::with_capacity(self.container.len());
+        for (container,c) in self.container.iter(){
+            marks.insert(container.clone(), c.lock().await.mark_pending().await?);
         }
-        if settings.max_columns <= 1 {
-            eprintln!("Failed to load settings, rewriting.\nERROR: \"max_columns\" cannot be 1 nor lower.");
-            settings.max_columns = 10;
-            rewrite = true;
+        let savepoints = self.savepoints.entry(self.active_session).or_default();
+        savepoints.retain(|s|s.name != name);
+        savepoints.push(Savepoint{name,marks});
+        Ok(())
+    }
+
+    /// Drops the changes staged after the savepoint `name` and the savepoints set after it, `name` itself stays set.
+    /// Containers without a mark had nothing pending that the savepoint covers, so all their changes are dropped.
+    async fn rollback_to(&mut self, name : &str) -> Result<(), Error> {
+        let savepoints = self.savepoints.entry(self.active_session).or_default();
+        let position = savepoints.iter().position(|s|s.name == name).ok_or(Error::from(TytoError::NotFound(format!("There is no savepoint named {}", name))))?;
+        savepoints.truncate(position + 1);
+        let mut savepoint = savepoints.pop().ok_or(gerr("The savepoint disappeared while rolling back to it"))?;
+        for (container,c) in self.container.iter(){
+            let mut c = c.lock().await;
+            match savepoint.marks.remove(container){
+                Some(mark) => c.restore_pending(mark).await?,
+                None => c.rollback().await?
+            }
         }
-        if settings.min_columns > settings.max_columns {
-            eprintln!("Failed to load settings, rewriting.\nERROR: \"min_columns\" count cannot be higher than \"max_column\"."); 
-            settings.min_columns = 1;
-            rewrite = true;
+        self.savepoint(savepoint.name).await
+    }
+
+    /// Forgets the marks of `container` after it was committed or rolled back on its own,
+    /// a later `rollback_to` then drops everything it stages from there on.
+    fn forget_savepoints(&mut self, container : &str) {
+        for savepoint in self.savepoints.entry(self.active_session).or_default().iter_mut(){
+            savepoint.marks.remove(container);
         }
-        if settings.workers < 1 {
-            eprintln!("Failed to load settings, rewriting.\nERROR: \"workers\" cannot be lower than zero.");
-            settings.workers = 1;
-            rewrite = true;
+    }
+
+    /// Runs every statement stored in `path` as one unit: either all of them are committed or none is.
+    /// The file is a sequence of frames, each a little-endian u64 length followed by a command encoded the way clients send it.
+    /// Containers created by the file are erased again when a statement fails. Commits of several containers
+    /// still happen one container at a time, so a failing disk during the final commit can leave some of them applied.
+    pub async fn apply_file(&mut self, path : &str) -> Result<Query, Error> {
+        let raw = fs::read(path)?;
+        let mut statements = Vec::new();
+        let mut cursor = 0usize;
+        while cursor < raw.len(){
+            let index = statements.len();
+            let frame = raw.get(cursor..cursor+8).ok_or(gerr(&format!("Statement {} of {} has a truncated length", index, path)))?;
+            let len = u64::from_le_bytes(frame.try_into().unwrap()) as usize;
+            cursor += 8;
+            let body = raw.get(cursor..cursor.saturating_add(len)).ok_or(gerr(&format!("Statement {} of {} is truncated", index, path)))?;
+            cursor += len;
+            let command = commands::decompile(&body.to_vec()).map_err(|e|gerr(&format!("Statement {} of {} can't be decoded: {}", index, path, e)))?;
+            let ast = command_to_ast(command).map_err(|e|gerr(&format!("Statement {} of {}: {}", index, path, e)))?;
+            match ast{
+                AST::Commit(_)|AST::Rollback(_)|AST::RecoveryCommit|AST::RecoveryRollback|AST::Savepoint(_)|AST::RollbackTo(_)|AST::Release(_)|AST::DeleteContainer(_) => return Err(Error::from(TytoError::InvalidInput(format!("Statement {} of {} can't be undone and isn't allowed in an atomic apply", index, path)))),
+                _ => statements.push(ast)
+            }
         }
-       
-        if rewrite {
-            
-            let new_yaml = serde_yaml::to_string(&settings)
-                .map_err(|e| Error::new(ErrorKind::Other, format!("Serialize failed: {}", e)))?;
-            
-            fs::write(&path, new_yaml)
-                .map_err(|e| Error::new(e.kind(), format!("Failed to rewrite {}: {}", SETTINGS_FILE, e)))?;
+        for (name,c) in self.container.iter(){
+            if c.lock().await.has_pending().await{
+                return Err(Error::from(TytoError::Conflict(format!("Failed to apply {}, the container {} has pending changes, commit or rollback them first.", path, name))))
+            }
+        }
+
+        let existing : HashSet<String> = self.container.keys().cloned().collect();
+        let count = statements.len();
+        for (index,ast) in statements.into_iter().enumerate(){
+            if let Err(e) = Box::pin(self.run(ast)).await{
+                self.rollback().await?;
+                let created : Vec<String> = self.container.keys().filter(|c|!existing.contains(*c)).cloned().collect();
+                for container in created{
+                    Box::pin(self.run(AST::DeleteContainer(AstDeleteContainer{container, purge: true}))).await?;
+                }
+                return Err(Error::new(e.kind(), format!("Statement {} of {} failed, nothing was applied: {}", index, path, e)))
+            }
+        }
+        self.commit().await?;
+        Ok(Query { rows: (vec!["applied".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(count as i64)]}]) })
+    }
+
+    /// Opens a snapshot on every container, searches carrying its id will not see anything committed afterwards.
+    pub async fn open_snapshot(&mut self) -> Result<u64, Error> {
+        self.expire_snapshots().await;
+        self.next_snapshot += 1;
+        let id = self.next_snapshot;
+        for (_, c) in self.container.iter() {
+            c.lock().await.snapshots.lock().await.insert(id, BTreeMap::new());
+        }
+        self.snapshots.insert(id, Instant::now());
+        Ok(id)
+    }
+
+    pub async fn close_snapshot(&mut self, id : u64) -> Result<(), Error> {
+        if self.snapshots.remove(&id).is_none(){
+            return Err(Error::from(TytoError::NotFound(format!("There is no open snapshot with the id {}", id))))
+        }
+        for (_, c) in self.container.iter() {
+            c.lock().await.snapshots.lock().await.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Closes the snapshots that have not been used for longer than `snapshot_timeout`.
+    async fn expire_snapshots(&mut self) {
+        let timeout = std::time::Duration::from_secs(self.settings.snapshot_timeout);
+        let expired : Vec<u64> = self.snapshots.iter().filter(|(_,used)|used.elapsed() > timeout).map(|(id,_)|*id).collect();
+        for id in expired{
+            let _ = self.close_snapshot(id).await;
+        }
+    }
+
+    /// Opens a cursor over a search, returning its first page.
+    async fn open_cursor(&mut self, structure : AstSearch, page : usize) -> Result<Query, Error> {
+        if page == 0{
+            return Err(Error::from(TytoError::InvalidInput("A cursor page must hold at least one row".to_string())))
+        }
+        if structure.count || structure.explain || structure.distinct || structure.limit.is_some() || structure.snapshot.is_some() || !structure.aggregates.is_empty() || !structure.group_by.is_empty(){
+            return Err(Error::from(TytoError::InvalidInput("PAGE only reads plain rows, it can't be combined with COUNT, aggregates, GROUP BY, DISTINCT, LIMIT, SNAPSHOT or EXPLAIN".to_string())))
+        }
+        if !self.container.contains_key(&structure.container){
+            return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))))
+        }
+        self.expire_cursors();
+        self.next_cursor += 1;
+        let id = self.next_cursor;
+        self.cursors.insert(id, Cursor{
+            container: structure.container,
+            conditions: structure.conditions,
+            col_nam: structure.col_nam,
+            principal: structure.principal,
+            page,
+            skip: structure.offset,
+            after: None,
+            used: Instant::now(),
+        });
+        self.fetch_cursor(id).await
+    }
+
+    /// Reads the next page of a cursor. The first row holds the cursor id to fetch the following page with,
+    /// or 0 once this page is the last one, in which case the cursor is closed.
+    async fn fetch_cursor(&mut self, id : u64) -> Result<Query, Error> {
+        self.expire_cursors();
+        let cursor = self.cursors.get(&id).ok_or(Error::from(TytoError::NotFound(format!("There is no open cursor with the id {}", id))))?.clone();
+        let container = match self.container.get(&cursor.container){
+            Some(c) => c.clone(),
+            None => {
+                self.cursors.remove(&id);
+                return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", cursor.container))))
+            }
+        };
+        let (sa,cn) = {
+            let c = container.lock().await;
+            (SearchArguments{
+                element_size: c.element_size,
+                header_offset: c.headers_offset as usize,
+                file: c.file.clone(),
+                conditions: QueryConditions::from_primitive_conditions(cursor.conditions.clone(),&c.column_properties,c.primary_key.clone())?.with_indexes(c.secondary_indexes.lock().await.kinds()).with_primary_index(c.options.primary_index),
+                // one row more than a page tells whether another page follows
+                limit: Some(cursor.page + 1),
+                offset: cursor.skip,
+                snapshot: None,
+                after: cursor.after,
+                parallelism: self.scan_threads(),
+                mmap: self.settings.mmap_scans,
+                deadline: self.deadline,
+                pending: false
+            },c.column_names())
+        };
+        self.throttle_scan(cursor.principal.as_deref(), &sa.conditions)?;
+        let (mut rows,offsets) = search(container, sa).await?;
+        let next = if rows.len() > cursor.page{
+            rows.truncate(cursor.page);
+            if let Some(c) = self.cursors.get_mut(&id){
+                c.after = Some(offsets[cursor.page - 1]);
+                c.skip = None;
+                c.used = Instant::now();
+            }
+            id
+        }else{
+            self.cursors.remove(&id);
+            0
+        };
+        let mut page = Vec::with_capacity(rows.len() + 1);
+        page.push(Row{data:vec![AlbaTypes::Bigint(next as i64)]});
+        page.extend(project_rows(rows, &cn, &cursor.col_nam));
+        Ok(Query { rows: (cursor.col_nam, page) })
+    }
+
+    /// Stores a search result under the key and container version it was computed for, see `ResultCache`.
+    fn cache_result(&mut self, container : &str, key : Option<(String,u64)>, q : Query) -> Query {
+        if let Some((key,version)) = key{
+            self.result_cache.insert(container, key, version, q.clone());
+        }
+        q
+    }
+
+    /// How many threads a full scan may use, `scan_threads` or the number of workers when it's 0.
+    fn scan_threads(&self) -> usize {
+        match self.settings.scan_threads{
+            0 => self.settings.workers.max(1) as usize,
+            n => n as usize
+        }
+    }
+
+    /// Parses a search whose conditions may compare columns with `?`, returning the id to execute it with.
+    async fn prepare(&mut self, structure : AstSearch) -> Result<Query, Error> {
+        if structure.page.is_some() || structure.snapshot.is_some() || structure.explain || structure.distinct || !structure.aggregates.is_empty() || !structure.group_by.is_empty(){
+            return Err(Error::from(TytoError::InvalidInput("Only plain searches and counts can be prepared, not cursors, snapshot reads, EXPLAIN, DISTINCT or aggregates".to_string())))
+        }
+        if self.prepared.len() >= MAX_PREPARED_STATEMENTS{
+            return Err(Error::from(TytoError::Busy(format!("There are already {} prepared statements, deallocate some first", MAX_PREPARED_STATEMENTS))))
+        }
+        self.check_column_access(structure.principal.as_deref(), &structure.container, &structure.col_nam, &structure.conditions)?;
+        let container = self.container.get(&structure.container).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))))?;
+        let conditions = {
+            let c = container.lock().await;
+            QueryConditions::from_primitive_conditions(structure.conditions, &c.column_properties, c.primary_key.clone())?
+        };
+        self.next_prepared += 1;
+        let id = self.next_prepared;
+        self.prepared.insert(id, PreparedStatement{
+            container: structure.container,
+            conditions,
+            col_nam: structure.col_nam,
+            principal: structure.principal,
+            limit: structure.limit,
+            offset: structure.offset,
+            count: structure.count,
+        });
+        Ok(Query { rows: (vec!["statement".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(id as i64)]}]) })
+    }
+
+    /// Runs a prepared statement with `values` bound to its placeholders.
+    async fn execute(&self, id : u64, values : Vec<AlbaTypes>) -> Result<Query, Error> {
+        let statement = self.prepared.get(&id).ok_or(Error::from(TytoError::NotFound(format!("There is no prepared statement with the id {}", id))))?;
+        let container = self.container.get(&statement.container).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", statement.container))))?.clone();
+        let (sa,cn) = {
+            let c = container.lock().await;
+            (SearchArguments{
+                element_size: c.element_size,
+                header_offset: c.headers_offset as usize,
+                file: c.file.clone(),
+                conditions: statement.conditions.bind(values)?.with_indexes(c.secondary_indexes.lock().await.kinds()).with_primary_index(c.options.primary_index),
+                limit: statement.limit,
+                offset: statement.offset,
+                snapshot: None,
+                after: None,
+                parallelism: self.scan_threads(),
+                mmap: self.settings.mmap_scans,
+                deadline: self.deadline,
+                pending: false
+            },c.column_names())
+        };
+        self.throttle_scan(statement.principal.as_deref(), &sa.conditions)?;
+        if statement.count{
+            let n = count(container, sa).await?;
+            return Ok(Query { rows: (vec!["count".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}]) })
+        }
+        let rows = project_rows(search(container, sa).await?.0, &cn, &statement.col_nam);
+        Ok(Query { rows: (statement.col_nam.clone(),rows) })
+    }
+
+    /// Drops the cursors that have not been read for longer than `cursor_timeout`.
+    fn expire_cursors(&mut self) {
+        let timeout = std::time::Duration::from_secs(self.settings.cursor_timeout);
+        self.cursors.retain(|_,c|c.used.elapsed() <= timeout);
+    }
+
+    /// Rewrites every live row of `name` through `transform` into a container laid out as `col_nam`/`col_val` with `options`,
+    /// compacting it on the way. The new file is written next to the old one and only renamed over it once complete,
+    /// so a crash before the rename leaves the original intact. The indexes are rebuilt from the new file afterwards.
+    async fn rewrite_container<F>(&mut self, name : &str, action : &str, col_nam : Vec<String>, col_val : Vec<AlbaTypes>, options : ContainerOptions, mut transform : F) -> Result<(),Error>
+    where F : FnMut(Vec<AlbaTypes>) -> Result<Vec<AlbaTypes>,Error>{
+        let path = format!("{}/{}", self.location, name);
+        let old = self.container.get(name).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", name))))?.clone();
+        let mut c = old.lock().await;
+        if c.has_pending().await{
+            return Err(Error::from(TytoError::Conflict(format!("Failed to {}, commit or rollback the pending changes first.", action))))
+        }
+        if !c.snapshots.lock().await.is_empty(){
+            return Err(Error::from(TytoError::Conflict(format!("Failed to {}, close the open snapshots first.", action))))
+        }
+        if c.text_heap.is_none() && col_val.iter().any(|v|matches!(v,AlbaTypes::Text(_))){
+            c.text_heap = Some(TextHeap::open(&*c.backend)?);
+        }
+        let element_size : usize = col_val.iter().map(|v|v.size()).sum();
+
+        let total_rows = (c.file.lock().await.len()? - c.headers_offset) / c.element_size as u64;
+        let mut body = Vec::new();
+        for row in 0..total_rows{
+            let values = match c.read_row_at(c.headers_offset + row * c.element_size as u64).await?{
+                Some(r) => r.data,
+                None => continue
+            };
+            body.extend_from_slice(&c.serialize_sized(&transform(values)?, &col_val)?);
+        }
+        if let Some(heap) = &c.text_heap{
+            heap.sync()?;
+        }
+        let kinds : Vec<(String,IndexKind)> = c.secondary_indexes.lock().await.indexes.iter().filter(|i|col_nam.contains(&i.column)).map(|i|(i.column.clone(),i.kind)).collect();
+        let in_memory = !c.backend.persistent();
+        // in memory the text heap and the sequence go away with the container, so they are handed to the new one
+        let (text_heap,sequence) = if in_memory{(c.text_heap.take(),c.sequence.take())}else{(None,None)};
+        drop(c);
+
+        let header = create_container_headers(col_nam.clone(), col_val.clone(), &options);
+        let c = if in_memory{
+            self.container.remove(name);
+            drop(old);
+            let headers_offset = header.len() as u64;
+            let mut content = header;
+            content.extend_from_slice(&body);
+            Container::new_in_memory(content, element_size, col_val.clone(), headers_offset, col_nam.clone(), options, self.settings.tuning()).await?
+        }else{
+            let temp = format!("{}.migrate", path);
+            {
+                let mut file = fs::File::create(&temp)?;
+                file.write_all(&header)?;
+                file.write_all(&body)?;
+                file.sync_all()?;
+            }
+            self.container.remove(name);
+            drop(old);
+            for suffix in [HASHMAP_SUFFIX,ORDERED_INDEX_SUFFIX,SECONDARY_INDEX_SUFFIX,GRAVEYARD_SUFFIX]{
+                let p = format!("{}{}", path, suffix);
+                if fs::exists(&p)?{
+                    fs::remove_file(p)?;
+                }
+            }
+            fs::rename(&temp, &path)?;
+            Container::new(&path, element_size, col_val.clone(), header.len() as u64, col_nam.clone(), options, self.settings.tuning()).await?
+        };
+        {
+            let mut container = c.lock().await;
+            if text_heap.is_some(){
+                container.text_heap = text_heap;
+            }
+            if sequence.is_some(){
+                container.sequence = sequence;
+            }
+            for (column,kind) in kinds{
+                container.create_index(&column, kind).await?;
+            }
+        }
+        c.lock().await.switch_session(self.active_session).await;
+        self.container.insert(name.to_string(), c);
+        if let Some(i) = self.containers.iter().position(|n|n == name){
+            if let Some(h) = self.headers.get_mut(i){
+                *h = (col_nam,col_val);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites `name` with its primary key converted to the type of `to`.
+    /// Every key must convert without loss and stay unique, otherwise nothing is touched.
+    async fn migrate_primary_key(&mut self, name : &str, to : AlbaTypes) -> Result<(),Error>{
+        let (position,options,col_nam,col_val) = {
+            let c = self.container.get(name).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", name))))?.lock().await;
+            let position = match c.key_positions.as_slice(){
+                [position] => *position,
+                _ => return Err(Error::from(TytoError::Schema("Failed to migrate the primary key, only single column keys can be migrated.".to_string())))
+            };
+            (position,c.options.clone(),c.column_names(),c.columns())
+        };
+        let from = col_val[position].clone();
+        if std::mem::discriminant(&from) == std::mem::discriminant(&to){
+            return Err(Error::from(TytoError::Schema(format!("The primary key is already a {:?}", to))))
+        }
+        if let AlbaTypes::NONE = to{
+            return Err(Error::from(TytoError::Schema("The primary key can't be migrated to NONE".to_string())))
+        }
+        if options.auto_increment && !matches!(to, AlbaTypes::Int(_) | AlbaTypes::Bigint(_)){
+            return Err(Error::from(TytoError::Schema("An auto-increment primary key can only be migrated to INT or BIGINT".to_string())))
+        }
+        let mut col_val = col_val;
+        col_val[position] = to.clone();
+
+        let mut seen = HashSet::new();
+        self.rewrite_container(name, "migrate the primary key", col_nam, col_val, options, |mut values|{
+            let original = values[position].clone();
+            let converted = to.try_from_existing(original.clone())
+                .map_err(|e|Error::from(TytoError::Schema(format!("Failed to migrate the primary key {:?}: {}", original, e))))?;
+            if from.try_from_existing(converted.clone()).ok().as_ref() != Some(&original){
+                return Err(Error::from(TytoError::Schema(format!("Failed to migrate the primary key, {:?} can't be represented as {:?} without loss", original, converted))))
+            }
+            if !seen.insert(ordered_key(&converted)){
+                return Err(Error::from(TytoError::Schema(format!("Failed to migrate the primary key, more than one key becomes {:?}", converted))))
+            }
+            values[position] = converted;
+            Ok(values)
+        }).await
+    }
+
+    /// Appends a column to `name`, existing rows get `default`, or the type's zero value when there is none.
+    /// A default is also kept in the header for the rows inserted later.
+    async fn add_column(&mut self, name : &str, column : String, col_type : AlbaTypes, default : Option<AlbaTypes>) -> Result<(),Error>{
+        let (mut options,mut col_nam,mut col_val) = {
+            let c = self.container.get(name).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", name))))?.lock().await;
+            (c.options.clone(),c.column_names(),c.columns())
+        };
+        if column.is_empty(){
+            return Err(Error::from(TytoError::Schema("Failed to add the column, its name can't be empty.".to_string())))
+        }
+        if col_nam.contains(&column){
+            return Err(Error::from(TytoError::Schema(format!("Failed to add the column, {} already has a column named {}", name, column))))
+        }
+        if let AlbaTypes::NONE = col_type{
+            return Err(Error::from(TytoError::Schema("Failed to add the column, its type can't be NONE.".to_string())))
+        }
+        if col_nam.len() + 1 > self.settings.max_columns as usize{
+            return Err(Error::from(TytoError::Schema("Failed to add the column, the count of columns would be higher than the maximum set on the settings file.".to_string())))
+        }
+        let value = match default{
+            Some(default) => {
+                let value = col_type.try_from_existing(default).map_err(|e|Error::from(TytoError::Schema(format!("Failed to add the column, the default of {} does not fit its type: {}", column, e))))?;
+                options.defaults.push((column.clone(),value.clone()));
+                value
+            },
+            None => col_type.clone()
+        };
+        col_nam.push(column);
+        col_val.push(col_type);
+
+        self.rewrite_container(name, "add the column", col_nam, col_val, options, |mut values|{
+            values.push(value.clone());
+            Ok(values)
+        }).await
+    }
+
+    /// Removes `column` from `name` along with its default, constraints and secondary index.
+    /// Primary key columns can't be dropped.
+    async fn drop_column(&mut self, name : &str, column : &str) -> Result<(),Error>{
+        let (mut options,mut col_nam,mut col_val,position) = {
+            let c = self.container.get(name).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", name))))?.lock().await;
+            let position = c.headers.iter().position(|h|h.0 == column).ok_or(Error::from(TytoError::Schema(format!("Failed to drop the column, {} has no column named {}", name, column))))?;
+            if c.key_positions.contains(&position){
+                return Err(Error::from(TytoError::Schema("Failed to drop the column, it is part of the primary key.".to_string())))
+            }
+            (c.options.clone(),c.column_names(),c.columns(),position)
+        };
+        if col_nam.len() <= self.settings.min_columns as usize{
+            return Err(Error::from(TytoError::Schema("Failed to drop the column, the count of columns would be lower than the minimum set on the settings file.".to_string())))
+        }
+        options.defaults.retain(|d|d.0 != column);
+        options.not_null.retain(|c|c != column);
+        options.unique.retain(|c|c != column);
+        options.empty_as_null.retain(|c|c != column);
+        col_nam.remove(position);
+        col_val.remove(position);
+
+        self.rewrite_container(name, "drop the column", col_nam, col_val, options, |mut values|{
+            values.remove(position);
+            Ok(values)
+        }).await
+    }
+
+    /// Writes a tar archive at `path` holding `settings.yaml`, `containers.yaml` and every file of every container under
+    /// the name it has in the data directory, returning how many files went in.
+    /// Every container stays locked until the archive is complete, and statements already queue on the database lock,
+    /// so all writes wait for about as long as copying the data directory takes. Uncommitted changes are captured
+    /// through the `.mr` records and replayed when the restored containers are loaded.
+    pub async fn backup(&self, path : &str) -> Result<u64,Error>{
+        let mut names : Vec<&String> = self.container.keys().collect();
+        names.sort();
+        let mut guards = Vec::with_capacity(names.len());
+        for name in names.iter(){
+            let c = self.container[*name].lock().await;
+            c.index_map.lock().await.sync()?;
+            c.secondary_indexes.lock().await.sync()?;
+            guards.push(c);
+        }
+        let mut files = vec![SETTINGS_FILE.to_string(), CONTAINERS_FILE.to_string()];
+        for name in names{
+            for suffix in CONTAINER_FILE_SUFFIXES{
+                files.push(format!("{}{}", name, suffix));
+            }
+        }
+        // built next to the target and renamed once complete, so a failed backup never leaves a partial archive at `path`
+        let temp = format!("{}.partial", path);
+        let mut archive = tar::Builder::new(fs::File::create(&temp)?);
+        let mut count = 0u64;
+        for file in files{
+            let source = format!("{}/{}", self.location, file);
+            if fs::exists(&source)?{
+                archive.append_path_with_name(&source, &file)?;
+                count += 1;
+            }
+        }
+        archive.into_inner()?.sync_all()?;
+        drop(guards);
+        fs::rename(&temp, path)?;
+        Ok(count)
+    }
+
+    /// Replaces the data directory with the contents of an archive written by `backup` and reloads it the way `connect` does,
+    /// returning how many containers were restored. Refused while any container exists unless `force` is set, in which
+    /// case the current containers go to the trash first (or are removed when the trash is disabled).
+    /// The archive is unpacked into a staging directory and every container file's header is read back before anything is replaced.
+    pub async fn restore(&mut self, archive : &str, force : bool) -> Result<u64,Error>{
+        if !self.containers.is_empty() && !force{
+            return Err(Error::from(TytoError::Conflict("Failed to restore, the data directory holds containers, delete them first or restore with FORCE.".to_string())))
+        }
+        let staging = PathBuf::from(&self.location).join(RESTORE_DIR);
+        if staging.exists(){
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+        let result = self.stage_restore(archive, &staging);
+        let names = match result{
+            Ok(names) => names,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e)
+            }
+        };
+
+        for name in self.containers.clone(){
+            self.container.remove(&name);
+            if self.settings.trash_retention > 0{
+                self.move_to_trash(&name)?;
+            }else{
+                for suffix in CONTAINER_FILE_SUFFIXES{
+                    let _ = fs::remove_file(format!("{}/{}{}", self.location, name, suffix));
+                }
+            }
+        }
+        for entry in fs::read_dir(&staging)?{
+            let entry = entry?;
+            fs::rename(entry.path(), PathBuf::from(&self.location).join(entry.file_name()))?;
+        }
+        fs::remove_dir_all(&staging)?;
+
+        self.container.clear();
+        self.containers.clear();
+        self.headers.clear();
+        self.load_settings()?;
+        self.load_containers().await?;
+        Ok(names.len() as u64)
+    }
+
+    /// Unpacks `archive` into `staging` and checks it can be adopted: only plain file names, both YAML files present
+    /// and a readable header for every listed container. Returns the listed containers.
+    fn stage_restore(&self, archive : &str, staging : &std::path::Path) -> Result<Vec<String>,Error>{
+        let mut archive = tar::Archive::new(fs::File::open(archive)?);
+        for entry in archive.entries()?{
+            let mut entry = entry?;
+            let name = entry.path()?.to_path_buf();
+            let plain = matches!(name.components().collect::<Vec<_>>().as_slice(), [std::path::Component::Normal(_)]);
+            if !plain{
+                return Err(gerr(&format!("Failed to restore, the archive holds the unexpected path {}", name.display())))
+            }
+            entry.unpack(staging.join(&name))?;
+        }
+        if !staging.join(SETTINGS_FILE).exists(){
+            return Err(gerr(&format!("Failed to restore, the archive has no {}", SETTINGS_FILE)))
+        }
+        let listed = fs::read_to_string(staging.join(CONTAINERS_FILE)).map_err(|e|gerr(&format!("Failed to restore, can't read {} from the archive: {}", CONTAINERS_FILE, e)))?;
+        let names : Vec<String> = serde_yaml::from_str(&listed).map_err(|e|gerr(&format!("Failed to restore, {} is invalid: {}", CONTAINERS_FILE, e)))?;
+        for name in names.iter(){
+            let file = fs::File::open(staging.join(name)).map_err(|e|gerr(&format!("Failed to restore, the container {} is missing from the archive: {}", name, e)))?;
+            get_container_headers(&file).map_err(|e|gerr(&format!("Failed to restore, the header of container {} can't be read: {}", name, e)))?;
+        }
+        Ok(names)
+    }
+
+    /// Loads newline-delimited JSON objects from `path` into `name` and commits them, all or nothing.
+    /// Each object is keyed by column name, columns it leaves out take their default, values are coerced by `AlbaTypes::from_json`.
+    async fn import_json(&mut self, name : &str, path : &str) -> Result<u64,Error>{
+        let container = self.container.get(name).ok_or(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", name))))?.clone();
+        let mut c = container.lock().await;
+        if c.has_pending().await{
+            return Err(Error::from(TytoError::Conflict(format!("Failed to import {}, the container {} has pending changes, commit or rollback them first.", path, name))))
+        }
+        let schema = c.column_properties.clone();
+        let mut rows = Vec::new();
+        for (n,line) in std::io::BufRead::lines(std::io::BufReader::new(fs::File::open(path)?)).enumerate(){
+            let line = line?;
+            if line.trim().is_empty(){
+                continue
+            }
+            let fail = |e : &dyn std::fmt::Display| gerr(&format!("Failed to import {}, line {}: {}", path, n + 1, e));
+            let object = match serde_json::from_str::<serde_json::Value>(&line).map_err(|e|fail(&e))?{
+                serde_json::Value::Object(object) => object,
+                _ => return Err(fail(&"expected a JSON object"))
+            };
+            let mut values = Vec::with_capacity(object.len());
+            for (column,value) in object{
+                let column_type = schema.get(&column).ok_or(fail(&format!("the column '{}' does not exist", column)))?;
+                values.push((column.clone(), column_type.from_json(value).map_err(|e|fail(&format!("column '{}': {}", column, e)))?));
+            }
+            rows.push(bind_named_row(&c, values).map_err(|e|fail(&e))?);
+        }
+        let n = rows.len() as u64;
+        c.push_rows(rows).await.map_err(|e|gerr(&format!("Failed to import {}: {}", path, e)))?;
+        if let Err(e) = c.commit().await{
+            let _ = c.rollback().await;
+            return Err(gerr(&format!("Failed to import {}: {}", path, e)))
+        }
+        Ok(n)
+    }
+
+    /// Moves every file of `name` into `.trash/<name>.<unix seconds>`, where it waits `trash_retention` seconds before being purged.
+    fn move_to_trash(&self, name : &str) -> Result<(),Error>{
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d|d.as_secs()).unwrap_or(0);
+        let dir = format!("{}/{}/{}.{}", self.location, TRASH_DIR, name, now);
+        fs::create_dir_all(&dir)?;
+        for suffix in CONTAINER_FILE_SUFFIXES{
+            let from = format!("{}/{}{}", self.location, name, suffix);
+            if fs::exists(&from)?{
+                fs::rename(from, format!("{}/{}{}", dir, name, suffix))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Trashed copies of `name`, newest first, as (trashed at, directory).
+    fn trashed(&self, name : Option<&str>) -> Result<Vec<(u64,PathBuf)>,Error>{
+        let trash = PathBuf::from(&self.location).join(TRASH_DIR);
+        if !trash.exists(){
+            return Ok(Vec::new())
+        }
+        let mut found = Vec::new();
+        for entry in fs::read_dir(trash)?{
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let (container,at) = match file_name.rsplit_once('.').and_then(|(c,t)|t.parse::<u64>().ok().map(|t|(c.to_string(),t))){
+                Some(a) => a,
+                None => continue
+            };
+            if name.is_none_or(|n|n == container){
+                found.push((at,entry.path()));
+            }
+        }
+        found.sort_by(|a,b|b.0.cmp(&a.0));
+        Ok(found)
+    }
+
+    /// Brings back the most recently trashed container called `name`.
+    async fn restore_from_trash(&mut self, name : &str) -> Result<(),Error>{
+        if self.container.contains_key(name){
+            return Err(Error::from(TytoError::Conflict(format!("Failed to restore, there is already a container named {}", name))))
+        }
+        let dir = match self.trashed(Some(name))?.into_iter().next(){
+            Some((_,dir)) => dir,
+            None => return Err(Error::from(TytoError::NotFound(format!("There is no trashed container named {}", name))))
+        };
+        for suffix in CONTAINER_FILE_SUFFIXES{
+            let from = dir.join(format!("{}{}", name, suffix));
+            if from.exists(){
+                fs::rename(from, format!("{}/{}{}", self.location, name, suffix))?;
+            }
+        }
+        fs::remove_dir(&dir)?;
+
+        let (he,options,header_offset) = self.get_container_headers(name)?;
+        let element_size = he.1.iter().map(|v|v.size()).sum();
+        let c = Container::new(&format!("{}/{}", self.location, name), element_size, he.1.clone(), header_offset, he.0.clone(), options, self.settings.tuning()).await?;
+        self.containers.push(name.to_string());
+        self.headers.push(he);
+        c.lock().await.switch_session(self.active_session).await;
+        self.container.insert(name.to_string(), c);
+        self.save_containers()
+    }
+
+    /// Deletes the trashed containers older than `trash_retention`, run by the scheduler.
+    fn purge_trash(&self) -> Result<(),Error>{
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d|d.as_secs()).unwrap_or(0);
+        for (at,dir) in self.trashed(None)?{
+            if now.saturating_sub(at) >= self.settings.trash_retention{
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails when `principal` is restricted on `container` and either selects or filters on a column it can't read.
+    fn check_column_access(&self, principal : Option<&str>, container : &str, columns : &[String], conditions : &PrimitiveQueryConditions) -> Result<(),Error>{
+        let principal = match principal{
+            Some(p) => p,
+            None => return Ok(())
+        };
+        let allowed = match self.settings.column_access.get(principal).and_then(|c|c.get(container)){
+            Some(a) => a,
+            None => return Ok(())
+        };
+        let filtered = conditions.0.iter().filter_map(|c|if let Token::String(name) = &c.0{Some(name)}else{None});
+        for column in columns.iter().chain(filtered){
+            if !allowed.contains(column){
+                return Err(Error::new(ErrorKind::PermissionDenied, format!("The column '{}' of container '{}' is not readable by '{}'", column, container, principal)))
+            }
+        }
+        Ok(())
+    }
+
+    /// Spends one token of `principal`'s rate limit when `conditions` can't be answered by an index.
+    fn throttle_scan(&self, principal : Option<&str>, conditions : &QueryConditions) -> Result<(),Error>{
+        let principal = match principal{
+            Some(p) => p,
+            None => return Ok(())
+        };
+        if let QueryType::Scan = conditions.query_type()?{
+            self.rate_limiters.check(principal, "scan")?;
+        }
+        Ok(())
+    }
+
+    pub async fn setup(&self) -> Result<(), Error> {
+        let db_path = &self.location;
+        
+        if !std::fs::exists(&db_path)? {
+            
+            std::fs::create_dir(&db_path)?;
+            
+        }
+        Ok(())
+    }
+    
+    fn load_settings(&mut self) -> Result<(), Error> {
+        let dir = PathBuf::from(&self.location);
+        
+        let path = dir.join(SETTINGS_FILE);
+        
+        fs::create_dir_all(&dir)?;
+        
+        if path.exists() && fs::metadata(&path)?.is_dir() {
             
+            fs::remove_dir(&path)?;
+            
+        }
+        if !path.is_file() {
+            
+            self.set_default_settings()?;
+            
+        }
+        let mut rewrite = false;
+        
+        let raw = fs::read_to_string(&path)
+            .map_err(|e| Error::new(e.kind(), format!("Failed to read {}: {}", SETTINGS_FILE, e)))?;
+        
+        let mut settings: Settings = serde_yaml::from_str(&raw)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid {}: {}", SETTINGS_FILE, e)))?;
+        
+        if settings.max_columns <= settings.min_columns {
+            logwarn!("Failed to load settings, rewriting. \"max_columns\" cannot be equal nor lower than \"min_columns\".");
+            settings.min_columns = 1;
+            rewrite = true;
+        }
+        if settings.max_columns <= 1 {
+            logwarn!("Failed to load settings, rewriting. \"max_columns\" cannot be 1 nor lower.");
+            settings.max_columns = 10;
+            rewrite = true;
+        }
+        if settings.min_columns > settings.max_columns {
+            logwarn!("Failed to load settings, rewriting. \"min_columns\" count cannot be higher than \"max_column\"."); 
+            settings.min_columns = 1;
+            rewrite = true;
+        }
+        if settings.ip.parse::<std::net::IpAddr>().is_err() {
+            logwarn!("Failed to load settings, rewriting. \"ip\" is \"{}\", which is not a valid IPv4 or IPv6 address, using {}.", settings.ip, DEFAULT_IP);
+            settings.ip = DEFAULT_IP.to_string();
+            rewrite = true;
+        }
+        if !(1..=65535).contains(&settings.port) {
+            logwarn!("Failed to load settings, rewriting. \"port\" is {}, it must be between 1 and 65535, using {}.", settings.port, DEFAULT_PORT);
+            settings.port = DEFAULT_PORT;
+            rewrite = true;
+        }
+        if settings.workers < 1 {
+            logwarn!("Failed to load settings, rewriting. \"workers\" cannot be lower than zero.");
+            settings.workers = 1;
+            rewrite = true;
         }
+        if settings.max_conditions < 1 {
+            logwarn!("Failed to load settings, rewriting. \"max_conditions\" cannot be lower than one.");
+            settings.max_conditions = default_max_conditions();
+            rewrite = true;
+        }
+        if settings.max_graveyard_length < 1 {
+            logwarn!("Failed to load settings, rewriting. \"max_graveyard_length\" cannot be lower than one.");
+            settings.max_graveyard_length = default_max_graveyard_length();
+            rewrite = true;
+        }
+        if settings.vacuum_chunk_size < MIN_VACUUM_CHUNK_SIZE {
+            logwarn!("Failed to load settings, rewriting. \"vacuum_chunk_size\" cannot be lower than {}.", MIN_VACUUM_CHUNK_SIZE);
+            settings.vacuum_chunk_size = default_vacuum_chunk_size();
+            rewrite = true;
+        }
+        if !(0.0..1.0).contains(&settings.auto_vacuum_threshold) {
+            logwarn!("Failed to load settings, rewriting. \"auto_vacuum_threshold\" must be at least 0 and below 1.");
+            settings.auto_vacuum_threshold = 0.0;
+            rewrite = true;
+        }
+        if settings.max_vacuum_pairs < 1 {
+            logwarn!("Failed to load settings, rewriting. \"max_vacuum_pairs\" cannot be lower than one.");
+            settings.max_vacuum_pairs = default_max_vacuum_pairs();
+            rewrite = true;
+        }
+       
+        if rewrite {
+            
+            let new_yaml = serde_yaml::to_string(&settings)
+                .map_err(|e| Error::new(ErrorKind::Other, format!("Serialize failed: {}", e)))?;
+            
+            fs::write(&path, new_yaml)
+                .map_err(|e| Error::new(e.kind(), format!("Failed to rewrite {}: {}", SETTINGS_FILE, e)))?;
+            
+        }
+        settings.durability.set();
+        self.read_only = settings.read_only;
+        set_group_commit_window(settings.group_commit_window);
+        self.rate_limiters = RateLimiters::new(&settings.rate_limits);
+        self.result_cache = ResultCache::new(settings.result_cache_size);
         self.settings = settings;
         
         Ok(())
     }
     
-    fn get_container_headers(&self, container_name: &str) -> Result<((Vec<String>, Vec<AlbaTypes>),u64), Error> {
+    fn get_container_headers(&self, container_name: &str) -> Result<((Vec<String>, Vec<AlbaTypes>),ContainerOptions,u64), Error> {
         let path = format!("{}/{}", self.location, container_name);
         let exists = fs::exists(&path)?;
         
         if exists {
-            let mut file = fs::File::open(&path)?;
-            let val = get_container_headers(&mut file)?;
-            return Ok(((val.0,val.1),val.2 as u64))
+            let mut val = get_container_headers(&fs::File::open(&path)?)?;
+            if val.4 < FORMAT_VERSION{
+                upgrade_container(&path, val.0, val.1, val.2, val.3, self.settings.tuning())?;
+                val = get_container_headers(&fs::File::open(&path)?)?;
+            }
+            return Ok(((val.0,val.1),val.2,val.3))
         }
         
-        Err(gerr("Container not found"))
+        Err(Error::from(TytoError::NotFound("Container not found".to_string())))
+    }
+    /// Runs `ast`, abandoning it once `timeout` passes. Only reads and statements that stage changes can be abandoned,
+    /// the rows a statement staged before that are dropped. Anything else writes files and runs to completion.
+    pub async fn run_with_timeout(&mut self, ast: AST, timeout: std::time::Duration) -> Result<Query, Error> {
+        let staged = match &ast{
+            AST::CreateRow(AstCreateRow{container,..}) | AST::CreateRows(AstCreateRows{container,..})
+            | AST::CreateRowNamed(crate::AstCreateRowNamed{container,..}) | AST::ReplaceRow(crate::AstReplaceRow{container,..})
+            | AST::EditRow(AstEditRow{container,..}) | AST::DeleteRow(AstDeleteRow{container,..}) => Some(container.clone()),
+            AST::Search(_) | AST::SearchSet(_) | AST::Exists(_) | AST::RowCount(_) | AST::IndexStats(_) | AST::Stats
+            | AST::FetchCursor(_) | AST::Prepare(_) | AST::Execute(_) => None,
+            _ => return self.run(ast).await
+        };
+        let mark = match staged.and_then(|name|self.container.get(&name).cloned()){
+            Some(container) => {
+                let mark = container.lock().await.mark_pending().await?;
+                Some((container,mark))
+            },
+            None => None
+        };
+        self.deadline = Some(Instant::now() + timeout);
+        let result = match tokio::time::timeout(timeout, self.run(ast)).await{
+            Ok(result) => result,
+            Err(_) => Err(Error::from(TytoError::Timeout(format!("The query ran past its timeout of {} ms", timeout.as_millis()))))
+        };
+        self.deadline = None;
+        if let (Err(e),Some((container,mark))) = (&result,mark){
+            if e.kind() == ErrorKind::TimedOut{
+                container.lock().await.restore_pending(mark).await?;
+            }
+        }
+        result
     }
     pub async fn run(&mut self, ast: AST) -> Result<Query, Error> {
         let min_column: usize = (self.settings.min_columns as usize).max(1);
         let max_columns: usize = self.settings.max_columns as usize;
+        let conditions = match &ast{
+            AST::Search(s) => Some(&s.conditions),
+            AST::Exists(s) => Some(&s.conditions),
+            AST::EditRow(s) => Some(&s.conditions),
+            AST::DeleteRow(s) => s.conditions.as_ref(),
+            _ => None
+        };
+        if self.read_only && changes_data(&ast){
+            return Err(Error::from(TytoError::PermissionDenied("The database is read-only, statements that change data are rejected.".to_string())));
+        }
+        if let Some(conditions) = conditions{
+            if conditions.0.len() > self.settings.max_conditions{
+                return Err(Error::from(TytoError::InvalidInput(format!("The query has {} conditions, the maximum set on the settings file is {}.", conditions.0.len(), self.settings.max_conditions))));
+            }
+        }
         
         match ast {
             AST::CreateContainer(structure) => {
                 if structure.name.len() > 60{
-                    return Err(gerr(&format!("Failed to create container, the maximum length of a container name is 60, the entered is {}",structure.name.len())))
+                    return Err(Error::from(TytoError::Schema(format!("Failed to create container, the maximum length of a container name is 60, the entered is {}",structure.name.len()))))
                 }
                 if structure.col_nam.len() != structure.col_val.len(){
-                    return Err(gerr("Failed to create container, the count of names does not match to the count of values"))
+                    return Err(Error::from(TytoError::Schema("Failed to create container, the count of names does not match to the count of values".to_string())))
                 }
                 if structure.col_val.len() == 0{
-                    return Err(gerr(&format!("Failed to create container, it must have at least {}",min_column)))
+                    return Err(Error::from(TytoError::Schema(format!("Failed to create container, it must have at least {}",min_column))))
                 }
                 if structure.col_val.len() > max_columns{
-                    return Err(gerr("Failed to create container, the count of columns are higher than the maximum set on the settings file."));
+                    return Err(Error::from(TytoError::Schema("Failed to create container, the count of columns are higher than the maximum set on the settings file.".to_string())));
+                }
+                for (n,column) in structure.primary_key.iter().enumerate(){
+                    if !structure.col_nam.contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the primary key uses the unknown column {}",column))))
+                    }
+                    if structure.primary_key[..n].contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the column {} appears twice in the primary key",column))))
+                    }
+                }
+                let single_key = match structure.primary_key.as_slice(){
+                    [] => structure.col_nam.first(),
+                    [column] => Some(column),
+                    _ => None
+                };
+                for (column,_) in structure.indexes.iter(){
+                    if !structure.col_nam.contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, cannot index the unknown column {}",column))))
+                    }
+                    if single_key == Some(column){
+                        return Err(Error::from(TytoError::Schema("Failed to create container, the primary key is already indexed and cannot take a secondary index.".to_string())))
+                    }
+                }
+                if structure.auto_increment{
+                    if single_key.is_none() || single_key != structure.col_nam.first(){
+                        return Err(Error::from(TytoError::Schema("Failed to create container, only a primary key made of the first column alone can auto-increment.".to_string())))
+                    }
+                    if !matches!(structure.col_val[0], AlbaTypes::Int(_) | AlbaTypes::Bigint(_)){
+                        return Err(Error::from(TytoError::Schema("Failed to create container, an auto-increment primary key must be INT or BIGINT.".to_string())))
+                    }
+                }
+                let mut defaults : Vec<(String,AlbaTypes)> = Vec::with_capacity(structure.defaults.len());
+                for (column,value) in structure.defaults{
+                    let position = match structure.col_nam.iter().position(|c|*c == column){
+                        Some(position) => position,
+                        None => return Err(Error::from(TytoError::Schema(format!("Failed to create container, cannot set a default for the unknown column {}",column))))
+                    };
+                    if defaults.iter().any(|d|d.0 == column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the column {} has more than one default",column))))
+                    }
+                    if structure.auto_increment && position == 0{
+                        return Err(Error::from(TytoError::Schema("Failed to create container, an auto-increment primary key can't have a default.".to_string())))
+                    }
+                    let value = structure.col_val[position].try_from_existing(value).map_err(|e|Error::from(TytoError::Schema(format!("Failed to create container, the default of column {} does not fit its type: {}",column,e))))?;
+                    defaults.push((column,value));
+                }
+                for (n,column) in structure.not_null.iter().enumerate(){
+                    if !structure.col_nam.contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, cannot make the unknown column {} NOT NULL",column))))
+                    }
+                    if structure.not_null[..n].contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the column {} is marked NOT NULL twice",column))))
+                    }
+                }
+                for (n,column) in structure.empty_as_null.iter().enumerate(){
+                    let position = match structure.col_nam.iter().position(|c|c == column){
+                        Some(position) => position,
+                        None => return Err(Error::from(TytoError::Schema(format!("Failed to create container, cannot store empty strings as NULL in the unknown column {}",column))))
+                    };
+                    if !structure.col_val[position].is_string(){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the column {} is not a string column and has no empty strings to store as NULL",column))))
+                    }
+                    if structure.empty_as_null[..n].contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the column {} stores empty strings as NULL twice",column))))
+                    }
+                    let in_key = if structure.primary_key.is_empty(){position == 0}else{structure.primary_key.contains(column)};
+                    if in_key{
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the primary key column {} can't store empty strings as NULL",column))))
+                    }
+                }
+                let mut indexes = structure.indexes;
+                for (n,column) in structure.unique.iter().enumerate(){
+                    if !structure.col_nam.contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, cannot make the unknown column {} UNIQUE",column))))
+                    }
+                    if structure.unique[..n].contains(column){
+                        return Err(Error::from(TytoError::Schema(format!("Failed to create container, the column {} is marked UNIQUE twice",column))))
+                    }
+                    if single_key == Some(column){
+                        return Err(Error::from(TytoError::Schema("Failed to create container, the primary key is already unique.".to_string())))
+                    }
+                    // uniqueness is checked through an ordered index, a hash one could report collisions as duplicates
+                    match indexes.iter().find(|i|i.0 == *column){
+                        Some((_,IndexKind::Hash)) => return Err(Error::from(TytoError::Schema(format!("Failed to create container, the UNIQUE column {} needs an ordered index, not a hash one",column)))),
+                        Some(_) => {},
+                        None => indexes.push((column.clone(),IndexKind::Ordered))
+                    }
                 }
+                // a key made of only the first column is the default and stays out of the header
+                let options = ContainerOptions{
+                    primary_key: if structure.primary_key.len() == 1 && structure.col_nam.first() == structure.primary_key.first(){Vec::new()}else{structure.primary_key.clone()},
+                    auto_increment: structure.auto_increment,
+                    defaults,
+                    not_null: structure.not_null,
+                    unique: structure.unique,
+                    primary_index: structure.primary_index,
+                    empty_as_null: structure.empty_as_null,
+                };
                 let path = format!("{}/{}",self.location,structure.name);
                 if self.container.get(&structure.name).is_some() || fs::exists(&path).unwrap(){
-                    return Err(gerr("Failed to create container, there is already a container with this name or a file with this name on the container directory."))
+                    return Err(Error::from(TytoError::Conflict("Failed to create container, there is already a container with this name or a file with this name on the container directory.".to_string())))
                 }
-                let mut file = fs::File::create_new(&path).unwrap();
                 let mut el : usize = 0;
                 for i in structure.col_val.iter(){
                     el += i.size()
                 }
+                let header = create_container_headers( structure.col_nam.clone(), structure.col_val.clone(), &options);
 
-                file.write_all(&create_container_headers( structure.col_nam.clone(), structure.col_val.clone())).unwrap();
-                self.containers.push(structure.name.clone());
-                
-                let c = Container::new(
-                    &path,
-                    el,
-                    structure.col_val,
-                    file.metadata()?.len(),
-                    structure.col_nam
-                ).await.unwrap();
+                // in-memory containers stay out of the container list, so they are never looked for on disk
+                let c = if structure.in_memory{
+                    let headers_offset = header.len() as u64;
+                    Container::new_in_memory(header, el, structure.col_val, headers_offset, structure.col_nam, options, self.settings.tuning()).await?
+                }else{
+                    let mut file = fs::File::create_new(&path).unwrap();
+                    file.write_all(&header).unwrap();
+                    self.containers.push(structure.name.clone());
+                    
+                    Container::new(
+                        &path,
+                        el,
+                        structure.col_val,
+                        file.metadata()?.len(),
+                        structure.col_nam,
+                        options,
+                        self.settings.tuning()
+                    ).await.unwrap()
+                };
+                {
+                    let mut container = c.lock().await;
+                    for (column,kind) in indexes{
+                        container.create_index(&column, kind).await?;
+                    }
+                }
+                c.lock().await.switch_session(self.active_session).await;
                 self.container.insert(structure.name, c);
                 self.save_containers().unwrap();
             },
@@ -542,107 +2380,339 @@ impl Database{
                 let mut container = match self.container.get_mut(&structure.container) {
                     None => {
                         
-                        return Err(gerr(&format!("Container '{}' does not exist.", structure.container)));
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
                     },
                     Some(a) => a.lock().await,
                 };
                 
-                if structure.col_nam.len() != structure.col_val.len() {
-                    
-                    return Err(gerr(&format!(
-                        "In CREATE ROW, expected {} values for the specified columns, but got {}",
-                        structure.col_nam.len(),
-                        structure.col_val.len()
-                    )));
+                let val = bind_row(&container, &structure.col_nam, structure.col_val)?;
+                if let Some(id) = container.push_row(val).await?{
+                    return Ok(Query{rows:(vec![container.primary_key[0].clone()],vec![Row{data:vec![id]}])})
                 }
-
-                let mut val  = container.columns();
-
-                let mut id_map = HashMap::new();
-                for i in container.column_names().into_iter().enumerate(){
-                    id_map.insert(i.1, i.0);
+            },
+            AST::CreateRows(structure) => {
+                let mut container = match self.container.get_mut(&structure.container) {
+                    None => {
+                        
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
+                    },
+                    Some(a) => a.lock().await,
+                };
+                let mut rows = Vec::with_capacity(structure.col_val.len());
+                for col_val in structure.col_val{
+                    rows.push(bind_row(&container, &structure.col_nam, col_val)?);
                 }
-
-                for i in structure.col_nam.into_iter().enumerate(){
-                    let val1 = &structure.col_val[i.0];
-                    if let Some(a) = id_map.get(&i.1){
-                        val[*a] = val1.clone();
-                    }
+                container.push_rows(rows).await?;
+            },
+            AST::MigratePrimaryKey(structure) => {
+                self.migrate_primary_key(&structure.container, structure.to).await?;
+            },
+            AST::AlterContainer(structure) => {
+                match structure.change{
+                    AlterChange::AddColumn{column,col_type,default} => self.add_column(&structure.container, column, col_type, default).await?,
+                    AlterChange::DropColumn(column) => self.drop_column(&structure.container, &column).await?,
+                }
+            },
+            AST::BulkLoad(structure) => {
+                let mut container = match self.container.get_mut(&structure.container) {
+                    None => {
+                        
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
+                    },
+                    Some(a) => a.lock().await,
+                };
+                let mut rows = Vec::with_capacity(structure.col_val.len());
+                for col_val in structure.col_val{
+                    rows.push(bind_row(&container, &structure.col_nam, col_val)?);
+                }
+                container.bulk_load(rows).await?;
+            },
+            AST::RowCount(structure) => {
+                let container = match self.container.get(&structure.container) {
+                    None => return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container)))),
+                    Some(a) => a.lock().await,
+                };
+                return Ok(Query{rows:(vec!["rows".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(container.row_count().await? as i64)]}])})
+            },
+            AST::ExportCsv(structure) => {
+                let container = match self.container.get(&structure.container) {
+                    None => return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container)))),
+                    Some(a) => a.lock().await,
+                };
+                let mut writer = std::io::BufWriter::new(fs::File::create(&structure.path)?);
+                let n = container.export_csv(&mut writer).await?;
+                return Ok(Query{rows:(vec!["rows".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}])})
+            },
+            AST::ExportJson(structure) => {
+                let container = match self.container.get(&structure.container) {
+                    None => return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container)))),
+                    Some(a) => a.lock().await,
+                };
+                let mut writer = std::io::BufWriter::new(fs::File::create(&structure.path)?);
+                let n = container.export_json(&mut writer).await?;
+                return Ok(Query{rows:(vec!["rows".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}])})
+            },
+            AST::ImportJson(structure) => {
+                let n = self.import_json(&structure.container, &structure.path).await?;
+                return Ok(Query{rows:(vec!["rows".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}])})
+            },
+            AST::Backup(structure) => {
+                let n = self.backup(&structure.path).await?;
+                return Ok(Query{rows:(vec!["files".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}])})
+            },
+            AST::Restore(structure) => {
+                let n = self.restore(&structure.path, structure.force).await?;
+                return Ok(Query{rows:(vec!["containers".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}])})
+            },
+            AST::Truncate(structure) => {
+                let mut container = match self.container.get_mut(&structure.container) {
+                    None => {
+                        
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
+                    },
+                    Some(a) => a.lock().await,
+                };
+                container.truncate().await?;
+            },
+            AST::CancelVacuum(structure) => {
+                let container = match self.container.get(&structure.container){
+                    Some(c) => c.clone(),
+                    None => return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))))
+                };
+                let progress = self.vacuums.get(&structure.container).filter(|p|p.running.load(Ordering::Relaxed)).cloned();
+                if let Some(progress) = &progress{
+                    progress.cancel.store(true, Ordering::Relaxed);
+                }
+                // the vacuum holds the container until it stopped, once it is free the vacuum is over
+                drop(container.lock().await);
+                return Ok(Query{rows:(vec!["cancelled".to_string()],vec![Row{data:vec![AlbaTypes::Bool(progress.is_some())]}])})
+            },
+            AST::Preallocate(structure) => {
+                let mut container = match self.container.get_mut(&structure.container) {
+                    None => {
+                        
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
+                    },
+                    Some(a) => a.lock().await,
+                };
+                container.preallocate(structure.rows).await?;
+            },
+            AST::ReplaceRow(structure) => {
+                let mut container = match self.container.get_mut(&structure.container) {
+                    None => {
+                        
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
+                    },
+                    Some(a) => a.lock().await,
+                };
+                container.replace_row(structure.col_val).await?;
+            },
+            AST::CreateRowNamed(structure) => {
+                let mut container = match self.container.get_mut(&structure.container) {
+                    None => {
+                        
+                        return Err(Error::from(TytoError::NotFound(format!("Container '{}' does not exist.", structure.container))));
+                    },
+                    Some(a) => a.lock().await,
+                };
+                let val = bind_named_row(&container, structure.values)?;
+                if let Some(id) = container.push_row(val).await?{
+                    return Ok(Query{rows:(vec![container.primary_key[0].clone()],vec![Row{data:vec![id]}])})
                 }
-
-                container.push_row(val).await?;                
             },
             AST::Search(structure) => {
+                let read : Vec<String> = structure.col_nam.iter().chain(structure.aggregates.iter().map(|a|&a.1)).chain(structure.group_by.iter()).cloned().collect();
+                self.check_column_access(structure.principal.as_deref(), &structure.container, &read, &structure.conditions)?;
+                if let Some(page) = structure.page{
+                    return self.open_cursor(structure, page).await
+                }
+                if let Some(id) = structure.snapshot{
+                    self.expire_snapshots().await;
+                    match self.snapshots.get_mut(&id){
+                        Some(used) => *used = Instant::now(),
+                        None => return Err(Error::from(TytoError::NotFound(format!("There is no open snapshot with the id {}", id))))
+                    }
+                }
                 let container = if let Some(a) = self.container.get(&structure.container){
                     a
                 }else{
-                    return Err(gerr("There is no container with the given name"))
+                    return Err(Error::from(TytoError::NotFound("There is no container with the given name".to_string())))
+                };
+                // snapshot reads see an older state than the cache describes
+                let cache_key = if self.result_cache.is_enabled() && structure.snapshot.is_none() && !structure.explain{
+                    let key = format!("{:?}", (&structure.conditions, &structure.col_nam, structure.limit, structure.offset, structure.count, &structure.aggregates, &structure.group_by, structure.distinct));
+                    Some((key, container.lock().await.version.load(std::sync::atomic::Ordering::SeqCst)))
+                }else{
+                    None
                 };
+                if let Some((key,version)) = &cache_key{
+                    if let Some(q) = self.result_cache.get(&structure.container, key, *version){
+                        return Ok(q)
+                    }
+                }
                 let sa = {
                     let c = container.clone();
                     let sa = c.lock().await;
 
-                    let col_prop = {
-                        let mut h = HashMap::new();
-                        for i in sa.headers.clone(){
-                            h.insert(i.0,i.1);
-                        }
-                        h
-                    };
-                    let pk = sa.headers[0].0.clone();
                     SearchArguments { 
                         element_size: sa.element_size,
                         header_offset: sa.headers_offset as usize,
                         file: sa.file.clone(),
-                        conditions: QueryConditions::from_primitive_conditions(structure.conditions,&col_prop,pk)?
+                        conditions: QueryConditions::from_primitive_conditions(structure.conditions,&sa.column_properties,sa.primary_key.clone())?.with_indexes(sa.secondary_indexes.lock().await.kinds()).with_primary_index(sa.options.primary_index),
+                        // DISTINCT has to see every match before the window is cut
+                        limit: if structure.distinct {None} else {structure.limit},
+                        offset: if structure.distinct {None} else {structure.offset},
+                        snapshot: structure.snapshot,
+                        after: None,
+                        parallelism: self.scan_threads(),
+                        mmap: self.settings.mmap_scans,
+                        deadline: self.deadline,
+                        pending: structure.pending
                     }
                 };
-                let mut rows = search(container.clone(), sa).await?.0;
-                let cn = {container.lock().await.column_names().clone()};
-                if structure.col_nam.len() != cn.len(){
-                let mut index_map = HashMap::with_capacity(cn.len());
-                let mut ide = Vec::with_capacity(cn.len());
-                for i in cn.into_iter().enumerate(){index_map.insert(i.1,i.0);}
-                    for i in structure.col_nam.iter(){
-                        if let Some(a) = index_map.get(i){
-                                ide.push(*a);
+                if structure.explain{
+                    return explain(container.clone(), &sa.conditions, structure.snapshot.is_some()).await
+                }
+                self.throttle_scan(structure.principal.as_deref(), &sa.conditions)?;
+                if structure.count{
+                    let n = count(container.clone(), sa).await?;
+                    return Ok(self.cache_result(&structure.container, cache_key, Query { rows: (vec!["count".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(n as i64)]}]) }))
+                }
+                if !structure.aggregates.is_empty() || !structure.group_by.is_empty(){
+                    let (template,group_positions) = {
+                        let c = container.lock().await;
+                        let template = structure.aggregates.iter().map(|(kind,column)|Accumulator::new(*kind, column, &c.headers)).collect::<Result<Vec<_>,Error>>()?;
+                        let mut positions = Vec::with_capacity(structure.group_by.len());
+                        for column in structure.group_by.iter(){
+                            positions.push(c.headers.iter().position(|h|h.0 == *column).ok_or(Error::from(TytoError::NotFound(format!("Can't group by '{}', the column doesn't exist", column))))?);
+                        }
+                        (template,positions)
+                    };
+                    // keyed by the order-preserving encoding of the group columns, so groups come out sorted by their key
+                    let mut groups : BTreeMap<Vec<u8>,(Vec<AlbaTypes>,Vec<Accumulator>)> = BTreeMap::new();
+                    for row in search(container.clone(), sa).await?.0{
+                        let key_values : Vec<AlbaTypes> = group_positions.iter().map(|p|row.data[*p].clone()).collect();
+                        let group = groups.entry(group_key(&key_values)).or_insert_with(||(key_values,template.clone()));
+                        for acc in group.1.iter_mut(){
+                            acc.push(&row.data)?;
                         }
                     }
-                    rows = rows.into_iter().map(|f|{
-                        let mut val = Vec::with_capacity(ide.len());
-                        for i in ide.iter(){
-                            val.push(f.data[*i].to_owned());
+                    if groups.is_empty() && structure.group_by.is_empty(){
+                        groups.insert(Vec::new(), (Vec::new(),template.clone()));
+                    }
+                    let mut rows = Vec::with_capacity(groups.len());
+                    for (_,(mut key_values,accumulators)) in groups{
+                        for acc in accumulators.iter(){
+                            key_values.push(acc.finish()?);
                         }
-                        Row{data:val}
-                    }).collect();
+                        rows.push(Row{data:key_values});
+                    }
+                    let columns = structure.group_by.iter().cloned().chain(template.iter().map(|a|a.label())).collect();
+                    return Ok(self.cache_result(&structure.container, cache_key, Query { rows: (columns,rows) }))
+                }
+                let cn = {container.lock().await.column_names().clone()};
+                let mut rows = project_rows(search(container.clone(), sa).await?.0, &cn, &structure.col_nam);
+                if structure.distinct{
+                    let mut seen = HashSet::with_capacity(rows.len());
+                    rows = rows.into_iter().filter(|r|seen.insert(group_key(&r.data))).skip(structure.offset.unwrap_or(0)).take(structure.limit.unwrap_or(usize::MAX)).collect();
                 }
                 let q = Query { rows: (structure.col_nam.clone(),rows ) };
                 
-                return Ok(q)
+                return Ok(self.cache_result(&structure.container, cache_key, q))
+            },
+            AST::SearchSet(structure) => {
+                let mut names : Vec<String> = self.container.keys().filter(|name|container_set_matches(&structure.pattern, name)).cloned().collect();
+                if names.is_empty(){
+                    return Err(Error::from(TytoError::NotFound(format!("No container matches '{}'", structure.pattern))));
+                }
+                if names.len() > MAX_CONTAINER_SET{
+                    return Err(Error::from(TytoError::InvalidInput(format!("'{}' matches {} containers, a search can span at most {}", structure.pattern, names.len(), MAX_CONTAINER_SET))));
+                }
+                names.sort();
+                let mut rows = Vec::new();
+                for name in names{
+                    let columns = self.container[&name].lock().await.column_names();
+                    if let Some(missing) = structure.col_nam.iter().find(|c|!columns.contains(c)){
+                        return Err(Error::from(TytoError::NotFound(format!("The container '{}' has no column '{}'", name, missing))));
+                    }
+                    let remaining = match structure.limit{
+                        Some(l) if rows.len() >= l => break,
+                        Some(l) => Some(l - rows.len()),
+                        None => None
+                    };
+                    let q = Box::pin(self.run(AST::Search(AstSearch{
+                        container: name,
+                        conditions: structure.conditions.clone(),
+                        col_nam: structure.col_nam.clone(),
+                        snapshot: None,
+                        principal: structure.principal.clone(),
+                        limit: remaining,
+                        offset: None,
+                        count: false,
+                        aggregates: Vec::new(),
+                        group_by: Vec::new(),
+                        distinct: false,
+                        explain: false,
+                        page: None,
+                        pending: false,
+                    }))).await?;
+                    rows.extend(q.rows.1);
+                }
+                return Ok(Query { rows: (structure.col_nam, rows) })
+            },
+            AST::Exists(structure) => {
+                self.check_column_access(structure.principal.as_deref(), &structure.container, &[], &structure.conditions)?;
+                let container = if let Some(a) = self.container.get(&structure.container){
+                    a
+                }else{
+                    return Err(Error::from(TytoError::NotFound("There is no container with the given name".to_string())))
+                };
+                let sa = {
+                    let c = container.clone();
+                    let sa = c.lock().await;
+
+                    SearchArguments { 
+                        element_size: sa.element_size,
+                        header_offset: sa.headers_offset as usize,
+                        file: sa.file.clone(),
+                        conditions: QueryConditions::from_primitive_conditions(structure.conditions,&sa.column_properties,sa.primary_key.clone())?.with_indexes(sa.secondary_indexes.lock().await.kinds()).with_primary_index(sa.options.primary_index),
+                        limit: Some(1),
+                        offset: None,
+                        snapshot: None,
+                        after: None,
+                        parallelism: self.scan_threads(),
+                        mmap: self.settings.mmap_scans,
+                        deadline: self.deadline,
+                        pending: false
+                    }
+                };
+                self.throttle_scan(structure.principal.as_deref(), &sa.conditions)?;
+                let found = !search(container.clone(), sa).await?.0.is_empty();
+                return Ok(Query { rows: (vec!["exists".to_string()],vec![Row{data:vec![AlbaTypes::Bool(found)]}]) })
             },
             AST::EditRow(structure) => {
                 let container = if let Some(a) = self.container.get(&structure.container){
                     a
                 }else{
-                    return Err(gerr("There is no container with the given name"))
+                    return Err(Error::from(TytoError::NotFound("There is no container with the given name".to_string())))
                 };
                 let sa = {
                     let c = container.clone();
                     let sa = c.lock().await;
 
-                    let col_prop = {
-                        let mut h = HashMap::new();
-                        for i in sa.headers.clone(){
-                            h.insert(i.0,i.1);
-                        }
-                        h
-                    };
-                    let pk = sa.headers[0].0.clone();
                     SearchArguments { 
                         element_size: sa.element_size,
                         header_offset: sa.headers_offset as usize,
                         file: sa.file.clone(),
-                        conditions: QueryConditions::from_primitive_conditions(structure.conditions,&col_prop,pk)?
+                        conditions: QueryConditions::from_primitive_conditions(structure.conditions,&sa.column_properties,sa.primary_key.clone())?.with_indexes(sa.secondary_indexes.lock().await.kinds()).with_primary_index(sa.options.primary_index),
+                        limit: None,
+                        offset: None,
+                        snapshot: None,
+                        after: None,
+                        parallelism: self.scan_threads(),
+                        mmap: self.settings.mmap_scans,
+                        deadline: self.deadline,
+                        pending: false
                     }
                 };
                 let mut rows = search(container.clone(), sa).await?;
@@ -661,50 +2731,55 @@ impl Database{
                     for j in indexes.iter(){
                         i.data[j.0] = j.1.clone();
                     }
+                    c.empty_strings_to_null(&mut i.data);
+                    c.check_not_null(&i.data)?;
                 }
-                for i in rows.0.iter().zip(rows.1.iter()){
-                    c.mvcc.lock().await.0.insert(*i.1, (MvccState::Edit,i.0.data.clone()));
-                }
-                
+                c.stage(MvccState::Edit, rows.1.into_iter().zip(rows.0).map(|(offset,row)|(offset,row.data)).collect()).await?;
+
                 return Ok(Query { rows: (vec![],vec![]) })
             },
             AST::DeleteRow(structure) => {
                 let container = if let Some(a) = self.container.get(&structure.container){
                     a
                 }else{
-                    return Err(gerr("There is no container with the given name"))
+                    return Err(Error::from(TytoError::NotFound("There is no container with the given name".to_string())))
                 };
                 let sa = {
                     let c = container.clone();
                     let sa = c.lock().await;
 
-                    let col_prop = {
-                        let mut h = HashMap::new();
-                        for i in sa.headers.clone(){
-                            h.insert(i.0,i.1);
-                        }
-                        h
-                    };
-                    let pk = sa.headers[0].0.clone();
                     SearchArguments { 
                         element_size: sa.element_size,
                         header_offset: sa.headers_offset as usize,
                         file: sa.file.clone(),
-                        conditions: QueryConditions::from_primitive_conditions(if let Some(a) = structure.conditions{a}else{(Vec::new(),Vec::new())},&col_prop,pk)?
+                        conditions: QueryConditions::from_primitive_conditions(if let Some(a) = structure.conditions{a}else{(Vec::new(),Vec::new())},&sa.column_properties,sa.primary_key.clone())?.with_indexes(sa.secondary_indexes.lock().await.kinds()).with_primary_index(sa.options.primary_index),
+                        limit: None,
+                        offset: None,
+                        snapshot: None,
+                        after: None,
+                        parallelism: self.scan_threads(),
+                        mmap: self.settings.mmap_scans,
+                        deadline: self.deadline,
+                        pending: false
                     }
                 };
                 
                 let (values,indexes) = search(container.clone(), sa).await?;
-                let container = container.lock().await;
-                let mut mvcc = container.mvcc.lock().await;
-                for (i,val) in indexes.into_iter().zip(values){
-                    mvcc.0.insert(i,(MvccState::Delete,vec![val.data[0].clone()]));
-                }
+                container.lock().await.stage(MvccState::Delete, indexes.into_iter().zip(values).map(|(offset,row)|(offset,row.data)).collect()).await?;
                 return Ok(Query{rows:(Vec::new(),Vec::new())})
             },
+            AST::ApplyFile(structure) => {
+                return self.apply_file(&structure.path).await
+            },
+            AST::RestoreContainer(structure) => {
+                self.restore_from_trash(&structure.container).await?;
+            },
             AST::DeleteContainer(structure) => {
-                
-                if self.containers.contains(&structure.container) {
+                self.result_cache.invalidate(&structure.container);
+                if !self.containers.contains(&structure.container) && self.container.contains_key(&structure.container){
+                    // an in-memory container has nothing on disk to trash or remove
+                    self.container.remove(&structure.container);
+                } else if self.containers.contains(&structure.container) {
                     let mut ind = Vec::new();
                     for (i, name) in self.containers.iter().enumerate() {
                         if structure.container == *name {
@@ -712,43 +2787,219 @@ impl Database{
                             
                         }
                     }
-                    for i in ind {
-                        self.containers.remove(i);
-                        
+                    for i in ind {
+                        self.containers.remove(i);
+                        
+                    }
+                    self.container.remove(&structure.container);
+                    
+                    if !structure.purge && self.settings.trash_retention > 0{
+                        self.move_to_trash(&structure.container)?;
+                    }else{
+                        for suffix in CONTAINER_FILE_SUFFIXES{
+                            let path = format!("{}/{}{}", self.location, structure.container, suffix);
+                            let _ = tokio::fs::remove_file(path).await;
+                        }
+                    }
+                    for suffix in CONTAINER_TEMP_SUFFIXES{
+                        let path = format!("{}/{}{}", self.location, structure.container, suffix);
+                        let _ = tokio::fs::remove_file(path).await;
+                    }
+
+                    
+                    self.save_containers()?;
+                    
+                } else {
+                    
+                    return Err(Error::from(TytoError::NotFound(format!("There is no database with the name {}", structure.container))));
+                }
+            },
+            AST::IndexStats(structure) => {
+                let mut names : Vec<&String> = match &structure.container{
+                    Some(name) => {
+                        if !self.container.contains_key(name){
+                            return Err(Error::from(TytoError::NotFound(format!("There is no container named {}", name))));
+                        }
+                        vec![name]
+                    },
+                    None => self.container.keys().collect()
+                };
+                names.sort();
+                let mut rows = Vec::with_capacity(names.len());
+                for name in names{
+                    let c = self.container[name].lock().await;
+                    let index = c.index_map.lock().await;
+                    let probes = index.probe_stats();
+                    rows.push(Row{data:vec![
+                        AlbaTypes::LargeString(name.clone()),
+                        AlbaTypes::LargeString(format!("{:?}",index.kind()).to_lowercase()),
+                        AlbaTypes::Bigint(index.len() as i64),
+                        AlbaTypes::Bigint(index.bucket_count() as i64),
+                        AlbaTypes::Bigint(probes.operations as i64),
+                        AlbaTypes::Float(probes.average()),
+                        AlbaTypes::Bigint(probes.max_probe as i64),
+                    ]});
+                }
+                let columns = ["container","kind","keys","buckets","operations","average_probe","max_probe"].iter().map(|c|c.to_string()).collect();
+                return Ok(Query { rows: (columns,rows) })
+            },
+            AST::VacuumAll => {
+                // one container at a time, each vacuum already syncs every row it moves
+                let mut names : Vec<String> = self.container.keys().cloned().collect();
+                names.sort();
+                let mut rows = Vec::with_capacity(names.len());
+                for name in names{
+                    let progress = self.vacuums.entry(name.clone()).or_default().clone();
+                    let mut c = self.container[&name].lock().await;
+                    // a vacuum throws away every staged change, containers in the middle of a transaction are left alone
+                    let vacuumed = !c.has_pending().await;
+                    if vacuumed{
+                        c.vacuum(&progress).await?;
+                    }
+                    let reclaimed = if vacuumed {progress.bytes_reclaimed.load(Ordering::Relaxed)} else {0};
+                    rows.push(Row{data:vec![
+                        AlbaTypes::LargeString(name),
+                        AlbaTypes::Bool(vacuumed),
+                        AlbaTypes::Bigint(reclaimed as i64),
+                    ]});
+                }
+                let columns = ["container","vacuumed","reclaimed"].iter().map(|c|c.to_string()).collect();
+                return Ok(Query { rows: (columns,rows) })
+            },
+            AST::Stats => {
+                let mut names : Vec<&String> = self.container.keys().collect();
+                names.sort();
+                // one row per container, so the count of rows is the count of containers
+                let mut rows = Vec::with_capacity(names.len());
+                for name in names{
+                    let progress = self.vacuums.get(name).cloned().unwrap_or_default();
+                    // a running vacuum holds its container until it is done, only its progress can be read meanwhile
+                    let c = match self.container[name].try_lock(){
+                        Ok(c) => Some(c),
+                        Err(_) if progress.running.load(Ordering::Relaxed) => None,
+                        Err(_) => Some(self.container[name].lock().await)
+                    };
+                    let mut data = vec![AlbaTypes::LargeString(name.clone())];
+                    match c{
+                        Some(c) => {
+                            let pending = c.mvcc.lock().await.0.len() + c.parked.values().map(|p|p.0.len()).sum::<usize>();
+                            let graveyard = c.graveyard.lock().await.len();
+                            let mut bytes = 0u64;
+                            if !c.backend.persistent(){
+                                bytes = c.file.lock().await.len()?;
+                            }else{
+                                for suffix in CONTAINER_FILE_SUFFIXES{
+                                    if let Ok(metadata) = fs::metadata(format!("{}/{}{}", self.location, name, suffix)){
+                                        bytes += metadata.len();
+                                    }
+                                }
+                            }
+                            data.extend([
+                                AlbaTypes::Bigint(c.row_count().await? as i64),
+                                AlbaTypes::Bigint(pending as i64),
+                                AlbaTypes::Bigint(graveyard as i64),
+                                AlbaTypes::Bigint(bytes as i64),
+                            ]);
+                        },
+                        None => data.extend([AlbaTypes::NONE,AlbaTypes::NONE,AlbaTypes::NONE,AlbaTypes::NONE])
+                    }
+                    data.extend([
+                        AlbaTypes::Bool(progress.running.load(Ordering::Relaxed)),
+                        AlbaTypes::Bigint(progress.rows_scanned.load(Ordering::Relaxed) as i64),
+                        AlbaTypes::Bigint(progress.pairs_relocated.load(Ordering::Relaxed) as i64),
+                        AlbaTypes::Bigint(progress.pairs_planned.load(Ordering::Relaxed) as i64),
+                        AlbaTypes::Bigint(progress.bytes_reclaimed.load(Ordering::Relaxed) as i64),
+                    ]);
+                    rows.push(Row{data});
+                }
+                let columns = ["container","rows","pending","graveyard","bytes","vacuuming","vacuum_scanned","vacuum_relocated","vacuum_planned","vacuum_reclaimed"].iter().map(|c|c.to_string()).collect();
+                return Ok(Query { rows: (columns,rows) })
+            },
+            AST::OpenSnapshot => {
+                let id = self.open_snapshot().await?;
+                return Ok(Query { rows: (vec!["snapshot".to_string()],vec![Row{data:vec![AlbaTypes::Bigint(id as i64)]}]) })
+            },
+            AST::CloseSnapshot(structure) => {
+                self.close_snapshot(structure.id).await?;
+            },
+            AST::RecoveryStatus => {
+                let mut names : Vec<&String> = self.container.keys().collect();
+                names.sort();
+                let mut rows = Vec::new();
+                for name in names{
+                    let c = self.container[name].lock().await;
+                    let recovered = match c.parked.get(&RECOVERED_SESSION){
+                        Some(r) => r,
+                        None => continue
+                    };
+                    let mut counts = [0i64;3];
+                    for (state,_) in recovered.0.values(){
+                        counts[match state{MvccState::Insert => 0, MvccState::Edit => 1, MvccState::Delete => 2}] += 1;
                     }
-                    self.container.remove(&structure.container);
-                    
-                    let path = format!("{}/{}", self.location, structure.container);
-                    let _ = tokio::fs::remove_file(path.clone()).await;
-                    let path = format!("{}/{}.index", self.location, structure.container);
-                    let _ = tokio::fs::remove_file(path.clone()).await;
-                    let path = format!("{}/{}.hashmap", self.location, structure.container);
-                    let _ = tokio::fs::remove_file(path).await;
-                    let path = format!("{}/{}.mr", self.location, structure.container);
-                    let _ = tokio::fs::remove_file(path).await;
-
-                    
-                    self.save_containers()?;
-                    
-                } else {
-                    
-                    return Err(gerr(&format!("There is no database with the name {}", structure.container)));
+                    rows.push(Row{data:vec![
+                        AlbaTypes::LargeString(name.clone()),
+                        AlbaTypes::Bigint(counts[0]),
+                        AlbaTypes::Bigint(counts[1]),
+                        AlbaTypes::Bigint(counts[2]),
+                    ]});
+                }
+                let columns = ["container","inserts","edits","deletes"].iter().map(|c|c.to_string()).collect();
+                return Ok(Query { rows: (columns,rows) })
+            },
+            AST::RecoveryCommit => {
+                self.settle_recovered(true).await?;
+            },
+            AST::RecoveryRollback => {
+                self.settle_recovered(false).await?;
+            },
+            AST::Savepoint(structure) => {
+                self.savepoint(structure.name).await?;
+            },
+            AST::RollbackTo(structure) => {
+                self.rollback_to(&structure.name).await?;
+            },
+            AST::Release(structure) => {
+                let savepoints = self.savepoints.entry(self.active_session).or_default();
+                match savepoints.iter().position(|s|s.name == structure.name){
+                    Some(position) => savepoints.truncate(position),
+                    None => return Err(Error::from(TytoError::NotFound(format!("There is no savepoint named {}", structure.name))))
+                }
+            },
+            AST::FetchCursor(structure) => {
+                return self.fetch_cursor(structure.id).await
+            },
+            AST::CloseCursor(structure) => {
+                if self.cursors.remove(&structure.id).is_none(){
+                    return Err(Error::from(TytoError::NotFound(format!("There is no open cursor with the id {}", structure.id))))
+                }
+            },
+            AST::Prepare(structure) => {
+                return self.prepare(structure).await
+            },
+            AST::Execute(structure) => {
+                return self.execute(structure.id, structure.values).await
+            },
+            AST::Deallocate(structure) => {
+                if self.prepared.remove(&structure.id).is_none(){
+                    return Err(Error::from(TytoError::NotFound(format!("There is no prepared statement with the id {}", structure.id))))
                 }
             },
             AST::Commit(structure) => {
-                
+                if let Some(container) = &structure.container{
+                    self.forget_savepoints(container);
+                }
                 match structure.container {
                     Some(container) => {
                         match self.container.get_mut(&container) {
                             Some(a) => {
                                 
                                 a.lock().await.commit().await.unwrap();
-                                
+                                self.queue_auto_vacuums(vec![container]).await;
                                 return Ok(Query{rows:(Vec::new(),Vec::new())});
                             },
                             None => {
                                 
-                                return Err(gerr(&format!("There is no container named {}", container)));
+                                return Err(Error::from(TytoError::NotFound(format!("There is no container named {}", container))));
                             }
                         }
                     },
@@ -760,7 +3011,9 @@ impl Database{
                 }
             },
             AST::Rollback(structure) => {
-                
+                if let Some(container) = &structure.container{
+                    self.forget_savepoints(container);
+                }
                 match structure.container {
                     Some(container) => {
                         match self.container.get_mut(&container) {
@@ -772,7 +3025,7 @@ impl Database{
                             },
                             None => {
                                 
-                                return Err(gerr(&format!("There is no container named {}", container)));
+                                return Err(Error::from(TytoError::NotFound(format!("There is no container named {}", container))));
                             }
                         }
                     },
@@ -795,8 +3048,13 @@ impl Database{
     // }
 }
 
+/// Opens the database in the directory named by `database_path`.
 pub async fn connect() -> Result<Database, Error>{
-    let dbp = database_path();
+    connect_at(&database_path()?).await
+}
+
+/// Opens the database in `dbp`, creating the directory if it does not exist.
+pub async fn connect_at(dbp : &str) -> Result<Database, Error>{
     let path : &str = if dbp.ends_with('/') {
         &dbp[..dbp.len()-1]
     }else{
@@ -819,7 +3077,7 @@ pub async fn connect() -> Result<Database, Error>{
     //     start_strix(strix.clone()).await;
     // }
 
-    let mut db = Database{location:database_path().to_string(),settings:Default::default(),containers:Vec::new(),headers:Vec::new(),container:HashMap::new()};
+    let mut db = Database{location:path.to_string(),settings:Default::default(),containers:Vec::new(),headers:Vec::new(),container:HashMap::new(),..Default::default()};
     db.setup().await?;
     if let Err(e) = db.load_settings(){
         logerr!("err: load_settings");
@@ -870,6 +3128,9 @@ fn ab_to_nat(a : AlbaTypes) -> NetworkAlbaTypes{
         AlbaTypes::MediumBytes(a) => NetworkAlbaTypes::Bytes(a),
         AlbaTypes::BigSBytes(a) => NetworkAlbaTypes::Bytes(a),
         AlbaTypes::LargeBytes(a) => NetworkAlbaTypes::Bytes(a),
+        AlbaTypes::Uuid(a) => NetworkAlbaTypes::String(format_uuid(&a)),
+        AlbaTypes::Timestamp(a) => NetworkAlbaTypes::String(format_timestamp(a)),
+        AlbaTypes::Decimal(a) => NetworkAlbaTypes::String(a.to_string()),
         AlbaTypes::NONE => NetworkAlbaTypes::U8(0),
     }
 }
@@ -904,6 +3165,9 @@ fn alba_types_to_token(alba_type: AlbaTypes) -> Token {
         AlbaTypes::MediumBytes(items) => Token::Bytes(items),
         AlbaTypes::BigSBytes(items) => Token::Bytes(items),
         AlbaTypes::LargeBytes(items) => Token::Bytes(items),
+        AlbaTypes::Uuid(u) => Token::String(format_uuid(&u)),
+        AlbaTypes::Timestamp(t) => Token::String(format_timestamp(t)),
+        AlbaTypes::Decimal(d) => Token::String(d.to_string()),
         AlbaTypes::NONE => Token::Int(0),
     }
 }
@@ -929,299 +3193,519 @@ fn conditions_to_tyto_db(t: (Vec<(String, LogicalOperator, NetworkAlbaTypes)>, V
 }
 use falcotcp::Server;
 
+/// An error response: the error status byte, the code of `e` (see `TytoError::code`) and its message.
+fn error_response(e : TytoError) -> Vec<u8>{
+    let mut b = vec![RESPONSE_ERROR,e.code()];
+    b.extend_from_slice(e.to_string().as_bytes());
+    b
+}
+
+/// How a request runs, read from the bytes put before its command.
+#[derive(Clone,Debug,Default)]
+struct RequestOptions{
+    /// Milliseconds every statement may run for, overrides `query_timeout`.
+    timeout : Option<u64>,
+    /// Overrides `transaction_isolation` for a transaction.
+    isolation : Option<Isolation>,
+    /// The snapshot the statements of a SNAPSHOT transaction read from.
+    snapshot : Option<u64>,
+    /// Whose changes the request stages, commits and rolls back, 0 when the client names no session.
+    session : u64,
+    /// The key the request was signed with, `None` when the keystore is empty.
+    principal : Option<String>,
+}
+
+/// Whether `ast` changes the data, the statements a read-only database rejects.
+/// Commits and rollbacks are let through, nothing can be staged for them to apply.
+fn changes_data(ast : &AST) -> bool{
+    matches!(ast, AST::CreateContainer(_) | AST::CreateRow(_) | AST::CreateRows(_) | AST::BulkLoad(_) | AST::CreateRowNamed(_)
+        | AST::ReplaceRow(_) | AST::Preallocate(_) | AST::Truncate(_) | AST::ImportJson(_) | AST::Restore(_) | AST::MigratePrimaryKey(_)
+        | AST::AlterContainer(_) | AST::EditRow(_) | AST::DeleteRow(_) | AST::DeleteContainer(_) | AST::RestoreContainer(_)
+        | AST::ApplyFile(_) | AST::RecoveryCommit | AST::RecoveryRollback | AST::VacuumAll)
+}
+
+/// Vacuums `name` without holding the database. Requests that may wait on the container are held back by
+/// `lock_unvacuumed` until the vacuum is over, without holding the database either, so the others are answered meanwhile.
+/// A vacuum throws away the changes every session staged in the container, so a container holding some,
+/// which may belong to an open transaction, is left alone and `false` is returned, as is one another vacuum is running on.
+async fn vacuum_container(db : &Arc<Mutex<Database>>, name : &str) -> Result<bool,Error>{
+    let (mut c, progress) = {
+        let mut ldb = db.lock().await;
+        let container = match ldb.container.get(name){
+            Some(c) => c.clone(),
+            None => return Ok(false)
+        };
+        // with the database held, only a vacuum can be holding the container
+        let c = match container.try_lock_owned(){
+            Ok(c) => c,
+            Err(_) => return Ok(false)
+        };
+        if c.has_pending().await{
+            return Ok(false)
+        }
+        let progress = ldb.vacuums.entry(name.to_string()).or_default().clone();
+        // marked before the database is released, so no request can see the container free and then wait on it
+        progress.running.store(true, Ordering::Relaxed);
+        (c, progress)
+    };
+    c.vacuum(&progress).await?;
+    Ok(true)
+}
+
+/// Whether running `ast` may wait on the container `name`. Statements that don't name a single container may touch any.
+fn touches(ast : &AST, name : &str) -> bool{
+    match ast{
+        AST::CreateContainer(crate::AstCreateContainer{name:container,..})
+        | AST::CreateRow(AstCreateRow{container,..}) | AST::CreateRows(AstCreateRows{container,..}) | AST::BulkLoad(AstCreateRows{container,..})
+        | AST::CreateRowNamed(crate::AstCreateRowNamed{container,..}) | AST::ReplaceRow(crate::AstReplaceRow{container,..})
+        | AST::Preallocate(crate::AstPreallocate{container,..}) | AST::Truncate(crate::AstTruncate{container})
+        | AST::RowCount(crate::AstRowCount{container}) | AST::ExportCsv(crate::AstExport{container,..}) | AST::ExportJson(crate::AstExport{container,..})
+        | AST::ImportJson(crate::AstImport{container,..}) | AST::MigratePrimaryKey(crate::AstMigratePrimaryKey{container,..})
+        | AST::AlterContainer(crate::AstAlterContainer{container,..}) | AST::EditRow(AstEditRow{container,..}) | AST::DeleteRow(AstDeleteRow{container,..})
+        | AST::DeleteContainer(AstDeleteContainer{container,..}) | AST::RestoreContainer(crate::AstRestoreContainer{container})
+        | AST::Search(AstSearch{container,..}) | AST::Prepare(AstSearch{container,..}) | AST::Exists(crate::AstExists{container,..})
+        | AST::IndexStats(crate::AstIndexStats{container:Some(container)}) | AST::Commit(AstCommit{container:Some(container)})
+        | AST::Rollback(AstRollback{container:Some(container)}) => container == name,
+        // STATS only reads the progress of a running vacuum and CANCEL VACUUM stops it at its next safe point
+        AST::Stats | AST::CancelVacuum(_) | AST::CloseCursor(_) | AST::Deallocate(_) => false,
+        _ => true
+    }
+}
+
+/// Locks the database once no vacuum runs on a container `touched` says the caller may wait on.
+/// The vacuum is waited for without holding the database, see `vacuum_container`.
+async fn lock_unvacuumed<'a>(mtx_db : &'a Arc<Mutex<Database>>, touched : impl Fn(&Database,&str) -> bool) -> tokio::sync::MutexGuard<'a,Database>{
+    loop{
+        let db = mtx_db.lock().await;
+        let busy = db.vacuums.iter()
+            .find(|(name,progress)|progress.running.load(Ordering::Relaxed) && touched(&db,name))
+            .and_then(|(name,_)|db.container.get(name).cloned());
+        match busy{
+            Some(container) => {
+                drop(db);
+                drop(container.lock().await);
+            },
+            None => return db
+        }
+    }
+}
+
+/// Waits on the group syncs of `tickets` and settles the commits that became durable.
+/// Fails if any sync failed, the changes of those commits stay in their container's record.
+async fn wait_group_syncs(mtx_db : &'static Arc<Mutex<Database>>, tickets : Vec<(String,Vec<SyncTicket>)>) -> Result<(),Error>{
+    if tickets.is_empty(){
+        return Ok(())
+    }
+    let mut synced = Vec::new();
+    let mut failure = None;
+    for (name, tickets) in tickets{
+        let mut count = 0;
+        for ticket in tickets{
+            match ticket.wait().await{
+                Ok(()) => count += 1,
+                Err(e) => failure = Some(e)
+            }
+        }
+        synced.push((name, count));
+    }
+    lock_unvacuumed(mtx_db, |_,name|synced.iter().any(|(n,_)|n == name)).await.settle_synced(synced).await?;
+    match failure{
+        Some(e) => Err(e),
+        None => Ok(())
+    }
+}
 
-async fn process(mtx_db : &'static Arc<Mutex<Database>>,c : commands) -> Result<Query,Vec<u8>>{
+/// Runs `c`, every statement of a batch under its own timeout.
+async fn process(mtx_db : &'static Arc<Mutex<Database>>,c : commands, options : RequestOptions) -> Result<Query,Vec<u8>>{
     Ok(match c{
         commands::Batch(batch_batch) => {
+            let mut options = options;
+            if batch_batch.transaction && options.snapshot.is_none(){
+                let mut db = lock_unvacuumed(mtx_db, |_,_|true).await;
+                if options.isolation.unwrap_or(db.settings.transaction_isolation) == Isolation::Snapshot{
+                    options.snapshot = Some(db.open_snapshot().await.map_err(|e|error_response(e.into()))?);
+                }
+            }
             let mut que = Vec::new();
+            let mut failure = None;
             for i in batch_batch.commands{
-                let prrperpoewr = Box::pin(process(mtx_db,i)).await;
-                match prrperpoewr{
+                match Box::pin(process(mtx_db,i,options.clone())).await{
                     Ok(a) => que.push(a),
                     Err(e) => {
-                        if batch_batch.transaction{
-                            if let Err(e) = mtx_db.lock().await.rollback().await{
-                                let mut b = vec![1u8];
-                                b.extend_from_slice(&e.to_string().as_bytes());
-                                return Err(b)
-                            };
-                        }
-                        return Err(e)
+                        failure = Some(e);
+                        break
                     }
                 };
             }
+            if let Some(id) = options.snapshot{
+                let _ = lock_unvacuumed(mtx_db, |_,_|true).await.close_snapshot(id).await;
+            }
+            if let Some(e) = failure{
+                if batch_batch.transaction{
+                    let mut db = lock_unvacuumed(mtx_db, |_,_|true).await;
+                    if let Err(e) = db.use_session(options.session).await{
+                        return Err(error_response(e.into()))
+                    };
+                    if let Err(e) = db.rollback().await{
+                        return Err(error_response(e.into()))
+                    };
+                }
+                return Err(e)
+            }
             if batch_batch.transaction{
-                if let Err(e) = mtx_db.lock().await.commit().await{
-                    let mut b = vec![1u8];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
+                let mut db = lock_unvacuumed(mtx_db, |_,_|true).await;
+                if let Err(e) = db.use_session(options.session).await{
+                    return Err(error_response(e.into()))
+                };
+                if let Err(e) = db.commit().await{
+                    return Err(error_response(e.into()))
+                };
+                let tickets = db.take_sync_tickets().await;
+                drop(db);
+                if let Err(e) = wait_group_syncs(mtx_db, tickets).await{
+                    return Err(error_response(e.into()))
                 };
             }
-            let mut q = if let Some(a) = que.first(){
-                a.to_owned()
-            }else{
-                return Ok(Query{rows:(Vec::new(),Vec::new())})
+            merge_batch(que)
+        },
+        c => {
+            let ast = match command_to_ast(c){
+                Ok(a) => a,
+                Err(e) => return Err(error_response(e.into()))
             };
-            if que.len() > 2{
-                for i in 0..que.len()-2{
-                    q.rows.1.extend_from_slice(&que[i].rows.1);
-                }
+            run_ast(mtx_db, ast, options).await?
+        },
+    })
+}
+
+/// The rows of every command of a batch in batch order, under the column names of the first command that returned some.
+fn merge_batch(results : Vec<Query>) -> Query{
+    let mut q = Query{rows:(Vec::new(),Vec::new())};
+    for result in results{
+        if q.rows.0.is_empty(){
+            q.rows.0 = result.rows.0;
+        }
+        q.rows.1.extend(result.rows.1);
+    }
+    q
+}
+
+/// Runs a single statement the way every request does: in its session, under its timeout,
+/// and acknowledged only once the group sync of its commit is done.
+async fn run_ast(mtx_db : &'static Arc<Mutex<Database>>, mut ast : AST, options : RequestOptions) -> Result<Query,Vec<u8>>{
+    let create_container = matches!(ast, AST::CreateContainer(_));
+    if let (AST::Search(search),Some(id)) = (&mut ast,options.snapshot){
+        search.snapshot = Some(id);
+        search.pending = true;
+    }
+    // reads are checked against the column access and rate limits of the key that signed them
+    match &mut ast{
+        AST::Search(AstSearch{principal,..}) | AST::Prepare(AstSearch{principal,..}) | AST::Exists(crate::AstExists{principal,..})
+            | AST::SearchSet(crate::AstSearchSet{principal,..}) => *principal = options.principal.clone(),
+        _ => {}
+    }
+    // switching sessions touches every container, STATS and CANCEL VACUUM don't depend on the session and skip it
+    let switch = !matches!(ast, AST::Stats | AST::CancelVacuum(_));
+    let mut db = lock_unvacuumed(mtx_db, |db,name|(switch && db.active_session != options.session) || touches(&ast,name)).await;
+    if let (AST::VacuumAll,Some(principal)) = (&ast,&options.principal){
+        if let Err(e) = db.rate_limiters.check(principal, "vacuum"){
+            return Err(error_response(e.into()))
+        }
+    }
+    if switch{
+        if let Err(e) = db.use_session(options.session).await{
+            return Err(error_response(e.into()))
+        }
+    }
+    let timeout = options.timeout.unwrap_or(db.settings.query_timeout);
+    let result = if timeout > 0{
+        db.run_with_timeout(ast, std::time::Duration::from_millis(timeout)).await
+    }else{
+        db.run(ast).await
+    };
+    let tickets = db.take_sync_tickets().await;
+    drop(db);
+    // the commit is only acknowledged once the group it joined synced it
+    let result = match wait_group_syncs(mtx_db, tickets).await{
+        Err(e) if result.is_ok() => Err(e),
+        _ => result
+    };
+    match result{
+        Ok(mut q) => {
+            if create_container{
+                q.rows.0.push("success".to_string());
+                q.rows.1.push(Row{data:vec![AlbaTypes::Bool(true)]});
             }
-            q
+            Ok(q)
         },
+        Err(e) => Err(error_response(e.into()))
+    }
+}
+
+/// Maps a single network command to the AST it runs as. Batches are unrolled by `process` and rejected here.
+fn command_to_ast(c : commands) -> Result<AST,Error>{
+    Ok(match c{
+        commands::Batch(_) => return Err(Error::from(TytoError::InvalidInput("A batch can't be nested inside another statement list".to_string()))),
         commands::CreateContainer(create_container) => {
-            let mut col_v = Vec::new();
+            let mut col_val = Vec::new();
             for f in create_container.col_val{
-                match AlbaTypes::from_id(f){
-                    Ok(a) => {
-                        col_v.push(a);
-                    },
-                    Err(e) => {
-                        let mut b = vec![1u8];
-                        b.extend_from_slice(&e.to_string().as_bytes());
-                        return Err(b)
-                    }
-                }
+                col_val.push(AlbaTypes::from_id(f)?);
             }
-            let mut db = mtx_db.lock().await;
-            let c =  db.run(AST::CreateContainer(crate::AstCreateContainer {
+            AST::CreateContainer(crate::AstCreateContainer {
                 name: create_container.name,
                 col_nam: create_container.col_nam,
-                col_val: col_v
-            })).await;
-            match c {
-                Ok(mut q) => {
-                    q.rows.0.push("success".to_string());
-                    q.rows.1.push(Row{data:vec![AlbaTypes::Bool(true)]});
-                    q
-                }
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
-        },
-        commands::CreateRow(create_row) => {
-            match mtx_db.lock().await.run(AST::CreateRow(AstCreateRow{
-                col_nam: create_row.col_nam,
-                col_val: create_row.col_val.iter().map(|f|{ab_from_nat(f.clone())}).collect(),
-                container: create_row.container
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
+                col_val,
+                indexes: Vec::new(),
+                primary_key: Vec::new(),
+                auto_increment: false,
+                defaults: Vec::new(),
+                not_null: Vec::new(),
+                unique: Vec::new(),
+                in_memory: false,
+                primary_index: IndexKind::Hash,
+                empty_as_null: Vec::new()
+            })
         },
+        commands::CreateRow(create_row) => AST::CreateRow(AstCreateRow{
+            col_nam: create_row.col_nam,
+            col_val: create_row.col_val.iter().map(|f|{ab_from_nat(f.clone())}).collect(),
+            container: create_row.container
+        }),
         commands::BatchCreateRows(create_row) => {
-            let mut bururu = None;
-            for col_val in create_row.col_val{
-                match mtx_db.lock().await.run(AST::CreateRow(AstCreateRow{
-                    col_nam: create_row.col_nam.clone(),
-                    col_val: col_val.iter().map(|f|{ab_from_nat(f.clone())}).collect(),
-                    container: create_row.container.clone()
-                })).await{
-                    Ok(a) => bururu = Some(a),
-                    Err(e) => {
-                        let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                        b.extend_from_slice(&e.to_string().as_bytes());
-                        return Err(b)
-                    }
-                }
-            }
-            if let Some(prrrprrrcatapim) = bururu{
-                prrrprrrcatapim
-            }else{
-                let b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                return Err(b)
-            }
-        },
-        commands::EditRow(edit_row) => {
-            match mtx_db.lock().await.run(AST::EditRow(AstEditRow{
-                col_nam: edit_row.col_nam,
-                col_val: edit_row.col_val.iter().map(|f|{ab_from_nat(f.clone())}).collect(),
-                container: edit_row.container,
-                conditions: conditions_to_tyto_db((edit_row.conditions.0,edit_row.conditions.1.iter().map(|f|{(f.0 as usize,f.1)}).collect()))
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
-        },
-        commands::DeleteRow(delete_row) => {
-            match mtx_db.lock().await.run(AST::DeleteRow(AstDeleteRow{
-                container: delete_row.container,
-                conditions: if let Some(s) = delete_row.conditions{Some(conditions_to_tyto_db(s))}else{None}
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
-        },
-        commands::DeleteContainer(delete_container) => {
-            match mtx_db.lock().await.run(AST::DeleteContainer(AstDeleteContainer{
-                container: delete_container.container,
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
-        },
-        commands::Search(search) => {
-            let mtx_db = &mtx_db;
-            match mtx_db.lock().await.run(AST::Search(AstSearch{
-                col_nam: search.col_nam,
-                container: search.container,
-                conditions: conditions_to_tyto_db((search.conditions.0,search.conditions.1.iter().map(|f|{(f.0 as usize ,f.1)}).collect()))
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
-        },
-        commands::Commit(commit) => {
-            match mtx_db.lock().await.run(AST::Commit(AstCommit{
-                container: commit.container
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
-            }
-        },
-        commands::Rollback(rollback) => {
-            match mtx_db.lock().await.run(AST::Rollback(AstRollback{
-                container: rollback.container,
-            })).await{
-                Ok(a) => a,
-                Err(e) => {
-                    let mut b = vec![1u8,73, 110, 118, 97, 108, 105, 100, 32, 104, 101, 97, 100, 101, 114, 115, 32];
-                    b.extend_from_slice(&e.to_string().as_bytes());
-                    return Err(b)
-                }
+            if create_row.col_val.is_empty(){
+                return Err(Error::from(TytoError::InvalidInput("BatchCreateRows needs at least one row".to_string())))
             }
+            AST::CreateRows(AstCreateRows{
+                col_nam: create_row.col_nam,
+                col_val: create_row.col_val.into_iter().map(|col_val|col_val.into_iter().map(ab_from_nat).collect()).collect(),
+                container: create_row.container
+            })
         },
+        commands::EditRow(edit_row) => AST::EditRow(AstEditRow{
+            col_nam: edit_row.col_nam,
+            col_val: edit_row.col_val.iter().map(|f|{ab_from_nat(f.clone())}).collect(),
+            container: edit_row.container,
+            conditions: conditions_to_tyto_db((edit_row.conditions.0,edit_row.conditions.1.iter().map(|f|{(f.0 as usize,f.1)}).collect()))
+        }),
+        commands::DeleteRow(delete_row) => AST::DeleteRow(AstDeleteRow{
+            container: delete_row.container,
+            conditions: if let Some(s) = delete_row.conditions{Some(conditions_to_tyto_db(s))}else{None}
+        }),
+        commands::DeleteContainer(delete_container) => AST::DeleteContainer(AstDeleteContainer{
+            container: delete_container.container,
+            purge: false,
+        }),
+        // the encoded Search only carries columns and conditions, LIMIT, OFFSET, COUNT, aggregates, GROUP BY,
+        // DISTINCT, EXPLAIN and PAGE are sent as a SEARCH statement after the STATEMENT byte, see `parser::parse`
+        commands::Search(search) => AST::Search(AstSearch{
+            col_nam: search.col_nam,
+            container: search.container,
+            snapshot: None,
+            principal: None,
+            limit: None,
+            offset: None,
+            count: false,
+            aggregates: Vec::new(),
+            group_by: Vec::new(),
+            distinct: false,
+            explain: false,
+            page: None,
+            pending: false,
+            conditions: conditions_to_tyto_db((search.conditions.0,search.conditions.1.iter().map(|f|{(f.0 as usize ,f.1)}).collect()))
+        }),
+        commands::Commit(commit) => AST::Commit(AstCommit{
+            container: commit.container
+        }),
+        commands::Rollback(rollback) => AST::Rollback(AstRollback{
+            container: rollback.container,
+        }),
     })
 }
 
+/// Reads the network secret, generating it on first start.
+/// Only the network server needs it, `connect` and the embedded API never touch `.secret`.
+fn load_secret_key(path : &str) -> Result<[u8;32],Error>{
+    let mut password : [u8;32] = [0u8;32];
+    if fs::exists(path)?{
+        let mut buffer : Vec<u8> = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut buffer)?;
+        if buffer.len() != password.len(){
+            return Err(Error::new(ErrorKind::InvalidData, format!("The secret key at {} must be exactly {} bytes long, found {}", path, password.len(), buffer.len())))
+        }
+        password[0..].copy_from_slice(&buffer);
+    }else{
+        let mut file = fs::File::create_new(path)?;
+        let mut osr = OsRng;
+        osr.try_fill_bytes(&mut password).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        file.write_all(&password)?;
+        file.flush()?;
+        file.sync_all()?;
+    }
+    Ok(password)
+}
+
 impl Database{
     pub async fn run_database(self) -> Result<(), Error>{
-        let mut password : [u8;32] = [0u8;32];
-        if fs::exists(secret_key_path()).unwrap(){
-            let mut buffer : Vec<u8> = Vec::new();
-            fs::File::open(secret_key_path()).unwrap().read_to_end(&mut buffer)?;
-            password[0..].copy_from_slice(&buffer);
-            // let bv : Vec<Vec<u8>> = val.iter().map(|s|{
-            //     match eng.decode(s){
-            //         Ok(a)=>a,
-            //         Err(e)=>{
-            //             logerr!("{}",e);
-            //         }
-            //     }
-            // }).collect();
-        }else{
-            let mut file = fs::File::create_new(secret_key_path()).unwrap();
-            let mut bytes: [u8; 32] = [0u8;32];
-            let mut osr = OsRng;
-            osr.try_fill_bytes(&mut bytes).unwrap();
-            let _ = file.write_all(&bytes);
-            file.flush()?;
-            file.sync_all()?;
-            password = bytes;
-        }
-        let host = format!("{}:{}",self.settings.ip.clone(),self.settings.port.clone());
+        let password = load_secret_key(&secret_key_path(&self.location))?;
+        let keystore = Arc::new(Keystore::load(keys_path(&self.location))?);
+        // validated by load_settings, the socket address brackets IPv6 addresses
+        let ip : std::net::IpAddr = self.settings.ip.parse().map_err(|e|gerr(&format!("Invalid ip {}: {}", self.settings.ip, e)))?;
+        let host = std::net::SocketAddr::new(ip, self.settings.port as u16).to_string();
         let workers = self.settings.workers as usize;
         let mtx_db: &'static Arc<Mutex<Database>> = Box::leak(Box::new(Arc::new(Mutex::new(self))));
 
-        let message_handler: Arc<(dyn Fn(Vec<u8>) -> Pin<Box<(dyn futures::Future<Output = Vec<u8>> + std::marker::Send + 'static)>> + std::marker::Send + Sync + 'static)> = Arc::new(move |input: Vec<u8>| { Box::pin(async move {
-            let mut val = vec![0u8];
-            val.extend_from_slice(&query_to_bytes(match commands::decompile(&input){
-                Ok(a) => {
-                    match process(mtx_db, a).await{
-                        Ok(a) => a,
-                        Err(e) => {return e}
-                    }
+        // automatic vacuums run one at a time, in the order commits queued them
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+        {
+            let mut db = mtx_db.lock().await;
+            if !db.read_only{
+                db.auto_vacuum = Some(sender);
+            }
+        }
+        tokio::spawn(async move{
+            while let Some(name) = receiver.recv().await{
+                match vacuum_container(mtx_db, &name).await{
+                    Ok(true) => loginfo!("Automatically vacuumed {}.", name),
+                    Ok(false) => logdebug!("Skipped the automatic vacuum of {}, it has pending changes or is being vacuumed.", name),
+                    Err(e) => logerr!("The automatic vacuum of {} failed: {}", name, e)
+                }
+            }
+        });
+
+        let message_handler: Arc<(dyn Fn(Vec<u8>) -> Pin<Box<(dyn futures::Future<Output = Vec<u8>> + std::marker::Send + 'static)>> + std::marker::Send + Sync + 'static)> = Arc::new(move |input: Vec<u8>| { let keystore = keystore.clone(); Box::pin(async move {
+            let (principal,command) = match keystore.authenticate(&input){
+                Ok((principal,command)) => (principal,command.to_vec()),
+                Err(e) => return error_response(e.into())
+            };
+            let mut compress = false;
+            let mut options = RequestOptions{principal, ..Default::default()};
+            let mut command : &[u8] = &command;
+            loop{
+                match command.split_first(){
+                    Some((&ACCEPTS_COMPRESSION,rest)) => {
+                        compress = true;
+                        command = rest;
+                    },
+                    Some((&QUERY_TIMEOUT,rest)) if rest.len() >= 4 => {
+                        options.timeout = Some(u32::from_be_bytes([rest[0],rest[1],rest[2],rest[3]]) as u64);
+                        command = &rest[4..];
+                    },
+                    Some((&SESSION,rest)) if rest.len() >= 8 => {
+                        options.session = u64::from_be_bytes([rest[0],rest[1],rest[2],rest[3],rest[4],rest[5],rest[6],rest[7]]);
+                        if options.session == RECOVERED_SESSION{
+                            return error_response(TytoError::Protocol(format!("The session {} is reserved for recovered changes", RECOVERED_SESSION)))
+                        }
+                        command = &rest[8..];
+                    },
+                    Some((&TRANSACTION_ISOLATION,rest)) if !rest.is_empty() => {
+                        options.isolation = Some(match rest[0]{
+                            0 => Isolation::ReadCommitted,
+                            1 => Isolation::Snapshot,
+                            level => return error_response(TytoError::Protocol(format!("Unknown isolation level {}", level)))
+                        });
+                        command = &rest[1..];
+                    },
+                    _ => break
+                }
+            }
+            let result = match command.split_first(){
+                Some((&STATEMENT,statement)) => match std::str::from_utf8(statement).map_err(|e|gerr(&e.to_string())).and_then(crate::parser::parse){
+                    Ok(ast) => run_ast(mtx_db, ast, options).await,
+                    Err(e) => return error_response(e.into())
                 },
-                Err(e) => {
-                    let mut b = vec![1u8];
-                    b.extend_from_slice(e.to_string().as_bytes());
-                    return b
+                _ => match commands::decompile(&command.to_vec()){
+                    Ok(a) => process(mtx_db, a, options).await,
+                    Err(e) => return error_response(TytoError::Protocol(e.to_string()))
                 }
+            };
+            let mut val = vec![0u8];
+            val.extend_from_slice(&query_to_bytes(match result{
+                Ok(a) => a,
+                Err(e) => return e
             }));
+            if compress && val.len() > COMPRESSION_THRESHOLD{
+                let mut compressed = vec![RESPONSE_COMPRESSED];
+                compressed.extend_from_slice(&lz4_flex::compress_prepend_size(&val[1..]));
+                // incompressible payloads, like random bytes, are better left alone
+                if compressed.len() < val.len(){
+                    return compressed
+                }
+            }
             val
         })});
 
         let db_lock = mtx_db.clone();
         let t = tokio::spawn(async move {
             let db = db_lock;
-            let vacuum_settings = {
+            if db.lock().await.read_only{
+                return
+            }
+            let (vacuum_settings,zone) = {
                 let ldb = db.lock().await;
-                ldb.settings.vacuum.clone()
+                let zone = ScheduleZone::parse(&ldb.settings.schedule_timezone).unwrap_or_else(|_|{
+                    logwarn!("Unknown schedule_timezone \"{}\", using local time.", ldb.settings.schedule_timezone);
+                    ScheduleZone::Local
+                });
+                (ldb.settings.vacuum.clone(),zone)
             };
             let mut once = Vec::new();
             let vacuum_settings : Vec<(String,String)> = vacuum_settings.into_iter().filter(|f| { if f.1.to_lowercase().contains("once"){once.push(f.clone());false}else{true} }).collect();
-            if !once.is_empty(){
-                let db = db.lock().await;
-                for i in once{
-                    if let Some(b) = db.container.get(&i.1){
-                        let _ = b.lock().await.vacuum().await;
-                    }
+            for i in once{
+                match vacuum_container(&db, &i.0).await{
+                    Ok(true) => {},
+                    Ok(false) => loginfo!("Skipped the vacuum of {}, it has pending changes or is being vacuumed.", i.0),
+                    Err(e) => logerr!("{}",e)
                 }
             }
             loop{
+                let trash_enabled = {
+                    let ldb = db.lock().await;
+                    if let Err(e) = ldb.purge_trash(){
+                        logerr!("{}",e);
+                    }
+                    ldb.settings.trash_retention > 0
+                };
                 let mut vacuum_parsed = Vec::new();
             
                 for i in vacuum_settings.iter(){
-                    if let Ok(b) = parse_schedule(i.1.as_str()){
+                    if let Ok(b) = parse_schedule(i.1.as_str(), &zone){
                         vacuum_parsed.push(
                             (i.0.clone(),
                             match b {
                                 Schedule::Duration(duration) => duration.num_seconds().max(0) as u64,
                                 Schedule::NextTime(duration) => duration.num_seconds().max(0) as u64,
                                 Schedule::NextMonthDayTime(_, _, _, duration) => duration.num_seconds().max(0) as u64,
+                                Schedule::NextWeekdayTime(_, _, duration) => duration.num_seconds().max(0) as u64,
                                 Schedule::Random(min, max) => {
                                     let min = min.max(0) as u64;
                                     let max = max.max(0) as u64;
                                     rand::rng().random_range(min..max)
                                 }
                                 Schedule::Once => 0,
+                                Schedule::Cron(_, duration) => duration.num_seconds().max(0) as u64,
                                 }
                          )
                         )
                     }else{
-                        eprintln!("failed to parse");
+                        logwarn!("Failed to parse the vacuum schedule \"{}\" of \"{}\"", i.1, i.0);
                     }
                 }
                 if vacuum_parsed.is_empty(){
-                    break;
+                    if !trash_enabled{
+                        break;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_secs(TRASH_PURGE_INTERVAL)).await;
+                    continue;
                 }
                 vacuum_parsed.sort_by_key(|f|f.1);
                 let mut growth = 0;
                 vacuum_parsed = vacuum_parsed.into_iter().map(|f|{let a=(f.0,f.1.saturating_sub(growth));growth+=f.1;a}).collect();
                 for i in vacuum_parsed{ 
                     tokio::time::sleep(std::time::Duration::from_secs(i.1+1)).await;
-                    let db = db.lock().await;
-                    if let Some(c) = db.container.get(&i.0){
-                        if let Err(e) = c.lock().await.vacuum().await{
-                            eprintln!("{}",e);
-                        };
+                    match vacuum_container(&db, &i.0).await{
+                        Ok(true) => {},
+                        Ok(false) => loginfo!("Skipped the scheduled vacuum of {}, it has pending changes or is being vacuumed.", i.0),
+                        Err(e) => logerr!("{}",e)
                     }
                 }
                 
@@ -1232,3 +3716,498 @@ impl Database{
         a
     }
 }
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed by the test once it is done.
+    fn temp_dir(name : &str) -> String{
+        let dir = std::env::temp_dir().join(format!("tytodb-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn legacy_containers_are_upgraded_on_load(){
+        let dir = temp_dir("upgrade");
+        let path = format!("{}/people", dir);
+        // row format 0: no version flag, big-endian numbers and Text stored inline
+        let mut file = 2u64.to_le_bytes().to_vec();
+        for (name,id) in [("id",3u8),("bio",6u8)]{
+            file.extend_from_slice(&(name.len() as u64).to_le_bytes());
+            file.extend_from_slice(name.as_bytes());
+            file.push(id);
+        }
+        let headers_offset = file.len() as u64;
+        for (id,bio) in [(1i64,"first"),(2i64,"second")]{
+            file.extend_from_slice(&id.to_be_bytes());
+            let mut text = bio.as_bytes().to_vec();
+            text.resize(crate::alba_types::LEGACY_TEXT_SIZE, 0);
+            file.extend_from_slice(&text);
+        }
+        fs::write(&path, &file).unwrap();
+
+        let (col_nam,col_val,options,offset,version) = get_container_headers(&fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!((version,offset), (0,headers_offset));
+        upgrade_container(&path, col_nam, col_val, options, offset, ContainerTuning::default()).unwrap();
+        let (col_nam,col_val,options,offset,version) = get_container_headers(&fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(version, FORMAT_VERSION);
+
+        let element_size = col_val.iter().map(|v|v.size()).sum();
+        let container = Container::new(&path, element_size, col_val, offset, col_nam, options, ContainerTuning::default()).await.unwrap();
+        let c = container.lock().await;
+        let first = c.read_row_at(offset).await.unwrap().unwrap().data;
+        let second = c.read_row_at(offset + element_size as u64).await.unwrap().unwrap().data;
+        assert_eq!(first, vec![AlbaTypes::Bigint(1),AlbaTypes::Text("first".to_string())]);
+        assert_eq!(second, vec![AlbaTypes::Bigint(2),AlbaTypes::Text("second".to_string())]);
+        drop(c);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn condition_chains_over_the_limit_are_rejected(){
+        let dir = temp_dir("conditions");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.settings.max_conditions = 3;
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, age][BIGINT, INT]").unwrap()).await.unwrap();
+        let e = db.run(crate::parser::parse("SEARCH id ON people WHERE age > 1 AND age > 2 AND age > 3 AND age > 4").unwrap()).await.unwrap_err();
+        assert!(matches!(TytoError::from(e), TytoError::InvalidInput(msg) if msg.contains("4 conditions")));
+        let e = db.run(crate::parser::parse("DELETE ROW ON people WHERE age > 1 OR age > 2 OR age > 3 OR age > 4").unwrap()).await.unwrap_err();
+        assert!(matches!(TytoError::from(e), TytoError::InvalidInput(_)));
+        db.run(crate::parser::parse("SEARCH id ON people WHERE age > 1 AND age > 2 AND age > 3").unwrap()).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_containers_leave_no_files(){
+        let dir = temp_dir("drop");
+        let mut db = connect_at(&dir).await.unwrap();
+        let files = |prefix : &str| -> Vec<String>{
+            fs::read_dir(&dir).unwrap().map(|e|e.unwrap().file_name().to_string_lossy().to_string()).filter(|n|n.starts_with(prefix)).collect()
+        };
+        for statement in [
+            "CREATE CONTAINER hashed [id, bio][BIGINT, TEXT] INDEX [bio][HASH]",
+            "CREATE CONTAINER ordered [id, name][BIGINT, SMALL-STRING] ORDERED KEY AUTO INCREMENT INDEX [name][ORDERED]",
+            "CREATE ROW [id, bio][1, 'a'] ON hashed",
+            "CREATE ROW [id, name][1, 'b'] ON ordered",
+        ]{
+            db.run(crate::parser::parse(statement).unwrap()).await.unwrap();
+        }
+        db.commit().await.unwrap();
+        assert!(files("hashed").contains(&format!("hashed{}", HASHMAP_SUFFIX)), "{:?}", files("hashed"));
+        assert!(files("ordered").contains(&format!("ordered{}", ORDERED_INDEX_SUFFIX)), "{:?}", files("ordered"));
+        for name in ["hashed","ordered"]{
+            assert!(files(name).contains(&format!("{}{}", name, SECONDARY_INDEX_SUFFIX)), "{:?}", files(name));
+            db.run(crate::parser::parse(&format!("DELETE CONTAINER {} PURGE", name)).unwrap()).await.unwrap();
+            assert!(files(name).is_empty(), "{:?}", files(name));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn batches_return_the_rows_of_every_command(){
+        let dir = temp_dir("batch");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[0,'a'],[1,'b'],[2,'c'],[3,'d'],[4,'e'],[5,'f'],[6,'g'],[7,'h']] ON people").unwrap()).await.unwrap();
+        // a command without rows first, its empty column list doesn't name the batch
+        let mut results = vec![Query{rows:(Vec::new(),Vec::new())}];
+        for (from,to) in [(0,2),(2,4),(4,6),(6,8)]{
+            let statement = format!("SEARCH id, name ON people WHERE id >= {} AND id < {}", from, to);
+            results.push(db.run(crate::parser::parse(&statement).unwrap()).await.unwrap());
+        }
+        let merged = merge_batch(results);
+        assert_eq!(merged.rows.0, vec!["id".to_string(),"name".to_string()]);
+        let ids : Vec<AlbaTypes> = merged.rows.1.iter().map(|r|r.data[0].clone()).collect();
+        assert_eq!(ids, (0..8).map(AlbaTypes::Bigint).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn not_null_columns_reject_none_on_insert_and_edit(){
+        let dir = temp_dir("not-null");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name, bio][BIGINT, SMALL-STRING, SMALL-STRING] NOT NULL [name]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("CREATE ROW [id, name][1, 'a'] ON people").unwrap()).await.unwrap();
+        db.commit().await.unwrap();
+        drop(db);
+        // the flag is read back from the headers
+        let mut db = connect_at(&dir).await.unwrap();
+        for statement in ["CREATE ROW [id, bio][2, 'b'] ON people", "CREATE ROW [id, name][2, NULL] ON people", "EDIT ROW [name][NULL] ON people WHERE id = 1"]{
+            let e = db.run(crate::parser::parse(statement).unwrap()).await.unwrap_err();
+            assert!(matches!(TytoError::from(e), TytoError::InvalidInput(msg) if msg.contains("name")), "{}", statement);
+        }
+        db.run(crate::parser::parse("EDIT ROW [bio][NULL] ON people WHERE id = 1").unwrap()).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restoring_a_backup_brings_the_rows_back(){
+        let dir = temp_dir("restore");
+        let archive = format!("{}/backup.tar", temp_dir("restore-archive"));
+        let mut db = connect_at(&dir).await.unwrap();
+        for statement in [
+            "CREATE CONTAINER people [id, name, bio][BIGINT, SMALL-STRING, TEXT] INDEX [name][ORDERED]",
+            "BULK LOAD [id, name, bio][[1,'a','first'],[2,'b','second'],[3,'c',NULL]] ON people",
+        ]{
+            db.run(crate::parser::parse(statement).unwrap()).await.unwrap();
+        }
+        let everything = crate::parser::parse("SEARCH id, name, bio ON people").unwrap();
+        let stored = db.run(everything.clone()).await.unwrap();
+        assert_eq!(stored.rows.1.len(), 3);
+        db.run(crate::parser::parse(&format!("BACKUP TO '{}'", archive)).unwrap()).await.unwrap();
+        db.run(crate::parser::parse("TRUNCATE people").unwrap()).await.unwrap();
+        assert!(db.run(everything.clone()).await.unwrap().rows.1.is_empty());
+
+        let e = db.run(crate::parser::parse(&format!("RESTORE FROM '{}'", archive)).unwrap()).await.unwrap_err();
+        assert!(matches!(TytoError::from(e), TytoError::Conflict(_)));
+        db.run(crate::parser::parse(&format!("RESTORE FROM '{}' FORCE", archive)).unwrap()).await.unwrap();
+        let restored = db.run(everything).await.unwrap();
+        assert_eq!(restored.rows.0, stored.rows.0);
+        assert_eq!(restored.rows.1.iter().map(|r|&r.data).collect::<Vec<_>>(), stored.rows.1.iter().map(|r|&r.data).collect::<Vec<_>>());
+        // the secondary index came back with the rows
+        let by_name = db.run(crate::parser::parse("SEARCH id ON people WHERE name = 'b'").unwrap()).await.unwrap();
+        assert_eq!(by_name.rows.1.iter().map(|r|&r.data).collect::<Vec<_>>(), vec![&vec![AlbaTypes::Bigint(2)]]);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(PathBuf::from(&archive).parent().unwrap()).unwrap();
+    }
+
+    fn at(date : &str) -> NaiveDateTime{
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn cron_expressions_fire_on_the_next_matching_minute(){
+        let cases = [
+            ("*/15 * * * *", "2024-03-08 10:07", "2024-03-08 10:15"),
+            ("*/15 * * * *", "2024-03-08 23:45", "2024-03-09 00:00"),
+            ("0 2 * * *", "2024-03-10 02:00", "2024-03-11 02:00"),
+            // weekdays, from a Friday morning to the Monday after
+            ("30 9 * * 1-5", "2024-03-08 10:00", "2024-03-11 09:30"),
+            ("0 0 1,15 * *", "2024-01-15 00:00", "2024-02-01 00:00"),
+            ("5/20 8-10 * * *", "2024-01-01 10:45", "2024-01-02 08:05"),
+            // day of month and day of week both given, either one fires
+            ("0 12 13 * 5", "2024-09-01 00:00", "2024-09-06 12:00"),
+            ("0 0 * * 7", "2024-09-02 00:00", "2024-09-08 00:00"),
+            ("0 0 29 2 *", "2024-03-01 00:00", "2028-02-29 00:00"),
+        ];
+        for (expression,after,next) in cases{
+            assert_eq!(CronSchedule::parse(expression).unwrap().next_after(at(after)), Some(at(next)), "{}", expression);
+        }
+        assert_eq!(CronSchedule::parse("0 0 30 2 *").unwrap().next_after(at("2024-01-01 00:00")), None);
+        assert_eq!(CronSchedule::parse("60 * * * *"), Err(ScheduleError::InvalidRange));
+        assert_eq!(CronSchedule::parse("*/0 * * * *"), Err(ScheduleError::InvalidNumber));
+        assert_eq!(CronSchedule::parse("* * * *"), Err(ScheduleError::InvalidFormat));
+        match parse_schedule("*/5 * * * *", &ScheduleZone::Local).unwrap(){
+            Schedule::Cron(_,wait) => assert!(wait > Duration::zero() && wait <= Duration::minutes(5)),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn weekday_schedules_roll_over_the_week(){
+        let two = NaiveTime::from_hms_opt(2, 0, 0).unwrap();
+        let next = |days : &str, now : &str| next_weekday_time(parse_weekdays(days).unwrap(), two, at(now));
+        // 2024-03-09 is a Saturday
+        assert_eq!(next("Mon", "2024-03-09 12:00"), Some(at("2024-03-11 02:00")));
+        assert_eq!(next("Sat", "2024-03-09 01:59"), Some(at("2024-03-09 02:00")));
+        // the time already passed today, so a week later
+        assert_eq!(next("Sat", "2024-03-09 02:00"), Some(at("2024-03-16 02:00")));
+        assert_eq!(next("weekdays", "2024-03-08 03:00"), Some(at("2024-03-11 02:00")));
+        assert_eq!(next("weekends", "2024-03-10 03:00"), Some(at("2024-03-16 02:00")));
+        assert_eq!(next("Tue,thursday", "2024-03-07 02:30"), Some(at("2024-03-12 02:00")));
+        // across the end of the year
+        assert_eq!(next("sunday", "2024-12-30 00:00"), Some(at("2025-01-05 02:00")));
+        assert_eq!(parse_weekdays("Mon,Funday"), Err(ScheduleError::UnknownDay));
+        assert_eq!(parse_schedule("Someday 02:00:00", &ScheduleZone::Local), Err(ScheduleError::UnknownDay));
+        assert!(matches!(parse_schedule("weekdays 02:00:00", &ScheduleZone::Local), Ok(Schedule::NextWeekdayTime(0b0111110,_,_))));
+    }
+
+    /// The (id, name) rows of `people` as a search of the active session sees them, read from `snapshot` when given.
+    async fn people(db : &mut Database, snapshot : Option<u64>) -> Vec<(AlbaTypes,AlbaTypes)>{
+        let mut ast = crate::parser::parse("SEARCH id, name ON people").unwrap();
+        if let AST::Search(search) = &mut ast{
+            search.snapshot = snapshot;
+            search.pending = snapshot.is_some();
+        }
+        let mut rows : Vec<(AlbaTypes,AlbaTypes)> = db.run(ast).await.unwrap().rows.1.into_iter().map(|r|(r.data[0].clone(),r.data[1].clone())).collect();
+        rows.sort_by_key(|r|format!("{:?}",r.0));
+        rows
+    }
+
+    #[tokio::test]
+    async fn isolation_levels_see_what_they_promise(){
+        let dir = temp_dir("isolation");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[1,'a'],[2,'b'],[3,'c']] ON people").unwrap()).await.unwrap();
+        let row = |id : i64, name : &str|(AlbaTypes::Bigint(id),AlbaTypes::SmallString(name.to_string()));
+
+        db.use_session(1).await.unwrap();
+        let snapshot = db.open_snapshot().await.unwrap();
+        for statement in ["CREATE ROW [id, name][4, 'd'] ON people", "EDIT ROW [name]['B'] ON people WHERE id = 2", "DELETE ROW ON people WHERE id = 3"]{
+            db.run(crate::parser::parse(statement).unwrap()).await.unwrap();
+        }
+        // another session commits after the transaction started
+        db.use_session(2).await.unwrap();
+        db.run(crate::parser::parse("CREATE ROW [id, name][5, 'e'] ON people").unwrap()).await.unwrap();
+        db.commit().await.unwrap();
+        db.use_session(1).await.unwrap();
+
+        // read committed: the latest committed rows, without the transaction's own changes
+        assert_eq!(people(&mut db, None).await, vec![row(1,"a"),row(2,"b"),row(3,"c"),row(5,"e")]);
+        // snapshot: the rows as the transaction started, with its own changes over them
+        assert_eq!(people(&mut db, Some(snapshot)).await, vec![row(1,"a"),row(2,"B"),row(4,"d")]);
+
+        db.commit().await.unwrap();
+        db.close_snapshot(snapshot).await.unwrap();
+        assert_eq!(people(&mut db, None).await, vec![row(1,"a"),row(2,"B"),row(4,"d"),row(5,"e")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rolling_back_to_a_savepoint_keeps_the_earlier_changes(){
+        let dir = temp_dir("savepoints");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[0,'zero']] ON people").unwrap()).await.unwrap();
+        let row = |id : i64, name : &str|(AlbaTypes::Bigint(id),AlbaTypes::SmallString(name.to_string()));
+        for statement in [
+            "CREATE ROW [id, name][1, 'one'] ON people",
+            "SAVEPOINT first",
+            "CREATE ROW [id, name][2, 'two'] ON people",
+            "EDIT ROW [name]['ZERO'] ON people WHERE id = 0",
+            "SAVEPOINT second",
+            "DELETE ROW ON people WHERE id = 0",
+        ]{
+            db.run(crate::parser::parse(statement).unwrap()).await.unwrap();
+        }
+        db.run(crate::parser::parse("ROLLBACK TO second").unwrap()).await.unwrap();
+        let pending = db.container["people"].lock().await.mvcc.lock().await.0.values().map(|(_,row)|row[0].clone()).collect::<Vec<_>>();
+        assert_eq!(pending.len(), 3, "{:?}", pending);
+        db.run(crate::parser::parse("ROLLBACK TO first").unwrap()).await.unwrap();
+        // the savepoints set after the one rolled back to are gone
+        assert!(matches!(TytoError::from(db.run(crate::parser::parse("ROLLBACK TO second").unwrap()).await.unwrap_err()), TytoError::NotFound(_)));
+        db.run(crate::parser::parse("RELEASE first").unwrap()).await.unwrap();
+        assert!(db.run(crate::parser::parse("ROLLBACK TO first").unwrap()).await.is_err());
+        db.commit().await.unwrap();
+        assert_eq!(people(&mut db, None).await, vec![row(0,"zero"),row(1,"one")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn staged_edits_and_deletes_survive_a_restart(){
+        let dir = temp_dir("recover-edits");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[1,'a'],[2,'b'],[3,'c']] ON people").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("EDIT ROW [name]['A'] ON people WHERE id = 1").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("DELETE ROW ON people WHERE id = 2").unwrap()).await.unwrap();
+        // gone without committing, only the MVCC record knows about the changes
+        drop(db);
+        let mut db = connect_at(&dir).await.unwrap();
+        let row = |id : i64, name : &str|(AlbaTypes::Bigint(id),AlbaTypes::SmallString(name.to_string()));
+        assert_eq!(people(&mut db, None).await, vec![row(1,"a"),row(2,"b"),row(3,"c")]);
+        let status = db.run(AST::RecoveryStatus).await.unwrap();
+        assert_eq!(status.rows.1[0].data, vec![AlbaTypes::LargeString("people".to_string()),AlbaTypes::Bigint(0),AlbaTypes::Bigint(1),AlbaTypes::Bigint(1)]);
+        db.run(AST::RecoveryCommit).await.unwrap();
+        assert_eq!(people(&mut db, None).await, vec![row(1,"A"),row(3,"c")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_recovery_policy_settles_changes_left_by_a_crash(){
+        let dir = temp_dir("recovery-policy");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("CREATE ROW [id, name][1, 'a'] ON people").unwrap()).await.unwrap();
+        db.commit().await.unwrap();
+        let row = |id : i64, name : &str|(AlbaTypes::Bigint(id),AlbaTypes::SmallString(name.to_string()));
+
+        // keep: pending apart from every session until settled, a commit of the session doesn't apply them
+        db.run(crate::parser::parse("CREATE ROW [id, name][2, 'b'] ON people").unwrap()).await.unwrap();
+        drop(db);
+        let mut db = connect_at(&dir).await.unwrap();
+        db.commit().await.unwrap();
+        assert_eq!(people(&mut db, None).await, vec![row(1,"a")]);
+        // nor can a client reach them by naming their session
+        assert!(matches!(TytoError::from(db.use_session(RECOVERED_SESSION).await.unwrap_err()), TytoError::Protocol(_)));
+        db.commit().await.unwrap();
+        assert_eq!(people(&mut db, None).await, vec![row(1,"a")]);
+        assert_eq!(db.run(AST::RecoveryStatus).await.unwrap().rows.1.len(), 1);
+        db.run(AST::RecoveryRollback).await.unwrap();
+        assert!(db.run(AST::RecoveryStatus).await.unwrap().rows.1.is_empty());
+
+        // commit: applied while the database opens
+        let settings = PathBuf::from(&dir).join(SETTINGS_FILE);
+        fs::write(&settings, fs::read_to_string(&settings).unwrap().replace("recovery: keep", "recovery: commit")).unwrap();
+        db.run(crate::parser::parse("CREATE ROW [id, name][3, 'c'] ON people").unwrap()).await.unwrap();
+        drop(db);
+        let mut db = connect_at(&dir).await.unwrap();
+        assert!(db.run(AST::RecoveryStatus).await.unwrap().rows.1.is_empty());
+        assert_eq!(people(&mut db, None).await, vec![row(1,"a"),row(3,"c")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn batched_io_goes_through_io_uring(){
+        let dir = temp_dir("uring");
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(format!("{}/rows", dir)).unwrap();
+        let entries : Vec<WriteEntry> = (0..BATCH_READ_LIMIT as u64 + 10).map(|i|WriteEntry{buffer: Arc::new(i.to_le_bytes().to_vec()), length: 8, offset: (i*8) as i64}).collect();
+        batch_write_data(&entries, file.as_raw_fd(), 1).unwrap();
+        // spans two rings and comes back in the order asked for
+        let offsets : Vec<u64> = (0..BATCH_READ_LIMIT as u64 + 10).rev().map(|i|i*8).collect();
+        let buffers = batch_reads_abs(&offsets, 8, &file).unwrap();
+        for (offset,buffer) in offsets.iter().zip(buffers){
+            assert_eq!(u64::from_le_bytes(buffer.try_into().unwrap()), offset/8);
+        }
+        let short = batch_reads_abs(&[(BATCH_READ_LIMIT as u64 + 9)*8 + 4], 8, &file).unwrap_err();
+        assert_eq!(short.kind(), ErrorKind::UnexpectedEof);
+        // a write the file refuses fails the batch instead of being dropped
+        let read_only = fs::File::open(format!("{}/rows", dir)).unwrap();
+        let refused = batch_write_data(&entries[..1], read_only.as_raw_fd(), 0).unwrap_err();
+        assert_eq!(refused.kind(), ErrorKind::WriteZero);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_row_formats_are_refused(){
+        let dir = temp_dir("newer");
+        let path = format!("{}/future", dir);
+        let mut file = (HEADER_VERSION_FLAG).to_le_bytes().to_vec();
+        file.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, &file).unwrap();
+        assert!(get_container_headers(&fs::File::open(&path).unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn freed_slots_are_reused_after_a_restart(){
+        let dir = temp_dir("graveyard");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[1,'a'],[2,'b'],[3,'c'],[4,'d'],[5,'e']] ON people").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("DELETE ROW ON people WHERE id = 2").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("DELETE ROW ON people WHERE id = 4").unwrap()).await.unwrap();
+        db.commit().await.unwrap();
+        let data = PathBuf::from(&dir).join("people");
+        let graveyard = PathBuf::from(&dir).join(format!("people{}", GRAVEYARD_SUFFIX));
+        assert_eq!(fs::metadata(&graveyard).unwrap().len(), 16);
+        let data_len = fs::metadata(&data).unwrap().len();
+        drop(db);
+
+        let mut db = connect_at(&dir).await.unwrap();
+        assert_eq!(db.container["people"].lock().await.graveyard.lock().await.len(), 2);
+        db.run(crate::parser::parse("CREATE ROW [id, name][6, 'f'] ON people").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("CREATE ROW [id, name][7, 'g'] ON people").unwrap()).await.unwrap();
+        db.commit().await.unwrap();
+        // both rows went into the freed slots, the file did not grow
+        assert_eq!(fs::metadata(&data).unwrap().len(), data_len);
+        assert_eq!(fs::metadata(&graveyard).unwrap().len(), 0);
+        db.run(crate::parser::parse("CREATE ROW [id, name][8, 'h'] ON people").unwrap()).await.unwrap();
+        db.commit().await.unwrap();
+        assert!(fs::metadata(&data).unwrap().len() > data_len);
+        let ids : Vec<AlbaTypes> = people(&mut db, None).await.into_iter().map(|r|r.0).collect();
+        assert_eq!(ids, [1,3,5,6,7,8].map(AlbaTypes::Bigint).to_vec());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_ip_and_port_fall_back_to_the_defaults(){
+        let dir = temp_dir("listen-settings");
+        drop(connect_at(&dir).await.unwrap());
+        let settings = PathBuf::from(&dir).join(SETTINGS_FILE);
+        let original = fs::read_to_string(&settings).unwrap();
+        let with = |ip : &str, port : &str| original.replace("ip: \"127.0.0.1\"", &format!("ip: \"{}\"", ip)).replace("port: 4287", &format!("port: {}", port));
+
+        // an invalid value falls back to its default, the valid one next to it is kept
+        for (ip,port,expected) in [("127.0.0.300","9000",(DEFAULT_IP,9000)),("localhost","0",(DEFAULT_IP,DEFAULT_PORT)),("10.0.0.1","65536",("10.0.0.1",DEFAULT_PORT))]{
+            fs::write(&settings, with(ip, port)).unwrap();
+            let db = connect_at(&dir).await.unwrap();
+            assert_eq!((db.settings.ip.as_str(),db.settings.port), expected);
+            // the file was rewritten with the values in use
+            let saved : Settings = serde_yaml::from_str(&fs::read_to_string(&settings).unwrap()).unwrap();
+            assert_eq!((saved.ip,saved.port), (db.settings.ip.clone(),db.settings.port));
+        }
+
+        // IPv6 addresses are valid
+        fs::write(&settings, with("::1", "9000")).unwrap();
+        let db = connect_at(&dir).await.unwrap();
+        assert_eq!((db.settings.ip.as_str(),db.settings.port), ("::1",9000));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restricted_principals_only_read_their_columns(){
+        let dir = temp_dir("column-access");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name, salary][BIGINT, SMALL-STRING, BIGINT]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name, salary][[1,'a',10],[2,'b',20]] ON people").unwrap()).await.unwrap();
+        drop(db);
+        let settings = PathBuf::from(&dir).join(SETTINGS_FILE);
+        fs::write(&settings, fs::read_to_string(&settings).unwrap().replace("column_access: {}", "column_access:\n  reports:\n    people: [\"id\", \"name\"]")).unwrap();
+        let db : &'static Arc<Mutex<Database>> = Box::leak(Box::new(Arc::new(Mutex::new(connect_at(&dir).await.unwrap()))));
+        let as_principal = |principal : Option<&str>|RequestOptions{principal: principal.map(|p|p.to_string()), ..Default::default()};
+
+        for statement in ["SEARCH salary ON people", "SEARCH id, salary ON people", "SEARCH id ON people WHERE salary > 15", "EXISTS ON people WHERE salary = 10", "SEARCH name ON SET people WHERE salary = 10"]{
+            let e = run_ast(db, crate::parser::parse(statement).unwrap(), as_principal(Some("reports"))).await.unwrap_err();
+            assert_eq!(e[..2], [RESPONSE_ERROR,TytoError::PermissionDenied(String::new()).code()], "{}", statement);
+        }
+        let q = run_ast(db, crate::parser::parse("SEARCH id, name ON people WHERE id = 2").unwrap(), as_principal(Some("reports"))).await.unwrap();
+        assert_eq!(q.rows.1[0].data, vec![AlbaTypes::Bigint(2),AlbaTypes::SmallString("b".to_string())]);
+        // principals the setting doesn't list, and unsigned requests, read everything
+        for principal in [Some("admin"),None]{
+            let q = run_ast(db, crate::parser::parse("SEARCH salary ON people WHERE salary > 15").unwrap(), as_principal(principal)).await.unwrap();
+            assert_eq!(q.rows.1.len(), 1);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scans_and_vacuums_spend_the_rate_limit_of_the_principal(){
+        let dir = temp_dir("rate-limits");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[1,'a'],[2,'b']] ON people").unwrap()).await.unwrap();
+        drop(db);
+        let settings = PathBuf::from(&dir).join(SETTINGS_FILE);
+        fs::write(&settings, fs::read_to_string(&settings).unwrap().replace("rate_limits: {}", "rate_limits:\n  reports: { per_second: 0.001, burst: 2 }")).unwrap();
+        let db : &'static Arc<Mutex<Database>> = Box::leak(Box::new(Arc::new(Mutex::new(connect_at(&dir).await.unwrap()))));
+        let as_principal = |principal : Option<&str>|RequestOptions{principal: principal.map(|p|p.to_string()), ..Default::default()};
+        let busy = [RESPONSE_ERROR,TytoError::Busy(String::new()).code()];
+
+        run_ast(db, crate::parser::parse("VACUUM ALL").unwrap(), as_principal(Some("reports"))).await.unwrap();
+        run_ast(db, crate::parser::parse("SEARCH name ON people WHERE name = 'b'").unwrap(), as_principal(Some("reports"))).await.unwrap();
+        for statement in ["VACUUM ALL","SEARCH name ON people WHERE name = 'b'"]{
+            let e = run_ast(db, crate::parser::parse(statement).unwrap(), as_principal(Some("reports"))).await.unwrap_err();
+            assert_eq!(e[..2], busy, "{}", statement);
+        }
+        // lookups answered by an index cost nothing, other principals have their own quota or none
+        run_ast(db, crate::parser::parse("SEARCH name ON people WHERE id = 2").unwrap(), as_principal(Some("reports"))).await.unwrap();
+        for principal in [Some("admin"),None]{
+            run_ast(db, crate::parser::parse("VACUUM ALL").unwrap(), as_principal(principal)).await.unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bulk_loads_keep_unique_columns_unique(){
+        let dir = temp_dir("unique-load");
+        let mut db = connect_at(&dir).await.unwrap();
+        db.run(crate::parser::parse("CREATE CONTAINER people [id, name][BIGINT, SMALL-STRING] UNIQUE [name]").unwrap()).await.unwrap();
+        db.run(crate::parser::parse("BULK LOAD [id, name][[1,'a'],[2,'b']] ON people").unwrap()).await.unwrap();
+        let row = |id : i64, name : &str|(AlbaTypes::Bigint(id),AlbaTypes::SmallString(name.to_string()));
+        // a duplicate inside the load and one of a committed row, neither load writes anything
+        for statement in ["BULK LOAD [id, name][[3,'c'],[4,'c']] ON people", "BULK LOAD [id, name][[3,'c'],[4,'a']] ON people"]{
+            let e = db.run(crate::parser::parse(statement).unwrap()).await.unwrap_err();
+            assert!(matches!(TytoError::from(e), TytoError::Conflict(_)), "{}", statement);
+            assert_eq!(people(&mut db, None).await, vec![row(1,"a"),row(2,"b")]);
+        }
+        db.run(crate::parser::parse("BULK LOAD [id, name][[3,'c']] ON people").unwrap()).await.unwrap();
+        assert_eq!(db.run(crate::parser::parse("SEARCH id ON people WHERE name = 'c'").unwrap()).await.unwrap().rows.1.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::{cmp::Ordering, fmt, io::{Error, ErrorKind}, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Most digits a decimal keeps after the point.
+pub const MAX_DECIMAL_SCALE : u8 = 28;
+
+/// Exact base-10 number, `mantissa / 10^scale`. Always kept normalized, without trailing zeros after the point,
+/// so two decimals holding the same number are equal field by field and hash the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Decimal{
+    mantissa : i128,
+    scale : u8,
+}
+
+fn pow10(exponent : u8) -> i128{
+    10i128.pow(exponent as u32)
+}
+
+fn overflow() -> Error{
+    Error::new(ErrorKind::InvalidData, "Decimal overflow")
+}
+
+impl Decimal{
+    pub fn new(mantissa : i128, scale : u8) -> Result<Self,Error>{
+        if scale > MAX_DECIMAL_SCALE{
+            return Err(Error::new(ErrorKind::InvalidData, format!("A decimal keeps at most {} digits after the point", MAX_DECIMAL_SCALE)))
+        }
+        let mut decimal = Decimal{mantissa, scale};
+        while decimal.scale > 0 && decimal.mantissa % 10 == 0{
+            decimal.mantissa /= 10;
+            decimal.scale -= 1;
+        }
+        Ok(decimal)
+    }
+    /// Whole part, rounded down, and the digits after the point as a count of `10^-MAX_DECIMAL_SCALE`.
+    /// Neither can overflow, which makes them the base of the comparison and of the index encoding.
+    fn parts(&self) -> (i128,i128){
+        let unit = pow10(self.scale);
+        (self.mantissa.div_euclid(unit), self.mantissa.rem_euclid(unit) * pow10(MAX_DECIMAL_SCALE - self.scale))
+    }
+    /// The mantissa at `scale`, which must not be below the current one.
+    fn rescaled(&self, scale : u8) -> Result<i128,Error>{
+        self.mantissa.checked_mul(pow10(scale - self.scale)).ok_or_else(overflow)
+    }
+    pub fn checked_add(&self, other : &Decimal) -> Result<Decimal,Error>{
+        let scale = self.scale.max(other.scale);
+        Decimal::new(self.rescaled(scale)?.checked_add(other.rescaled(scale)?).ok_or_else(overflow)?, scale)
+    }
+    /// Divides by `divisor`, keeping as many digits after the point as fit, rounded half away from zero.
+    pub fn div_int(&self, divisor : i128) -> Result<Decimal,Error>{
+        if divisor == 0{
+            return Err(Error::new(ErrorKind::InvalidData, "Decimal division by zero"))
+        }
+        let mut scale = MAX_DECIMAL_SCALE;
+        while scale > self.scale && self.rescaled(scale).is_err(){
+            scale -= 1;
+        }
+        let dividend = self.rescaled(scale)?;
+        let quotient = dividend / divisor;
+        let remainder = dividend % divisor;
+        let rounding = if remainder.unsigned_abs() * 2 >= divisor.unsigned_abs(){
+            if (dividend < 0) != (divisor < 0) {-1} else {1}
+        }else{
+            0
+        };
+        Decimal::new(quotient + rounding, scale)
+    }
+    pub fn from_f64(value : f64) -> Result<Decimal,Error>{
+        if !value.is_finite(){
+            return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NaN or infinite float to Decimal"))
+        }
+        // the shortest representation that reads back as the same float
+        value.to_string().parse()
+    }
+    pub fn to_f64(&self) -> f64{
+        self.mantissa as f64 / pow10(self.scale) as f64
+    }
+    /// Encoding whose byte order is the numeric order, for ordered indexes.
+    pub fn ordered_key(&self) -> Vec<u8>{
+        let (whole, fraction) = self.parts();
+        let mut key = (whole ^ i128::MIN).to_be_bytes().to_vec();
+        key.extend_from_slice(&fraction.to_be_bytes());
+        key
+    }
+    pub fn to_bytes(&self) -> [u8;17]{
+        let mut bytes = [0u8;17];
+        bytes[..16].copy_from_slice(&self.mantissa.to_le_bytes());
+        bytes[16] = self.scale;
+        bytes
+    }
+    pub fn from_bytes(bytes : [u8;17]) -> Result<Decimal,Error>{
+        let mut mantissa = [0u8;16];
+        mantissa.copy_from_slice(&bytes[..16]);
+        Decimal::new(i128::from_le_bytes(mantissa), bytes[16])
+    }
+}
+
+impl From<i64> for Decimal{
+    fn from(value : i64) -> Self{
+        Decimal{mantissa: value as i128, scale: 0}
+    }
+}
+
+impl Ord for Decimal{
+    fn cmp(&self, other : &Self) -> Ordering{
+        self.parts().cmp(&other.parts())
+    }
+}
+
+impl PartialOrd for Decimal{
+    fn partial_cmp(&self, other : &Self) -> Option<Ordering>{
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for Decimal{
+    type Err = Error;
+    /// Reads an optional sign, digits and an optional point followed by more digits, e.g. `-12.50`.
+    fn from_str(s : &str) -> Result<Self,Error>{
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("{} is not a valid decimal", s));
+        let (negative, digits) = match s.strip_prefix('-'){
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s))
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty(){
+            return Err(invalid())
+        }
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > MAX_DECIMAL_SCALE as usize{
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} has more than {} digits after the point", s, MAX_DECIMAL_SCALE)))
+        }
+        let mut mantissa = 0i128;
+        for c in whole.chars().chain(fraction.chars()){
+            let digit = c.to_digit(10).ok_or_else(invalid)? as i128;
+            mantissa = mantissa.checked_mul(10).and_then(|m|m.checked_add(digit)).ok_or_else(overflow)?;
+        }
+        Decimal::new(if negative {-mantissa} else {mantissa}, fraction.len() as u8)
+    }
+}
+
+impl fmt::Display for Decimal{
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let sign = if self.mantissa < 0 {"-"} else {""};
+        if self.scale == 0{
+            return write!(f, "{}{}", sign, digits)
+        }
+        let digits = format!("{:0>width$}", digits, width = self.scale as usize + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fmt, io::{self, ErrorKind}};
+
+/// What went wrong, as far as a client is concerned.
+/// Inside the server it travels in an `io::Error` where needed and `From<io::Error>` recovers it on the way out.
+#[derive(Debug)]
+pub enum TytoError{
+    /// A container, column, snapshot, cursor or statement that doesn't exist.
+    NotFound(String),
+    /// A key or UNIQUE value already in use, or pending changes in the way of the operation.
+    Conflict(String),
+    /// A value that doesn't fit the type of the column it is meant for.
+    TypeMismatch(String),
+    /// A container definition or schema change that isn't valid.
+    Schema(String),
+    /// A malformed command, condition or argument.
+    InvalidInput(String),
+    PermissionDenied(String),
+    /// Rate limited or locked, the same request may succeed later.
+    Busy(String),
+    /// The request couldn't be decoded or authenticated.
+    Protocol(String),
+    /// The query ran past its timeout and was abandoned.
+    Timeout(String),
+    /// A failure of the underlying storage.
+    Io(io::Error),
+    /// Anything not classified yet.
+    Other(String),
+}
+
+impl TytoError{
+    /// The code sent to clients after the error status byte, see `error_response`.
+    ///
+    /// | code | error |
+    /// |------|-------|
+    /// | 0 | Other |
+    /// | 1 | NotFound |
+    /// | 2 | Conflict |
+    /// | 3 | TypeMismatch |
+    /// | 4 | Schema |
+    /// | 5 | InvalidInput |
+    /// | 6 | PermissionDenied |
+    /// | 7 | Busy |
+    /// | 8 | Protocol |
+    /// | 9 | Io |
+    /// | 10 | Timeout |
+    pub fn code(&self) -> u8{
+        match self{
+            TytoError::Other(_) => 0,
+            TytoError::NotFound(_) => 1,
+            TytoError::Conflict(_) => 2,
+            TytoError::TypeMismatch(_) => 3,
+            TytoError::Schema(_) => 4,
+            TytoError::InvalidInput(_) => 5,
+            TytoError::PermissionDenied(_) => 6,
+            TytoError::Busy(_) => 7,
+            TytoError::Protocol(_) => 8,
+            TytoError::Io(_) => 9,
+            TytoError::Timeout(_) => 10,
+        }
+    }
+    pub fn kind(&self) -> ErrorKind{
+        match self{
+            TytoError::NotFound(_) => ErrorKind::NotFound,
+            TytoError::Conflict(_) => ErrorKind::AlreadyExists,
+            TytoError::TypeMismatch(_) => ErrorKind::InvalidData,
+            TytoError::Schema(_) => ErrorKind::InvalidInput,
+            TytoError::InvalidInput(_) => ErrorKind::InvalidInput,
+            TytoError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            TytoError::Busy(_) => ErrorKind::WouldBlock,
+            TytoError::Protocol(_) => ErrorKind::InvalidData,
+            TytoError::Timeout(_) => ErrorKind::TimedOut,
+            TytoError::Io(e) => e.kind(),
+            TytoError::Other(_) => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for TytoError{
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            TytoError::NotFound(msg) | TytoError::Conflict(msg) | TytoError::TypeMismatch(msg) | TytoError::Schema(msg)
+            | TytoError::InvalidInput(msg) | TytoError::PermissionDenied(msg) | TytoError::Busy(msg) | TytoError::Protocol(msg)
+            | TytoError::Timeout(msg) | TytoError::Other(msg) => f.write_str(msg),
+            TytoError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TytoError{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self{
+            TytoError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+/// Errors raised as a `TytoError` come back as they were. OS errors are `Io`,
+/// the others are classified by the kind they were raised with.
+impl From<io::Error> for TytoError{
+    fn from(e : io::Error) -> Self{
+        if e.raw_os_error().is_some(){
+            return TytoError::Io(e)
+        }
+        if e.get_ref().is_some_and(|inner|inner.is::<TytoError>()){
+            if let Some(Ok(inner)) = e.into_inner().map(|inner|inner.downcast::<TytoError>()){
+                return *inner
+            }
+            return TytoError::Other("Lost the error while unwrapping it".to_string())
+        }
+        let msg = e.to_string();
+        match e.kind(){
+            ErrorKind::NotFound => TytoError::NotFound(msg),
+            ErrorKind::AlreadyExists | ErrorKind::AddrInUse => TytoError::Conflict(msg),
+            ErrorKind::InvalidData => TytoError::TypeMismatch(msg),
+            ErrorKind::InvalidInput => TytoError::InvalidInput(msg),
+            ErrorKind::PermissionDenied => TytoError::PermissionDenied(msg),
+            ErrorKind::WouldBlock => TytoError::Busy(msg),
+            ErrorKind::TimedOut => TytoError::Timeout(msg),
+            ErrorKind::UnexpectedEof => TytoError::Io(e),
+            _ => TytoError::Other(msg),
+        }
+    }
+}
+
+impl From<TytoError> for io::Error{
+    fn from(e : TytoError) -> Self{
+        match e{
+            TytoError::Io(e) => e,
+            e => io::Error::new(e.kind(), e)
+        }
+    }
+}
@@ -1,7 +1,21 @@
-use std::{collections::hash_map::DefaultHasher, fs::{self, File, OpenOptions}, hash::{Hash, Hasher}, io::Error, os::unix::fs::FileExt, path::Path};
+use std::{collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap}, hash::{Hash, Hasher}, io::Error, ops::Bound, sync::Arc};
+
+use crate::{alba_types::AlbaTypes, container::{get_index, get_key_index, ordered_key, ordered_key_index}, storage::{flush_as, Backend, Durability, Storage}};
 
 const BUCKET_CAPACITY : u64 = 4096;
 const BUCKET_SIZE : u64 = 73728; // 4096 cells * 18 bytes/cell
+/// Appended to the container path for its primary key index.
+pub const HASHMAP_SUFFIX : &str = ".hashmap";
+/// Appended to the container path for the primary key index being rebuilt by a rebucket.
+pub const HASHMAP_TEMP_SUFFIX : &str = ".temp.hashmap";
+/// Appended to the container path for its ordered primary key index.
+pub const ORDERED_INDEX_SUFFIX : &str = ".ordered";
+/// Appended to the container path for the ordered primary key index being compacted.
+pub const ORDERED_INDEX_TEMP_SUFFIX : &str = ".ordered.temp";
+/// Appended to the container path for its secondary indexes.
+pub const SECONDARY_INDEX_SUFFIX : &str = ".index";
+/// Appended to the container path for the secondary indexes being written by a sync.
+pub const SECONDARY_INDEX_TEMP_SUFFIX : &str = ".index.temp";
 
 #[derive(PartialEq, Debug)]
 enum CellState {
@@ -49,31 +63,52 @@ impl Cell{
         bytes
     }
 }
+/// Counts how many cells `get`/`insert` had to look at before settling, a rising average means it is time to rebucket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProbeStats{
+    pub operations : u64,
+    pub total_probes : u64,
+    pub max_probe : u64,
+}
+
+impl ProbeStats{
+    fn record(&mut self, probes : u64){
+        self.operations += 1;
+        self.total_probes += probes;
+        self.max_probe = self.max_probe.max(probes);
+    }
+    pub fn average(&self) -> f64{
+        if self.operations == 0 {0.0} else {self.total_probes as f64 / self.operations as f64}
+    }
+}
+
 #[derive(Debug)]
 pub struct Hashmap{
     length : u64,
     bucket_count : u64,
-    file : File,
-    path: String,
+    file : Box<dyn Storage>,
+    backend : Arc<dyn Backend>,
+    probes : ProbeStats,
 }
 impl Hashmap{
-    pub fn new(path : String) -> Result<Self,Error> {
-        let filepath = format!("{}.hashmap", &path);
-        if !Path::new(&filepath).exists(){
-            let f = fs::File::create_new(&filepath)?;
-            f.set_len(8+BUCKET_SIZE)?;
-            f.write_all_at(&0u64.to_le_bytes(), 0)?;
-            return Ok(Hashmap { length: 0, bucket_count: 1, file:f, path})
-        }
-        let file = OpenOptions::new().read(true).write(true).open(filepath)?;
+    pub fn new(backend : Arc<dyn Backend>) -> Result<Self,Error> {
+        Self::open(backend, HASHMAP_SUFFIX)
+    }
+    fn open(backend : Arc<dyn Backend>, suffix : &str) -> Result<Self,Error> {
+        let file = backend.open(suffix)?;
+        if file.len()? == 0{
+            file.set_len(8+BUCKET_SIZE)?;
+            file.write_all_at(&0u64.to_le_bytes(), 0)?;
+            return Ok(Hashmap { length: 0, bucket_count: 1, file, backend, probes: ProbeStats::default()})
+        }
         let length = {
             let mut load = [0u8;8];
             file.read_exact_at(&mut load, 0)?;
             u64::from_le_bytes(load)
         };
-        let file_size = file.metadata()?.len();
+        let file_size = file.len()?;
         let bucket_count = (file_size - 8) / BUCKET_SIZE;
-        Ok(Hashmap { length, bucket_count, file, path})
+        Ok(Hashmap { length, bucket_count, file, backend, probes: ProbeStats::default()})
     }
 
     fn h(&self,key:u64) -> u64{let mut h=DefaultHasher::new();key.hash(&mut h);h.finish()}
@@ -97,8 +132,10 @@ impl Hashmap{
         let (start_ptr, bucket_start_ptr) = self.get_initial_ptr(key);
         let mut ptr = start_ptr;
         let mut tombstone_ptr = None;
+        let mut probes = 0u64;
 
         loop {
+            probes += 1;
             let mut bin = [0u8;18];
             self.file.read_exact_at(&mut bin, ptr)?;
             let cell = Cell::from_bytes(bin);
@@ -112,12 +149,14 @@ impl Hashmap{
                 let new_cell = Cell { key, value, state: CellState::Occupied };
                 self.file.write_all_at(&new_cell.as_bytes(), write_ptr)?;
                 self.length += 1;
+                self.probes.record(probes);
                 return Ok(());
             }
 
             if cell.state == CellState::Occupied && cell.key == key {
                 let new_cell = Cell { key, value, state: CellState::Occupied };
                 self.file.write_all_at(&new_cell.as_bytes(), ptr)?;
+                self.probes.record(probes);
                 return Ok(());
             }
 
@@ -126,6 +165,7 @@ impl Hashmap{
                 ptr = bucket_start_ptr;
             }
             if ptr == start_ptr {
+                self.probes.record(probes);
                 return Err(Error::new(std::io::ErrorKind::Other, "Bucket is full, rebucket failed"));
             }
         }
@@ -134,17 +174,21 @@ impl Hashmap{
     pub fn get(&mut self, key : u64) -> Result<Option<u64>,Error>{
         let (start_ptr, bucket_start_ptr) = self.get_initial_ptr(key);
         let mut ptr = start_ptr;
+        let mut probes = 0u64;
 
         loop {
+            probes += 1;
             let mut bin = [0u8;18];
             self.file.read_exact_at(&mut bin, ptr)?;
             let cell = Cell::from_bytes(bin);
 
             if cell.state == CellState::Empty {
+                self.probes.record(probes);
                 return Ok(None);
             }
 
             if cell.state == CellState::Occupied && cell.key == key {
+                self.probes.record(probes);
                 return Ok(Some(cell.value));
             }
 
@@ -153,6 +197,7 @@ impl Hashmap{
                 ptr = bucket_start_ptr;
             }
             if ptr == start_ptr {
+                self.probes.record(probes);
                 return Ok(None);
             }
         }
@@ -188,17 +233,40 @@ impl Hashmap{
         }
     }
 
+    /// Drops every key, shrinking the map back to a single empty bucket.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.file.set_len(8)?;
+        self.file.set_len(8 + BUCKET_SIZE)?;
+        self.length = 0;
+        self.bucket_count = 1;
+        self.probes = ProbeStats::default();
+        self.sync()
+    }
+
     pub fn rebucket(&mut self) -> Result<(), Error> {
-        let temp_path_str = format!("{}.temp", self.path);
-        let _ = fs::remove_file(format!("{}.hashmap", &temp_path_str));
-        let mut new_hm = Hashmap::new(temp_path_str.clone())?;
+        self.rebucket_to(self.bucket_count * 10)
+    }
+
+    /// Grows the map once so `additional` more keys fit under the load factor, instead of rebucketing several times while they are inserted.
+    pub fn reserve(&mut self, additional : u64) -> Result<(), Error> {
+        let target = self.length + additional;
+        let needed = (target * 100).div_ceil(70 * BUCKET_CAPACITY).max(1);
+        if needed > self.bucket_count {
+            self.rebucket_to(needed)?;
+        }
+        Ok(())
+    }
+
+    fn rebucket_to(&mut self, new_bucket_count : u64) -> Result<(), Error> {
+        // the new map is built next to the old one and moved over it once complete
+        self.backend.remove(HASHMAP_TEMP_SUFFIX)?;
+        let mut new_hm = Hashmap::open(self.backend.clone(), HASHMAP_TEMP_SUFFIX)?;
 
-        let new_bucket_count = self.bucket_count * 10;
         let new_len = 8 + new_bucket_count * BUCKET_SIZE;
         new_hm.file.set_len(new_len)?;
         new_hm.bucket_count = new_bucket_count;
 
-        let old_file_len = self.file.metadata()?.len();
+        let old_file_len = self.file.len()?;
         let mut read_ptr = 8;
         
         loop {
@@ -216,25 +284,472 @@ impl Hashmap{
 
         new_hm.sync()?;
 
-        let old_filepath = format!("{}.hashmap", self.path);
-        let temp_filepath = format!("{}.hashmap", temp_path_str);
-
-        self.file = new_hm.file;
         self.bucket_count = new_hm.bucket_count;
         self.length = new_hm.length;
+        drop(new_hm);
 
-        fs::remove_file(&old_filepath)?;
-        fs::rename(temp_filepath, &old_filepath)?;
-        
-        self.file = OpenOptions::new().read(true).write(true).open(&old_filepath)?;
+        self.backend.rename(HASHMAP_TEMP_SUFFIX, HASHMAP_SUFFIX)?;
+        self.file = self.backend.open(HASHMAP_SUFFIX)?;
+        self.probes = ProbeStats::default();
 
         Ok(())
     }
 
+    pub fn probe_stats(&self) -> ProbeStats{
+        self.probes
+    }
+    pub fn len(&self) -> u64{
+        self.length
+    }
+    pub fn bucket_count(&self) -> u64{
+        self.bucket_count
+    }
+
 
     pub fn sync(&mut self) -> Result<(),Error>{
+        self.sync_as(Durability::current())
+    }
+    pub fn sync_as(&mut self, durability : Durability) -> Result<(),Error>{
         self.file.write_all_at(&self.length.to_le_bytes(), 0)?;
+        flush_as(&*self.file, durability)
+    }
+}
+
+const ORDERED_LOG_REMOVE : u8 = 0;
+const ORDERED_LOG_INSERT : u8 = 1;
+/// The log is rewritten from the live keys once it holds this many entries more than twice their count.
+const ORDERED_LOG_SLACK : u64 = 4096;
+
+/// Primary key index kept in key order, so ranges over the key are answered without a scan.
+/// The keys live in a `BTreeMap`, the `.ordered` file is a log of the inserts and removals since it was last rewritten,
+/// replayed on open. Each entry is the operation byte, the key length as a u64, the key and, for inserts, the row offset.
+#[derive(Debug)]
+pub struct OrderedIndex{
+    entries : BTreeMap<Vec<u8>,u64>,
+    file : Box<dyn Storage>,
+    backend : Arc<dyn Backend>,
+    /// Log entries not written yet, appended by the next `sync`.
+    unsynced : Vec<u8>,
+    /// Entries in the log, written or not.
+    logged : u64,
+}
+
+impl OrderedIndex{
+    pub fn new(backend : Arc<dyn Backend>) -> Result<Self,Error>{
+        let file = backend.open(ORDERED_INDEX_SUFFIX)?;
+        let mut buffer = vec![0u8;file.len()? as usize];
+        file.read_exact_at(&mut buffer, 0)?;
+        let mut entries = BTreeMap::new();
+        let mut cursor = 0usize;
+        let mut logged = 0u64;
+        // an entry cut short by a crash during a sync was never acknowledged, the log ends before it
+        while let Some((end, operation, key, offset)) = Self::read_entry(&buffer, cursor){
+            match operation{
+                ORDERED_LOG_INSERT => {entries.insert(key, offset);},
+                _ => {entries.remove(&key);}
+            }
+            cursor = end;
+            logged += 1;
+        }
+        if cursor < buffer.len(){
+            file.set_len(cursor as u64)?;
+        }
+        Ok(OrderedIndex { entries, file, backend, unsynced: Vec::new(), logged })
+    }
+    fn read_entry(buffer : &[u8], mut cursor : usize) -> Option<(usize, u8, Vec<u8>, u64)>{
+        let operation = read_bytes(buffer, &mut cursor, 1).ok()?[0];
+        let key_len = read_u64(buffer, &mut cursor).ok()? as usize;
+        let key = read_bytes(buffer, &mut cursor, key_len).ok()?.to_vec();
+        let offset = if operation == ORDERED_LOG_INSERT {read_u64(buffer, &mut cursor).ok()?} else {0};
+        Some((cursor, operation, key, offset))
+    }
+    fn log(&mut self, operation : u8, key : &[u8], offset : u64){
+        self.unsynced.push(operation);
+        self.unsynced.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        self.unsynced.extend_from_slice(key);
+        if operation == ORDERED_LOG_INSERT{
+            self.unsynced.extend_from_slice(&offset.to_le_bytes());
+        }
+        self.logged += 1;
+    }
+    pub fn insert(&mut self, key : Vec<u8>, offset : u64){
+        self.log(ORDERED_LOG_INSERT, &key, offset);
+        self.entries.insert(key, offset);
+    }
+    pub fn get(&self, key : &[u8]) -> Option<u64>{
+        self.entries.get(key).copied()
+    }
+    pub fn remove(&mut self, key : &[u8]) -> bool{
+        if self.entries.remove(key).is_none(){
+            return false
+        }
+        self.log(ORDERED_LOG_REMOVE, key, 0);
+        true
+    }
+    /// Offsets of the rows whose key lies between `lower` and `upper`, in key order.
+    pub fn range(&self, lower : Bound<Vec<u8>>, upper : Bound<Vec<u8>>) -> Vec<u64>{
+        if let (Bound::Included(l)|Bound::Excluded(l), Bound::Included(u)|Bound::Excluded(u)) = (&lower, &upper){
+            if l > u || (l == u && !(matches!(lower, Bound::Included(_)) && matches!(upper, Bound::Included(_)))){
+                return Vec::new()
+            }
+        }
+        self.entries.range((lower, upper)).map(|(_,offset)|*offset).collect()
+    }
+    pub fn len(&self) -> u64{
+        self.entries.len() as u64
+    }
+    pub fn clear(&mut self) -> Result<(),Error>{
+        self.entries.clear();
+        self.unsynced.clear();
+        self.logged = 0;
+        self.file.set_len(0)?;
         self.file.sync_all()
     }
+    /// Appends the pending log entries, or rewrites the log from the live keys when removals and overwrites made it too long.
+    /// Backends that don't outlive the process only keep the keys in memory.
+    pub fn sync_as(&mut self, durability : Durability) -> Result<(),Error>{
+        if !self.backend.persistent(){
+            self.unsynced.clear();
+            return Ok(())
+        }
+        if self.logged > self.len() * 2 + ORDERED_LOG_SLACK{
+            return self.compact()
+        }
+        if !self.unsynced.is_empty(){
+            let end = self.file.len()?;
+            self.file.write_all_at(&self.unsynced, end)?;
+            self.unsynced.clear();
+        }
+        flush_as(&*self.file, durability)
+    }
+    fn compact(&mut self) -> Result<(),Error>{
+        self.unsynced.clear();
+        self.logged = 0;
+        let entries = std::mem::take(&mut self.entries);
+        for (key, offset) in entries.iter(){
+            self.log(ORDERED_LOG_INSERT, key, *offset);
+        }
+        self.entries = entries;
+        self.backend.remove(ORDERED_INDEX_TEMP_SUFFIX)?;
+        let file = self.backend.open(ORDERED_INDEX_TEMP_SUFFIX)?;
+        file.write_all_at(&self.unsynced, 0)?;
+        file.sync_all()?;
+        drop(file);
+        self.unsynced.clear();
+        self.backend.rename(ORDERED_INDEX_TEMP_SUFFIX, ORDERED_INDEX_SUFFIX)?;
+        self.file = self.backend.open(ORDERED_INDEX_SUFFIX)?;
+        Ok(())
+    }
+}
+
+/// The primary key index of a container, picked when the container is created.
+/// Both map the key to the row offset, only the ordered one answers ranges over the key.
+#[derive(Debug)]
+pub enum PrimaryIndex{
+    Hash(Hashmap),
+    Ordered(OrderedIndex),
 }
 
+impl PrimaryIndex{
+    pub fn new(backend : Arc<dyn Backend>, kind : IndexKind) -> Result<Self,Error>{
+        Ok(match kind{
+            IndexKind::Hash => PrimaryIndex::Hash(Hashmap::new(backend)?),
+            IndexKind::Ordered => PrimaryIndex::Ordered(OrderedIndex::new(backend)?),
+        })
+    }
+    /// Suffix of the file holding a `kind` primary key index.
+    pub fn suffix(kind : IndexKind) -> &'static str{
+        match kind{
+            IndexKind::Hash => HASHMAP_SUFFIX,
+            IndexKind::Ordered => ORDERED_INDEX_SUFFIX,
+        }
+    }
+    pub fn kind(&self) -> IndexKind{
+        match self{
+            PrimaryIndex::Hash(_) => IndexKind::Hash,
+            PrimaryIndex::Ordered(_) => IndexKind::Ordered,
+        }
+    }
+    pub fn insert(&mut self, key : &[AlbaTypes], offset : u64) -> Result<(),Error>{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.insert(get_key_index(key), offset),
+            PrimaryIndex::Ordered(index) => {index.insert(ordered_key_index(key), offset);Ok(())},
+        }
+    }
+    pub fn get(&mut self, key : &[AlbaTypes]) -> Result<Option<u64>,Error>{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.get(get_key_index(key)),
+            PrimaryIndex::Ordered(index) => Ok(index.get(&ordered_key_index(key))),
+        }
+    }
+    pub fn remove(&mut self, key : &[AlbaTypes]) -> Result<bool,Error>{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.remove(get_key_index(key)),
+            PrimaryIndex::Ordered(index) => Ok(index.remove(&ordered_key_index(key))),
+        }
+    }
+    /// Offsets of the rows whose single column key lies between `lower` and `upper`, `None` for hash indexes.
+    pub fn range(&self, lower : Bound<&AlbaTypes>, upper : Bound<&AlbaTypes>) -> Option<Vec<u64>>{
+        match self{
+            PrimaryIndex::Hash(_) => None,
+            PrimaryIndex::Ordered(index) => Some(index.range(lower.map(ordered_key_index_of), upper.map(ordered_key_index_of))),
+        }
+    }
+    pub fn clear(&mut self) -> Result<(),Error>{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.clear(),
+            PrimaryIndex::Ordered(index) => index.clear(),
+        }
+    }
+    /// Makes room for `additional` more keys, only the hash index needs it.
+    pub fn reserve(&mut self, additional : u64) -> Result<(),Error>{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.reserve(additional),
+            PrimaryIndex::Ordered(_) => Ok(()),
+        }
+    }
+    pub fn sync(&mut self) -> Result<(),Error>{
+        self.sync_as(Durability::current())
+    }
+    pub fn sync_as(&mut self, durability : Durability) -> Result<(),Error>{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.sync_as(durability),
+            PrimaryIndex::Ordered(index) => index.sync_as(durability),
+        }
+    }
+    /// The file the index is kept in.
+    pub fn storage(&self) -> &dyn Storage{
+        match self{
+            PrimaryIndex::Hash(hm) => &*hm.file,
+            PrimaryIndex::Ordered(index) => &*index.file,
+        }
+    }
+    pub fn len(&self) -> u64{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.len(),
+            PrimaryIndex::Ordered(index) => index.len(),
+        }
+    }
+    /// Buckets of the hash index, 0 for an ordered one.
+    pub fn bucket_count(&self) -> u64{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.bucket_count(),
+            PrimaryIndex::Ordered(_) => 0,
+        }
+    }
+    /// Probe counts of the hash index, always empty for an ordered one.
+    pub fn probe_stats(&self) -> ProbeStats{
+        match self{
+            PrimaryIndex::Hash(hm) => hm.probe_stats(),
+            PrimaryIndex::Ordered(_) => ProbeStats::default(),
+        }
+    }
+}
+
+fn ordered_key_index_of(value : &AlbaTypes) -> Vec<u8>{
+    ordered_key_index(std::slice::from_ref(value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind{
+    #[default]
+    Hash,
+    Ordered,
+}
+
+impl IndexKind{
+    pub fn id(&self) -> u8{
+        match self{
+            IndexKind::Hash => 0,
+            IndexKind::Ordered => 1,
+        }
+    }
+    pub fn from_id(id : u8) -> Result<Self,Error>{
+        match id{
+            0 => Ok(IndexKind::Hash),
+            1 => Ok(IndexKind::Ordered),
+            x => Err(Error::new(std::io::ErrorKind::InvalidData, format!("Unknown index kind: {}", x))),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SecondaryEntries{
+    Hash(HashMap<Vec<u8>,BTreeSet<u64>>),
+    Ordered(BTreeMap<Vec<u8>,BTreeSet<u64>>),
+}
+
+/// A non-unique index over one non primary key column, mapping column values to the offsets of the rows holding them.
+/// Hash indexes only answer equality, ordered ones also answer ranges.
+#[derive(Debug)]
+pub struct SecondaryIndex{
+    pub column : String,
+    pub kind : IndexKind,
+    entries : SecondaryEntries,
+    by_offset : HashMap<u64,Vec<u8>>,
+}
+
+impl SecondaryIndex{
+    pub fn new(column : String, kind : IndexKind) -> Self{
+        let entries = match kind{
+            IndexKind::Hash => SecondaryEntries::Hash(HashMap::new()),
+            IndexKind::Ordered => SecondaryEntries::Ordered(BTreeMap::new()),
+        };
+        SecondaryIndex { column, kind, entries, by_offset: HashMap::new() }
+    }
+    fn key(&self, value : &AlbaTypes) -> Vec<u8>{
+        match self.kind{
+            IndexKind::Hash => get_index(value.clone()).to_le_bytes().to_vec(),
+            IndexKind::Ordered => ordered_key(value),
+        }
+    }
+    fn insert_key(&mut self, key : Vec<u8>, offset : u64){
+        self.remove_offset(offset);
+        match &mut self.entries{
+            SecondaryEntries::Hash(m) => {m.entry(key.clone()).or_default().insert(offset);},
+            SecondaryEntries::Ordered(m) => {m.entry(key.clone()).or_default().insert(offset);},
+        }
+        self.by_offset.insert(offset, key);
+    }
+    pub fn insert(&mut self, value : &AlbaTypes, offset : u64){
+        let key = self.key(value);
+        self.insert_key(key, offset);
+    }
+    pub fn remove_offset(&mut self, offset : u64){
+        let key = match self.by_offset.remove(&offset){
+            Some(k) => k,
+            None => return
+        };
+        let emptied = match &mut self.entries{
+            SecondaryEntries::Hash(m) => m.get_mut(&key).map(|s|{s.remove(&offset);s.is_empty()}),
+            SecondaryEntries::Ordered(m) => m.get_mut(&key).map(|s|{s.remove(&offset);s.is_empty()}),
+        };
+        if emptied == Some(true){
+            match &mut self.entries{
+                SecondaryEntries::Hash(m) => {m.remove(&key);},
+                SecondaryEntries::Ordered(m) => {m.remove(&key);},
+            }
+        }
+    }
+    /// Moves whatever key points at `from` so it points at `to`, used when vacuum relocates a row.
+    pub fn relocate(&mut self, from : u64, to : u64){
+        if let Some(key) = self.by_offset.get(&from).cloned(){
+            self.remove_offset(from);
+            self.insert_key(key, to);
+        }
+    }
+    pub fn clear(&mut self){
+        self.by_offset.clear();
+        match &mut self.entries{
+            SecondaryEntries::Hash(m) => m.clear(),
+            SecondaryEntries::Ordered(m) => m.clear(),
+        }
+    }
+    pub fn lookup(&self, value : &AlbaTypes) -> Vec<u64>{
+        let key = self.key(value);
+        let set = match &self.entries{
+            SecondaryEntries::Hash(m) => m.get(&key),
+            SecondaryEntries::Ordered(m) => m.get(&key),
+        };
+        set.map(|s|s.iter().copied().collect()).unwrap_or_default()
+    }
+    pub fn range(&self, lower : Bound<&AlbaTypes>, upper : Bound<&AlbaTypes>) -> Result<Vec<u64>,Error>{
+        let map = match &self.entries{
+            SecondaryEntries::Ordered(m) => m,
+            SecondaryEntries::Hash(_) => return Err(Error::new(std::io::ErrorKind::Unsupported, format!("The index on column {} is a hash index and cannot answer range lookups", self.column)))
+        };
+        let lower = lower.map(ordered_key);
+        let upper = upper.map(ordered_key);
+        if let (Bound::Included(l)|Bound::Excluded(l), Bound::Included(u)|Bound::Excluded(u)) = (&lower,&upper){
+            if l > u || (l == u && !(matches!(lower,Bound::Included(_)) && matches!(upper,Bound::Included(_)))){
+                return Ok(Vec::new())
+            }
+        }
+        Ok(map.range((lower,upper)).flat_map(|(_,s)|s.iter().copied()).collect())
+    }
+    fn write_into(&self, buffer : &mut Vec<u8>){
+        buffer.extend_from_slice(&(self.column.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(self.column.as_bytes());
+        buffer.push(self.kind.id());
+        buffer.extend_from_slice(&(self.by_offset.len() as u64).to_le_bytes());
+        for (offset,key) in self.by_offset.iter(){
+            buffer.extend_from_slice(&(key.len() as u64).to_le_bytes());
+            buffer.extend_from_slice(key);
+            buffer.extend_from_slice(&offset.to_le_bytes());
+        }
+    }
+}
+
+/// Every secondary index of a container, stored together in the `.index` file next to it.
+#[derive(Debug)]
+pub struct SecondaryIndexes{
+    backend : Arc<dyn Backend>,
+    pub indexes : Vec<SecondaryIndex>,
+}
+
+fn read_u64(buffer : &[u8], cursor : &mut usize) -> Result<u64,Error>{
+    let bytes = buffer.get(*cursor..*cursor+8).ok_or(Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated secondary index file"))?;
+    *cursor += 8;
+    let mut load = [0u8;8];
+    load.copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(load))
+}
+fn read_bytes<'a>(buffer : &'a [u8], cursor : &mut usize, len : usize) -> Result<&'a [u8],Error>{
+    let bytes = buffer.get(*cursor..*cursor+len).ok_or(Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated secondary index file"))?;
+    *cursor += len;
+    Ok(bytes)
+}
+
+impl SecondaryIndexes{
+    pub fn new(backend : Arc<dyn Backend>) -> Result<Self,Error>{
+        let mut indexes = Vec::new();
+        if backend.exists(SECONDARY_INDEX_SUFFIX)?{
+            let file = backend.open(SECONDARY_INDEX_SUFFIX)?;
+            let mut buffer = vec![0u8;file.len()? as usize];
+            file.read_exact_at(&mut buffer, 0)?;
+            let mut cursor = 0usize;
+            let count = read_u64(&buffer, &mut cursor)?;
+            for _ in 0..count{
+                let name_len = read_u64(&buffer, &mut cursor)? as usize;
+                let column = String::from_utf8_lossy(read_bytes(&buffer, &mut cursor, name_len)?).to_string();
+                let kind = IndexKind::from_id(read_bytes(&buffer, &mut cursor, 1)?[0])?;
+                let mut index = SecondaryIndex::new(column, kind);
+                let entries = read_u64(&buffer, &mut cursor)?;
+                for _ in 0..entries{
+                    let key_len = read_u64(&buffer, &mut cursor)? as usize;
+                    let key = read_bytes(&buffer, &mut cursor, key_len)?.to_vec();
+                    let offset = read_u64(&buffer, &mut cursor)?;
+                    index.insert_key(key, offset);
+                }
+                indexes.push(index);
+            }
+        }
+        Ok(SecondaryIndexes { backend, indexes })
+    }
+    pub fn get(&self, column : &str) -> Option<&SecondaryIndex>{
+        self.indexes.iter().find(|i|i.column == column)
+    }
+    pub fn kinds(&self) -> HashMap<String,IndexKind>{
+        self.indexes.iter().map(|i|(i.column.clone(),i.kind)).collect()
+    }
+    /// Writes the indexes to the `.index` file. Backends that don't outlive the process only keep them in `indexes`.
+    pub fn sync(&self) -> Result<(),Error>{
+        if !self.backend.persistent(){
+            return Ok(())
+        }
+        if self.indexes.is_empty(){
+            return self.backend.remove(SECONDARY_INDEX_SUFFIX)
+        }
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&(self.indexes.len() as u64).to_le_bytes());
+        for index in self.indexes.iter(){
+            index.write_into(&mut buffer);
+        }
+        let file = self.backend.open(SECONDARY_INDEX_TEMP_SUFFIX)?;
+        file.set_len(0)?;
+        file.write_all_at(&buffer, 0)?;
+        file.sync_all()?;
+        drop(file);
+        self.backend.rename(SECONDARY_INDEX_TEMP_SUFFIX, SECONDARY_INDEX_SUFFIX)
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::HashMap, fs, io::{Error, ErrorKind}, sync::RwLock, time::SystemTime};
+
+use base64::{engine::general_purpose, Engine};
+
+/// Named client keys read from a YAML map of name to base64 encoded 32 byte key.
+/// With no keys file every connection is trusted through the shared `.secret` alone, as before.
+/// Once the file exists every message has to be wrapped as `name length (u8), name, tag (32 bytes), command`,
+/// the tag being the BLAKE3 keyed hash of the command under the named key.
+#[derive(Debug)]
+pub struct Keystore{
+    path : String,
+    /// The keys and the modification time of the file they were read from.
+    state : RwLock<(HashMap<String,[u8;32]>,Option<SystemTime>)>,
+}
+
+fn read_keys(path : &str) -> Result<(HashMap<String,[u8;32]>,Option<SystemTime>),Error>{
+    if !fs::exists(path)?{
+        return Ok((HashMap::new(),None))
+    }
+    let modified = fs::metadata(path)?.modified().ok();
+    let raw : HashMap<String,String> = serde_yaml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e|Error::new(ErrorKind::InvalidData, format!("The keys file {} is invalid: {}", path, e)))?;
+    let mut keys = HashMap::with_capacity(raw.len());
+    for (name,encoded) in raw{
+        if name.is_empty() || name.len() > u8::MAX as usize{
+            return Err(Error::new(ErrorKind::InvalidData, format!("Key names in {} must be 1 to 255 bytes long, '{}' is not", path, name)))
+        }
+        let key : [u8;32] = general_purpose::STANDARD.decode(encoded.trim()).ok().and_then(|k|k.try_into().ok())
+            .ok_or(Error::new(ErrorKind::InvalidData, format!("The key '{}' in {} must be 32 bytes encoded as base64", name, path)))?;
+        keys.insert(name, key);
+    }
+    Ok((keys,modified))
+}
+
+impl Keystore{
+    pub fn load(path : String) -> Result<Self,Error>{
+        let state = read_keys(&path)?;
+        Ok(Keystore{path,state:RwLock::new(state)})
+    }
+    /// Re-reads the keys file when it changed since the last read, so keys can be rotated without a restart.
+    /// A file that fails to parse keeps the previous keys in place.
+    pub fn reload_if_changed(&self) -> Result<(),Error>{
+        let modified = if fs::exists(&self.path)?{fs::metadata(&self.path)?.modified().ok()}else{None};
+        if self.state.read().map(|s|s.1 == modified).unwrap_or(false){
+            return Ok(())
+        }
+        let state = read_keys(&self.path)?;
+        if let Ok(mut current) = self.state.write(){
+            *current = state;
+        }
+        Ok(())
+    }
+    /// Checks the envelope of `input`, returning the name of the key that signed it and the command inside.
+    /// Without keys the input is the command itself and carries no name.
+    pub fn authenticate<'a>(&self, input : &'a [u8]) -> Result<(Option<String>,&'a [u8]),Error>{
+        let _ = self.reload_if_changed();
+        let state = self.state.read().map_err(|_|Error::new(ErrorKind::Other, "The keystore lock is poisoned"))?;
+        if state.0.is_empty(){
+            return Ok((None,input))
+        }
+        let denied = || Error::new(ErrorKind::PermissionDenied, "The message is not signed by a known key");
+        let name_len = *input.first().ok_or_else(denied)? as usize;
+        let name = input.get(1..1+name_len).ok_or_else(denied)?;
+        let tag = input.get(1+name_len..1+name_len+32).ok_or_else(denied)?;
+        let command = &input[1+name_len+32..];
+        let name = String::from_utf8_lossy(name).to_string();
+        let key = state.0.get(&name).ok_or_else(denied)?;
+        let tag : [u8;32] = tag.try_into().map_err(|_|denied())?;
+        // blake3::Hash compares in constant time
+        if blake3::keyed_hash(key, command) != blake3::Hash::from(tag){
+            return Err(denied())
+        }
+        Ok((Some(name),command))
+    }
+}
@@ -4,9 +4,19 @@ mod container;
 mod row;
 mod query;
 mod alba_types;
+mod decimal;
+mod aggregate;
 mod query_conditions;
+mod parser;
+mod rate_limit;
+mod keystore;
+mod error;
+mod result_cache;
+mod storage;
 use std::io::{Error,ErrorKind};
 use alba_types::AlbaTypes;
+use indexing::IndexKind;
+use aggregate::AggKind;
 use tokio;
 use database::connect;
 
@@ -80,7 +90,49 @@ pub enum Token{
 
 - CREATE <Instance> ...
 | CREATE CONTAINER <name> [col_nam][col_typ] 
+| CREATE CONTAINER <name> [col_nam][col_typ] INDEX [col_nam][HASH|ORDERED]
+| CREATE CONTAINER <name> [col_nam][col_typ] KEY [col_nam]
+| CREATE CONTAINER <name> [col_nam][col_typ] AUTO INCREMENT
+| CREATE CONTAINER <name> [col_nam][col_typ] DEFAULT [col_nam][col_val]
+| CREATE CONTAINER <name> [col_nam][col_typ] NOT NULL [col_nam]
+| CREATE CONTAINER <name> [col_nam][col_typ] UNIQUE [col_nam]
+| CREATE CONTAINER <name> [col_nam][col_typ] EMPTY AS NULL [col_nam]
+| CREATE CONTAINER <name> [col_nam][col_typ] IN MEMORY
+| CREATE CONTAINER <name> [col_nam][col_typ] ORDERED KEY
 | CREATE ROW [col_nam][col_val] ON <container:name>
+| CREATE ROW {col_nam: col_val, ...} ON <container:name>
+
+- REPLACE <Instance> ...
+| REPLACE ROW [col_val] ON <container:name>
+
+- BULK LOAD [col_nam][[col_val], ...] ON <container:name>
+
+- PREALLOCATE <rows> ON <container:name>
+
+- TRUNCATE <container:name>
+
+- CANCEL VACUUM <container:name>
+
+- VACUUM ALL
+
+- ROW COUNT ON <container:name>
+
+- EXPORT <format> <container:name> TO <path>
+| EXPORT CSV <container:name> TO <path>
+| EXPORT JSON <container:name> TO <path>
+
+- IMPORT JSON <path> INTO <container:name>
+
+- BACKUP TO <path>
+
+- RESTORE FROM <path> [FORCE]
+
+- MIGRATE PRIMARY KEY ON <container:name> TO <col_typ>
+
+- ALTER CONTAINER <container:name> ...
+| ALTER CONTAINER <container:name> ADD COLUMN <col_nam> <col_typ>
+| ALTER CONTAINER <container:name> ADD COLUMN <col_nam> <col_typ> DEFAULT <col_val>
+| ALTER CONTAINER <container:name> DROP COLUMN <col_nam>
 
 - EDIT <Instance> ...
 | EDIT ROW [col_name][col_val] ON <container:name> WHERE <conditions>
@@ -89,22 +141,120 @@ pub enum Token{
 | DELETE ROW ON <container> WHERE <conditions>
 | DELETE ROW ON <container>
 | DELETE CONTAINER <container>
+| DELETE CONTAINER <container> PURGE
+
+- RESTORE CONTAINER <container>
+
+- APPLY <path>
 
 - SEARCH <col_nam> ON <container> ... 
 | SEARCH <col_nam> ON <container>
 | SEARCH <col_nam> ON <container> WHERE <conditions>
+| SEARCH <col_nam> ON <container> SNAPSHOT <id> WHERE <conditions>
+| SEARCH <col_nam> ON <container> [LIMIT <n>] [OFFSET <n>] WHERE <conditions>
+| SEARCH DISTINCT <col_nam> ON <container> [LIMIT <n>] [OFFSET <n>] WHERE <conditions>
+| SEARCH COUNT ON <container> WHERE <conditions>
+| SEARCH [SUM|AVG|MIN|MAX](<col_nam>), ... ON <container> WHERE <conditions>
+| SEARCH [SUM|AVG|MIN|MAX](<col_nam>), ... ON <container> WHERE <conditions> GROUP BY <col_nam>, ...
+| SEARCH <col_nam> ON SET <prefix>* [LIMIT <n>] WHERE <conditions>
+| EXPLAIN SEARCH <col_nam> ON <container> WHERE <conditions>
+| SEARCH <col_nam> ON <container> PAGE <n> [OFFSET <n>] WHERE <conditions>
+| SEARCH <col_nam> ON SET <first>..<last> [LIMIT <n>] WHERE <conditions>
+
+- STATS
+
+- INDEX STATS ...
+| INDEX STATS
+| INDEX STATS ON <container>
+
+- SNAPSHOT ...
+| SNAPSHOT OPEN
+| SNAPSHOT CLOSE <id>
+
+- CURSOR ...
+| CURSOR FETCH <id>
+| CURSOR CLOSE <id>
+
+- PREPARE SEARCH <col_nam> ON <container> [LIMIT <n>] [OFFSET <n>] WHERE <conditions using ?>
+| PREPARE SEARCH COUNT ON <container> WHERE <conditions using ?>
+
+- EXECUTE <id> [col_val]
+
+- DEALLOCATE <id>
+
+- RECOVERY ...
+| RECOVERY STATUS
+| RECOVERY COMMIT
+| RECOVERY ROLLBACK
+
+- SAVEPOINT <name>
+
+- ROLLBACK TO <name>
+
+- RELEASE <name>
+
+- EXISTS ON <container> ...
+| EXISTS ON <container> WHERE <conditions>
+
+- <conditions> ...
+| <col_nam> [=|==|!=|>|<|>=|<=|&>|&&>|&&&>] <col_val>
+| <col_nam> BETWEEN <col_val> AND <col_val>
+| <col_nam> IN (<col_val>, ...)
+| <col_nam> [LIKE|ILIKE] <pattern>
+| <col_nam> IS [NOT] NULL
+| <col_nam> NOT <operator> ...
+| <conditions> [AND|OR] <conditions>
+| (<conditions>)
+
+Lists are written [a, b, ...], strings are quoted, bytes are 0x followed by hex digits and ? is a value bound by EXECUTE.
+Clients send statements as text after the STATEMENT byte, see `parser::parse`.
 
 */
 #[derive(Debug, Clone, PartialEq)]
 enum AST{
     CreateContainer(AstCreateContainer),
     CreateRow(AstCreateRow),
+    CreateRows(AstCreateRows),
+    BulkLoad(AstCreateRows),
+    CreateRowNamed(AstCreateRowNamed),
+    ReplaceRow(AstReplaceRow),
+    Preallocate(AstPreallocate),
+    Truncate(AstTruncate),
+    CancelVacuum(AstCancelVacuum),
+    VacuumAll,
+    RowCount(AstRowCount),
+    ExportCsv(AstExport),
+    ExportJson(AstExport),
+    ImportJson(AstImport),
+    Backup(AstBackup),
+    Restore(AstRestore),
+    MigratePrimaryKey(AstMigratePrimaryKey),
+    AlterContainer(AstAlterContainer),
     EditRow(AstEditRow),
     DeleteRow(AstDeleteRow),
     DeleteContainer(AstDeleteContainer),
+    RestoreContainer(AstRestoreContainer),
+    ApplyFile(AstApplyFile),
     Search(AstSearch),
+    SearchSet(AstSearchSet),
+    Exists(AstExists),
+    IndexStats(AstIndexStats),
+    Stats,
+    OpenSnapshot,
+    CloseSnapshot(AstCloseSnapshot),
+    FetchCursor(AstCursor),
+    CloseCursor(AstCursor),
+    Prepare(AstSearch),
+    Execute(AstExecute),
+    Deallocate(AstDeallocate),
     Commit(AstCommit),
     Rollback(AstRollback),
+    RecoveryStatus,
+    RecoveryCommit,
+    RecoveryRollback,
+    Savepoint(AstSavepoint),
+    RollbackTo(AstSavepoint),
+    Release(AstSavepoint),
 }
 
 
@@ -114,6 +264,23 @@ struct AstCreateContainer{
     name : String,
     col_nam : Vec<String>,
     col_val : Vec<AlbaTypes>,
+    indexes : Vec<(String,IndexKind)>,
+    /// Columns of a composite primary key in key order, empty for the first column alone.
+    primary_key : Vec<String>,
+    /// Fills the first column from a persisted counter when a row leaves it out.
+    auto_increment : bool,
+    /// Values for columns a new row leaves out, checked against the column types on creation.
+    defaults : Vec<(String,AlbaTypes)>,
+    /// Columns that must always hold a value.
+    not_null : Vec<String>,
+    /// Columns besides the primary key that can't repeat a value.
+    unique : Vec<String>,
+    /// Keeps the container and its indexes in memory only, it is gone after a restart.
+    in_memory : bool,
+    /// Keeps the primary key in an ordered index instead of the hash one, so ranges over it skip the scan.
+    primary_index : IndexKind,
+    /// String columns where an empty string is stored as NULL, by default the two stay distinct.
+    empty_as_null : Vec<String>,
 }
 #[derive(Debug, Clone, PartialEq)]
 struct AstCreateRow{
@@ -122,6 +289,80 @@ struct AstCreateRow{
     container : String
 }
 #[derive(Debug, Clone, PartialEq)]
+struct AstCreateRows{
+    col_nam : Vec<String>,
+    col_val : Vec<Vec<AlbaTypes>>,
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstCreateRowNamed{
+    values : Vec<(String,AlbaTypes)>,
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstReplaceRow{
+    col_val : Vec<AlbaTypes>,
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstPreallocate{
+    rows : u64,
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstTruncate{
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstCancelVacuum{
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstRowCount{
+    container : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstExport{
+    container : String,
+    path : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstImport{
+    container : String,
+    path : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstBackup{
+    path : String
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstRestore{
+    path : String,
+    /// Replace the containers that already exist instead of refusing.
+    force : bool
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstMigratePrimaryKey{
+    container : String,
+    to : AlbaTypes
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstAlterContainer{
+    container : String,
+    change : AlterChange
+}
+#[derive(Debug, Clone, PartialEq)]
+enum AlterChange{
+    /// Appends a column, existing rows take `default` or the type's zero value.
+    AddColumn{
+        column : String,
+        col_type : AlbaTypes,
+        default : Option<AlbaTypes>
+    },
+    /// Removes a column that isn't part of the primary key.
+    DropColumn(String),
+}
+#[derive(Debug, Clone, PartialEq)]
 struct AstEditRow{
     col_nam : Vec<String>,
     col_val : Vec<AlbaTypes>,
@@ -136,6 +377,16 @@ struct AstDeleteRow{
 #[derive(Debug, Clone, PartialEq)]
 struct AstDeleteContainer{
     container : String,
+    /// Erase the files right away instead of moving them to the trash.
+    purge : bool,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstApplyFile{
+    path : String,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstRestoreContainer{
+    container : String,
 }
 
 type AlbaContainer = String;
@@ -145,6 +396,61 @@ struct AstSearch{
     container : AlbaContainer,
     conditions : (Vec<(Token,Token,Token)>,Vec<(usize,char)>),
     col_nam : Vec<String>,
+    snapshot : Option<u64>,
+    /// Who is reading, checked against `column_access` in the settings. `None` is unrestricted.
+    principal : Option<String>,
+    limit : Option<usize>,
+    offset : Option<usize>,
+    /// Return a single `count` row instead of the matching rows.
+    count : bool,
+    /// Return a single row holding one value per aggregate instead of the matching rows.
+    aggregates : Vec<(AggKind,String)>,
+    /// Buckets the matching rows by these columns, each output row is the group key followed by its aggregates.
+    group_by : Vec<String>,
+    /// Drops repeated rows after projection, LIMIT and OFFSET then apply to the distinct rows.
+    distinct : bool,
+    /// Return the plan `search` would follow instead of the matching rows.
+    explain : bool,
+    /// Open a cursor returning this many rows per page instead of every matching row at once.
+    page : Option<usize>,
+    /// Lay the changes staged in the container over the rows read, set for searches of a SNAPSHOT transaction.
+    pending : bool,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstSearchSet{
+    pattern : String,
+    conditions : (Vec<(Token,Token,Token)>,Vec<(usize,char)>),
+    col_nam : Vec<String>,
+    principal : Option<String>,
+    limit : Option<usize>,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstExists{
+    container : AlbaContainer,
+    conditions : (Vec<(Token,Token,Token)>,Vec<(usize,char)>),
+    principal : Option<String>,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstIndexStats{
+    container : Option<String>,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstCloseSnapshot{
+    id : u64,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstCursor{
+    id : u64,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstExecute{
+    id : u64,
+    /// One value per `?` of the prepared conditions, in order.
+    values : Vec<AlbaTypes>,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstDeallocate{
+    id : u64,
 }
 #[derive(Debug, Clone, PartialEq)]
 struct AstCommit{
@@ -155,14 +461,19 @@ struct AstCommit{
 struct AstRollback{
     container : Option<String>,
 }
+#[derive(Debug, Clone, PartialEq)]
+struct AstSavepoint{
+    name : String,
+}
 
 fn gerr(msg : &str) -> Error{Error::new(ErrorKind::Other, msg.to_string())}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    better_logs::init();
     let db = match connect().await{
-        Ok(database) => {println!("connected");database},
-        Err(e) => panic!("{}",e.to_string())
+        Ok(database) => {loginfo!("connected");database},
+        Err(e) => {logerr!("{}",e);return Err(e.into())}
     };
     if let Err(e) = db.run_database().await{
         logerr!("{}",e);
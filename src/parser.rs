@@ -0,0 +1,782 @@
+use std::io::Error;
+
+use crate::{aggregate::AggKind, alba_types::AlbaTypes, error::TytoError, indexing::IndexKind, Token, AST, AlterChange, AstAlterContainer, AstApplyFile, AstBackup, AstCancelVacuum, AstCloseSnapshot, AstCreateContainer, AstCreateRow, AstCreateRowNamed, AstCreateRows, AstCursor, AstDeallocate, AstDeleteContainer, AstDeleteRow, AstEditRow, AstExecute, AstExists, AstExport, AstImport, AstIndexStats, AstMigratePrimaryKey, AstPreallocate, AstReplaceRow, AstRestore, AstRestoreContainer, AstRowCount, AstSavepoint, AstSearch, AstSearchSet, AstTruncate};
+
+type Conditions = (Vec<(Token,Token,Token)>,Vec<(usize,char)>);
+
+fn syntax(msg : String) -> Error{
+    Error::from(TytoError::InvalidInput(msg))
+}
+
+/// Splits a statement into tokens: words are `Keyword`s, quoted text is a `String`, `0x` hex is `Bytes`,
+/// `?` is an `Argument` and brackets, commas, `*`, `..` and comparison operators are `Operator`s.
+fn lex(input : &str) -> Result<Vec<Token>,Error>{
+    let chars : Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len(){
+        let c = chars[i];
+        let starts_number = c.is_ascii_digit() || (c == '-' && chars.get(i+1).is_some_and(|n|n.is_ascii_digit()));
+        if c.is_whitespace(){
+            i += 1;
+        }else if c == '\'' || c == '"'{
+            let mut text = String::new();
+            i += 1;
+            loop{
+                match chars.get(i){
+                    None => return Err(syntax("A quoted string is never closed".to_string())),
+                    Some(&q) if q == c => {i += 1;break},
+                    Some('\\') => {
+                        text.push(match chars.get(i+1){
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some(&escaped) => escaped,
+                            None => return Err(syntax("A quoted string ends in an escape".to_string()))
+                        });
+                        i += 2;
+                    },
+                    Some(&ch) => {text.push(ch);i += 1}
+                }
+            }
+            tokens.push(Token::String(text));
+        }else if c == '0' && matches!(chars.get(i+1), Some('x')|Some('X')){
+            let start = i + 2;
+            i = start;
+            while chars.get(i).is_some_and(|h|h.is_ascii_hexdigit()){i += 1;}
+            let hex : String = chars[start..i].iter().collect();
+            if !hex.len().is_multiple_of(2){
+                return Err(syntax(format!("The bytes 0x{} have an odd number of digits",hex)))
+            }
+            tokens.push(Token::Bytes((0..hex.len()).step_by(2).map(|at|u8::from_str_radix(&hex[at..at+2],16).unwrap()).collect()));
+        }else if starts_number{
+            let start = i;
+            i += 1;
+            while chars.get(i).is_some_and(|d|d.is_ascii_digit()){i += 1;}
+            let mut float = false;
+            // `1..5` is a range of two integers, not a float
+            if chars.get(i) == Some(&'.') && chars.get(i+1).is_some_and(|d|d.is_ascii_digit()){
+                float = true;
+                i += 1;
+                while chars.get(i).is_some_and(|d|d.is_ascii_digit()){i += 1;}
+            }
+            if matches!(chars.get(i), Some('e')|Some('E')) && chars.get(i+1).is_some_and(|d|d.is_ascii_digit() || *d == '-' || *d == '+'){
+                float = true;
+                i += 2;
+                while chars.get(i).is_some_and(|d|d.is_ascii_digit()){i += 1;}
+            }
+            let number : String = chars[start..i].iter().collect();
+            tokens.push(if float{
+                Token::Float(number.parse().map_err(|_|syntax(format!("Invalid number {}",number)))?)
+            }else{
+                Token::Int(number.parse().map_err(|_|syntax(format!("The integer {} is out of range",number)))?)
+            });
+        }else if c.is_alphabetic() || c == '_'{
+            let start = i;
+            while chars.get(i).is_some_and(|w|w.is_alphanumeric() || *w == '_' || *w == '-'){i += 1;}
+            tokens.push(Token::Keyword(chars[start..i].iter().collect()));
+        }else if "=!<>&".contains(c){
+            let start = i;
+            while chars.get(i).is_some_and(|o|"=!<>&".contains(*o)){i += 1;}
+            // regular expression flags follow `&&&>` directly, as in `&&&>im`
+            if chars[start..i].iter().collect::<String>() == "&&&>"{
+                while chars.get(i).is_some_and(|f|f.is_ascii_alphabetic()){i += 1;}
+            }
+            tokens.push(Token::Operator(chars[start..i].iter().collect()));
+        }else if c == '.' && chars.get(i+1) == Some(&'.'){
+            tokens.push(Token::Operator("..".to_string()));
+            i += 2;
+        }else if c == '?'{
+            tokens.push(Token::Argument);
+            i += 1;
+        }else if "[]{}(),:*".contains(c){
+            tokens.push(Token::Operator(c.to_string()));
+            i += 1;
+        }else{
+            return Err(syntax(format!("Unexpected character '{}'",c)))
+        }
+    }
+    Ok(tokens)
+}
+
+/// The column type a keyword of the statement grammar names.
+fn column_type(name : &str) -> Option<AlbaTypes>{
+    Some(match name.to_uppercase().as_str(){
+        "CHAR" => AlbaTypes::Char('\0'),
+        "INT" => AlbaTypes::Int(0),
+        "BIGINT" => AlbaTypes::Bigint(0),
+        "BOOL" => AlbaTypes::Bool(false),
+        "FLOAT" => AlbaTypes::Float(0.0),
+        "TEXT" => AlbaTypes::Text(String::new()),
+        "NANO-STRING" => AlbaTypes::NanoString(String::new()),
+        "SMALL-STRING" => AlbaTypes::SmallString(String::new()),
+        "MEDIUM-STRING" => AlbaTypes::MediumString(String::new()),
+        "BIG-STRING" => AlbaTypes::BigString(String::new()),
+        "LARGE-STRING" => AlbaTypes::LargeString(String::new()),
+        "NANO-BYTES" => AlbaTypes::NanoBytes(Vec::new()),
+        "SMALL-BYTES" => AlbaTypes::SmallBytes(Vec::new()),
+        "MEDIUM-BYTES" => AlbaTypes::MediumBytes(Vec::new()),
+        "BIG-BYTES" => AlbaTypes::BigSBytes(Vec::new()),
+        "LARGE-BYTES" => AlbaTypes::LargeBytes(Vec::new()),
+        "UUID" => AlbaTypes::Uuid([0;16]),
+        "TIMESTAMP" => AlbaTypes::Timestamp(0),
+        "DECIMAL" => AlbaTypes::Decimal(crate::decimal::Decimal::from(0)),
+        _ => return None
+    })
+}
+
+/// Walks the tokens of one statement.
+struct Parser{
+    tokens : Vec<Token>,
+    at : usize,
+}
+
+impl Parser{
+    fn peek(&self) -> Option<&Token>{
+        self.tokens.get(self.at)
+    }
+    fn next(&mut self) -> Result<Token,Error>{
+        let token = self.tokens.get(self.at).cloned().ok_or_else(||syntax("The statement ends too early".to_string()))?;
+        self.at += 1;
+        Ok(token)
+    }
+    /// Whether the next token is the keyword or symbol `word`, keywords compare case insensitively.
+    fn is(&self, word : &str) -> bool{
+        match self.peek(){
+            Some(Token::Keyword(k)) => k.eq_ignore_ascii_case(word),
+            Some(Token::Operator(o)) => o == word,
+            _ => false
+        }
+    }
+    /// Takes the next token if it is `word`.
+    fn eat(&mut self, word : &str) -> bool{
+        let is = self.is(word);
+        if is{
+            self.at += 1;
+        }
+        is
+    }
+    fn expect(&mut self, word : &str) -> Result<(),Error>{
+        if self.eat(word){
+            return Ok(())
+        }
+        Err(syntax(match self.peek(){
+            Some(token) => format!("Expected {} but found {:?}",word,token),
+            None => format!("Expected {} but the statement ends",word)
+        }))
+    }
+    /// A container, column or savepoint name, a bare word or a quoted string.
+    fn name(&mut self) -> Result<String,Error>{
+        match self.next()?{
+            Token::Keyword(name) | Token::String(name) => Ok(name),
+            token => Err(syntax(format!("Expected a name but found {:?}",token)))
+        }
+    }
+    fn path(&mut self) -> Result<String,Error>{
+        match self.next()?{
+            Token::String(path) | Token::Keyword(path) => Ok(path),
+            token => Err(syntax(format!("Expected a path but found {:?}",token)))
+        }
+    }
+    fn number(&mut self) -> Result<u64,Error>{
+        match self.next()?{
+            Token::Int(n) if n >= 0 => Ok(n as u64),
+            token => Err(syntax(format!("Expected a positive integer but found {:?}",token)))
+        }
+    }
+    fn column_type(&mut self) -> Result<AlbaTypes,Error>{
+        let name = self.name()?;
+        column_type(&name).ok_or_else(||syntax(format!("Unknown column type {}",name)))
+    }
+    /// A literal value, strings come out as `LargeString` and are fitted to the column they are meant for later.
+    fn value(&mut self) -> Result<AlbaTypes,Error>{
+        Ok(match self.next()?{
+            Token::String(s) => AlbaTypes::LargeString(s),
+            Token::Int(i) => match i32::try_from(i){
+                Ok(i) => AlbaTypes::Int(i),
+                Err(_) => AlbaTypes::Bigint(i)
+            },
+            Token::Float(f) => AlbaTypes::Float(f),
+            Token::Bytes(b) => AlbaTypes::LargeBytes(b),
+            Token::Keyword(k) if k.eq_ignore_ascii_case("true") => AlbaTypes::Bool(true),
+            Token::Keyword(k) if k.eq_ignore_ascii_case("false") => AlbaTypes::Bool(false),
+            Token::Keyword(k) if k.eq_ignore_ascii_case("null") => AlbaTypes::NONE,
+            token => return Err(syntax(format!("Expected a value but found {:?}",token)))
+        })
+    }
+    /// `[item, ...]`, the list may be empty.
+    fn list<T>(&mut self, mut item : impl FnMut(&mut Self) -> Result<T,Error>) -> Result<Vec<T>,Error>{
+        self.expect("[")?;
+        let mut items = Vec::new();
+        while !self.eat("]"){
+            if !items.is_empty(){
+                self.expect(",")?;
+            }
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+    /// The value side of a condition as a token, `?` stays an `Argument` to be bound by EXECUTE.
+    fn condition_value(&mut self) -> Result<Token,Error>{
+        Ok(match self.next()?{
+            token @ (Token::String(_) | Token::Int(_) | Token::Float(_) | Token::Bytes(_) | Token::Argument) => token,
+            Token::Keyword(k) if k.eq_ignore_ascii_case("true") => Token::Bool(true),
+            Token::Keyword(k) if k.eq_ignore_ascii_case("false") => Token::Bool(false),
+            token => return Err(syntax(format!("Expected a value to compare with but found {:?}",token)))
+        })
+    }
+    /// `<column> <operator> <value>`, with the word operators of `QueryConditions`:
+    /// `BETWEEN a AND b`, `IN (a, ...)`, `LIKE`, `ILIKE`, `IS [NOT] NULL`, any of them negated by a leading `NOT`.
+    fn condition(&mut self) -> Result<(Token,Token,Token),Error>{
+        let column = self.name()?;
+        let (operator,value) = self.operator()?;
+        Ok((Token::String(column),Token::Operator(operator),value))
+    }
+    fn operator(&mut self) -> Result<(String,Token),Error>{
+        if self.eat("not"){
+            let (operator,value) = self.operator()?;
+            return Ok((format!("not {}",operator),value))
+        }
+        if self.eat("is"){
+            let operator = if self.eat("not"){"is not null"}else{"is null"};
+            self.expect("null")?;
+            return Ok((operator.to_string(),Token::Group(Vec::new())))
+        }
+        if self.eat("between"){
+            let low = self.condition_value()?;
+            self.expect("and")?;
+            return Ok(("between".to_string(),Token::Group(vec![low,self.condition_value()?])))
+        }
+        if self.eat("in"){
+            self.expect("(")?;
+            let mut members = vec![self.condition_value()?];
+            while self.eat(","){
+                members.push(self.condition_value()?);
+            }
+            self.expect(")")?;
+            return Ok(("in".to_string(),Token::Group(members)))
+        }
+        for word in ["like","ilike"]{
+            if self.eat(word){
+                return Ok((word.to_string(),self.condition_value()?))
+            }
+        }
+        match self.next()?{
+            Token::Operator(operator) if operator.starts_with(['=','!','<','>','&']) => Ok((operator,self.condition_value()?)),
+            token => Err(syntax(format!("Expected a comparison operator but found {:?}",token)))
+        }
+    }
+    /// Conditions joined by AND and OR, grouped with parentheses, in the form `QueryConditions::from_primitive_conditions` takes.
+    fn conditions(&mut self) -> Result<Conditions,Error>{
+        let mut atoms = Vec::new();
+        let mut gates = Vec::new();
+        let mut depth = 0usize;
+        loop{
+            while self.eat("("){
+                gates.push((atoms.len(),'('));
+                depth += 1;
+            }
+            atoms.push(self.condition()?);
+            while depth > 0 && self.eat(")"){
+                gates.push((atoms.len() - 1,')'));
+                depth -= 1;
+            }
+            if self.eat("and"){
+                gates.push((atoms.len() - 1,'a'));
+            }else if self.eat("or"){
+                gates.push((atoms.len() - 1,'o'));
+            }else{
+                break
+            }
+        }
+        if depth > 0{
+            return Err(syntax("A parenthesis of the conditions is never closed".to_string()))
+        }
+        Ok((atoms,gates))
+    }
+    fn optional_where(&mut self) -> Result<Option<Conditions>,Error>{
+        if self.eat("where"){
+            return Ok(Some(self.conditions()?))
+        }
+        Ok(None)
+    }
+    /// The search after `SEARCH`: `[DISTINCT] <col_nam>, ...`, `COUNT` or `[SUM|AVG|MIN|MAX](<col_nam>), ...`,
+    /// then `ON <container>`, any of `SNAPSHOT <id>`, `LIMIT <n>`, `OFFSET <n>` and `PAGE <n>`,
+    /// and last `WHERE <conditions>` and `GROUP BY <col_nam>, ...`.
+    fn search(&mut self) -> Result<AstSearch,Error>{
+        let mut search = AstSearch{container: String::new(), conditions: Default::default(), col_nam: Vec::new(), snapshot: None, principal: None, limit: None, offset: None, count: false, aggregates: Vec::new(), group_by: Vec::new(), distinct: false, explain: false, page: None, pending: false};
+        search.distinct = self.eat("distinct");
+        if self.eat("count"){
+            search.count = true;
+        }else if self.aggregate_ahead(){
+            loop{
+                let kind = match self.name()?.to_uppercase().as_str(){
+                    "SUM" => AggKind::Sum,
+                    "AVG" => AggKind::Avg,
+                    "MIN" => AggKind::Min,
+                    _ => AggKind::Max
+                };
+                self.expect("(")?;
+                search.aggregates.push((kind,self.name()?));
+                self.expect(")")?;
+                if !self.eat(","){
+                    break
+                }
+            }
+        }else{
+            search.col_nam = self.columns()?;
+        }
+        self.expect("on")?;
+        search.container = self.name()?;
+        loop{
+            if self.eat("snapshot"){
+                search.snapshot = Some(self.number()?);
+            }else if self.eat("limit"){
+                search.limit = Some(self.number()? as usize);
+            }else if self.eat("offset"){
+                search.offset = Some(self.number()? as usize);
+            }else if self.eat("page"){
+                search.page = Some(self.number()? as usize);
+            }else{
+                break
+            }
+        }
+        search.conditions = self.optional_where()?.unwrap_or_default();
+        if self.eat("group"){
+            self.expect("by")?;
+            search.group_by = self.columns()?;
+        }
+        Ok(search)
+    }
+    /// Whether the next tokens open an aggregate such as `SUM(`.
+    fn aggregate_ahead(&self) -> bool{
+        let kind = matches!(self.peek(), Some(Token::Keyword(k)) if ["sum","avg","min","max"].iter().any(|a|k.eq_ignore_ascii_case(a)));
+        kind && self.tokens.get(self.at + 1) == Some(&Token::Operator("(".to_string()))
+    }
+    /// Column names separated by commas.
+    fn columns(&mut self) -> Result<Vec<String>,Error>{
+        let mut columns = vec![self.name()?];
+        while self.eat(","){
+            columns.push(self.name()?);
+        }
+        Ok(columns)
+    }
+    fn create_container(&mut self) -> Result<AstCreateContainer,Error>{
+        let name = self.name()?;
+        let col_nam = self.list(|p|p.name())?;
+        let col_val = self.list(|p|p.column_type())?;
+        let mut create = AstCreateContainer{name, col_nam, col_val, indexes: Vec::new(), primary_key: Vec::new(), auto_increment: false, defaults: Vec::new(), not_null: Vec::new(), unique: Vec::new(), in_memory: false, primary_index: IndexKind::Hash, empty_as_null: Vec::new()};
+        while self.peek().is_some(){
+            if self.eat("index"){
+                let columns = self.list(|p|p.name())?;
+                let kinds = self.list(|p|match p.name()?.to_uppercase().as_str(){
+                    "HASH" => Ok(IndexKind::Hash),
+                    "ORDERED" => Ok(IndexKind::Ordered),
+                    kind => Err(syntax(format!("Unknown index kind {}, expected HASH or ORDERED",kind)))
+                })?;
+                if columns.len() != kinds.len(){
+                    return Err(syntax("INDEX needs one kind per column".to_string()))
+                }
+                create.indexes.extend(columns.into_iter().zip(kinds));
+            }else if self.eat("ordered"){
+                self.expect("key")?;
+                create.primary_index = IndexKind::Ordered;
+            }else if self.eat("key"){
+                create.primary_key = self.list(|p|p.name())?;
+            }else if self.eat("auto"){
+                self.expect("increment")?;
+                create.auto_increment = true;
+            }else if self.eat("default"){
+                let columns = self.list(|p|p.name())?;
+                let values = self.list(|p|p.value())?;
+                if columns.len() != values.len(){
+                    return Err(syntax("DEFAULT needs one value per column".to_string()))
+                }
+                create.defaults.extend(columns.into_iter().zip(values));
+            }else if self.eat("not"){
+                self.expect("null")?;
+                create.not_null.extend(self.list(|p|p.name())?);
+            }else if self.eat("unique"){
+                create.unique.extend(self.list(|p|p.name())?);
+            }else if self.eat("empty"){
+                self.expect("as")?;
+                self.expect("null")?;
+                create.empty_as_null.extend(self.list(|p|p.name())?);
+            }else if self.eat("in"){
+                self.expect("memory")?;
+                create.in_memory = true;
+            }else{
+                return Err(syntax(format!("Unknown CREATE CONTAINER option {:?}",self.peek())))
+            }
+        }
+        Ok(create)
+    }
+    fn create_row(&mut self) -> Result<AST,Error>{
+        if self.eat("{"){
+            let mut values = Vec::new();
+            while !self.eat("}"){
+                if !values.is_empty(){
+                    self.expect(",")?;
+                }
+                let column = self.name()?;
+                self.expect(":")?;
+                values.push((column,self.value()?));
+            }
+            self.expect("on")?;
+            return Ok(AST::CreateRowNamed(AstCreateRowNamed{values, container: self.name()?}))
+        }
+        let col_nam = self.list(|p|p.name())?;
+        let col_val = self.list(|p|p.value())?;
+        self.expect("on")?;
+        Ok(AST::CreateRow(AstCreateRow{col_nam, col_val, container: self.name()?}))
+    }
+    fn statement(&mut self) -> Result<AST,Error>{
+        let first = self.name()?.to_uppercase();
+        Ok(match first.as_str(){
+            "CREATE" => {
+                if self.eat("container"){
+                    AST::CreateContainer(self.create_container()?)
+                }else{
+                    self.expect("row")?;
+                    self.create_row()?
+                }
+            },
+            "REPLACE" => {
+                self.expect("row")?;
+                let col_val = self.list(|p|p.value())?;
+                self.expect("on")?;
+                AST::ReplaceRow(AstReplaceRow{col_val, container: self.name()?})
+            },
+            "BULK" => {
+                self.expect("load")?;
+                let col_nam = self.list(|p|p.name())?;
+                let col_val = self.list(|p|p.list(|p|p.value()))?;
+                self.expect("on")?;
+                AST::BulkLoad(AstCreateRows{col_nam, col_val, container: self.name()?})
+            },
+            "PREALLOCATE" => {
+                let rows = self.number()?;
+                self.expect("on")?;
+                AST::Preallocate(AstPreallocate{rows, container: self.name()?})
+            },
+            "TRUNCATE" => AST::Truncate(AstTruncate{container: self.name()?}),
+            "CANCEL" => {
+                self.expect("vacuum")?;
+                AST::CancelVacuum(AstCancelVacuum{container: self.name()?})
+            },
+            "VACUUM" => {
+                self.expect("all")?;
+                AST::VacuumAll
+            },
+            "ROW" => {
+                self.expect("count")?;
+                self.expect("on")?;
+                AST::RowCount(AstRowCount{container: self.name()?})
+            },
+            "EXPORT" => {
+                let csv = if self.eat("csv"){true}else{self.expect("json")?;false};
+                let container = self.name()?;
+                self.expect("to")?;
+                let export = AstExport{container, path: self.path()?};
+                if csv{AST::ExportCsv(export)}else{AST::ExportJson(export)}
+            },
+            "IMPORT" => {
+                self.expect("json")?;
+                let path = self.path()?;
+                self.expect("into")?;
+                AST::ImportJson(AstImport{container: self.name()?, path})
+            },
+            "BACKUP" => {
+                self.expect("to")?;
+                AST::Backup(AstBackup{path: self.path()?})
+            },
+            "RESTORE" => {
+                if self.eat("container"){
+                    AST::RestoreContainer(AstRestoreContainer{container: self.name()?})
+                }else{
+                    self.expect("from")?;
+                    let path = self.path()?;
+                    AST::Restore(AstRestore{path, force: self.eat("force")})
+                }
+            },
+            "MIGRATE" => {
+                self.expect("primary")?;
+                self.expect("key")?;
+                self.expect("on")?;
+                let container = self.name()?;
+                self.expect("to")?;
+                AST::MigratePrimaryKey(AstMigratePrimaryKey{container, to: self.column_type()?})
+            },
+            "ALTER" => {
+                self.expect("container")?;
+                let container = self.name()?;
+                let change = if self.eat("add"){
+                    self.expect("column")?;
+                    let column = self.name()?;
+                    let col_type = self.column_type()?;
+                    let default = if self.eat("default"){Some(self.value()?)}else{None};
+                    AlterChange::AddColumn{column, col_type, default}
+                }else{
+                    self.expect("drop")?;
+                    self.expect("column")?;
+                    AlterChange::DropColumn(self.name()?)
+                };
+                AST::AlterContainer(AstAlterContainer{container, change})
+            },
+            "EDIT" => {
+                self.expect("row")?;
+                let col_nam = self.list(|p|p.name())?;
+                let col_val = self.list(|p|p.value())?;
+                self.expect("on")?;
+                let container = self.name()?;
+                self.expect("where")?;
+                AST::EditRow(AstEditRow{col_nam, col_val, container, conditions: self.conditions()?})
+            },
+            "DELETE" => {
+                if self.eat("container"){
+                    let container = self.name()?;
+                    AST::DeleteContainer(AstDeleteContainer{container, purge: self.eat("purge")})
+                }else{
+                    self.expect("row")?;
+                    self.expect("on")?;
+                    let container = self.name()?;
+                    AST::DeleteRow(AstDeleteRow{container, conditions: self.optional_where()?})
+                }
+            },
+            "APPLY" => AST::ApplyFile(AstApplyFile{path: self.path()?}),
+            "SEARCH" => self.search_statement()?,
+            "EXPLAIN" => {
+                self.expect("search")?;
+                AST::Search(AstSearch{explain: true, ..self.search()?})
+            },
+            "STATS" => AST::Stats,
+            "INDEX" => {
+                self.expect("stats")?;
+                AST::IndexStats(AstIndexStats{container: if self.eat("on"){Some(self.name()?)}else{None}})
+            },
+            "SNAPSHOT" => {
+                if self.eat("open"){
+                    AST::OpenSnapshot
+                }else{
+                    self.expect("close")?;
+                    AST::CloseSnapshot(AstCloseSnapshot{id: self.number()?})
+                }
+            },
+            "CURSOR" => {
+                let fetch = if self.eat("fetch"){true}else{self.expect("close")?;false};
+                let cursor = AstCursor{id: self.number()?};
+                if fetch{AST::FetchCursor(cursor)}else{AST::CloseCursor(cursor)}
+            },
+            "PREPARE" => {
+                self.expect("search")?;
+                AST::Prepare(self.search()?)
+            },
+            "EXECUTE" => {
+                let id = self.number()?;
+                let values = if self.is("["){self.list(|p|p.value())?}else{Vec::new()};
+                AST::Execute(AstExecute{id, values})
+            },
+            "DEALLOCATE" => AST::Deallocate(AstDeallocate{id: self.number()?}),
+            "RECOVERY" => {
+                if self.eat("status"){
+                    AST::RecoveryStatus
+                }else if self.eat("commit"){
+                    AST::RecoveryCommit
+                }else{
+                    self.expect("rollback")?;
+                    AST::RecoveryRollback
+                }
+            },
+            "SAVEPOINT" => AST::Savepoint(AstSavepoint{name: self.name()?}),
+            "ROLLBACK" => {
+                self.expect("to")?;
+                AST::RollbackTo(AstSavepoint{name: self.name()?})
+            },
+            "RELEASE" => AST::Release(AstSavepoint{name: self.name()?}),
+            "EXISTS" => {
+                self.expect("on")?;
+                let container = self.name()?;
+                let conditions = self.optional_where()?.unwrap_or_default();
+                AST::Exists(AstExists{container, conditions, principal: None})
+            },
+            _ => return Err(syntax(format!("Unknown statement {}",first)))
+        })
+    }
+    /// The part of a SEARCH after the keyword, on a container or on a set of them.
+    fn search_statement(&mut self) -> Result<AST,Error>{
+        let start = self.at;
+        let col_nam = self.columns().unwrap_or_default();
+        if col_nam.is_empty() || !self.eat("on") || !self.eat("set"){
+            self.at = start;
+            return Ok(AST::Search(self.search()?))
+        }
+        let mut pattern = self.name()?;
+        if self.eat("*"){
+            pattern.push('*');
+        }else if self.eat(".."){
+            pattern.push_str("..");
+            pattern.push_str(&self.name()?);
+        }
+        let limit = if self.eat("limit"){Some(self.number()? as usize)}else{None};
+        let conditions = self.optional_where()?.unwrap_or_default();
+        Ok(AST::SearchSet(AstSearchSet{pattern, conditions, col_nam, principal: None, limit}))
+    }
+}
+
+/// Parses one statement written in the grammar at the top of `main.rs`, as sent after the `STATEMENT` byte.
+pub fn parse(input : &str) -> Result<AST,Error>{
+    let mut parser = Parser{tokens: lex(input)?, at: 0};
+    let ast = parser.statement()?;
+    if let Some(token) = parser.peek(){
+        return Err(syntax(format!("Unexpected {:?} after the end of the statement",token)))
+    }
+    Ok(ast)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn container(ast : AST) -> AstCreateContainer{
+        match ast{
+            AST::CreateContainer(c) => c,
+            other => panic!("not a CREATE CONTAINER: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn create_container_takes_every_option(){
+        let c = container(parse("CREATE CONTAINER people [id, name, bio][BIGINT, SMALL-STRING, TEXT] KEY [id] ORDERED KEY AUTO INCREMENT INDEX [name][HASH] DEFAULT [bio]['none'] NOT NULL [name] UNIQUE [name] EMPTY AS NULL [bio] IN MEMORY").unwrap());
+        assert_eq!(c.name, "people");
+        assert_eq!(c.col_nam, vec!["id","name","bio"]);
+        assert_eq!(c.col_val, vec![AlbaTypes::Bigint(0),AlbaTypes::SmallString(String::new()),AlbaTypes::Text(String::new())]);
+        assert_eq!(c.primary_key, vec!["id"]);
+        assert_eq!(c.primary_index, IndexKind::Ordered);
+        assert!(c.auto_increment && c.in_memory);
+        assert_eq!(c.indexes, vec![("name".to_string(),IndexKind::Hash)]);
+        assert_eq!(c.defaults, vec![("bio".to_string(),AlbaTypes::LargeString("none".to_string()))]);
+        assert_eq!((c.not_null,c.unique,c.empty_as_null), (vec!["name".to_string()],vec!["name".to_string()],vec!["bio".to_string()]));
+        assert!(parse("CREATE CONTAINER people [id][BIGINT] SOMETIMES").is_err());
+        assert!(parse("CREATE CONTAINER people [id][WHATEVER]").is_err());
+    }
+
+    #[test]
+    fn rows_take_literals_of_every_kind(){
+        assert_eq!(parse("CREATE ROW [id, name, ok, score, raw, gone] ['x', \"it's\", TRUE, -1.5, 0x00ff, NULL] ON people").unwrap(), AST::CreateRow(AstCreateRow{
+            col_nam: ["id","name","ok","score","raw","gone"].iter().map(|c|c.to_string()).collect(),
+            col_val: vec![AlbaTypes::LargeString("x".to_string()),AlbaTypes::LargeString("it's".to_string()),AlbaTypes::Bool(true),AlbaTypes::Float(-1.5),AlbaTypes::LargeBytes(vec![0,255]),AlbaTypes::NONE],
+            container: "people".to_string()
+        }));
+        assert_eq!(parse("CREATE ROW {id: 3000000000, name: 'a'} ON people").unwrap(), AST::CreateRowNamed(AstCreateRowNamed{
+            values: vec![("id".to_string(),AlbaTypes::Bigint(3000000000)),("name".to_string(),AlbaTypes::LargeString("a".to_string()))],
+            container: "people".to_string()
+        }));
+        assert_eq!(parse("BULK LOAD [id][[1],[2]] ON people").unwrap(), AST::BulkLoad(AstCreateRows{
+            col_nam: vec!["id".to_string()], col_val: vec![vec![AlbaTypes::Int(1)],vec![AlbaTypes::Int(2)]], container: "people".to_string()
+        }));
+        assert!(parse("CREATE ROW [id]['unclosed] ON people").is_err());
+    }
+
+    #[test]
+    fn conditions_keep_their_groups_and_gates(){
+        let search = match parse("SEARCH id, name ON people WHERE (id > 1 AND name NOT LIKE 'a%') OR id IN (7, ?) OR name IS NOT NULL").unwrap(){
+            AST::Search(s) => s,
+            other => panic!("not a SEARCH: {:?}", other)
+        };
+        assert_eq!(search.col_nam, vec!["id","name"]);
+        let column = |c : &str|Token::String(c.to_string());
+        let operator = |o : &str|Token::Operator(o.to_string());
+        assert_eq!(search.conditions.0, vec![
+            (column("id"),operator(">"),Token::Int(1)),
+            (column("name"),operator("not like"),Token::String("a%".to_string())),
+            (column("id"),operator("in"),Token::Group(vec![Token::Int(7),Token::Argument])),
+            (column("name"),operator("is not null"),Token::Group(Vec::new())),
+        ]);
+        assert_eq!(search.conditions.1, vec![(0,'('),(0,'a'),(1,')'),(1,'o'),(2,'o')]);
+        match parse("DELETE ROW ON people WHERE id BETWEEN 1 AND 5 AND name &&&>i 'x'").unwrap(){
+            AST::DeleteRow(d) => {
+                assert_eq!(d.conditions.unwrap().0, vec![(column("id"),operator("between"),Token::Group(vec![Token::Int(1),Token::Int(5)])),(column("name"),operator("&&&>i"),Token::String("x".to_string()))]);
+            },
+            other => panic!("not a DELETE ROW: {:?}", other)
+        }
+        assert!(parse("SEARCH id ON people WHERE (id > 1").is_err());
+    }
+
+    #[test]
+    fn every_statement_of_the_grammar_parses(){
+        let statements = [
+            ("REPLACE ROW [1, 'a'] ON people", AST::ReplaceRow(AstReplaceRow{col_val: vec![AlbaTypes::Int(1),AlbaTypes::LargeString("a".to_string())], container: "people".to_string()})),
+            ("PREALLOCATE 100 ON people", AST::Preallocate(AstPreallocate{rows: 100, container: "people".to_string()})),
+            ("TRUNCATE people", AST::Truncate(AstTruncate{container: "people".to_string()})),
+            ("CANCEL VACUUM people", AST::CancelVacuum(AstCancelVacuum{container: "people".to_string()})),
+            ("VACUUM ALL", AST::VacuumAll),
+            ("ROW COUNT ON people", AST::RowCount(AstRowCount{container: "people".to_string()})),
+            ("EXPORT CSV people TO '/tmp/people.csv'", AST::ExportCsv(AstExport{container: "people".to_string(), path: "/tmp/people.csv".to_string()})),
+            ("EXPORT JSON people TO 'p.json'", AST::ExportJson(AstExport{container: "people".to_string(), path: "p.json".to_string()})),
+            ("IMPORT JSON 'p.json' INTO people", AST::ImportJson(AstImport{container: "people".to_string(), path: "p.json".to_string()})),
+            ("BACKUP TO 'b.tar'", AST::Backup(AstBackup{path: "b.tar".to_string()})),
+            ("RESTORE FROM 'b.tar' FORCE", AST::Restore(AstRestore{path: "b.tar".to_string(), force: true})),
+            ("RESTORE CONTAINER people", AST::RestoreContainer(AstRestoreContainer{container: "people".to_string()})),
+            ("MIGRATE PRIMARY KEY ON people TO BIGINT", AST::MigratePrimaryKey(AstMigratePrimaryKey{container: "people".to_string(), to: AlbaTypes::Bigint(0)})),
+            ("ALTER CONTAINER people ADD COLUMN age INT DEFAULT 18", AST::AlterContainer(AstAlterContainer{container: "people".to_string(), change: AlterChange::AddColumn{column: "age".to_string(), col_type: AlbaTypes::Int(0), default: Some(AlbaTypes::Int(18))}})),
+            ("ALTER CONTAINER people DROP COLUMN age", AST::AlterContainer(AstAlterContainer{container: "people".to_string(), change: AlterChange::DropColumn("age".to_string())})),
+            ("DELETE CONTAINER people PURGE", AST::DeleteContainer(AstDeleteContainer{container: "people".to_string(), purge: true})),
+            ("APPLY 'statements.bin'", AST::ApplyFile(AstApplyFile{path: "statements.bin".to_string()})),
+            ("SEARCH id ON SET logs_* LIMIT 5", AST::SearchSet(AstSearchSet{pattern: "logs_*".to_string(), conditions: Default::default(), col_nam: vec!["id".to_string()], principal: None, limit: Some(5)})),
+            ("SEARCH id ON SET a..c", AST::SearchSet(AstSearchSet{pattern: "a..c".to_string(), conditions: Default::default(), col_nam: vec!["id".to_string()], principal: None, limit: None})),
+            ("EXISTS ON people WHERE id = 1", AST::Exists(AstExists{container: "people".to_string(), conditions: (vec![(Token::String("id".to_string()),Token::Operator("=".to_string()),Token::Int(1))],Vec::new()), principal: None})),
+            ("STATS", AST::Stats),
+            ("INDEX STATS", AST::IndexStats(AstIndexStats{container: None})),
+            ("INDEX STATS ON people", AST::IndexStats(AstIndexStats{container: Some("people".to_string())})),
+            ("SNAPSHOT OPEN", AST::OpenSnapshot),
+            ("SNAPSHOT CLOSE 4", AST::CloseSnapshot(AstCloseSnapshot{id: 4})),
+            ("CURSOR FETCH 2", AST::FetchCursor(AstCursor{id: 2})),
+            ("CURSOR CLOSE 2", AST::CloseCursor(AstCursor{id: 2})),
+            ("EXECUTE 1 [5]", AST::Execute(AstExecute{id: 1, values: vec![AlbaTypes::Int(5)]})),
+            ("DEALLOCATE 1", AST::Deallocate(AstDeallocate{id: 1})),
+            ("RECOVERY STATUS", AST::RecoveryStatus),
+            ("RECOVERY COMMIT", AST::RecoveryCommit),
+            ("RECOVERY ROLLBACK", AST::RecoveryRollback),
+            ("SAVEPOINT before_import", AST::Savepoint(AstSavepoint{name: "before_import".to_string()})),
+            ("ROLLBACK TO before_import", AST::RollbackTo(AstSavepoint{name: "before_import".to_string()})),
+            ("release before_import", AST::Release(AstSavepoint{name: "before_import".to_string()})),
+        ];
+        for (statement,ast) in statements{
+            assert_eq!(parse(statement).unwrap(), ast, "{}", statement);
+        }
+        assert!(matches!(parse("PREPARE SEARCH id ON people WHERE id = ?").unwrap(), AST::Prepare(_)));
+        assert!(matches!(parse("EDIT ROW [name]['b'] ON people WHERE id = 1").unwrap(), AST::EditRow(_)));
+        assert!(parse("STATS NOW").is_err());
+        assert!(parse("SEARCH id ON people LIMIT -1").is_err());
+        assert!(parse("FROBNICATE people").is_err());
+    }
+
+    fn search(statement : &str) -> AstSearch{
+        match parse(statement).unwrap(){
+            AST::Search(s) => s,
+            other => panic!("not a SEARCH: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn search_modifiers_are_read(){
+        let s = search("SEARCH DISTINCT id, name ON people SNAPSHOT 3 LIMIT 10 OFFSET 20 WHERE id > 1");
+        assert!(s.distinct && !s.count && !s.explain);
+        assert_eq!((s.snapshot,s.limit,s.offset,s.page), (Some(3),Some(10),Some(20),None));
+        assert_eq!(s.col_nam, vec!["id","name"]);
+        assert!(search("SEARCH COUNT ON people WHERE id > 1").count);
+        let s = search("SEARCH SUM(score), avg(score), MIN(id), MAX(id) ON people WHERE id > 1 GROUP BY team, city");
+        assert_eq!(s.aggregates, vec![(AggKind::Sum,"score".to_string()),(AggKind::Avg,"score".to_string()),(AggKind::Min,"id".to_string()),(AggKind::Max,"id".to_string())]);
+        assert_eq!(s.group_by, vec!["team","city"]);
+        assert!(s.col_nam.is_empty());
+        // a column may be named like an aggregate
+        assert_eq!(search("SEARCH sum ON people").col_nam, vec!["sum"]);
+        let s = search("EXPLAIN SEARCH id ON people WHERE id = 1");
+        assert!(s.explain);
+        let s = search("SEARCH id ON people PAGE 50 OFFSET 5");
+        assert_eq!((s.page,s.offset), (Some(50),Some(5)));
+        match parse("PREPARE SEARCH COUNT ON people LIMIT 1 WHERE id = ?").unwrap(){
+            AST::Prepare(p) => assert!(p.count && p.limit == Some(1)),
+            other => panic!("not a PREPARE: {:?}", other)
+        }
+    }
+}
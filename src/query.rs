@@ -1,9 +1,9 @@
-use std::{fs::File, io::Error, os::unix::fs::FileExt, sync::Arc, usize, vec};
+use std::{collections::BTreeMap, io::Error, sync::Arc, time::Instant, usize, vec};
 use tokio::sync::Mutex;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use serde::{Deserialize, Serialize};
-use crate::container::MAX_GRAVEYARD_LENGTH_IN_MEMORY;
-use crate::{container::Container, query_conditions::{QueryConditions, QueryIndexType, QueryType}, row::Row, Token};
+use crate::{alba_types::AlbaTypes, container::{Container, MvccState}, error::TytoError, gerr, query_conditions::{QueryConditions, QueryIndexType, QueryType, SecondaryLookup}, row::Row, storage::SharedStorage, Token};
 
 pub type PrimitiveQueryConditions = (Vec<(Token, Token, Token)>, Vec<(usize, char)>);
 
@@ -18,37 +18,233 @@ pub struct Query {
 pub struct SearchArguments {
     pub element_size : usize,
     pub header_offset : usize,
-    pub file : Arc<Mutex<File>>,
-    pub conditions : QueryConditions
-
+    pub file : SharedStorage,
+    pub conditions : QueryConditions,
+    /// Stops reading the container once this many matching rows have been collected.
+    pub limit : Option<usize>,
+    /// Matching rows to skip before collecting any, counted in file order.
+    pub offset : Option<usize>,
+    /// Reads the container as it was when this snapshot was opened instead of its current state.
+    pub snapshot : Option<u64>,
+    /// Only rows stored past this file offset are read, cursors pass the offset of the last row of the previous page.
+    pub after : Option<u64>,
+    /// How many threads of the scan pool a full scan is spread over, 1 reads it on the calling task.
+    pub parallelism : usize,
+    /// Reads a full scan through a memory map of the container file instead of copying it chunk by chunk.
+    pub mmap : bool,
+    /// The read gives up with a timeout error once this passes, checked between chunks and candidate windows.
+    pub deadline : Option<Instant>,
+    /// Lays the changes staged in the container over the rows read, see `walk_pending`.
+    pub pending : bool,
 }
 const CHUNK_SIZE_BYTES : usize = 4096 * 10;
+/// A parallel scan hands every thread a run of this many chunks at once.
+const CHUNKS_PER_RANGE : usize = 16;
+/// Index-resolved rows are fetched this many at a time in one io_uring batch.
+const CANDIDATE_READ_WINDOW : usize = 256;
+
+
+lazy_static::lazy_static!{
+    /// Threads shared by every parallel scan, `SearchArguments::parallelism` decides how many ranges one scan is split into.
+    static ref SCAN_POOL : rayon::ThreadPool = rayon::ThreadPoolBuilder::new().num_threads(num_cpus::get()).thread_name(|i|format!("tyto-scan-{}",i)).build().expect("Failed to start the scan threads");
+}
 
+/// Runs `f` on the current thread, handing the tokio worker's other tasks to another worker meanwhile when the runtime has any.
+fn blocking<R>(f : impl FnOnce() -> R) -> R{
+    match tokio::runtime::Handle::try_current(){
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => tokio::task::block_in_place(f),
+        _ => f()
+    }
+}
+
+/// Fails once `deadline` has passed.
+fn check_deadline(deadline : Option<Instant>) -> Result<(),Error>{
+    if deadline.is_some_and(|d|Instant::now() >= d){
+        return Err(Error::from(TytoError::Timeout("The query ran past its timeout".to_string())))
+    }
+    Ok(())
+}
 
 pub async fn search(container: Arc<Mutex<Container>>, args: SearchArguments) -> Result<(Vec<Row>,Vec<u64>), Error> {
-    let file = args.file.lock().await;
+    let (rows,offsets,_) = if args.pending{walk_pending(container, args, true).await?}else{walk(container, args, true).await?};
+    Ok((rows,offsets))
+}
+
+/// Counts the rows `search` would return without keeping any of them, rows aren't even decoded when there are no conditions.
+pub async fn count(container: Arc<Mutex<Container>>, args: SearchArguments) -> Result<u64, Error> {
+    if args.pending{
+        return Ok(walk_pending(container, args, false).await?.2)
+    }
+    Ok(walk(container, args, false).await?.2)
+}
+
+/// Reads as `walk` does with the changes staged in the container laid over the rows it finds:
+/// a staged delete hides its row, a staged edit replaces it and staged inserts join the rows when they match.
+/// OFFSET and LIMIT only apply after the overlay, so every match is read first.
+async fn walk_pending(container: Arc<Mutex<Container>>, mut args: SearchArguments, collect: bool) -> Result<(Vec<Row>,Vec<u64>,u64), Error> {
+    let (column_names,pending) = {
+        let c = container.lock().await;
+        let pending = c.mvcc.lock().await.0.clone();
+        (c.column_names(),pending)
+    };
+    let (skip,limit) = (args.offset.take().unwrap_or(0),args.limit.take().unwrap_or(usize::MAX));
+    let conditions = args.conditions.clone();
+    let (rows,offsets,_) = walk(container, args, true).await?;
+    let mut merged : BTreeMap<u64,Row> = offsets.into_iter().zip(rows).filter(|(o,_)|!pending.contains_key(o)).collect();
+    for (offset,(state,data)) in pending{
+        if let MvccState::Delete = state{
+            continue
+        }
+        let row = Row{data};
+        if conditions.row_match(&row, &column_names)?{
+            merged.insert(offset, row);
+        }
+    }
+    let mut res = (Vec::new(),Vec::new(),0);
+    for (offset,row) in merged.into_iter().skip(skip).take(limit){
+        res.2 += 1;
+        if collect{res.0.push(row);res.1.push(offset);}
+    }
+    Ok(res)
+}
+
+/// Describes how `search` would answer `conditions` without reading any row: the plan, the index it uses,
+/// the keys or bounds it probes and an upper bound of the rows it reads.
+pub async fn explain(container: Arc<Mutex<Container>>, conditions: &QueryConditions, snapshot: bool) -> Result<Query, Error> {
     let lck = container.lock().await;
-    let size = file.metadata().unwrap().len() as usize;
-    if size == args.header_offset{
-        return Ok((Vec::new(),Vec::new()))
+    let total = lck.row_count().await? as i64;
+    let text = |s : &str| AlbaTypes::LargeString(s.to_string());
+    let (plan,index,keys,estimate) = match conditions.query_type()?{
+        QueryType::Empty => ("Empty","","".to_string(),0),
+        // the indexes only describe the current state, snapshot reads always scan
+        _ if snapshot => ("Scan","","".to_string(),total),
+        QueryType::Scan => ("Scan","","".to_string(),total),
+        QueryType::Indexed(QueryIndexType::Strict(keys)) => ("Indexed","primary key",format!("{:?}",keys),(keys.len() as i64).min(total)),
+        QueryType::Indexed(QueryIndexType::Range(range)) => {
+            let index_len = lck.index_map.lock().await.len();
+            let span = range.end().abs_diff(*range.start());
+            if range.is_empty(){
+                ("Indexed","primary key range",format!("{:?}",range),0)
+            }else if span < index_len{
+                ("Indexed","primary key range",format!("{:?}",range),(span as i64).saturating_add(1).min(total))
+            }else{
+                // as in `walk`, a range wider than the index is cheaper to scan
+                ("Scan","",format!("{:?}",range),total)
+            }
+        },
+        QueryType::Indexed(QueryIndexType::PrimaryRange(lower,upper)) => {
+            let bounds = format!("{:?}",(&lower,&upper));
+            match lck.index_map.lock().await.range(lower.as_ref(),upper.as_ref()){
+                Some(offsets) => ("Indexed","ordered primary key range",bounds,offsets.len() as i64),
+                None => ("Scan","",bounds,total)
+            }
+        },
+        QueryType::Indexed(QueryIndexType::Secondary(column,lookup)) => {
+            let secondary = lck.secondary_indexes.lock().await;
+            let index = secondary.get(&column).ok_or(gerr(&format!("There is no index on the column {}",column)))?;
+            let estimate = match &lookup{
+                SecondaryLookup::Equal(value) => index.lookup(value).len(),
+                SecondaryLookup::Range(lower,upper) => index.range(lower.as_ref(),upper.as_ref())?.len(),
+            };
+            return Ok(Query{rows:(
+                ["plan","index","keys","estimated_rows"].iter().map(|c|c.to_string()).collect(),
+                vec![Row{data:vec![text("Indexed"),text(&format!("secondary index on {}",column)),text(&format!("{:?}",lookup)),AlbaTypes::Bigint(estimate as i64)]}]
+            )})
+        },
+    };
+    Ok(Query{rows:(
+        ["plan","index","keys","estimated_rows"].iter().map(|c|c.to_string()).collect(),
+        vec![Row{data:vec![text(plan),text(index),text(&keys),AlbaTypes::Bigint(estimate)]}]
+    )})
+}
+
+async fn walk(container: Arc<Mutex<Container>>, args: SearchArguments, collect: bool) -> Result<(Vec<Row>,Vec<u64>,u64), Error> {
+    // the container is always locked before its file, the order a vacuum takes them in
+    let lck = container.lock().await;
+    let file = args.file.lock().await;
+    let snapshots = lck.snapshots.lock().await;
+    let images = match args.snapshot{
+        Some(id) => Some(snapshots.get(&id).ok_or(gerr(&format!("The snapshot {} is not open for this container",id)))?),
+        None => None
+    };
+    let size = file.len()? as usize;
+    if args.limit == Some(0) || (size == args.header_offset && images.is_none_or(|i|i.is_empty())){
+        return Ok((Vec::new(),Vec::new(),0))
     }
     let empty = vec![255u8;args.element_size];
     let column_names = &lck.column_names();
-    let qt = args.conditions.query_type()?;
+    // the indexes only describe the current state, snapshot reads always scan
+    let qt = match args.conditions.query_type()?{
+        QueryType::Empty => return Ok((Vec::new(),Vec::new(),0)),
+        _ if images.is_some() => QueryType::Scan,
+        qt => qt
+    };
     let mut gy = lck.graveyard.lock().await;
-    if let QueryType::Indexed(QueryIndexType::Strict(u)) = qt{
-        let mut res = (Vec::new(),Vec::new());
-        println!("u:{:?}",u);
-        for u in u{
-            if let Some(offset) = lck.index_map.lock().await.get(u)?{
-                if gy.contains(&offset) {continue;}
-                let mut buff = vec![0u8;args.element_size];
-                file.read_exact_at(&mut buff, offset)?;
+    let mut skip = args.offset.unwrap_or(0);
+    let mut matched = 0u64;
+    let decode = collect || !args.conditions.is_empty();
+    let candidates = match qt{
+        QueryType::Indexed(QueryIndexType::Strict(u)) => {
+            let mut index_map = lck.index_map.lock().await;
+            let mut offsets = Vec::with_capacity(u.len());
+            for key in u{
+                if let Some(offset) = index_map.get(&key)?{
+                    offsets.push(offset);
+                }
+            }
+            Some(offsets)
+        },
+        QueryType::Indexed(QueryIndexType::Range(range)) => {
+            let mut index_map = lck.index_map.lock().await;
+            // probing key by key only pays off while the range is not wider than the index, otherwise scan
+            if range.is_empty(){
+                Some(Vec::new())
+            }else if range.end().abs_diff(*range.start()) < index_map.len(){
+                let mut offsets = Vec::new();
+                for key in range{
+                    if let Some(offset) = index_map.get(&[AlbaTypes::Bigint(key)])?{
+                        offsets.push(offset);
+                    }
+                }
+                Some(offsets)
+            }else{
+                None
+            }
+        },
+        // a hash primary index can't answer ranges, the rows are scanned instead
+        QueryType::Indexed(QueryIndexType::PrimaryRange(lower,upper)) => lck.index_map.lock().await.range(lower.as_ref(),upper.as_ref()),
+        QueryType::Indexed(QueryIndexType::Secondary(column,lookup)) => {
+            let secondary = lck.secondary_indexes.lock().await;
+            let index = secondary.get(&column).ok_or(gerr(&format!("There is no index on the column {}",column)))?;
+            Some(match lookup{
+                SecondaryLookup::Equal(value) => index.lookup(&value),
+                SecondaryLookup::Range(lower,upper) => index.range(lower.as_ref(),upper.as_ref())?,
+            })
+        },
+        QueryType::Empty => Some(Vec::new()),
+        QueryType::Scan => None
+    };
+    if let Some(mut candidates) = candidates{
+        // visited in file order, so OFFSET and cursors see the same order as a scan
+        candidates.sort_unstable();
+        candidates.dedup();
+        if let Some(after) = args.after{
+            candidates.retain(|o|*o > after);
+        }
+        candidates.retain(|o|!gy.contains(o));
+        let mut res = (Vec::new(),Vec::new(),0);
+        // read in windows so a LIMIT stops the reads early instead of fetching every candidate up front
+        'candidates: for window in candidates.chunks(CANDIDATE_READ_WINDOW){
+            check_deadline(args.deadline)?;
+            let buffers = file.read_batch(window, args.element_size)?;
+            for (offset,buff) in window.iter().zip(buffers){
                 if buff == empty{continue;}
                 let b = Row{data:lck.deserialize_row(&buff).await?};
-                println!("b: {:?}",b);
                 if args.conditions.row_match(&b, column_names)?{
-                    res.0.push(b);res.1.push(u);
+                    if skip > 0{skip -= 1;continue;}
+                    res.2 += 1;
+                    if collect{res.0.push(b);res.1.push(*offset);}
+                    if args.limit.is_some_and(|l| res.2 >= l as u64){break 'candidates;}
                 }
             }
         }
@@ -56,36 +252,228 @@ pub async fn search(container: Arc<Mutex<Container>>, args: SearchArguments) ->
         return Ok(res)
     }
 
-    let total_rows = (file.metadata()?.len() as usize - args.header_offset)/args.element_size;
+    let total_rows = (file.len()? as usize - args.header_offset)/args.element_size;
+    // the scan starts on the row that follows `after`
+    let first_row = match args.after{
+        Some(after) => ((after as usize).saturating_sub(args.header_offset)/args.element_size + 1).min(total_rows),
+        None => 0
+    };
+    let base = args.header_offset + first_row * args.element_size;
+    let total_rows = total_rows - first_row;
     let rows_per_it = (CHUNK_SIZE_BYTES / args.element_size).max(1);
-    let chunk_size = (rows_per_it * args.element_size).min(total_rows*args.element_size);
-    let count_its = (total_rows / rows_per_it).max(1);
-    let mut space_gy = gy.len();
+    let chunk_size = rows_per_it * args.element_size;
+    // the last chunk holds whatever is left when total_rows isn't a multiple of rows_per_it
+    let count_its = total_rows.div_ceil(rows_per_it);
     let mut rows = Vec::new();
     let mut offsets = Vec::new();
-    for i in 0..count_its{ 
-        let mut buffer = vec![0u8;chunk_size];
-        let file_offset = args.header_offset as u64 + (i * chunk_size) as u64;
-        file.read_exact_at(&mut buffer, file_offset).unwrap();
-
+    let container = &*lck;
+    let graveyard = &*gy;
+    // reads chunk `i` and returns its matching rows with their offsets, along with the empty slots it came across
+    // Writers only touch the file while holding the container lock, which is held here until the scan ends,
+    // and a vacuum shrinking the file needs it as well, so the mapping can't change or be cut short under the scan.
+    // It is made for every scan, which also picks up whatever the file grew by since the last one.
+    // in-memory containers have no file to map and always read
+    let mapped = match file.as_file(){
+        Some(f) if args.mmap && count_its > 0 => Some(unsafe { memmap2::Mmap::map(f)? }),
+        _ => None
+    };
+    let scan_chunk = |i : usize| -> Result<(Vec<(u64,Row)>,Vec<u64>),Error>{
+        let start = base + i * chunk_size;
+        let len = (total_rows - i * rows_per_it).min(rows_per_it) * args.element_size;
+        let owned;
+        let buffer : &[u8] = match &mapped{
+            Some(map) => map.get(start..start + len).ok_or(gerr("The container file is shorter than its mapping"))?,
+            None => {
+                let mut buffer = vec![0u8;len];
+                file.read_exact_at(&mut buffer, start as u64)?;
+                owned = buffer;
+                &owned
+            }
+        };
+        let mut found = Vec::new();
+        let mut empties = Vec::new();
         for (j,row_bin) in buffer.chunks_exact(args.element_size).enumerate(){
-            
-            let offset_in_file = args.header_offset+i*chunk_size+j*args.element_size;
-            if gy.get(&(offset_in_file as u64)).is_some(){continue;};
-            if row_bin == empty{
-                if space_gy < MAX_GRAVEYARD_LENGTH_IN_MEMORY{
-                    space_gy += 1;
-                    gy.insert(offset_in_file.clone() as u64);
+            let offset_in_file = (base+i*chunk_size+j*args.element_size) as u64;
+            let row_bin = match images.and_then(|i|i.get(&offset_in_file)){
+                Some(Some(image)) => image.as_slice(),
+                Some(None) => continue,
+                None => {
+                    if graveyard.contains(&offset_in_file){continue;};
+                    if row_bin == empty{
+                        empties.push(offset_in_file);
+                        continue;
+                    }
+                    row_bin
                 }
-                continue;
+            };
+            let row = if decode{Row { data: container.decode_row(row_bin)? }}else{Row { data: Vec::new() }};
+            if !decode || args.conditions.row_match(&row, column_names)?{
+                found.push((offset_in_file,row));
             }
-            let bare_row = lck.deserialize_row(row_bin).await?;
-            let row = Row { data: bare_row };
+        }
+        Ok((found,empties))
+    };
+    let scan_chunk = &scan_chunk;
+    let parallelism = args.parallelism.max(1);
+    // a sequential scan goes chunk by chunk, a parallel one reads `parallelism` runs of chunks at once on the scan pool
+    let batch = if parallelism > 1{parallelism * CHUNKS_PER_RANGE}else{1};
+    let mut empties = Vec::new();
+    // results are merged back in file order, so LIMIT and OFFSET behave as in a sequential scan
+    'scan: for first in (0..count_its).step_by(batch){
+        check_deadline(args.deadline)?;
+        let last = (first + batch).min(count_its);
+        let results = if last - first == 1{
+            vec![scan_chunk(first)]
+        }else{
+            let per_range = (last - first).div_ceil(parallelism);
+            let ranges : Vec<_> = (first..last).step_by(per_range).map(|start|start..(start + per_range).min(last)).collect();
+            blocking(||SCAN_POOL.install(||ranges.into_par_iter().map(|range|range.map(scan_chunk).collect::<Vec<_>>()).collect::<Vec<_>>())).into_iter().flatten().collect()
+        };
+        for result in results{
+            let (found,chunk_empties) = result?;
+            empties.extend(chunk_empties);
+            for (offset,row) in found{
+                if skip > 0{skip -= 1;continue;}
+                matched += 1;
+                if collect{
+                    offsets.push(offset);
+                    rows.push(row);
+                }
+                if args.limit.is_some_and(|l| matched >= l as u64){break 'scan;}
+            }
+        }
+    }
+    let space_gy = lck.tuning.max_graveyard_length.saturating_sub(gy.len());
+    gy.extend(empties.into_iter().take(space_gy));
+    // rows that existed for the snapshot but were cut off the file by a later vacuum
+    if let Some(images) = images{
+        for (offset,image) in images.range((size as u64).max(args.after.map_or(0,|a|a+1))..){
+            if args.limit.is_some_and(|l| matched >= l as u64){break;}
+            let image = match image{
+                Some(i) => i,
+                None => continue
+            };
+            let row = Row { data: lck.deserialize_row(image).await? };
             if args.conditions.row_match(&row, &column_names)?{
-                offsets.push(offset_in_file as u64);
-                rows.push(row);
+                if skip > 0{skip -= 1;continue;}
+                matched += 1;
+                if collect{
+                    offsets.push(*offset);
+                    rows.push(row);
+                }
             }
         }
     }
-    Ok((rows,offsets))
+    Ok((rows,offsets,matched))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::collections::HashMap;
+    use std::ops::Bound;
+    use crate::{container::{ContainerOptions, ContainerTuning}, indexing::IndexKind};
+
+    /// A container on disk holding `rows` rows of an id and a score cycling through 0..100.
+    async fn scored_container(name : &str, rows : i64) -> (Arc<Mutex<Container>>,std::path::PathBuf){
+        let dir = std::env::temp_dir().join(format!("tytodb-query-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let types = vec![AlbaTypes::Bigint(0),AlbaTypes::Int(0),AlbaTypes::SmallString(String::new())];
+        let element_size = types.iter().map(|t|t.size()).sum();
+        let path = dir.join("scores").to_string_lossy().to_string();
+        let names = ["id","score","label"].iter().map(|n|n.to_string()).collect();
+        let container = Container::new(&path, element_size, types, 0, names, ContainerOptions::default(), ContainerTuning::default()).await.unwrap();
+        {
+            let mut c = container.lock().await;
+            c.bulk_load((0..rows).map(|id|vec![AlbaTypes::Bigint(id),AlbaTypes::Int((id % 100) as i32),AlbaTypes::SmallString(format!("row {}",id))]).collect()).await.unwrap();
+        }
+        (container,dir)
+    }
+
+    async fn arguments(container : &Arc<Mutex<Container>>, below : i32, parallelism : usize, mmap : bool) -> SearchArguments{
+        let c = container.lock().await;
+        let properties = HashMap::from([("score".to_string(),AlbaTypes::Int(0))]);
+        let conditions = QueryConditions::from_primitive_conditions((vec![(Token::String("score".to_string()),Token::Operator("<".to_string()),Token::Int(below as i64))],Vec::new()), &properties, Vec::new()).unwrap();
+        SearchArguments{element_size: c.element_size, header_offset: c.headers_offset as usize, file: c.file.clone(), conditions, limit: None, offset: None, snapshot: None, after: None, parallelism, mmap, deadline: None, pending: false}
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn parallel_scans_match_the_sequential_scan(){
+        let (container,dir) = scored_container("parallel", 20_000).await;
+        let (rows,offsets) = search(container.clone(), arguments(&container, 10, 1, false).await).await.unwrap();
+        assert_eq!(rows.len(), 2_000);
+        for (parallelism,mmap) in [(4,false),(4,true),(1,true)]{
+            let (other_rows,other_offsets) = search(container.clone(), arguments(&container, 10, parallelism, mmap).await).await.unwrap();
+            assert_eq!((&other_rows.iter().map(|r|&r.data).collect::<Vec<_>>(),&other_offsets), (&rows.iter().map(|r|&r.data).collect::<Vec<_>>(),&offsets));
+        }
+        // LIMIT and OFFSET count in file order whatever the parallelism
+        let mut args = arguments(&container, 10, 4, false).await;
+        args.offset = Some(1_500);
+        args.limit = Some(100);
+        let (_,page) = search(container.clone(), args).await.unwrap();
+        assert_eq!(page, offsets[1_500..1_600].to_vec());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn scans_read_the_partial_last_chunk(){
+        let element_size = AlbaTypes::Bigint(0).size() + AlbaTypes::Int(0).size() + AlbaTypes::SmallString(String::new()).size();
+        let rows = (CHUNK_SIZE_BYTES / element_size) as i64 * 3 + 7;
+        let (container,dir) = scored_container("partial", rows).await;
+        for (parallelism,mmap) in [(1,false),(1,true),(2,false)]{
+            let (found,_) = search(container.clone(), arguments(&container, 100, parallelism, mmap).await).await.unwrap();
+            assert_eq!(found.len() as i64, rows);
+            assert_eq!(found.last().unwrap().data[0], AlbaTypes::Bigint(rows - 1));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ordered_secondary_indexes_answer_ranges(){
+        let (container,dir) = scored_container("ordered", 1_000).await;
+        let (scanned,scanned_offsets) = search(container.clone(), arguments(&container, 10, 1, false).await).await.unwrap();
+        container.lock().await.create_index("score", IndexKind::Ordered).await.unwrap();
+        let mut args = arguments(&container, 10, 1, false).await;
+        let kinds = container.lock().await.secondary_indexes.lock().await.kinds();
+        let properties = HashMap::from([("score".to_string(),AlbaTypes::Int(0))]);
+        args.conditions = QueryConditions::from_primitive_conditions((vec![(Token::String("score".to_string()),Token::Operator("<".to_string()),Token::Int(10))],Vec::new()), &properties, vec!["id".to_string()]).unwrap().with_indexes(kinds);
+        assert!(matches!(args.conditions.query_type().unwrap(), QueryType::Indexed(QueryIndexType::Secondary(ref column,SecondaryLookup::Range(Bound::Unbounded,Bound::Excluded(AlbaTypes::Int(10))))) if column == "score"));
+        let (rows,offsets) = search(container.clone(), args).await.unwrap();
+        assert_eq!(rows.len(), 100);
+        assert_eq!(offsets, scanned_offsets);
+        assert_eq!(rows.iter().map(|r|&r.data).collect::<Vec<_>>(), scanned.iter().map(|r|&r.data).collect::<Vec<_>>());
+        // bounds on both sides, inclusive and exclusive
+        let c = container.lock().await;
+        let secondary = c.secondary_indexes.lock().await;
+        let index = secondary.get("score").unwrap();
+        assert_eq!(index.range(Bound::Included(&AlbaTypes::Int(5)),Bound::Included(&AlbaTypes::Int(7))).unwrap().len(), 30);
+        assert_eq!(index.range(Bound::Excluded(&AlbaTypes::Int(5)),Bound::Excluded(&AlbaTypes::Int(7))).unwrap().len(), 10);
+        assert!(index.range(Bound::Included(&AlbaTypes::Int(7)),Bound::Excluded(&AlbaTypes::Int(7))).unwrap().is_empty());
+        assert!(index.range(Bound::Included(&AlbaTypes::Int(9)),Bound::Included(&AlbaTypes::Int(1))).unwrap().is_empty());
+        drop(secondary);
+        drop(c);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Scan throughput of the buffered and memory mapped read paths, sequential and parallel.
+    /// `cargo test --release scan_throughput -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn scan_throughput(){
+        let rows = 1_000_000;
+        let (container,dir) = scored_container("throughput", rows).await;
+        for (parallelism,mmap) in [(1,false),(1,true),(4,false),(4,true)]{
+            let mut best = f64::MAX;
+            for _ in 0..5{
+                let args = arguments(&container, 50, parallelism, mmap).await;
+                let start = Instant::now();
+                let (found,_) = search(container.clone(), args).await.unwrap();
+                best = best.min(start.elapsed().as_secs_f64());
+                assert_eq!(found.len() as i64, rows/2);
+            }
+            println!("parallelism {} mmap {}: {:.1} ms, {:.2} M rows/s", parallelism, mmap, best*1000.0, rows as f64/best/1e6);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
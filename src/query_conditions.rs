@@ -1,10 +1,113 @@
-use std::{collections::HashMap, io::{self, Error, ErrorKind}, mem::discriminant};
-use regex::Regex;
+use std::{cmp::Ordering, collections::HashMap, io::{self, Error, ErrorKind}, mem::discriminant, ops::{Bound, RangeInclusive}};
+use regex::{Regex, RegexBuilder};
 
-use crate::container::get_index;
-use crate::{alba_types::AlbaTypes, gerr, Token, query::PrimitiveQueryConditions, row::Row};
+use crate::{container::ordered_key, decimal::Decimal, indexing::IndexKind};
+use crate::{alba_types::{parse_timestamp, parse_uuid, truncate_at_char_boundary, AlbaTypes}, error::TytoError, Token, query::PrimitiveQueryConditions, row::Row};
 
 
+/// Orders two bounds of a condition, None when they can't be ordered or only through a lossy float conversion,
+/// e.g. a Bigint past 2^53 against a Float.
+fn exact_cmp(a : &AlbaTypes, b : &AlbaTypes) -> Option<Ordering>{
+    const EXACT_IN_F64 : u64 = 1 << 53;
+    match (a,b){
+        (AlbaTypes::Bigint(i), AlbaTypes::Float(_)) | (AlbaTypes::Float(_), AlbaTypes::Bigint(i)) if i.unsigned_abs() > EXACT_IN_F64 => None,
+        (AlbaTypes::Decimal(_), AlbaTypes::Float(_)) | (AlbaTypes::Float(_), AlbaTypes::Decimal(_)) => None,
+        _ => numeric_cmp(a, b).ok().flatten()
+    }
+}
+
+/// Orders two numeric values, promoting Int to Bigint and either to Float when the types differ.
+/// None means the values can't be ordered (a NaN is involved).
+fn numeric_cmp(a : &AlbaTypes, b : &AlbaTypes) -> Result<Option<Ordering>,TytoError>{
+    Ok(match (a,b){
+        (AlbaTypes::Int(x), AlbaTypes::Int(y)) => Some(x.cmp(y)),
+        (AlbaTypes::Bigint(x), AlbaTypes::Bigint(y)) => Some(x.cmp(y)),
+        (AlbaTypes::Int(x), AlbaTypes::Bigint(y)) => Some((*x as i64).cmp(y)),
+        (AlbaTypes::Bigint(x), AlbaTypes::Int(y)) => Some(x.cmp(&(*y as i64))),
+        (AlbaTypes::Float(x), AlbaTypes::Float(y)) => x.partial_cmp(y),
+        (AlbaTypes::Int(x), AlbaTypes::Float(y)) => (*x as f64).partial_cmp(y),
+        (AlbaTypes::Float(x), AlbaTypes::Int(y)) => x.partial_cmp(&(*y as f64)),
+        (AlbaTypes::Bigint(x), AlbaTypes::Float(y)) => (*x as f64).partial_cmp(y),
+        (AlbaTypes::Float(x), AlbaTypes::Bigint(y)) => x.partial_cmp(&(*y as f64)),
+        // timestamps only order among themselves, condition values are already read as the column type
+        (AlbaTypes::Timestamp(x), AlbaTypes::Timestamp(y)) => Some(x.cmp(y)),
+        // decimals compare exactly with each other and with integers, a float turns them into a float
+        (AlbaTypes::Decimal(x), AlbaTypes::Decimal(y)) => Some(x.cmp(y)),
+        (AlbaTypes::Decimal(x), AlbaTypes::Int(y)) => Some(x.cmp(&Decimal::from(*y as i64))),
+        (AlbaTypes::Int(x), AlbaTypes::Decimal(y)) => Some(Decimal::from(*x as i64).cmp(y)),
+        (AlbaTypes::Decimal(x), AlbaTypes::Bigint(y)) => Some(x.cmp(&Decimal::from(*y))),
+        (AlbaTypes::Bigint(x), AlbaTypes::Decimal(y)) => Some(Decimal::from(*x).cmp(y)),
+        (AlbaTypes::Decimal(x), AlbaTypes::Float(y)) => x.to_f64().partial_cmp(y),
+        (AlbaTypes::Float(x), AlbaTypes::Decimal(y)) => x.partial_cmp(&y.to_f64()),
+        _ => return Err(TytoError::TypeMismatch("Invalid type for numeric comparison".to_string()))
+    })
+}
+
+/// Upper bound on the compiled size of a condition regex, so a pathological pattern fails to compile instead of eating memory.
+const REGEX_SIZE_LIMIT : usize = 1 << 20;
+
+/// Flags of a regex condition, written as letters after the operator: `&&&>i` is case-insensitive,
+/// `&&&>s` lets `.` match new lines, `&&&>m` makes `^`/`$` match at line ends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+struct RegexFlags{
+    case_insensitive : bool,
+    dot_matches_new_line : bool,
+    multi_line : bool,
+}
+
+impl RegexFlags{
+    fn parse(letters : &str) -> Result<Self,TytoError>{
+        let mut flags = RegexFlags::default();
+        for letter in letters.chars(){
+            match letter{
+                'i' => flags.case_insensitive = true,
+                's' => flags.dot_matches_new_line = true,
+                'm' => flags.multi_line = true,
+                _ => return Err(TytoError::InvalidInput(format!("Unknown regex flag '{}', the supported ones are i, s and m.",letter)))
+            }
+        }
+        Ok(flags)
+    }
+}
+
+type RegexCache = HashMap<(String,RegexFlags), Regex>;
+
+/// Compiles `pattern` with `flags` once per `row_match` call.
+fn cached_regex(regex_cache: &mut RegexCache, pattern: String, flags: RegexFlags) -> Result<&Regex, TytoError>{
+    let key = (pattern,flags);
+    if !regex_cache.contains_key(&key){
+        let compiled = RegexBuilder::new(&key.0)
+            .case_insensitive(flags.case_insensitive)
+            .dot_matches_new_line(flags.dot_matches_new_line)
+            .multi_line(flags.multi_line)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e|TytoError::InvalidInput(e.to_string()))?;
+        regex_cache.insert(key.clone(), compiled);
+    }
+    Ok(&regex_cache[&key])
+}
+
+/// Turns a LIKE pattern into an anchored regex: `%` is any run of characters, `_` a single one,
+/// a backslash makes the next character literal and everything else matches itself.
+fn like_to_regex(pattern : &str) -> String{
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next(){
+        match c{
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            '\\' => if let Some(next) = chars.next(){
+                regex.push_str(&regex::escape(&next.to_string()));
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
 fn string_to_char(s: String) -> Result<char, io::Error> {
     let mut chars = s.chars();
 
@@ -14,6 +117,155 @@ fn string_to_char(s: String) -> Result<char, io::Error> {
     }
 }
 
+/// Reads a condition value written as `token` into the type of the column it is compared against.
+fn token_to_value(column_type : &AlbaTypes, token : Token) -> Result<AlbaTypes,TytoError>{
+    Ok(match column_type{
+        AlbaTypes::Text(_) => {
+            if let Token::String(string) = token{
+                AlbaTypes::Text(string)
+            }else {
+                return Err(TytoError::TypeMismatch("No string found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Int(_) => {
+            if let Token::Int(number) = token{
+                AlbaTypes::Int(number as i32)
+            }else {
+                return Err(TytoError::TypeMismatch("No integer found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Bigint(_) => {
+            if let Token::Int(number) = token{
+                AlbaTypes::Bigint(number)
+            }else {
+                return Err(TytoError::TypeMismatch("No integer found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Float(_) => {
+            if let Token::Float(number) = token{
+                AlbaTypes::Float(number)
+            }else {
+                return Err(TytoError::TypeMismatch("No float found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Bool(_) => {
+            if let Token::Bool(bool) = token{
+                AlbaTypes::Bool(bool)
+            }else {
+                return Err(TytoError::TypeMismatch("No bool found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Char(_) => {
+            if let Token::String(char) = token{
+                AlbaTypes::Char(string_to_char(char)?)
+            }else {
+                return Err(TytoError::TypeMismatch("No char found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::NanoString(_) => {
+            if let Token::String(nano_string) = token{
+                AlbaTypes::NanoString(truncate_at_char_boundary(&nano_string, 10).to_string())
+            }else {
+                return Err(TytoError::TypeMismatch("No nano_string found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::SmallString(_) => {
+            if let Token::String(small_string) = token{
+                AlbaTypes::SmallString(truncate_at_char_boundary(&small_string, 100).to_string())
+            }else {
+                return Err(TytoError::TypeMismatch("No small_string found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::MediumString(_) => {
+            if let Token::String(medium_string) = token{
+                AlbaTypes::MediumString(truncate_at_char_boundary(&medium_string, 500).to_string())
+            }else {
+                return Err(TytoError::TypeMismatch("No medium_string found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::BigString(_) => {
+            if let Token::String(big_string) = token{
+                AlbaTypes::BigString(truncate_at_char_boundary(&big_string, 2000).to_string())
+            }else {
+                return Err(TytoError::TypeMismatch("No big_string found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::LargeString(_) => {
+            if let Token::String(large_string) = token{
+                AlbaTypes::LargeString(truncate_at_char_boundary(&large_string, 3000).to_string())
+            }else {
+                return Err(TytoError::TypeMismatch("No large_string found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::NanoBytes(_) => {
+            if let Token::Bytes(mut nano_bytes) = token{
+                nano_bytes.truncate(10);
+                AlbaTypes::NanoBytes(nano_bytes)
+            }else {
+                return Err(TytoError::TypeMismatch("No nano_bytes found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::SmallBytes(_) => {
+            if let Token::Bytes(mut small_bytes) = token{
+                small_bytes.truncate(1000);
+                AlbaTypes::SmallBytes(small_bytes)
+            }else {
+                return Err(TytoError::TypeMismatch("No small_bytes found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::MediumBytes(_) => {
+            if let Token::Bytes(mut medium_bytes) = token{
+                medium_bytes.truncate(10000);
+                AlbaTypes::MediumBytes(medium_bytes)
+            }else {
+                return Err(TytoError::TypeMismatch("No medium_bytes found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::BigSBytes(_) => {
+            if let Token::Bytes(mut big_bytes) = token{
+                big_bytes.truncate(100000);
+                AlbaTypes::BigSBytes(big_bytes)
+            }else {
+                return Err(TytoError::TypeMismatch("No big_bytes found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::LargeBytes(_) => {
+            if let Token::Bytes(mut large_bytes) = token{
+                large_bytes.truncate(1000000);
+                AlbaTypes::LargeBytes(large_bytes)
+            }else {
+                return Err(TytoError::TypeMismatch("No large_bytes found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Decimal(_) => {
+            let decimal = match token{
+                Token::String(decimal) => decimal.trim().parse(),
+                Token::Int(number) => Ok(Decimal::from(number)),
+                Token::Float(number) => Decimal::from_f64(number),
+                _ => return Err(TytoError::TypeMismatch("No decimal found in the ComparisionToken".to_string()))
+            };
+            AlbaTypes::Decimal(decimal.map_err(|e|TytoError::TypeMismatch(e.to_string()))?)
+        },
+        AlbaTypes::Timestamp(_) => {
+            match token{
+                Token::String(timestamp) => AlbaTypes::Timestamp(parse_timestamp(timestamp.trim()).map_err(|e|TytoError::TypeMismatch(e.to_string()))?),
+                Token::Int(micros) => AlbaTypes::Timestamp(micros),
+                _ => return Err(TytoError::TypeMismatch("No timestamp found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::Uuid(_) => {
+            match token{
+                Token::String(uuid) => AlbaTypes::Uuid(parse_uuid(uuid.trim()).map_err(|e|TytoError::TypeMismatch(e.to_string()))?),
+                Token::Bytes(bytes) => AlbaTypes::Uuid(bytes.try_into().map_err(|_|TytoError::TypeMismatch("A uuid is exactly 16 bytes".to_string()))?),
+                _ => return Err(TytoError::TypeMismatch("No uuid found in the ComparisionToken".to_string()))
+            }
+        },
+        AlbaTypes::NONE => {
+            return Err(TytoError::TypeMismatch("Failed to extract the value from the column_properties".to_string()))
+        },
+    })
+}
+
 #[derive(Clone, Copy, Debug)]
 enum LogicalGate{
     And,
@@ -25,24 +277,57 @@ pub struct QueryConditionAtom{
     column : String,
     operator : Operator,
     value : AlbaTypes,
+    /// Upper bound of a BETWEEN, `value` being the lower one.
+    upper : Option<AlbaTypes>,
+    /// Candidates of an IN, `value` is unused for it.
+    list : Vec<AlbaTypes>,
+    /// Set by a `NOT` prefix on the operator, inverts the atom's result.
+    negate : bool,
+    /// Used by the regex and LIKE operators.
+    regex_flags : RegexFlags,
+    /// Set to the column type when the value is a `?` of a prepared statement, filled by `bind`.
+    placeholder : Option<AlbaTypes>,
 }
+/// A condition tree node, a group holds its own chain which is evaluated before the gate that follows it.
+#[derive(Clone,Debug)]
+enum ConditionNode{
+    Atom(QueryConditionAtom),
+    Group(Vec<(ConditionNode,Option<LogicalGate>)>),
+}
+
 #[derive(Clone,Default,Debug)]
 pub struct QueryConditions{
-    primary_key : Option<String>,
-    chain : Vec<(QueryConditionAtom,Option<LogicalGate>)>
+    /// Key columns in key order, more than one for a composite key.
+    primary_key : Vec<String>,
+    chain : Vec<(ConditionNode,Option<LogicalGate>)>,
+    indexes : HashMap<String,IndexKind>,
+    /// Kind of the container's primary key index, an ordered one also answers ranges over the key.
+    primary_index : IndexKind,
+}
+
+#[derive(Debug)]
+pub enum SecondaryLookup{
+    Equal(AlbaTypes),
+    Range(Bound<AlbaTypes>,Bound<AlbaTypes>),
 }
 
 #[derive(Debug)]
 pub enum QueryIndexType {
-    Strict(Vec<u64>),
-    // Range(Range<u64>),
-    // InclusiveRange(RangeInclusive<u64>), 
+    /// Primary keys to probe, each with the value of every key column in key order.
+    Strict(Vec<Vec<AlbaTypes>>),
+    Secondary(String,SecondaryLookup),
+    /// Integer primary keys are their own index key, so a range can be answered by probing every key in it.
+    Range(RangeInclusive<i64>),
+    /// Range over a single column primary key kept in an ordered index.
+    PrimaryRange(Bound<AlbaTypes>,Bound<AlbaTypes>),
 }
 
 #[derive(Debug)]
 pub enum QueryType{
     Scan,
     Indexed(QueryIndexType),
+    /// The conditions can never hold, nothing has to be read.
+    Empty,
 }
 
 #[derive(Clone,Debug)]
@@ -56,22 +341,45 @@ enum Operator{
     Different,
     StringContains,
     StringCaseInsensitiveContains,
-    StringRegularExpression
+    StringRegularExpression,
+    /// Inclusive range, `column BETWEEN low AND high`.
+    Between,
+    /// Membership, `column IN (a, b, c)`.
+    In,
+    /// SQL pattern match on string columns, `value` holds the pattern already turned into a regex by `like_to_regex`
+    /// and `regex_flags` lets `%` span new lines.
+    Like,
+    /// Case-insensitive `Like`.
+    ILike,
+    /// The row value is `AlbaTypes::NONE`, takes no value.
+    IsNull,
+    /// The row value is anything but `AlbaTypes::NONE`, takes no value.
+    IsNotNull,
 }
 
 // ranges | infinity<bool> | InclusiveRange
 
 impl QueryConditions{
-    pub fn from_primitive_conditions(primitive_conditions : PrimitiveQueryConditions, column_properties : &HashMap<String,AlbaTypes>,primary_key : String) -> Result<Self,Error>{
-        let mut chain : Vec<(QueryConditionAtom,Option<LogicalGate>)> = Vec::new();
+    /// Besides the gates, the gate list may carry `(i,'(')` to open a group before atom `i`
+    /// and `(i,')')` to close one after it, so `(a AND b) OR c` is `[(0,'('),(0,'a'),(1,')'),(1,'o')]`.
+    pub fn from_primitive_conditions(primitive_conditions : PrimitiveQueryConditions, column_properties : &HashMap<String,AlbaTypes>,primary_key : Vec<String>) -> Result<Self,TytoError>{
+        // the innermost open group is the last one, the top level chain is the first
+        let mut stack : Vec<Vec<(ConditionNode,Option<LogicalGate>)>> = vec![Vec::new()];
         let condition_chunk = primitive_conditions.0;
         let condition_logical_gates_vec = primitive_conditions.1;
         let mut condition_logical_gates = HashMap::new();
+        let mut opens : HashMap<usize,usize> = HashMap::new();
+        let mut closes : HashMap<usize,usize> = HashMap::new();
         for i in condition_logical_gates_vec{
+            match i.1{
+                '(' => {*opens.entry(i.0).or_default() += 1; continue},
+                ')' => {*closes.entry(i.0).or_default() += 1; continue},
+                _ => {}
+            }
             condition_logical_gates.insert(i.0, match i.1{
                 'a'|'A' => LogicalGate::And,
                 'o'|'O' => LogicalGate::Or,
-                _ => return  Err(gerr("Failed to load LogicalGate, invalid token."))
+                _ => return  Err(TytoError::InvalidInput("Failed to load LogicalGate, invalid token.".to_string()))
             });
         }
         for (index,value) in condition_chunk.iter().enumerate(){
@@ -80,11 +388,19 @@ impl QueryConditions{
             let column = if let Token::String(name) = value.0{
                 name
             }else{
-                return Err(gerr("Failed to get QueryConditions, but failed to gather the column_name."))
+                return Err(TytoError::InvalidInput("Failed to get QueryConditions, but failed to gather the column_name.".to_string()))
             };
             
+            let mut negate = false;
+            let mut regex_flags = RegexFlags::default();
+            let mut placeholder = None;
             let operator = if let Token::Operator(operator_name) = value.1{
-                match operator_name.as_str(){
+                let mut operator_name = operator_name.trim();
+                if operator_name.len() > 4 && operator_name.get(..4).is_some_and(|p|p.eq_ignore_ascii_case("not ")){
+                    negate = true;
+                    operator_name = operator_name[4..].trim_start();
+                }
+                match operator_name{
                     "=" => Operator::Equal,
                     "==" => Operator::StrictEqual,
                     ">=" => Operator::GreaterEquality,
@@ -95,155 +411,157 @@ impl QueryConditions{
                     "&>" => Operator::StringContains,
                     "&&>" => Operator::StringCaseInsensitiveContains,
                     "&&&>" => Operator::StringRegularExpression,
+                    name if name.starts_with("&&&>") => {
+                        regex_flags = RegexFlags::parse(&name[4..])?;
+                        Operator::StringRegularExpression
+                    },
+                    name if name.eq_ignore_ascii_case("between") => Operator::Between,
+                    name if name.eq_ignore_ascii_case("in") => Operator::In,
+                    name if name.eq_ignore_ascii_case("like") => Operator::Like,
+                    name if name.eq_ignore_ascii_case("ilike") => Operator::ILike,
+                    name if name.eq_ignore_ascii_case("is null") => Operator::IsNull,
+                    name if name.eq_ignore_ascii_case("is not null") => Operator::IsNotNull,
                     _ => {
-                        return Err(gerr("Failed to get operator, invalid token contant."))
+                        return Err(TytoError::InvalidInput("Failed to get operator, invalid token contant.".to_string()))
                     }
                 }
             }else{
-                return Err(gerr("Failed to get operator, invalid token,"))
+                return Err(TytoError::InvalidInput("Failed to get operator, invalid token,".to_string()))
             };
 
-            let column_value = if let Some(column_type) = column_properties.get(&column){
-                match column_type{
-                    AlbaTypes::Text(_) => {
-                        if let Token::String(string) = value.2{
-                            AlbaTypes::Text(string)
-                        }else {
-                            return Err(gerr("No string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Int(_) => {
-                        if let Token::Int(number) = value.2{
-                            AlbaTypes::Int(number as i32)
-                        }else {
-                            return Err(gerr("No integer found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Bigint(_) => {
-                        if let Token::Int(number) = value.2{
-                            AlbaTypes::Bigint(number)
-                        }else {
-                            return Err(gerr("No integer found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Float(_) => {
-                        if let Token::Float(number) = value.2{
-                            AlbaTypes::Float(number)
-                        }else {
-                            return Err(gerr("No float found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Bool(_) => {
-                        if let Token::Bool(bool) = value.2{
-                            AlbaTypes::Bool(bool)
-                        }else {
-                            return Err(gerr("No bool found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Char(_) => {
-                        if let Token::String(char) = value.2{
-                            AlbaTypes::Char(string_to_char(char)?)
-                        }else {
-                            return Err(gerr("No char found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::NanoString(_) => {
-                        if let Token::String(mut nano_string) = value.2{
-                            nano_string.truncate(10);
-                            AlbaTypes::NanoString(nano_string)
-                        }else {
-                            return Err(gerr("No nano_string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::SmallString(_) => {
-                        if let Token::String(mut small_string) = value.2{
-                            small_string.truncate(100);
-                            AlbaTypes::SmallString(small_string)
-                        }else {
-                            return Err(gerr("No small_string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::MediumString(_) => {
-                        if let Token::String(mut medium_string) = value.2{
-                            medium_string.truncate(500);
-                            AlbaTypes::SmallString(medium_string)
-                        }else {
-                            return Err(gerr("No medium_string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::BigString(_) => {
-                        if let Token::String(mut big_string) = value.2{
-                            big_string.truncate(2000);
-                            AlbaTypes::SmallString(big_string)
-                        }else {
-                            return Err(gerr("No big_string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::LargeString(_) => {
-                        if let Token::String(mut large_string) = value.2{
-                            large_string.truncate(3000);
-                            AlbaTypes::SmallString(large_string)
-                        }else {
-                            return Err(gerr("No large_string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::NanoBytes(_) => {
-                        if let Token::Bytes(mut nano_bytes) = value.2{
-                            nano_bytes.truncate(10);
-                            AlbaTypes::NanoBytes(nano_bytes)
-                        }else {
-                            return Err(gerr("No nano_bytes found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::SmallBytes(_) => {
-                        if let Token::Bytes(mut small_bytes) = value.2{
-                            small_bytes.truncate(1000);
-                            AlbaTypes::SmallBytes(small_bytes)
-                        }else {
-                            return Err(gerr("No small_bytes found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::MediumBytes(_) => {
-                        if let Token::Bytes(mut medium_bytes) = value.2{
-                            medium_bytes.truncate(10000);
-                            AlbaTypes::MediumBytes(medium_bytes)
-                        }else {
-                            return Err(gerr("No medium_bytes found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::BigSBytes(_) => {
-                        if let Token::Bytes(mut big_bytes) = value.2{
-                            big_bytes.truncate(100000);
-                            AlbaTypes::BigSBytes(big_bytes)
-                        }else {
-                            return Err(gerr("No big_bytes found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::LargeBytes(_) => {
-                        if let Token::Bytes(mut large_bytes) = value.2{
-                            large_bytes.truncate(1000000);
-                            AlbaTypes::BigSBytes(large_bytes)
-                        }else {
-                            return Err(gerr("No large_bytes found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::NONE => {
-                        return Err(gerr("Failed to extract the value from the column_properties"))
-                    },
-                } 
-            }else{
-                return Err(gerr("Failed to generate QueryConditions, that happened because no column_property has been found with the given column-names"))
+            let column_type = match column_properties.get(&column){
+                Some(t) => t,
+                None => return Err(TytoError::NotFound("Failed to generate QueryConditions, that happened because no column_property has been found with the given column-names".to_string()))
+            };
+            let mut list = Vec::new();
+            let (column_value,upper) = match operator{
+                Operator::Between => {
+                    let (low,high) = match value.2{
+                        Token::Group(mut bounds) if bounds.len() == 2 => {
+                            let high = bounds.pop().unwrap();
+                            (bounds.pop().unwrap(),high)
+                        },
+                        _ => return Err(TytoError::InvalidInput("BETWEEN needs a group of exactly two values, the lower and the upper bound".to_string()))
+                    };
+                    (token_to_value(column_type, low)?,Some(token_to_value(column_type, high)?))
+                },
+                Operator::In => {
+                    let members = match value.2{
+                        Token::Group(members) => members,
+                        _ => return Err(TytoError::InvalidInput("IN needs a group of values".to_string()))
+                    };
+                    for member in members{
+                        list.push(token_to_value(column_type, member)?);
+                    }
+                    (AlbaTypes::NONE,None)
+                },
+                Operator::IsNull | Operator::IsNotNull => (AlbaTypes::NONE,None),
+                Operator::Like | Operator::ILike => {
+                    let pattern = match (column_type,value.2){
+                        (AlbaTypes::Text(_)|AlbaTypes::NanoString(_)|AlbaTypes::SmallString(_)|AlbaTypes::MediumString(_)|AlbaTypes::BigString(_)|AlbaTypes::LargeString(_),Token::String(pattern)) => pattern,
+                        (AlbaTypes::Text(_)|AlbaTypes::NanoString(_)|AlbaTypes::SmallString(_)|AlbaTypes::MediumString(_)|AlbaTypes::BigString(_)|AlbaTypes::LargeString(_),_) => return Err(TytoError::TypeMismatch("LIKE needs a string pattern".to_string())),
+                        _ => return Err(TytoError::TypeMismatch(format!("LIKE can only be used on string columns, {} is not one",column)))
+                    };
+                    regex_flags = RegexFlags{case_insensitive: matches!(operator,Operator::ILike), dot_matches_new_line: true, multi_line: false};
+                    (AlbaTypes::Text(like_to_regex(&pattern)),None)
+                },
+                _ if value.2 == Token::Argument => {
+                    placeholder = Some(column_type.clone());
+                    (AlbaTypes::NONE,None)
+                },
+                _ => (token_to_value(column_type, value.2)?,None)
             };
 
             let gate = condition_logical_gates
                 .get(&index)
                 .map(|a| a.clone());
 
-            chain.push((QueryConditionAtom{column,operator,value:column_value},gate));
+            for _ in 0..opens.get(&index).copied().unwrap_or(0){
+                stack.push(Vec::new());
+            }
+            let atom = QueryConditionAtom{column,operator,value:column_value,upper,list,negate,regex_flags,placeholder};
+            stack.last_mut().unwrap().push((ConditionNode::Atom(atom),None));
+            for _ in 0..closes.get(&index).copied().unwrap_or(0){
+                if stack.len() < 2{
+                    return Err(TytoError::InvalidInput(format!("Failed to get QueryConditions, the group closed after condition {} was never opened.",index)))
+                }
+                let group = stack.pop().unwrap();
+                stack.last_mut().unwrap().push((ConditionNode::Group(group),None));
+            }
+            // the gate joins whatever ended at this atom, itself or the groups it closed, with what follows
+            stack.last_mut().unwrap().last_mut().unwrap().1 = gate;
+        }
+        if stack.len() != 1{
+            return Err(TytoError::InvalidInput("Failed to get QueryConditions, a condition group was opened but never closed.".to_string()))
+        }
+        let chain = stack.pop().unwrap();
+        return Ok(QueryConditions { chain, primary_key, indexes : HashMap::new(), primary_index : IndexKind::Hash})
+    }
+    pub fn is_empty(&self) -> bool{
+        self.chain.is_empty()
+    }
+    /// How many `?` values the conditions take.
+    pub fn placeholders(&self) -> usize{
+        self.atoms().iter().filter(|a|a.placeholder.is_some()).count()
+    }
+    /// Returns the conditions with the `?` placeholders filled by `values` in order, each has to be of its column's type.
+    pub fn bind(&self, values : Vec<AlbaTypes>) -> Result<Self,TytoError>{
+        fn fill(chain : &mut [(ConditionNode,Option<LogicalGate>)], values : &mut std::vec::IntoIter<AlbaTypes>, bound : &mut usize) -> Result<(),TytoError>{
+            for (node,_) in chain{
+                match node{
+                    ConditionNode::Atom(atom) => if let Some(column_type) = &atom.placeholder{
+                        *bound += 1;
+                        let value = values.next().ok_or(TytoError::InvalidInput("Not enough values to bind".to_string()))?;
+                        if discriminant(&value) != discriminant(column_type){
+                            return Err(TytoError::TypeMismatch(format!("Value {} is compared with the column {}, {:?} doesn't match its type", bound, atom.column, value)))
+                        }
+                        atom.value = value;
+                    },
+                    ConditionNode::Group(group) => fill(group, values, bound)?
+                }
+            }
+            Ok(())
         }
-        return Ok(QueryConditions { chain, primary_key : Some(primary_key)})
+        let expected = self.placeholders();
+        if values.len() != expected{
+            return Err(TytoError::InvalidInput(format!("The statement takes {} values, {} were given", expected, values.len())))
+        }
+        let mut bound = self.clone();
+        fill(&mut bound.chain, &mut values.into_iter(), &mut 0)?;
+        Ok(bound)
     }
-    pub fn row_match(&self, row: &Row,row_headers: &Vec<String>) -> Result<bool, Error> {
+    /// Every atom of the tree, in order.
+    fn atoms(&self) -> Vec<&QueryConditionAtom>{
+        fn collect<'a>(chain : &'a [(ConditionNode,Option<LogicalGate>)], out : &mut Vec<&'a QueryConditionAtom>){
+            for (node,_) in chain{
+                match node{
+                    ConditionNode::Atom(atom) => out.push(atom),
+                    ConditionNode::Group(group) => collect(group, out)
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.chain, &mut out);
+        out
+    }
+    /// Whether an OR gate appears at any depth, without one the grouping doesn't change the result.
+    fn has_or(&self) -> bool{
+        fn any_or(chain : &[(ConditionNode,Option<LogicalGate>)]) -> bool{
+            chain.iter().any(|(node,gate)|matches!(gate,Some(LogicalGate::Or)) || matches!(node,ConditionNode::Group(group) if any_or(group)))
+        }
+        any_or(&self.chain)
+    }
+    /// Lets `query_type` plan lookups through the secondary indexes declared on the container.
+    pub fn with_indexes(mut self, indexes : HashMap<String,IndexKind>) -> Self{
+        self.indexes = indexes;
+        self
+    }
+    /// Lets `query_type` plan ranges over the primary key when the container keeps it in an ordered index.
+    pub fn with_primary_index(mut self, kind : IndexKind) -> Self{
+        self.primary_index = kind;
+        self
+    }
+    pub fn row_match(&self, row: &Row,row_headers: &Vec<String>) -> Result<bool, TytoError> {
         
         
         if self.chain.is_empty() {
@@ -251,257 +569,194 @@ impl QueryConditions{
             return Ok(true);
         }
         
-        let mut result = false;
-        let mut regex_cache: HashMap<String, Regex> = HashMap::new();
-        
+        let mut regex_cache: RegexCache = HashMap::new();
+        Self::chain_match(&self.chain, row, row_headers, &mut regex_cache)
+    }
+
+    /// Evaluates one level of the condition tree, a group is evaluated as a whole before its gate applies.
+    /// AND binds tighter than OR, as in SQL, so `a AND b OR c` is `(a AND b) OR c`; a missing gate counts as AND
+    /// and the gate of the last node is ignored. Nodes are only evaluated while they can still change the result.
+    fn chain_match(chain: &[(ConditionNode,Option<LogicalGate>)], row: &Row, row_headers: &Vec<String>, regex_cache: &mut RegexCache) -> Result<bool, TytoError> {
+        // the AND term being built, once false the rest of the term doesn't need evaluating
+        let mut term = true;
+        let len = chain.len();
+        for (i,(node, logical_gate)) in chain.iter().enumerate() {
+            if term{
+                term = match node{
+                    ConditionNode::Atom(atom) => Self::atom_match(atom, row, row_headers, regex_cache)?.unwrap_or(false),
+                    ConditionNode::Group(group) => Self::chain_match(group, row, row_headers, regex_cache)?
+                };
+            }
+            if i + 1 < len && matches!(logical_gate, Some(LogicalGate::Or)){
+                if term{
+                    return Ok(true)
+                }
+                term = true;
+            }
+        }
+        Ok(term)
+    }
+
+    /// None when the row has no value for the atom's column.
+    fn atom_match(query_condition: &QueryConditionAtom, row: &Row, row_headers: &Vec<String>, regex_cache: &mut RegexCache) -> Result<Option<bool>, TytoError> {
+        let column = &query_condition.column;
+        let value = &query_condition.value;
+        //println!("{:?}\t{:?}\t{:?}",query_condition,logical_gate,row);
+        let row_value = match row.get_by_name(column, row_headers){
+            Ok(val) => val,
+            Err(_) => return Ok(None)
+        };
         
-        let len = self.chain.len();
-        for (i,(query_condition, logical_gate)) in self.chain.iter().enumerate() {
-            let column = &query_condition.column;
-            let value = &query_condition.value;
-            //println!("{:?}\t{:?}\t{:?}",query_condition,logical_gate,row);
-            let ci = {
-                let mut c = 0usize;
-                for i in row.data.iter().zip(row_headers.iter()).enumerate(){
-                    if *i.1.1 == *column{c = i.0;break;} ;
+        let check = match query_condition.operator {
+            Operator::Equal | Operator::StrictEqual => {
+                
+                
+                let result = *value == *row_value;
+                
+                result
+            },
+            Operator::Greater | Operator::GreaterEquality | Operator::Lower | Operator::LowerEquality => {
+                match (numeric_cmp(row_value, value)?,&query_condition.operator){
+                    (Some(o),Operator::Greater) => o == Ordering::Greater,
+                    (Some(o),Operator::GreaterEquality) => o != Ordering::Less,
+                    (Some(o),Operator::Lower) => o == Ordering::Less,
+                    (Some(o),Operator::LowerEquality) => o != Ordering::Greater,
+                    _ => false
                 }
-                c
-            };
-            
-            let row_value = if let Some(val) = row.data.get(ci) {
+            },
+            Operator::Between => {
+                let upper = match &query_condition.upper{
+                    Some(u) => u,
+                    None => return Err(TytoError::InvalidInput("BETWEEN condition is missing its upper bound".to_string()))
+                };
+                numeric_cmp(row_value, value)?.is_some_and(|o|o != Ordering::Less)
+                    && numeric_cmp(row_value, upper)?.is_some_and(|o|o != Ordering::Greater)
+            },
+            Operator::Different => {
+                *value != *row_value
+            },
+            Operator::In => {
+                query_condition.list.contains(row_value)
+            },
+            Operator::IsNull => matches!(row_value, AlbaTypes::NONE),
+            Operator::IsNotNull => !matches!(row_value, AlbaTypes::NONE),
+            Operator::StringContains | Operator::StringCaseInsensitiveContains => {
+                let case_insensitive = discriminant(&query_condition.operator) == 
+                                      discriminant(&Operator::StringCaseInsensitiveContains);
                 
-                val
-            } else {
                 
-                continue;
-            };
-            
-            let check = match query_condition.operator {
-                Operator::Equal | Operator::StrictEqual => {
-                    
-                    
-                    let result = *value == *row_value;
-                    
-                    result
-                },
-                Operator::Greater | Operator::GreaterEquality | Operator::Lower | Operator::LowerEquality => {
-                    
-                    
-                    let opd = discriminant(&query_condition.operator);
-                    let equality = (opd == discriminant(&Operator::GreaterEquality)) || 
-                                  (opd == discriminant(&Operator::LowerEquality));
-                    let lower = (opd == discriminant(&Operator::Lower)) || 
-                               (opd == discriminant(&Operator::LowerEquality));
-                    
-                    
-    
-                    match (row_value, value) {
-                        (AlbaTypes::Int(x), AlbaTypes::Int(y)) => {
-                            
-                            let result = if lower { if equality { x <= y } else { x < y } } 
-                            else { if equality { x >= y } else { x > y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Bigint(x), AlbaTypes::Bigint(y)) => {
-                            
-                            let result = if lower { if equality { x <= y } else { x < y } } 
-                            else { if equality { x >= y } else { x > y } };
-                            result
-                        },
-                        (AlbaTypes::Float(x), AlbaTypes::Float(y)) => {
-                            
-                            let result = if lower { if equality { x <= y } else { x < y } } 
-                            else { if equality { x >= y } else { x > y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Int(x), AlbaTypes::Bigint(y)) => {
-                            let x_promoted = *x as i64;
-                            
-                            let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
-                            else { if equality { x_promoted >= *y } else { x_promoted > *y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Bigint(x), AlbaTypes::Int(y)) => {
-                            let y_promoted = *y as i64;
-                            
-                            let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
-                            else { if equality { *x >= y_promoted } else { *x > y_promoted } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Int(x), AlbaTypes::Float(y)) => {
-                            let x_promoted = *x as f64;
-                            
-                            let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
-                            else { if equality { x_promoted >= *y } else { x_promoted > *y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Float(x), AlbaTypes::Int(y)) => {
-                            let y_promoted = *y as f64;
-                            
-                            let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
-                            else { if equality { *x >= y_promoted } else { *x > y_promoted } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Bigint(x), AlbaTypes::Float(y)) => {
-                            let x_promoted = *x as f64;
-                            
-                            let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
-                            else { if equality { x_promoted >= *y } else { x_promoted > *y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Float(x), AlbaTypes::Bigint(y)) => {
-                            let y_promoted = *y as f64;
-                            if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
-                            else { if equality { *x >= y_promoted } else { *x > y_promoted } }
-                        },
-                        _ => {
-                            
-                            return Err(gerr("Invalid type for numeric comparison"));
-                        }
-                    }
-                },
-                Operator::Different => {
-                    *value != *row_value
-                },
-                Operator::StringContains | Operator::StringCaseInsensitiveContains => {
-                    let case_insensitive = discriminant(&query_condition.operator) == 
-                                          discriminant(&Operator::StringCaseInsensitiveContains);
-                    
-                    
-                    
-                    let row_string = match row_value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-                    
-                    let value_string = match value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-    
-                    if case_insensitive {
-                        row_string.to_lowercase().contains(&value_string.to_lowercase())
-                    } else {   
-                        row_string.contains(&value_string)
+                
+                let row_string = match row_value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
+                    _ => {
+                        
+                        return Err(TytoError::TypeMismatch("Invalid, the entered type cannot make string operations".to_string()));
                     }
-                },
-                Operator::StringRegularExpression => {
-                    
-                    
-                    let row_string = match row_value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-                    
-                    let value_string = match value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-    
-                    
-    
-                    let regex_result = if let Some(cached_regex) = regex_cache.get(&value_string) {
-                        cached_regex.is_match(&row_string)
-                    } else {
+                };
+                
+                let value_string = match value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
+                    _ => {
                         
-                        let re = Regex::new(&value_string);
-                        match re {
-                            Ok(compiled_regex) => {
-                                let match_result = compiled_regex.is_match(&row_string);
-                                
-                                regex_cache.insert(value_string, compiled_regex);
-                                match_result
-                            },
-                            Err(e) => {
-                                
-                                return Err(gerr(&e.to_string()));
-                            }
-                        }
-                    };
-                    
-                    regex_result
+                        return Err(TytoError::TypeMismatch("Invalid, the entered type cannot make string operations".to_string()));
+                    }
+                };
+
+                if case_insensitive {
+                    row_string.to_lowercase().contains(&value_string.to_lowercase())
+                } else {   
+                    row_string.contains(&value_string)
                 }
-            };
-            
-            
-            //println!("check:{}",check);
-            if let Some(gate) = logical_gate {
-                if i == len-1 {return Ok(check)}
-                match gate {
-                    LogicalGate::And => {
-                        if !check {
-                            result = false;
-                            break;
-                        }
+            },
+            Operator::StringRegularExpression => {
+                
+                
+                let row_string = match row_value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
+                    _ => {
                         
-                    },
-                    LogicalGate::Or => {
-                        if check {
-                            
-                            result = true;
-                            break;
-                        }
+                        return Err(TytoError::TypeMismatch("Invalid, the entered type cannot make string operations".to_string()));
+                    }
+                };
+                
+                let value_string = match value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
+                    _ => {
                         
+                        return Err(TytoError::TypeMismatch("Invalid, the entered type cannot make string operations".to_string()));
                     }
-                }
-            } else {
-                result = check;
+                };
+
+                
+
+                cached_regex(regex_cache, value_string, query_condition.regex_flags)?.is_match(&row_string)
+            },
+            Operator::Like | Operator::ILike => {
+                let row_string = match row_value {
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s,
+                    _ => return Err(TytoError::TypeMismatch("LIKE can only be used on string columns".to_string()))
+                };
+                let pattern = match value {
+                    AlbaTypes::Text(s) => s,
+                    _ => return Err(TytoError::InvalidInput("LIKE condition is missing its pattern".to_string()))
+                };
+                cached_regex(regex_cache, pattern.clone(), query_condition.regex_flags)?.is_match(row_string)
             }
-        }
-    
-        
-        Ok(result)
+        };
+        // NOT applies to the atom alone, before its gate joins it with the rest of the chain
+        let check = check != query_condition.negate;
+        Ok(Some(check))
     }
 
-    pub fn query_type(&self) -> Result<QueryType, Error> {
-        if self.chain.is_empty() || self.primary_key.is_none() {
+    pub fn query_type(&self) -> Result<QueryType, TytoError> {
+        if self.is_unsatisfiable(){
+            return Ok(QueryType::Empty);
+        }
+        if self.chain.is_empty() || self.primary_key.is_empty() {
             return Ok(QueryType::Scan);
         }
-        let pk = self.primary_key.clone().unwrap();
-        let chain : Vec<(QueryConditionAtom,Option<LogicalGate>)> = self.chain.clone().into_iter().filter(|p|p.0.column == pk).collect();
-        if chain.is_empty(){
+        if self.primary_key.len() > 1{
+            return Ok(self.composite_key_query_type())
+        }
+        let pk = self.primary_key[0].clone();
+        // with an OR anywhere the key lookups only cover every match when every atom is one of them
+        if self.has_or() && !self.atoms().iter().all(|a|a.column == pk && !a.negate && matches!(a.operator,Operator::Equal|Operator::StrictEqual|Operator::In)){
             return Ok(QueryType::Scan)
         }
+        let chain : Vec<&QueryConditionAtom> = self.atoms().into_iter().filter(|a|a.column == pk && !a.negate).collect();
+        if chain.is_empty(){
+            return Ok(self.secondary_query_type())
+        }
         let mut index_array = Vec::new();
-        for i in chain{
-            match i.0.operator{
+        for atom in chain{
+            match atom.operator{
                 Operator::Equal|Operator::StrictEqual => {
-                    index_array.push(get_index(i.0.value))
+                    index_array.push(vec![atom.value.clone()])
+                },
+                Operator::In => {
+                    for member in atom.list.iter(){
+                        let key = vec![member.clone()];
+                        if !index_array.contains(&key){
+                            index_array.push(key);
+                        }
+                    }
                 },
                 _ => {continue;}
                 
@@ -509,10 +764,359 @@ impl QueryConditions{
         }
         if !index_array.is_empty(){
             Ok(QueryType::Indexed(QueryIndexType::Strict(index_array)))    
+        }else if self.primary_index == IndexKind::Ordered{
+            match self.primary_key_bounds(&pk){
+                Some((lower,upper)) => Ok(QueryType::Indexed(QueryIndexType::PrimaryRange(lower,upper))),
+                None => Ok(self.secondary_query_type())
+            }
+        }else if let Some(range) = self.primary_key_range(&pk){
+            Ok(QueryType::Indexed(QueryIndexType::Range(range)))
         }else{
-            Ok(QueryType::Scan)
+            Ok(self.secondary_query_type())
+        }
+
+    }
+
+    /// A composite key can only be looked up when every one of its columns is fixed by an equality and every gate is an AND.
+    fn composite_key_query_type(&self) -> QueryType{
+        if self.has_or(){
+            return QueryType::Scan
+        }
+        let atoms = self.atoms();
+        let mut values = Vec::with_capacity(self.primary_key.len());
+        for column in self.primary_key.iter(){
+            let fixed = atoms.iter().find(|a|a.column == *column && !a.negate && matches!(a.operator,Operator::Equal|Operator::StrictEqual));
+            match fixed{
+                Some(atom) => values.push(atom.value.clone()),
+                None => return self.secondary_query_type()
+            }
+        }
+        QueryType::Indexed(QueryIndexType::Strict(vec![values]))
+    }
+
+    /// Bounds the comparisons put on an integer primary key, when both ends are known and every gate is an AND.
+    fn primary_key_range(&self, pk : &str) -> Option<RangeInclusive<i64>>{
+        if self.has_or(){
+            return None
+        }
+        let integer = |v : &AlbaTypes| match v{
+            AlbaTypes::Int(i) => Some(*i as i64),
+            AlbaTypes::Bigint(i) => Some(*i),
+            _ => None
+        };
+        let mut lower : Option<i64> = None;
+        let mut upper : Option<i64> = None;
+        for atom in self.atoms().into_iter().filter(|a|a.column == pk && !a.negate){
+            let (low,high) = match atom.operator{
+                Operator::Greater => (Some(integer(&atom.value)?.checked_add(1)?),None),
+                Operator::GreaterEquality => (Some(integer(&atom.value)?),None),
+                Operator::Lower => (None,Some(integer(&atom.value)?.checked_sub(1)?)),
+                Operator::LowerEquality => (None,Some(integer(&atom.value)?)),
+                Operator::Between => (Some(integer(&atom.value)?),Some(integer(atom.upper.as_ref()?)?)),
+                _ => continue
+            };
+            if let Some(low) = low{
+                lower = Some(lower.map_or(low,|l|l.max(low)));
+            }
+            if let Some(high) = high{
+                upper = Some(upper.map_or(high,|u|u.min(high)));
+            }
         }
+        Some(lower?..=upper?)
+    }
+
+    /// Tightest bounds the comparisons put on a primary key of any type, when every gate is an AND.
+    /// Values are compared through their ordered index encoding, the same order the index keeps.
+    fn primary_key_bounds(&self, pk : &str) -> Option<(Bound<AlbaTypes>,Bound<AlbaTypes>)>{
+        if self.has_or(){
+            return None
+        }
+        // of two bounds on the same side, keeps the one letting fewer keys through
+        fn tighter(current : Bound<AlbaTypes>, new : Bound<AlbaTypes>, wanted : Ordering) -> Bound<AlbaTypes>{
+            let (current_value,new_value) = match (&current,&new){
+                (_,Bound::Unbounded) => return current,
+                (Bound::Unbounded,_) => return new,
+                (Bound::Included(c)|Bound::Excluded(c),Bound::Included(n)|Bound::Excluded(n)) => (ordered_key(c),ordered_key(n))
+            };
+            match new_value.cmp(&current_value){
+                Ordering::Equal if matches!(new,Bound::Excluded(_)) => new,
+                order if order == wanted => new,
+                _ => current
+            }
+        }
+        let mut lower = Bound::Unbounded;
+        let mut upper = Bound::Unbounded;
+        let mut bounded = false;
+        for atom in self.atoms().into_iter().filter(|a|a.column == pk && !a.negate){
+            let value = atom.value.clone();
+            let (low,high) = match atom.operator{
+                Operator::Greater => (Bound::Excluded(value),Bound::Unbounded),
+                Operator::GreaterEquality => (Bound::Included(value),Bound::Unbounded),
+                Operator::Lower => (Bound::Unbounded,Bound::Excluded(value)),
+                Operator::LowerEquality => (Bound::Unbounded,Bound::Included(value)),
+                Operator::Between => (Bound::Included(value),Bound::Included(atom.upper.clone()?)),
+                _ => continue
+            };
+            lower = tighter(lower, low, Ordering::Greater);
+            upper = tighter(upper, high, Ordering::Less);
+            bounded = true;
+        }
+        bounded.then_some((lower,upper))
+    }
+
+    /// Cheap contradiction check for chains made only of AND gates, e.g. `x > 5 AND x < 2` or `x = 1 AND x = 2`.
+    /// Returns false whenever it can't tell, so a true result is always safe to short-circuit on.
+    /// Bounds are compared exactly, a pair only ordered through a lossy float conversion is never taken as a contradiction.
+    pub fn is_unsatisfiable(&self) -> bool{
+        if self.has_or(){
+            return false
+        }
+        // keeps the bound letting fewer values through, or the current one when both can't be ordered,
+        // dropping a bound only loses a chance to find a contradiction
+        fn tighter<'a>(current : Option<(&'a AlbaTypes,bool)>, value : &'a AlbaTypes, inclusive : bool, wanted : Ordering) -> Option<(&'a AlbaTypes,bool)>{
+            let (bound,bound_inclusive) = match current{
+                Some(c) => c,
+                None => return exact_cmp(value, value).map(|_|(value,inclusive))
+            };
+            match exact_cmp(value, bound){
+                Some(order) if order == wanted => Some((value,inclusive)),
+                Some(Ordering::Equal) if !inclusive => Some((value,false)),
+                _ => Some((bound,bound_inclusive))
+            }
+        }
+        // per column: lower bound, upper bound (value, inclusive), the required value and the excluded ones
+        let mut columns : HashMap<&str,(Option<(&AlbaTypes,bool)>,Option<(&AlbaTypes,bool)>,Option<&AlbaTypes>,Vec<&AlbaTypes>)> = HashMap::new();
+        for atom in self.atoms().into_iter().filter(|a|!a.negate){
+            let entry = columns.entry(atom.column.as_str()).or_default();
+            match &atom.operator{
+                Operator::Equal|Operator::StrictEqual => {
+                    if entry.2.is_some_and(|v|*v != atom.value){
+                        return true
+                    }
+                    entry.2 = Some(&atom.value);
+                },
+                Operator::Different => entry.3.push(&atom.value),
+                Operator::In if atom.list.is_empty() => return true,
+                Operator::Greater|Operator::GreaterEquality => {
+                    entry.0 = tighter(entry.0, &atom.value, matches!(atom.operator,Operator::GreaterEquality), Ordering::Greater);
+                },
+                Operator::Lower|Operator::LowerEquality => {
+                    entry.1 = tighter(entry.1, &atom.value, matches!(atom.operator,Operator::LowerEquality), Ordering::Less);
+                },
+                Operator::Between => {
+                    entry.0 = tighter(entry.0, &atom.value, true, Ordering::Greater);
+                    if let Some(upper) = &atom.upper{
+                        entry.1 = tighter(entry.1, upper, true, Ordering::Less);
+                    }
+                },
+                _ => {}
+            }
+        }
+        for (lower,upper,equal,different) in columns.values(){
+            if let (Some((l,li)),Some((u,ui))) = (lower,upper){
+                match exact_cmp(l, u){
+                    Some(Ordering::Greater) => return true,
+                    Some(Ordering::Equal) if !(*li && *ui) => return true,
+                    _ => {}
+                }
+            }
+            if let Some(equal) = equal{
+                if different.contains(equal){
+                    return true
+                }
+                let below = lower.is_some_and(|(l,i)|matches!(exact_cmp(equal, l),Some(Ordering::Less)) || (!i && exact_cmp(equal, l) == Some(Ordering::Equal)));
+                let above = upper.is_some_and(|(u,i)|matches!(exact_cmp(equal, u),Some(Ordering::Greater)) || (!i && exact_cmp(equal, u) == Some(Ordering::Equal)));
+                if below || above{
+                    return true
+                }
+            }
+        }
+        false
+    }
+
+    /// Picks the first atom a secondary index can answer: equality on any index, comparisons on ordered ones.
+    /// Only used when every gate is an AND, otherwise rows outside the index lookup could still match.
+    fn secondary_query_type(&self) -> QueryType{
+        if self.has_or(){
+            return QueryType::Scan
+        }
+        for atom in self.atoms().into_iter().filter(|a|!a.negate){
+            let kind = match self.indexes.get(&atom.column){
+                Some(k) => k,
+                None => continue
+            };
+            let value = atom.value.clone();
+            let lookup = match (&atom.operator,kind){
+                (Operator::Equal|Operator::StrictEqual,_) => SecondaryLookup::Equal(value),
+                (Operator::Greater,IndexKind::Ordered) => SecondaryLookup::Range(Bound::Excluded(value),Bound::Unbounded),
+                (Operator::GreaterEquality,IndexKind::Ordered) => SecondaryLookup::Range(Bound::Included(value),Bound::Unbounded),
+                (Operator::Lower,IndexKind::Ordered) => SecondaryLookup::Range(Bound::Unbounded,Bound::Excluded(value)),
+                (Operator::LowerEquality,IndexKind::Ordered) => SecondaryLookup::Range(Bound::Unbounded,Bound::Included(value)),
+                (Operator::Between,IndexKind::Ordered) => match &atom.upper{
+                    Some(upper) => SecondaryLookup::Range(Bound::Included(value),Bound::Included(upper.clone())),
+                    None => continue
+                },
+                _ => continue
+            };
+            return QueryType::Indexed(QueryIndexType::Secondary(atom.column.clone(),lookup))
+        }
+        QueryType::Scan
+    }
+
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn conditions(column : &str, operator : &str, value : Token, column_type : AlbaTypes) -> QueryConditions{
+        let properties = HashMap::from([(column.to_string(),column_type)]);
+        QueryConditions::from_primitive_conditions((vec![(Token::String(column.to_string()),Token::Operator(operator.to_string()),value)],Vec::new()), &properties, Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn string_conditions_keep_the_column_variant(){
+        let headers = vec!["name".to_string()];
+        let variants : [fn(String) -> AlbaTypes;5] = [AlbaTypes::NanoString, AlbaTypes::SmallString, AlbaTypes::MediumString, AlbaTypes::BigString, AlbaTypes::LargeString];
+        for variant in variants{
+            let c = conditions("name", "=", Token::String("tyto".to_string()), variant(String::new()));
+            assert!(c.row_match(&Row{data: vec![variant("tyto".to_string())]}, &headers).unwrap(), "{:?}", variant(String::new()));
+            assert!(!c.row_match(&Row{data: vec![variant("alba".to_string())]}, &headers).unwrap());
+        }
+    }
+
+    #[test]
+    fn string_conditions_truncate_on_a_char_boundary(){
+        // the 10 byte cut of a NanoString lands inside the last 'é'
+        let value = "abcdefghié".to_string();
+        let c = conditions("name", "=", Token::String(value), AlbaTypes::NanoString(String::new()));
+        let headers = vec!["name".to_string()];
+        assert!(c.row_match(&Row{data: vec![AlbaTypes::NanoString("abcdefghi".to_string())]}, &headers).unwrap());
+    }
+
+    #[test]
+    fn not_prefix_check_handles_multibyte_operators(){
+        // byte 4 of the operator falls inside the 'é'
+        let properties = HashMap::from([("id".to_string(),AlbaTypes::Int(0))]);
+        let result = QueryConditions::from_primitive_conditions((vec![(Token::String("id".to_string()),Token::Operator("noté =".to_string()),Token::Int(1))],Vec::new()), &properties, Vec::new());
+        assert!(result.is_err());
+    }
+
+    fn chain(atoms : Vec<(&str,&str,Token)>, gates : Vec<(usize,char)>, column_type : AlbaTypes) -> QueryConditions{
+        let properties = HashMap::from([("x".to_string(),column_type)]);
+        let atoms = atoms.into_iter().map(|(c,o,v)|(Token::String(c.to_string()),Token::Operator(o.to_string()),v)).collect();
+        QueryConditions::from_primitive_conditions((atoms,gates), &properties, Vec::new()).unwrap()
+    }
 
+    #[test]
+    fn contradictions_are_unsatisfiable(){
+        let int = AlbaTypes::Int(0);
+        assert!(chain(vec![("x",">",Token::Int(5)),("x","<",Token::Int(2))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        assert!(chain(vec![("x","=",Token::Int(1)),("x","=",Token::Int(2))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        assert!(chain(vec![("x",">",Token::Int(3)),("x","<=",Token::Int(3))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        assert!(chain(vec![("x","=",Token::Int(3)),("x","!=",Token::Int(3))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        assert!(chain(vec![("x","between",Token::Group(vec![Token::Int(1),Token::Int(4)])),("x",">",Token::Int(9))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        assert!(matches!(chain(vec![("x",">",Token::Int(5)),("x","<",Token::Int(2))], vec![(0,'a')], int).query_type().unwrap(), QueryType::Empty));
     }
 
+    #[test]
+    fn satisfiable_conditions_are_kept(){
+        let int = AlbaTypes::Int(0);
+        assert!(!chain(vec![("x",">",Token::Int(2)),("x","<",Token::Int(5))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        assert!(!chain(vec![("x",">=",Token::Int(3)),("x","<=",Token::Int(3))], vec![(0,'a')], int.clone()).is_unsatisfiable());
+        // an OR lets either side match on its own
+        assert!(!chain(vec![("x",">",Token::Int(5)),("x","<",Token::Int(2))], vec![(0,'o')], int.clone()).is_unsatisfiable());
+        // a negated atom isn't a bound
+        assert!(!chain(vec![("x","not >",Token::Int(5)),("x","<",Token::Int(2))], vec![(0,'a')], int).is_unsatisfiable());
+    }
+
+    #[test]
+    fn bigint_bounds_compare_exactly(){
+        // both values round to the same f64
+        let c = chain(vec![("x","=",Token::Int(1234567890123456789)),("x",">",Token::Int(1234567890123456700))], vec![(0,'a')], AlbaTypes::Bigint(0));
+        assert!(!c.is_unsatisfiable());
+        let c = chain(vec![("x",">",Token::Int(1234567890123456789)),("x","<",Token::Int(1234567890123456700))], vec![(0,'a')], AlbaTypes::Bigint(0));
+        assert!(c.is_unsatisfiable());
+    }
+
+    /// Matches a row against atoms that are true or false as listed, joined by `gates` ('a' or 'o').
+    fn evaluate(values : &[bool], gates : &[char]) -> bool{
+        let atoms = values.iter().map(|v|("x","=",Token::Int(if *v {1} else {2}))).collect();
+        let c = chain(atoms, gates.iter().copied().enumerate().collect(), AlbaTypes::Int(0));
+        c.row_match(&Row{data: vec![AlbaTypes::Int(1)]}, &vec!["x".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn mixed_chains_bind_and_before_or(){
+        assert!(evaluate(&[true,false,true], &['a','o']));
+        assert!(!evaluate(&[false,false,true], &['o','a']));
+        // left to right would give false here
+        assert!(evaluate(&[true,false,false], &['o','a']));
+        assert!(!evaluate(&[false,true,true,false], &['a','o','a']));
+        assert!(evaluate(&[false,true,true,true], &['a','o','a']));
+    }
+
+    #[test]
+    fn uniform_chains_are_all_or_any(){
+        assert!(evaluate(&[true,true,true], &['a','a']));
+        assert!(!evaluate(&[true,true,false], &['a','a']));
+        assert!(!evaluate(&[false,true,true], &['a','a']));
+        assert!(!evaluate(&[false,false,false], &['o','o']));
+        assert!(evaluate(&[false,false,true], &['o','o']));
+        assert!(evaluate(&[true,false,false], &['o','o']));
+    }
+
+    #[test]
+    fn uuid_conditions_take_the_string_form(){
+        let text = "123e4567-e89b-12d3-a456-426614174000";
+        let uuid = crate::alba_types::parse_uuid(text).unwrap();
+        assert_eq!(crate::alba_types::format_uuid(&uuid), text);
+        assert_eq!(crate::alba_types::parse_uuid(&text.replace('-', "").to_uppercase()).unwrap(), uuid);
+        for invalid in ["123e4567-e89b-12d3-a456-42661417400", "123e4567+e89b-12d3-a456-426614174000", "+23e4567-e89b-12d3-a456-426614174000", "g23e4567e89b12d3a456426614174000"]{
+            assert!(crate::alba_types::parse_uuid(invalid).is_err(), "{}", invalid);
+        }
+        let headers = vec!["id".to_string()];
+        let c = conditions("id", "=", Token::String(text.to_string()), AlbaTypes::Uuid([0;16]));
+        assert!(c.row_match(&Row{data: vec![AlbaTypes::Uuid(uuid)]}, &headers).unwrap());
+        assert!(!c.row_match(&Row{data: vec![AlbaTypes::Uuid([0;16])]}, &headers).unwrap());
+        let c = conditions("id", "!=", Token::String(text.to_string()), AlbaTypes::Uuid([0;16]));
+        assert!(c.row_match(&Row{data: vec![AlbaTypes::Uuid([0;16])]}, &headers).unwrap());
+        let properties = HashMap::from([("id".to_string(),AlbaTypes::Uuid([0;16]))]);
+        let malformed = (vec![(Token::String("id".to_string()),Token::Operator("=".to_string()),Token::String("not a uuid".to_string()))],Vec::new());
+        assert!(matches!(QueryConditions::from_primitive_conditions(malformed, &properties, Vec::new()), Err(TytoError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn timestamp_ranges_filter_by_instant(){
+        let at = |s : &str| Row{data: vec![AlbaTypes::Timestamp(crate::alba_types::parse_timestamp(s).unwrap())]};
+        let headers = vec!["x".to_string()];
+        let january = chain(vec![("x",">=",Token::String("2024-01-01".to_string())),("x","<",Token::String("2024-02-01T00:00:00Z".to_string()))], vec![(0,'a')], AlbaTypes::Timestamp(0));
+        for (time,inside) in [
+            ("2023-12-31T23:59:59.999999Z", false),
+            ("2024-01-01T00:00:00Z", true),
+            ("2024-01-15 12:30", true),
+            // 23:30 an hour behind UTC is already February
+            ("2024-01-31T23:30:00-01:00", false),
+            ("2024-01-31T23:30:00+01:00", true),
+            ("2024-02-01", false),
+        ]{
+            assert_eq!(january.row_match(&at(time), &headers).unwrap(), inside, "{}", time);
+        }
+        let between = chain(vec![("x","between",Token::Group(vec![Token::String("2024-03-01".to_string()),Token::Int(crate::alba_types::parse_timestamp("2024-03-02").unwrap())]))], Vec::new(), AlbaTypes::Timestamp(0));
+        assert!(between.row_match(&at("2024-03-02T00:00:00Z"), &headers).unwrap());
+        assert!(!between.row_match(&at("2024-03-02T00:00:01Z"), &headers).unwrap());
+        let properties = HashMap::from([("x".to_string(),AlbaTypes::Timestamp(0))]);
+        let malformed = (vec![(Token::String("x".to_string()),Token::Operator(">".to_string()),Token::String("yesterday".to_string()))],Vec::new());
+        assert!(matches!(QueryConditions::from_primitive_conditions(malformed, &properties, Vec::new()), Err(TytoError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn decimal_conditions_compare_exactly(){
+        let headers = vec!["x".to_string()];
+        let sum = Row{data: vec![AlbaTypes::Decimal(Decimal::from_f64(0.1).unwrap().checked_add(&Decimal::from_f64(0.2).unwrap()).unwrap())]};
+        assert!(conditions("x", "=", Token::String("0.30".to_string()), AlbaTypes::Decimal(Decimal::from(0))).row_match(&sum, &headers).unwrap());
+        assert!(!conditions("x", ">", Token::String("0.3".to_string()), AlbaTypes::Decimal(Decimal::from(0))).row_match(&sum, &headers).unwrap());
+        assert!(conditions("x", ">", Token::String("0.2999999999999999999999999999".to_string()), AlbaTypes::Decimal(Decimal::from(0))).row_match(&sum, &headers).unwrap());
+        assert!(conditions("x", "<", Token::Int(1), AlbaTypes::Decimal(Decimal::from(0))).row_match(&sum, &headers).unwrap());
+    }
 }
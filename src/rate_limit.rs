@@ -0,0 +1,64 @@
+use std::{collections::HashMap, io::{Error, ErrorKind}, sync::atomic::{AtomicU64, Ordering}, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RateLimit{
+    pub per_second : f64,
+    pub burst : u32,
+}
+
+/// Token bucket kept as a single atomic (GCRA): `tat` is the instant, in nanoseconds since `start`, at which the bucket is full again.
+#[derive(Debug)]
+pub struct TokenBucket{
+    start : Instant,
+    interval : u64,
+    capacity : u64,
+    tat : AtomicU64,
+}
+
+impl TokenBucket{
+    pub fn new(limit : RateLimit) -> Self{
+        let interval = (1_000_000_000f64 / limit.per_second.max(f64::MIN_POSITIVE)).min(u64::MAX as f64) as u64;
+        TokenBucket{
+            start: Instant::now(),
+            interval: interval.max(1),
+            capacity: interval.max(1).saturating_mul(limit.burst.max(1) as u64),
+            tat: AtomicU64::new(0),
+        }
+    }
+    /// Takes one token, or tells how long to wait until one is available.
+    pub fn try_acquire(&self) -> Result<(),Duration>{
+        let now = self.start.elapsed().as_nanos() as u64;
+        let mut tat = self.tat.load(Ordering::Relaxed);
+        loop{
+            let new_tat = tat.max(now).saturating_add(self.interval);
+            let limit = now.saturating_add(self.capacity);
+            if new_tat > limit{
+                return Err(Duration::from_nanos(new_tat - limit))
+            }
+            match self.tat.compare_exchange_weak(tat, new_tat, Ordering::Relaxed, Ordering::Relaxed){
+                Ok(_) => return Ok(()),
+                Err(current) => tat = current
+            }
+        }
+    }
+}
+
+/// One bucket per principal, built from the `rate_limits` setting.
+#[derive(Debug, Default)]
+pub struct RateLimiters(HashMap<String,TokenBucket>);
+
+impl RateLimiters{
+    pub fn new(limits : &HashMap<String,RateLimit>) -> Self{
+        RateLimiters(limits.iter().map(|(principal,limit)|(principal.clone(),TokenBucket::new(*limit))).collect())
+    }
+    /// Principals without a configured limit are never throttled.
+    pub fn check(&self, principal : &str, operation : &str) -> Result<(),Error>{
+        let bucket = match self.0.get(principal){
+            Some(b) => b,
+            None => return Ok(())
+        };
+        bucket.try_acquire().map_err(|wait|Error::new(ErrorKind::WouldBlock, format!("Rate limited, '{}' exceeded its {} quota, retry after {} ms", principal, operation, wait.as_millis().max(1))))
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use crate::query::Query;
+
+struct CachedQuery{
+    /// `Container::version` when the result was computed, it no longer holds once the container moved on.
+    version : u64,
+    used : u64,
+    query : Query,
+}
+
+/// Least recently used search results, keyed by container and a rendering of the search.
+/// A capacity of 0 disables it.
+#[derive(Default)]
+pub struct ResultCache{
+    capacity : usize,
+    tick : u64,
+    entries : HashMap<(String,String),CachedQuery>,
+}
+
+impl std::fmt::Debug for ResultCache{
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        f.debug_struct("ResultCache").field("capacity",&self.capacity).field("entries",&self.entries.len()).finish()
+    }
+}
+
+impl ResultCache{
+    pub fn new(capacity : usize) -> Self{
+        ResultCache{capacity,tick:0,entries:HashMap::new()}
+    }
+    pub fn is_enabled(&self) -> bool{
+        self.capacity > 0
+    }
+    /// Returns the cached result, unless the container changed since it was stored.
+    pub fn get(&mut self, container : &str, key : &str, version : u64) -> Option<Query>{
+        let entry_key = (container.to_string(),key.to_string());
+        let entry = self.entries.get_mut(&entry_key)?;
+        if entry.version != version{
+            self.entries.remove(&entry_key);
+            return None
+        }
+        self.tick += 1;
+        entry.used = self.tick;
+        Some(entry.query.clone())
+    }
+    pub fn insert(&mut self, container : &str, key : String, version : u64, query : Query){
+        if !self.is_enabled(){
+            return
+        }
+        let entry_key = (container.to_string(),key);
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&entry_key){
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_,e)|e.used).map(|(k,_)|k.clone()){
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(entry_key, CachedQuery{version,used:self.tick,query});
+    }
+    /// Drops every result of `container`.
+    pub fn invalidate(&mut self, container : &str){
+        self.entries.retain(|(name,_),_|name != container);
+    }
+}
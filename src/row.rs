@@ -1,9 +1,190 @@
+use std::io::Error;
+
 use serde::{Deserialize, Serialize};
 
-use crate::alba_types::AlbaTypes;
+use crate::{alba_types::AlbaTypes, error::TytoError, gerr};
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Row{
     pub data : Vec<AlbaTypes>,
-}
\ No newline at end of file
+}
+// the typed getters are for code embedding the database, the server itself matches on the values
+#[allow(dead_code)]
+impl Row{
+    fn column(&self, col_index : usize) -> Result<&AlbaTypes,Error>{
+        self.data.get(col_index).ok_or(gerr(&format!("The row has {} columns, there is no column {}",self.data.len(),col_index)))
+    }
+    fn mismatch<T>(&self, col_index : usize, expected : &str) -> Result<T,Error>{
+        Err(Error::from(TytoError::TypeMismatch(format!("Column {} holds {:?}, which can't be read as {}",col_index,self.data[col_index],expected))))
+    }
+    /// `Ok(None)` means the column is empty, an error means it holds a value of another type.
+    pub fn get_i64(&self, col_index : usize) -> Result<Option<i64>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Int(i) => Ok(Some(*i as i64)),
+            AlbaTypes::Bigint(i) => Ok(Some(*i)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "an integer")
+        }
+    }
+    pub fn get_i32(&self, col_index : usize) -> Result<Option<i32>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Int(i) => Ok(Some(*i)),
+            AlbaTypes::Bigint(i) => match i32::try_from(*i){
+                Ok(i) => Ok(Some(i)),
+                Err(_) => Err(Error::from(TytoError::TypeMismatch(format!("Column {} holds {}, which doesn't fit in a 32 bit integer",col_index,i))))
+            },
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "an integer")
+        }
+    }
+    pub fn get_f64(&self, col_index : usize) -> Result<Option<f64>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Float(f) => Ok(Some(*f)),
+            AlbaTypes::Int(i) => Ok(Some(*i as f64)),
+            AlbaTypes::Bigint(i) => Ok(Some(*i as f64)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "a float")
+        }
+    }
+    pub fn get_bool(&self, col_index : usize) -> Result<Option<bool>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Bool(b) => Ok(Some(*b)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "a bool")
+        }
+    }
+    pub fn get_char(&self, col_index : usize) -> Result<Option<char>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Char(c) => Ok(Some(*c)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "a char")
+        }
+    }
+    pub fn get_str(&self, col_index : usize) -> Result<Option<&str>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Text(s)|AlbaTypes::NanoString(s)|AlbaTypes::SmallString(s)|AlbaTypes::MediumString(s)|AlbaTypes::BigString(s)|AlbaTypes::LargeString(s) => Ok(Some(s)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "a string")
+        }
+    }
+    pub fn get_bytes(&self, col_index : usize) -> Result<Option<&[u8]>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::NanoBytes(b)|AlbaTypes::SmallBytes(b)|AlbaTypes::MediumBytes(b)|AlbaTypes::BigSBytes(b)|AlbaTypes::LargeBytes(b) => Ok(Some(b)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "bytes")
+        }
+    }
+    pub fn get_uuid(&self, col_index : usize) -> Result<Option<[u8;16]>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Uuid(u) => Ok(Some(*u)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "a uuid")
+        }
+    }
+    pub fn get_timestamp(&self, col_index : usize) -> Result<Option<chrono::DateTime<chrono::Utc>>,Error>{
+        match self.column(col_index)?{
+            AlbaTypes::Timestamp(t) => Ok(chrono::DateTime::from_timestamp_micros(*t)),
+            AlbaTypes::NONE => Ok(None),
+            _ => self.mismatch(col_index, "a timestamp")
+        }
+    }
+    /// Looks a column up by name, `headers` being the column names the row was returned with.
+    pub fn get_by_name(&self, name : &str, headers : &[String]) -> Result<&AlbaTypes,Error>{
+        let col_index = headers.iter().position(|h|h == name).ok_or(gerr(&format!("There is no column named {}",name)))?;
+        self.column(col_index)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn headers() -> Vec<String>{
+        vec!["id".to_string(),"name".to_string()]
+    }
+
+    fn is_mismatch(e : Error) -> bool{
+        matches!(TytoError::from(e), TytoError::TypeMismatch(_))
+    }
+
+    #[test]
+    fn get_by_name_follows_the_headers(){
+        let row = Row{data: vec![AlbaTypes::Bigint(7), AlbaTypes::NanoString("ana".to_string())]};
+        assert_eq!(row.get_by_name("id", &headers()).unwrap(), &AlbaTypes::Bigint(7));
+        assert_eq!(row.get_by_name("name", &headers()).unwrap(), &AlbaTypes::NanoString("ana".to_string()));
+    }
+
+    #[test]
+    fn get_by_name_errors_on_unknown_or_missing_columns(){
+        let row = Row{data: vec![AlbaTypes::Bigint(7)]};
+        assert!(row.get_by_name("age", &headers()).is_err());
+        assert!(row.get_by_name("name", &headers()).is_err());
+    }
+
+    #[test]
+    fn integers_widen_and_narrow_when_they_fit(){
+        let row = Row{data: vec![AlbaTypes::Int(-3), AlbaTypes::Bigint(1 << 40), AlbaTypes::NONE, AlbaTypes::Float(1.5), AlbaTypes::Bigint(12)]};
+        assert_eq!(row.get_i64(0).unwrap(), Some(-3));
+        assert_eq!(row.get_i64(1).unwrap(), Some(1 << 40));
+        assert_eq!(row.get_i64(2).unwrap(), None);
+        assert!(is_mismatch(row.get_i64(3).unwrap_err()));
+        assert_eq!(row.get_i32(0).unwrap(), Some(-3));
+        assert_eq!(row.get_i32(4).unwrap(), Some(12));
+        assert!(is_mismatch(row.get_i32(1).unwrap_err()));
+        assert!(row.get_i64(5).is_err());
+    }
+
+    #[test]
+    fn floats_take_integers_too(){
+        let row = Row{data: vec![AlbaTypes::Float(2.5), AlbaTypes::Int(2), AlbaTypes::Bigint(-4), AlbaTypes::Bool(true)]};
+        assert_eq!(row.get_f64(0).unwrap(), Some(2.5));
+        assert_eq!(row.get_f64(1).unwrap(), Some(2.0));
+        assert_eq!(row.get_f64(2).unwrap(), Some(-4.0));
+        assert!(is_mismatch(row.get_f64(3).unwrap_err()));
+    }
+
+    #[test]
+    fn bools_and_chars_only_read_their_own_variant(){
+        let row = Row{data: vec![AlbaTypes::Bool(false), AlbaTypes::Char('x'), AlbaTypes::NONE]};
+        assert_eq!(row.get_bool(0).unwrap(), Some(false));
+        assert_eq!(row.get_char(1).unwrap(), Some('x'));
+        assert_eq!((row.get_bool(2).unwrap(),row.get_char(2).unwrap()), (None,None));
+        assert!(is_mismatch(row.get_bool(1).unwrap_err()));
+        assert!(is_mismatch(row.get_char(0).unwrap_err()));
+    }
+
+    #[test]
+    fn every_string_variant_reads_as_str(){
+        let text = "ana".to_string();
+        let row = Row{data: vec![AlbaTypes::Text(text.clone()), AlbaTypes::NanoString(text.clone()), AlbaTypes::SmallString(text.clone()),
+            AlbaTypes::MediumString(text.clone()), AlbaTypes::BigString(text.clone()), AlbaTypes::LargeString(text.clone()), AlbaTypes::NONE, AlbaTypes::Int(1)]};
+        for col_index in 0..6{
+            assert_eq!(row.get_str(col_index).unwrap(), Some("ana"));
+        }
+        assert_eq!(row.get_str(6).unwrap(), None);
+        assert!(is_mismatch(row.get_str(7).unwrap_err()));
+    }
+
+    #[test]
+    fn every_bytes_variant_reads_as_a_slice(){
+        let bytes = vec![1u8,2,3];
+        let row = Row{data: vec![AlbaTypes::NanoBytes(bytes.clone()), AlbaTypes::SmallBytes(bytes.clone()), AlbaTypes::MediumBytes(bytes.clone()),
+            AlbaTypes::BigSBytes(bytes.clone()), AlbaTypes::LargeBytes(bytes.clone()), AlbaTypes::NanoString("abc".to_string())]};
+        for col_index in 0..5{
+            assert_eq!(row.get_bytes(col_index).unwrap(), Some(&bytes[..]));
+        }
+        assert!(is_mismatch(row.get_bytes(5).unwrap_err()));
+    }
+
+    #[test]
+    fn uuids_and_timestamps_read_as_their_types(){
+        let row = Row{data: vec![AlbaTypes::Uuid([7;16]), AlbaTypes::Timestamp(1_700_000_000_123_456), AlbaTypes::NONE]};
+        assert_eq!(row.get_uuid(0).unwrap(), Some([7;16]));
+        assert_eq!(row.get_timestamp(1).unwrap().unwrap().timestamp_micros(), 1_700_000_000_123_456);
+        assert_eq!(row.get_uuid(2).unwrap(), None);
+        assert_eq!(row.get_timestamp(2).unwrap(), None);
+        assert!(is_mismatch(row.get_uuid(1).unwrap_err()));
+        assert!(is_mismatch(row.get_timestamp(0).unwrap_err()));
+    }
+}
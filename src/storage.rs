@@ -0,0 +1,432 @@
+use std::{collections::HashMap, fs::File, io::{Error, ErrorKind}, sync::{atomic::{AtomicU64, AtomicU8, Ordering}, Arc, RwLock}, time::Duration};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Mutex};
+use crate::{database::WriteEntry, gerr, logwarn};
+#[cfg(target_os = "linux")]
+use crate::database::{batch_reads_abs, batch_write_data};
+
+/// Most writes submitted to a single ring, larger batches are split.
+#[cfg(target_os = "linux")]
+const BATCH_WRITE_LIMIT : usize = 3000;
+
+/// How far a commit, a staged change or an index update is pushed towards the disk before it is acknowledged.
+/// Structural rewrites (vacuum, bulk loads, truncates, migrations, backups) always sync fully whatever the level.
+#[derive(Serialize,Deserialize,Clone,Copy,Debug,Default,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability{
+    /// Data and metadata are synced, nothing acknowledged is lost, even when the machine loses power.
+    #[default]
+    Full,
+    /// Only the data is synced. A power loss can lose a change that grew a file, since its new length may not be on disk yet.
+    DataOnly,
+    /// The sync runs in the background after the write. A process crash loses nothing,
+    /// a power loss can lose the last acknowledged changes and leave the indexes behind the container file.
+    Async,
+    /// Never synced, the OS writes the pages back whenever it decides. Only for data that can be rebuilt.
+    Off,
+}
+
+static DURABILITY : AtomicU8 = AtomicU8::new(0);
+
+impl Durability{
+    /// Makes `self` the level of every container in the process.
+    pub fn set(self){
+        DURABILITY.store(self as u8, Ordering::Relaxed);
+    }
+    pub fn current() -> Durability{
+        match DURABILITY.load(Ordering::Relaxed){
+            1 => Durability::DataOnly,
+            2 => Durability::Async,
+            3 => Durability::Off,
+            _ => Durability::Full,
+        }
+    }
+}
+
+/// Syncs `storage` as the current durability level asks.
+pub fn flush<S : Storage + ?Sized>(storage : &S) -> Result<(),Error>{
+    flush_as(storage, Durability::current())
+}
+
+/// Syncs `storage` as `durability` asks.
+pub fn flush_as<S : Storage + ?Sized>(storage : &S, durability : Durability) -> Result<(),Error>{
+    match durability{
+        Durability::Full => storage.sync_all(),
+        Durability::DataOnly => storage.sync_data(),
+        Durability::Async => {
+            if let Some(file) = storage.as_file(){
+                sync_in_background(file.try_clone()?)?;
+            }
+            Ok(())
+        },
+        Durability::Off => Ok(()),
+    }
+}
+
+/// Syncs `file` on the blocking pool, or right away outside of a runtime.
+fn sync_in_background(file : File) -> Result<(),Error>{
+    match tokio::runtime::Handle::try_current(){
+        Ok(runtime) => {
+            runtime.spawn_blocking(move ||{
+                if let Err(e) = file.sync_all(){
+                    logwarn!("Background sync failed, the last changes may not survive a power loss: {}", e);
+                }
+            });
+            Ok(())
+        },
+        Err(_) => file.sync_all()
+    }
+}
+
+/// Microseconds a commit group stays open after its first commit, 0 disables group commit.
+static GROUP_COMMIT_WINDOW : AtomicU64 = AtomicU64::new(0);
+/// The group commits join until its window closes.
+static OPEN_GROUP : std::sync::Mutex<Option<Arc<CommitGroup>>> = std::sync::Mutex::new(None);
+
+/// Files written by the commits of one window, synced together once it closes.
+#[derive(Debug)]
+struct CommitGroup{
+    files : std::sync::Mutex<Vec<File>>,
+    done : watch::Sender<Option<Result<(),String>>>,
+}
+
+/// Resolves once the sync of the commit group it was taken from is done.
+#[derive(Debug)]
+pub struct SyncTicket(watch::Receiver<Option<Result<(),String>>>);
+
+impl SyncTicket{
+    pub async fn wait(mut self) -> Result<(),Error>{
+        let result = self.0.wait_for(|r|r.is_some()).await.map_err(|_|gerr("A commit group was dropped before its sync"))?.clone();
+        result.unwrap_or(Ok(())).map_err(|e|gerr(&format!("The group sync failed: {}",e)))
+    }
+}
+
+/// Sets how long a commit group waits for more commits before syncing, in microseconds.
+pub fn set_group_commit_window(micros : u64){
+    GROUP_COMMIT_WINDOW.store(micros, Ordering::Relaxed);
+}
+
+/// Whether commits leave their sync to a commit group. Levels that don't wait for the sync have nothing to share.
+pub fn group_commit_enabled() -> bool{
+    GROUP_COMMIT_WINDOW.load(Ordering::Relaxed) > 0 && matches!(Durability::current(), Durability::Full | Durability::DataOnly)
+}
+
+/// Adds `files` to the open commit group, opening one when there is none, and returns the ticket of its sync.
+pub fn join_commit_group(files : Vec<File>) -> Result<SyncTicket,Error>{
+    let mut open = OPEN_GROUP.lock().map_err(|_|gerr("The commit group lock was poisoned"))?;
+    let group = match &*open{
+        Some(group) => group.clone(),
+        None => {
+            let group = Arc::new(CommitGroup{files:std::sync::Mutex::new(Vec::new()),done:watch::channel(None).0});
+            *open = Some(group.clone());
+            let window = Duration::from_micros(GROUP_COMMIT_WINDOW.load(Ordering::Relaxed));
+            tokio::spawn(async move{
+                tokio::time::sleep(window).await;
+                sync_commit_group().await;
+            });
+            group
+        }
+    };
+    // joined under the lock of the open group, so the files are in before it can close
+    group.files.lock().map_err(|_|gerr("A commit group lock was poisoned"))?.extend(files);
+    Ok(SyncTicket(group.done.subscribe()))
+}
+
+/// Closes the open group, syncs every file its commits wrote and wakes them.
+async fn sync_commit_group(){
+    let group = match OPEN_GROUP.lock().unwrap_or_else(|e|e.into_inner()).take(){
+        Some(group) => group,
+        None => return
+    };
+    let files = std::mem::take(&mut *group.files.lock().unwrap_or_else(|e|e.into_inner()));
+    let durability = Durability::current();
+    let result = tokio::task::spawn_blocking(move || -> Result<(),Error>{
+        for file in files.iter(){
+            flush_as(file, durability)?;
+        }
+        Ok(())
+    }).await.map_err(|e|gerr(&e.to_string())).and_then(|r|r);
+    group.done.send_replace(Some(result.map_err(|e|e.to_string())));
+}
+
+/// Reads exactly `buffer.len()` bytes of `file` at `offset`.
+#[cfg(unix)]
+pub fn read_exact_at(file : &File, buffer : &mut [u8], offset : u64) -> Result<(),Error>{
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+/// Reads exactly `buffer.len()` bytes of `file` at `offset`.
+/// `seek_read` moves the file cursor, nothing here relies on it.
+#[cfg(windows)]
+pub fn read_exact_at(file : &File, mut buffer : &mut [u8], mut offset : u64) -> Result<(),Error>{
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty(){
+        match file.seek_read(buffer, offset){
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(())
+}
+/// Writes the whole of `buffer` into `file` at `offset`.
+#[cfg(unix)]
+pub fn write_all_at(file : &File, buffer : &[u8], offset : u64) -> Result<(),Error>{
+    std::os::unix::fs::FileExt::write_all_at(file, buffer, offset)
+}
+/// Writes the whole of `buffer` into `file` at `offset`.
+#[cfg(windows)]
+pub fn write_all_at(file : &File, mut buffer : &[u8], mut offset : u64) -> Result<(),Error>{
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty(){
+        match file.seek_write(buffer, offset){
+            Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(n) => {
+                buffer = &buffer[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(())
+}
+
+/// Positioned access to the bytes of one container file, implemented by `File` and by `MemoryFile` for containers that never touch the disk.
+/// `File` sends batches through io_uring on Linux, everywhere else batches go one entry at a time.
+pub trait Storage : std::fmt::Debug + Send + Sync{
+    fn read_exact_at(&self, buffer : &mut [u8], offset : u64) -> Result<(),Error>;
+    fn write_all_at(&self, buffer : &[u8], offset : u64) -> Result<(),Error>;
+    fn set_len(&self, len : u64) -> Result<(),Error>;
+    fn sync_all(&self) -> Result<(),Error>;
+    /// Syncs the content without the metadata that isn't needed to read it back.
+    fn sync_data(&self) -> Result<(),Error>{
+        self.sync_all()
+    }
+    fn len(&self) -> Result<u64,Error>;
+    /// Reads `size` bytes at each of `offsets`, returning the buffers in the order of `offsets`.
+    fn read_batch(&self, offsets : &[u64], size : usize) -> Result<Vec<Vec<u8>>,Error>{
+        offsets.iter().map(|offset|{
+            let mut buffer = vec![0u8;size];
+            self.read_exact_at(&mut buffer, *offset)?;
+            Ok(buffer)
+        }).collect()
+    }
+    /// Writes every entry at its offset and syncs as `durability` asks once they are all done.
+    fn write_batch(&self, entries : &[WriteEntry], durability : Durability) -> Result<(),Error>{
+        for entry in entries{
+            self.write_all_at(&entry.buffer[..entry.length], entry.offset as u64)?;
+        }
+        flush_as(self, durability)
+    }
+    /// The file behind the storage, if any, for the scans that map it into memory.
+    fn as_file(&self) -> Option<&File>{
+        None
+    }
+}
+
+/// A container file shared between the container and the searches reading it.
+pub type SharedStorage = Arc<Mutex<Box<dyn Storage>>>;
+
+impl Storage for File{
+    fn read_exact_at(&self, buffer : &mut [u8], offset : u64) -> Result<(),Error>{
+        read_exact_at(self, buffer, offset)
+    }
+    fn write_all_at(&self, buffer : &[u8], offset : u64) -> Result<(),Error>{
+        write_all_at(self, buffer, offset)
+    }
+    fn set_len(&self, len : u64) -> Result<(),Error>{
+        File::set_len(self, len)
+    }
+    fn sync_all(&self) -> Result<(),Error>{
+        File::sync_all(self)
+    }
+    fn sync_data(&self) -> Result<(),Error>{
+        File::sync_data(self)
+    }
+    fn len(&self) -> Result<u64,Error>{
+        Ok(self.metadata()?.len())
+    }
+    #[cfg(target_os = "linux")]
+    fn read_batch(&self, offsets : &[u64], size : usize) -> Result<Vec<Vec<u8>>,Error>{
+        batch_reads_abs(offsets, size, self)
+    }
+    #[cfg(target_os = "linux")]
+    fn write_batch(&self, entries : &[WriteEntry], durability : Durability) -> Result<(),Error>{
+        let sync = match durability{
+            Durability::Full => 1,
+            Durability::DataOnly => 2,
+            Durability::Async | Durability::Off => 0,
+        };
+        for chunk in entries.chunks(BATCH_WRITE_LIMIT){
+            batch_write_data(chunk, self.as_raw_fd(), sync)?;
+        }
+        if durability == Durability::Async{
+            flush_as(self, durability)?;
+        }
+        Ok(())
+    }
+    fn as_file(&self) -> Option<&File>{
+        Some(self)
+    }
+}
+
+/// A file kept entirely in memory, its content is gone once every handle to it is dropped.
+/// Clones share the same content, the way two opens of a path share the file.
+#[derive(Debug,Default,Clone)]
+pub struct MemoryFile(Arc<RwLock<Vec<u8>>>);
+
+impl MemoryFile{
+    pub fn new(content : Vec<u8>) -> Self{
+        MemoryFile(Arc::new(RwLock::new(content)))
+    }
+}
+
+impl Storage for MemoryFile{
+    fn read_exact_at(&self, buffer : &mut [u8], offset : u64) -> Result<(),Error>{
+        let content = self.0.read().map_err(|_|gerr("A memory file lock was poisoned"))?;
+        let start = offset as usize;
+        match content.get(start..start + buffer.len()){
+            Some(bytes) => {buffer.copy_from_slice(bytes);Ok(())},
+            None => Err(Error::new(ErrorKind::UnexpectedEof, format!("Reading {} bytes at {} is past the end of a {} bytes memory file", buffer.len(), offset, content.len())))
+        }
+    }
+    fn write_all_at(&self, buffer : &[u8], offset : u64) -> Result<(),Error>{
+        let mut content = self.0.write().map_err(|_|gerr("A memory file lock was poisoned"))?;
+        let start = offset as usize;
+        if content.len() < start + buffer.len(){
+            content.resize(start + buffer.len(), 0);
+        }
+        content[start..start + buffer.len()].copy_from_slice(buffer);
+        Ok(())
+    }
+    fn set_len(&self, len : u64) -> Result<(),Error>{
+        self.0.write().map_err(|_|gerr("A memory file lock was poisoned"))?.resize(len as usize, 0);
+        Ok(())
+    }
+    fn sync_all(&self) -> Result<(),Error>{
+        Ok(())
+    }
+    fn len(&self) -> Result<u64,Error>{
+        Ok(self.0.read().map_err(|_|gerr("A memory file lock was poisoned"))?.len() as u64)
+    }
+}
+
+/// Where a container keeps its files. Every file is named by the suffix appended to the container's name,
+/// the empty suffix being the data file, so the container, its indexes and its MVCC record only go through here
+/// and a new backend only has to implement this and `Storage`.
+pub trait Backend : std::fmt::Debug + Send + Sync{
+    /// Opens the file `suffix` names, created empty when missing.
+    fn open(&self, suffix : &str) -> Result<Box<dyn Storage>,Error>;
+    /// Whether the file `suffix` names exists.
+    fn exists(&self, suffix : &str) -> Result<bool,Error>;
+    /// Removes the file `suffix` names, doing nothing when it is missing.
+    fn remove(&self, suffix : &str) -> Result<(),Error>;
+    /// Moves the file `from` names over the one `to` names.
+    fn rename(&self, from : &str, to : &str) -> Result<(),Error>;
+    /// Whether the files outlive the process.
+    fn persistent(&self) -> bool;
+}
+
+/// The files of the container at `path` on disk, `path` followed by each suffix. The default backend.
+#[derive(Debug,Clone)]
+pub struct DiskBackend{
+    path : String,
+}
+
+impl DiskBackend{
+    pub fn new(path : &str) -> Self{
+        DiskBackend{path:path.to_string()}
+    }
+    fn file_path(&self, suffix : &str) -> String{
+        format!("{}{}",self.path,suffix)
+    }
+}
+
+impl Backend for DiskBackend{
+    fn open(&self, suffix : &str) -> Result<Box<dyn Storage>,Error>{
+        Ok(Box::new(std::fs::OpenOptions::new().read(true).write(true).create(true).open(self.file_path(suffix))?))
+    }
+    fn exists(&self, suffix : &str) -> Result<bool,Error>{
+        std::fs::exists(self.file_path(suffix))
+    }
+    fn remove(&self, suffix : &str) -> Result<(),Error>{
+        match std::fs::remove_file(self.file_path(suffix)){
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result
+        }
+    }
+    fn rename(&self, from : &str, to : &str) -> Result<(),Error>{
+        std::fs::rename(self.file_path(from), self.file_path(to))
+    }
+    fn persistent(&self) -> bool{
+        true
+    }
+}
+
+/// Files held in memory by suffix, dropped with the backend.
+#[derive(Debug,Default)]
+pub struct MemoryBackend{
+    files : std::sync::Mutex<HashMap<String,MemoryFile>>,
+}
+
+impl MemoryBackend{
+    /// A backend whose data file starts as `content`.
+    pub fn new(content : Vec<u8>) -> Self{
+        MemoryBackend{files:std::sync::Mutex::new(HashMap::from([(String::new(),MemoryFile::new(content))]))}
+    }
+    fn files(&self) -> Result<std::sync::MutexGuard<'_,HashMap<String,MemoryFile>>,Error>{
+        self.files.lock().map_err(|_|gerr("A memory backend lock was poisoned"))
+    }
+}
+
+impl Backend for MemoryBackend{
+    fn open(&self, suffix : &str) -> Result<Box<dyn Storage>,Error>{
+        Ok(Box::new(self.files()?.entry(suffix.to_string()).or_default().clone()))
+    }
+    fn exists(&self, suffix : &str) -> Result<bool,Error>{
+        Ok(self.files()?.contains_key(suffix))
+    }
+    fn remove(&self, suffix : &str) -> Result<(),Error>{
+        self.files()?.remove(suffix);
+        Ok(())
+    }
+    fn rename(&self, from : &str, to : &str) -> Result<(),Error>{
+        let mut files = self.files()?;
+        match files.remove(from){
+            Some(file) => {files.insert(to.to_string(), file);Ok(())},
+            None => Err(Error::new(ErrorKind::NotFound, format!("There is no memory file {} to rename", from)))
+        }
+    }
+    fn persistent(&self) -> bool{
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn positioned_io_reads_and_writes_whole_buffers(){
+        let dir = std::env::temp_dir().join(format!("tytodb-storage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(dir.join("rows")).unwrap();
+        // writing past the end grows the file, the gap reads back as zeroes
+        write_all_at(&file, b"tyto", 8).unwrap();
+        write_all_at(&file, b"alba", 0).unwrap();
+        let mut buffer = [1u8;12];
+        read_exact_at(&file, &mut buffer, 0).unwrap();
+        assert_eq!(&buffer, b"alba\0\0\0\0tyto");
+        let short = read_exact_at(&file, &mut [0u8;8], 8).unwrap_err();
+        assert_eq!(short.kind(), ErrorKind::UnexpectedEof);
+        drop(file);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}